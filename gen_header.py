@@ -0,0 +1,121 @@
+"""
+Generates a C header for the extern "C" functions of the Rust library.
+For example python3 gen_header.py src/rs/step_c_abi.rs > src/rs/shortcut.h
+"""
+import re
+import sys
+
+C_TYPES = {
+    "bool": "bool",
+    "f32": "float",
+    "f64": "double",
+    "i32": "int32_t",
+    "u8": "uint8_t",
+    "u16": "uint16_t",
+    "u32": "uint32_t",
+    "u64": "uint64_t",
+    "usize": "size_t",
+    "c_char": "char",
+    "c_void": "void",
+}
+WRAPPER_ARGS = "r_raw: *mut f32, d_raw: *const f32, n: usize"
+WRAPPER_RET = "i32"
+
+EXTERN_FN = re.compile(r'#\[no_mangle\]\s*pub (?:unsafe )?extern "C" fn (\w+)\(')
+RETURN = re.compile(r'\s*(?:->\s*([^{]+?))?\s*\{')
+FN_POINTER = re.compile(r'^Option<extern "C" fn\((.*)\)(?:\s*->\s*(.+?))?>$')
+WRAPPER_CALL = re.compile(r'^create_extern_c_wrapper!\((\w+),', re.MULTILINE)
+CONST = re.compile(r'^pub const (\w+): i32 = (-?\d+);', re.MULTILINE)
+
+def c_type(rust_type, opaque):
+    rust_type = rust_type.strip()
+    for prefix, qualifier in (("*mut ", ""), ("*const ", "const ")):
+        if rust_type.startswith(prefix):
+            inner = rust_type[len(prefix):].strip()
+            pointee = inner.split("::")[-1]
+            if not inner.startswith("*") and pointee not in C_TYPES and pointee[0].isupper():
+                opaque.add(pointee)
+                return qualifier + pointee + "*"
+            pointee = c_type(rust_type[len(prefix):], opaque)
+            # A qualifier of a pointer to a pointer goes after the pointee, as in `float* const*`.
+            if pointee.endswith("*"):
+                return pointee + (" const*" if qualifier else "*")
+            return qualifier + pointee + "*"
+    return C_TYPES[rust_type.split("::")[-1]]
+
+def split_args(args):
+    """Splits at the commas that are not inside the parentheses of a function pointer type."""
+    parts, depth, start = [], 0, 0
+    for i, c in enumerate(args):
+        depth += {"(": 1, ")": -1}.get(c, 0)
+        if c == "," and depth == 0:
+            parts.append(args[start:i])
+            start = i + 1
+    parts.append(args[start:])
+    return [part.strip() for part in parts if part.strip()]
+
+def declaration(arg_name, arg_type, opaque):
+    pointer = FN_POINTER.match(arg_type.strip())
+    if pointer:
+        params, ret = pointer.groups()
+        params = ", ".join(c_type(param, opaque) for param in split_args(params)) or "void"
+        return "{} (*{})({})".format(c_type(ret, opaque) if ret else "void", arg_name, params)
+    return "{} {}".format(c_type(arg_type, opaque), arg_name)
+
+def extern_fns(source):
+    """The name, arguments and return type of each extern "C" function."""
+    for match in EXTERN_FN.finditer(source):
+        depth, end = 1, match.end()
+        while depth:
+            depth += {"(": 1, ")": -1}.get(source[end], 0)
+            end += 1
+        ret = RETURN.match(source, end).group(1)
+        yield match.group(1), source[match.end():end - 1], ret
+
+def prototype(name, args, ret, opaque):
+    params = []
+    for arg in split_args(args):
+        arg_name, arg_type = arg.split(":", 1)
+        params.append(declaration(arg_name.strip(), arg_type, opaque))
+    ret_type = c_type(ret, opaque) if ret else "void"
+    return "{} {}({});".format(ret_type, name, ", ".join(params) or "void")
+
+def generate(source):
+    lines = [
+        "/* Generated by gen_header.py, do not edit. */",
+        "#ifndef SHORTCUT_H",
+        "#define SHORTCUT_H",
+        "",
+        "#include <stdbool.h>",
+        "#include <stddef.h>",
+        "#include <stdint.h>",
+        "",
+        "#ifdef __cplusplus",
+        "extern \"C\" {",
+        "#endif",
+        "",
+    ]
+    for name, value in CONST.findall(source):
+        lines.append("#define {} {}".format(name, value))
+    lines.append("")
+    opaque = set()
+    prototypes = [prototype(name, args, ret, opaque) for name, args, ret in extern_fns(source)]
+    prototypes += [prototype(name, WRAPPER_ARGS, WRAPPER_RET, opaque) for name in WRAPPER_CALL.findall(source)]
+    for name in sorted(opaque):
+        lines.append("typedef struct {0} {0};".format(name))
+    if opaque:
+        lines.append("")
+    lines += prototypes
+    lines += [
+        "",
+        "#ifdef __cplusplus",
+        "}",
+        "#endif",
+        "",
+        "#endif",
+    ]
+    return "\n".join(lines) + "\n"
+
+if __name__ == "__main__":
+    with open(sys.argv[1]) as f:
+        sys.stdout.write(generate(f.read()))
@@ -0,0 +1,141 @@
+use std::alloc::{alloc, dealloc, Layout};
+use std::ptr::NonNull;
+
+/// Alignment of every buffer, one cache line and one `f32x16`.
+pub const ALIGN: usize = crate::simd::ALIGN;
+/// Size and alignment of a transparent huge page on x86_64 and most aarch64 Linux systems.
+pub const HUGE_PAGE: usize = 2 << 20;
+
+/// Alignment of a buffer of `bytes`, `HUGE_PAGE` if it is worth backing with huge pages.
+fn align_for(bytes: usize, huge_pages: bool) -> usize {
+    if huge_pages && bytes >= HUGE_PAGE { HUGE_PAGE } else { ALIGN }
+}
+
+/// Asks the kernel to back `bytes` at `ptr` with transparent huge pages, a hint it may ignore.
+#[cfg(target_os = "linux")]
+fn advise_huge_pages(ptr: *mut u8, bytes: usize) {
+    extern "C" {
+        fn madvise(addr: *mut u8, len: usize, advice: i32) -> i32;
+    }
+    const MADV_HUGEPAGE: i32 = 14;
+    unsafe { madvise(ptr, bytes, MADV_HUGEPAGE) };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_huge_pages(_ptr: *mut u8, _bytes: usize) {}
+
+/// Allocates `bytes` aligned to `align`, or returns `None` if the size overflows or the
+/// allocation fails. At least one byte is allocated, so that the pointer is unique.
+fn allocate(bytes: usize, align: usize) -> Option<(NonNull<u8>, Layout)> {
+    let layout = Layout::from_size_align(bytes.max(1), align).ok()?;
+    let ptr = NonNull::new(unsafe { alloc(layout) })?;
+    if align == HUGE_PAGE {
+        advise_huge_pages(ptr.as_ptr(), layout.size());
+    }
+    Some((ptr, layout))
+}
+
+/// A growable `f32` buffer aligned to `ALIGN` bytes, and optionally backed by transparent
+/// huge pages if it is at least `HUGE_PAGE` bytes.
+pub struct AlignedBuffer {
+    ptr: NonNull<f32>,
+    layout: Layout,
+    len: usize,
+    huge_pages: bool,
+}
+
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// An empty buffer that grows without huge pages.
+    pub fn new() -> Self {
+        Self::with_len(0, 0.0, false)
+    }
+
+    /// An empty buffer that grows with huge pages.
+    pub fn with_huge_pages() -> Self {
+        Self::with_len(0, 0.0, true)
+    }
+
+    /// `len` copies of `value`.
+    pub fn with_len(len: usize, value: f32, huge_pages: bool) -> Self {
+        let bytes = len.checked_mul(std::mem::size_of::<f32>()).expect("buffer size overflows usize");
+        let (ptr, layout) = allocate(bytes, align_for(bytes, huge_pages)).expect("failed to allocate an aligned buffer");
+        let mut buffer = AlignedBuffer { ptr: ptr.cast(), layout, len, huge_pages };
+        buffer.as_mut_slice().fill(value);
+        buffer
+    }
+
+    /// Sets the length to `len` and every element to `value`, reusing the allocation if it is
+    /// large enough.
+    pub fn reset(&mut self, len: usize, value: f32) {
+        if len.saturating_mul(std::mem::size_of::<f32>()) > self.layout.size() {
+            *self = Self::with_len(len, value, self.huge_pages);
+            return;
+        }
+        self.len = len;
+        self.as_mut_slice().fill(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Default for AlignedBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr().cast(), self.layout) };
+    }
+}
+
+/// `len` uninitialized `f32`s aligned like an `AlignedBuffer` with huge pages, for callers that
+/// cannot hold on to a Rust value, or null if they cannot be allocated.
+/// The pointer must be freed with `free_raw`.
+pub fn alloc_raw(len: usize) -> *mut f32 {
+    let Some(bytes) = len.checked_mul(std::mem::size_of::<f32>()) else { return std::ptr::null_mut() };
+    // The data starts one alignment into the block, leaving room for the layout right before it.
+    let align = align_for(bytes, true);
+    let Some((block, layout)) = bytes.checked_add(align).and_then(|size| allocate(size, align)) else {
+        return std::ptr::null_mut();
+    };
+    unsafe {
+        let data = block.as_ptr().add(align);
+        let header = (data as *mut usize).sub(2);
+        header.write(layout.size());
+        header.add(1).write(layout.align());
+        data.cast()
+    }
+}
+
+/// Frees a pointer returned by `alloc_raw`, or does nothing if it is null.
+///
+/// # Safety
+///
+/// `ptr` must be null or come from `alloc_raw` and not have been freed before.
+pub unsafe fn free_raw(ptr: *mut f32) {
+    if ptr.is_null() {
+        return;
+    }
+    let header = (ptr as *const usize).sub(2);
+    let (size, align) = (header.read(), header.add(1).read());
+    dealloc((ptr as *mut u8).sub(align), Layout::from_size_align_unchecked(size, align));
+}
@@ -0,0 +1,467 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{check_lengths, layout, step_vec, StepContext, StepError, ThreadConfig};
+
+/// Marks a pair of vertices in a predecessor matrix with no path between them.
+pub const NO_PATH: usize = usize::MAX;
+
+/// Options for `apsp_with_options`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApspOptions {
+    /// Stops squaring after the first step that shortens no distance, instead of always doing
+    /// the `⌈log₂ n⌉` steps that cover paths of `n - 1` edges.
+    pub stop_at_fixed_point: bool,
+    /// A file to save `d` and the number of edges its paths cover to after every
+    /// `checkpoint_every` steps, which `resume` continues from. Each checkpoint is written next to
+    /// it first and then renamed over it, so that the file always holds a complete checkpoint.
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: usize,
+    /// The threads of each step.
+    pub threads: ThreadConfig,
+}
+
+impl Default for ApspOptions {
+    fn default() -> Self {
+        ApspOptions { stop_at_fixed_point: true, checkpoint: None, checkpoint_every: 1, threads: ThreadConfig::default() }
+    }
+}
+
+/// Replaces `d` with the lengths of the shortest paths between all pairs of vertices, by squaring
+/// it with the fastest `step` until paths of `n - 1` edges are covered or nothing changes.
+/// The distance from each vertex to itself is set to zero first.
+pub fn apsp(d: &mut [f32], n: usize) -> Result<(), StepError> {
+    apsp_with_options(d, n, &ApspOptions::default())
+}
+
+/// `apsp` with the choices in `options`.
+pub fn apsp_with_options(d: &mut [f32], n: usize, options: &ApspOptions) -> Result<(), StepError> {
+    check_lengths(d, d, n)?;
+    for i in 0..n {
+        d[n*i + i] = 0.0;
+    }
+    square(d, n, 1, options)
+}
+
+/// Squares `d`, whose paths cover `edges` edges, until they cover `n - 1`.
+fn square(d: &mut [f32], n: usize, mut edges: usize, options: &ApspOptions) -> Result<(), StepError> {
+    let mut ctx = StepContext::with_threads(n, options.threads.clone());
+    let mut r = vec![0.0; n * n];
+    let mut steps = 0;
+    while edges + 1 < n {
+        if options.stop_at_fixed_point {
+            if !ctx.step_changed(&mut r, d)? {
+                break;
+            }
+        } else {
+            ctx.step(&mut r, d)?;
+        }
+        d.copy_from_slice(&r);
+        edges *= 2;
+        steps += 1;
+        if let Some(path) = &options.checkpoint {
+            if steps % options.checkpoint_every.max(1) == 0 {
+                write_checkpoint(path, d, n, edges)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Continues `apsp` from the checkpoint at `path`, written with `ApspOptions::checkpoint`, and
+/// returns `n` and the lengths of the shortest paths. Further checkpoints are written to `path`
+/// after every step.
+pub fn resume(path: impl AsRef<Path>) -> Result<(usize, Vec<f32>), StepError> {
+    let path = path.as_ref();
+    resume_with_options(path, &ApspOptions { checkpoint: Some(path.to_path_buf()), ..Default::default() })
+}
+
+/// `resume` with the choices in `options`, which may write checkpoints elsewhere or not at all.
+pub fn resume_with_options(path: impl AsRef<Path>, options: &ApspOptions) -> Result<(usize, Vec<f32>), StepError> {
+    let (n, edges, mut d) = read_checkpoint(path.as_ref())?;
+    square(&mut d, n, edges, options)?;
+    Ok((n, d))
+}
+
+/// A checkpoint is `CHECKPOINT_MAGIC`, `n` and the number of edges covered as little-endian
+/// `u64`s, and the `n * n` elements of `d` as little-endian `f32`s, row by row.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"SHORTAPS";
+const CHECKPOINT_HEADER_LEN: usize = 24;
+
+fn write_checkpoint(path: &Path, d: &[f32], n: usize, edges: usize) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let file = File::create(&partial)?;
+    let mut writer = BufWriter::new(&file);
+    writer.write_all(CHECKPOINT_MAGIC)?;
+    writer.write_all(&(n as u64).to_le_bytes())?;
+    writer.write_all(&(edges as u64).to_le_bytes())?;
+    for x in d {
+        writer.write_all(&x.to_le_bytes())?;
+    }
+    writer.flush()?;
+    drop(writer);
+    file.sync_all()?;
+    fs::rename(&partial, path)
+}
+
+/// Fails with `io::ErrorKind::InvalidData` for anything but a complete checkpoint, since
+/// `StepError::Io` keeps only the kind of an error.
+fn read_checkpoint(path: &Path) -> io::Result<(usize, usize, Vec<f32>)> {
+    let invalid = io::Error::from(io::ErrorKind::InvalidData);
+    let bytes = fs::read(path)?;
+    let Some((header, elements)) = bytes.split_at_checked(CHECKPOINT_HEADER_LEN) else {
+        return Err(invalid);
+    };
+    let read_u64 = |bytes: &[u8]| usize::try_from(u64::from_le_bytes(bytes.try_into().unwrap()));
+    let (Ok(n), Ok(edges)) = (read_u64(&header[8..16]), read_u64(&header[16..24])) else { return Err(invalid) };
+    let len = n.checked_mul(n).and_then(|len| len.checked_mul(std::mem::size_of::<f32>()));
+    if &header[..8] != CHECKPOINT_MAGIC || edges == 0 || len != Some(elements.len()) {
+        return Err(invalid);
+    }
+    let d = elements.chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect();
+    Ok((n, edges, d))
+}
+
+fn check_pred(pred: &[usize], d: &[f32], n: usize) -> Result<(), StepError> {
+    if pred.len() != n * n {
+        return Err(StepError::LengthMismatch { n, r_len: pred.len(), d_len: d.len() });
+    }
+    Ok(())
+}
+
+/// Like `step`, also writing the `k` that minimizes `d[n*i + k] + d[n*k + j]` into `pred[n*i + j]`,
+/// or `NO_PATH` if all the sums are infinite.
+pub fn step_with_pred(r: &mut [f32], pred: &mut [usize], d: &[f32], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    check_pred(pred, d, n)?;
+    let t = layout::transpose_blocked(d, n, n);
+    for i in 0..n {
+        let d_row = &d[n*i..n*(i + 1)];
+        for (j, t_row) in t.chunks(n).enumerate() {
+            let (mut v, mut best) = (f32::INFINITY, NO_PATH);
+            for (k, (&x, &y)) in d_row.iter().zip(t_row).enumerate() {
+                let z = x + y;
+                if z < v {
+                    v = z;
+                    best = k;
+                }
+            }
+            r[n*i + j] = v;
+            pred[n*i + j] = best;
+        }
+    }
+    Ok(())
+}
+
+/// Like `apsp`, also filling `pred` with the vertex before `j` on a shortest path from `i` to `j`
+/// in `pred[n*i + j]`, which is `i` itself for `i == j`, or `NO_PATH` if `j` is unreachable.
+pub fn apsp_with_pred(d: &mut [f32], pred: &mut [usize], n: usize) -> Result<(), StepError> {
+    check_lengths(d, d, n)?;
+    check_pred(pred, d, n)?;
+    for i in 0..n {
+        d[n*i + i] = 0.0;
+        for j in 0..n {
+            pred[n*i + j] = if d[n*i + j] < f32::INFINITY { i } else { NO_PATH };
+        }
+    }
+    let mut r = vec![0.0; n * n];
+    let mut via = vec![NO_PATH; n * n];
+    let mut edges = 1;
+    while edges + 1 < n {
+        step_with_pred(&mut r, &mut via, d, n)?;
+        if r == d {
+            break;
+        }
+        let last = pred.to_vec();
+        for i in 0..n {
+            for j in 0..n {
+                let k = via[n*i + j];
+                if k != NO_PATH && k != j && r[n*i + j] < d[n*i + j] {
+                    pred[n*i + j] = last[n*k + j];
+                }
+            }
+        }
+        d.copy_from_slice(&r);
+        edges *= 2;
+    }
+    Ok(())
+}
+
+/// The vertices on a shortest path from `i` to `j`, read from a predecessor matrix filled by
+/// `apsp_with_pred`, or `None` if there is no path.
+pub fn path(pred: &[usize], n: usize, i: usize, j: usize) -> Option<Vec<usize>> {
+    let mut path = vec![j];
+    let mut v = j;
+    while v != i {
+        v = pred[n*i + v];
+        if v == NO_PATH || path.len() > n {
+            return None;
+        }
+        path.push(v);
+    }
+    path.reverse();
+    Some(path)
+}
+
+/// The lengths of the shortest paths from `source` to every vertex, by Bellman-Ford: starting from
+/// zero at `source` and infinity elsewhere, each round relaxes all edges at once with `step_vec`,
+/// until a round shortens nothing. That takes at most `n` rounds of `O(n^2)` each, instead of the
+/// `O(n^3 log n)` of `apsp`, and allows negative edges, unless they make a cycle of negative length
+/// reachable from `source`, which returns `StepError::NegativeCycle`.
+pub fn bellman_ford(d: &[f32], n: usize, source: u32) -> Result<Vec<f32>, StepError> {
+    check_lengths(d, d, n)?;
+    if source as usize >= n {
+        return Err(StepError::IndexOutOfRange { index: source, n });
+    }
+    let mut dist = vec![f32::INFINITY; n];
+    dist[source as usize] = 0.0;
+    let mut next = vec![0.0; n];
+    let mut shortened = None;
+    for _ in 0..n {
+        step_vec(&mut next, d, &dist, n)?;
+        shortened = None;
+        for (v, (x, &y)) in dist.iter_mut().zip(&next).enumerate() {
+            if y < *x {
+                *x = y;
+                shortened = Some(v);
+            }
+        }
+        if shortened.is_none() {
+            return Ok(dist);
+        }
+    }
+    // Without a negative cycle every shortest path has at most `n - 1` edges, found by round `n - 1`.
+    Err(StepError::NegativeCycle { vertex: shortened.unwrap_or(source as usize) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::{generate, Generator};
+
+    /// Graphs of every shape of `gen` with whole edge weights, whose sums are exact in any order.
+    fn graphs(n: usize) -> Vec<(Generator, Vec<f32>)> {
+        [Generator::Uniform, Generator::Geometric, Generator::PowerLaw, Generator::Banded]
+            .into_iter()
+            .map(|generator| {
+                let (_, d) = generate(generator, n, n as u64);
+                (generator, d.into_iter().map(|x| if x.is_finite() { (x * 16.0).floor() } else { x }).collect())
+            })
+            .collect()
+    }
+
+    /// The shortest paths by Floyd–Warshall, which `reference::step` must leave unchanged.
+    fn floyd_warshall(d: &[f32], n: usize) -> Vec<f32> {
+        let mut e = d.to_vec();
+        for i in 0..n {
+            e[n*i + i] = 0.0;
+        }
+        for k in 0..n {
+            for i in 0..n {
+                for j in 0..n {
+                    e[n*i + j] = e[n*i + j].min(e[n*i + k] + e[n*k + j]);
+                }
+            }
+        }
+        let mut r = vec![0.0; n * n];
+        crate::reference::step(&mut r, &e, n);
+        assert_eq!(r, e, "n = {}", n);
+        e
+    }
+
+    const SIZES: [usize; 6] = [1, 2, 3, 7, 33, 70];
+
+    #[test]
+    fn apsp_matches_floyd_warshall() {
+        for n in SIZES {
+            for (generator, d) in graphs(n) {
+                let expected = floyd_warshall(&d, n);
+                let mut e = d.clone();
+                apsp(&mut e, n).unwrap();
+                assert_eq!(e, expected, "{:?} n = {}", generator, n);
+                for stop_at_fixed_point in [false, true] {
+                    let threads = ThreadConfig::with_threads(3);
+                    let options = ApspOptions { stop_at_fixed_point, threads, ..Default::default() };
+                    let mut e = d.clone();
+                    apsp_with_options(&mut e, n, &options).unwrap();
+                    assert_eq!(e, expected, "{:?} n = {} {}", generator, n, stop_at_fixed_point);
+                }
+            }
+        }
+        assert!(apsp(&mut [0.0; 3], 2).is_err());
+    }
+
+    #[test]
+    fn square_continues_from_the_edges_covered() {
+        let n = 33;
+        for (generator, d) in graphs(n) {
+            let expected = floyd_warshall(&d, n);
+            let mut e = d.clone();
+            for i in 0..n {
+                e[n*i + i] = 0.0;
+            }
+            let mut r = vec![0.0; n * n];
+            // Paths of up to 4 edges.
+            for _ in 0..2 {
+                crate::reference::step(&mut r, &e, n);
+                e.copy_from_slice(&r);
+            }
+            let covered = e.clone();
+            let options = ApspOptions { stop_at_fixed_point: false, ..Default::default() };
+            square(&mut e, n, n - 1, &options).unwrap();
+            assert_eq!(e, covered, "{:?}", generator);
+            square(&mut e, n, 4, &options).unwrap();
+            assert_eq!(e, expected, "{:?}", generator);
+        }
+    }
+
+    #[test]
+    fn bellman_ford_matches_floyd_warshall() {
+        for n in SIZES {
+            for (generator, d) in graphs(n) {
+                let expected = floyd_warshall(&d, n);
+                for source in 0..n {
+                    let dist = bellman_ford(&d, n, source as u32).unwrap();
+                    assert_eq!(dist, expected[n*source..n*(source + 1)], "{:?} n = {} from {}", generator, n, source);
+                }
+                assert!(matches!(bellman_ford(&d, n, n as u32), Err(StepError::IndexOutOfRange { .. })));
+            }
+        }
+    }
+
+    #[test]
+    fn bellman_ford_allows_negative_edges_but_not_negative_cycles() {
+        let n = 6;
+        // A chain 0 -> 1 -> ... -> 5 of edges of length -1, and a shortcut 0 -> 5 of length -2.
+        let mut d = vec![f32::INFINITY; n * n];
+        for i in 0..n - 1 {
+            d[n*i + i + 1] = -1.0;
+        }
+        d[n - 1] = -2.0;
+        assert_eq!(bellman_ford(&d, n, 0).unwrap(), [0.0, -1.0, -2.0, -3.0, -4.0, -5.0]);
+        assert_eq!(bellman_ford(&d, n, 3).unwrap()[..3], [f32::INFINITY; 3]);
+        // The edge 4 -> 2 closes the cycle 2 -> 3 -> 4 -> 2 of length -1, reachable only from 0 to 4.
+        d[n*4 + 2] = 1.0;
+        assert!(matches!(bellman_ford(&d, n, 0), Err(StepError::NegativeCycle { .. })));
+        assert!(matches!(bellman_ford(&d, n, 4), Err(StepError::NegativeCycle { .. })));
+        let mut alone = [f32::INFINITY; 6];
+        alone[5] = 0.0;
+        assert_eq!(bellman_ford(&d, n, 5).unwrap(), alone);
+    }
+
+    /// The length of `path` in `d`, by adding its edges in order.
+    fn length(d: &[f32], n: usize, path: &[usize]) -> f32 {
+        path.windows(2).map(|edge| d[n*edge[0] + edge[1]]).sum()
+    }
+
+    #[test]
+    fn paths_of_apsp_with_pred_have_the_lengths_of_the_shortest_paths() {
+        // A graph of 5 vertices and 2 more that nothing reaches, and that reach nothing.
+        let (_, graph) = graphs(5).swap_remove(1);
+        let mut isolated = vec![f32::INFINITY; 49];
+        for (row, graph_row) in isolated.chunks_mut(7).zip(graph.chunks(5)) {
+            row[..5].copy_from_slice(graph_row);
+        }
+        let mut cases = vec![(None, isolated, 7)];
+        for n in SIZES {
+            cases.extend(graphs(n).into_iter().map(|(generator, d)| (Some(generator), d, n)));
+        }
+        let mut unreachable = 0;
+        for (generator, d, n) in cases {
+            let expected = floyd_warshall(&d, n);
+            let (mut e, mut pred) = (d.clone(), vec![0; n * n]);
+            apsp_with_pred(&mut e, &mut pred, n).unwrap();
+            assert_eq!(e, expected, "{:?} n = {}", generator, n);
+            for i in 0..n {
+                for j in 0..n {
+                    let Some(path) = path(&pred, n, i, j) else {
+                        assert_eq!(e[n*i + j], f32::INFINITY, "{:?} n = {} from {} to {}", generator, n, i, j);
+                        assert_eq!(pred[n*i + j], NO_PATH, "{:?} n = {} from {} to {}", generator, n, i, j);
+                        unreachable += 1;
+                        continue;
+                    };
+                    assert_eq!((path[0], path[path.len() - 1]), (i, j), "{:?} n = {}", generator, n);
+                    assert_eq!(length(&e, n, &path), e[n*i + j], "{:?} n = {} {:?}", generator, n, path);
+                    assert_eq!(length(&d, n, &path), e[n*i + j], "{:?} n = {} {:?}", generator, n, path);
+                }
+            }
+        }
+        // From and to each of the 2 isolated vertices.
+        assert!(unreachable >= 2 * 2 * 6, "{} unreachable pairs", unreachable);
+        assert!(apsp_with_pred(&mut [0.0; 4], &mut [0; 3], 2).is_err());
+    }
+
+    /// A checkpoint file for the test `name` of its own, which is removed if it exists.
+    fn checkpoint_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("shortcut-{}-{}.ckpt", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn resuming_from_a_checkpoint_matches_an_uninterrupted_apsp() {
+        let n = 70;
+        let path = checkpoint_path("resume");
+        for (generator, d) in graphs(n) {
+            let mut expected = d.clone();
+            apsp(&mut expected, n).unwrap();
+            // The 7 steps to cover paths of 69 edges save a checkpoint after the sixth, of 64 edges.
+            let checkpoint = Some(path.clone());
+            let options = ApspOptions { stop_at_fixed_point: false, checkpoint, checkpoint_every: 2, ..Default::default() };
+            let mut e = d.clone();
+            apsp_with_options(&mut e, n, &options).unwrap();
+            assert_eq!(e, expected, "{:?}", generator);
+            let (_, edges, partway) = read_checkpoint(&path).unwrap();
+            assert_eq!(edges, 64, "{:?}", generator);
+            let written = fs::read(&path).unwrap();
+            let options = ApspOptions { checkpoint: None, ..Default::default() };
+            assert_eq!(resume_with_options(&path, &options), Ok((n, expected.clone())), "{:?}", generator);
+            assert_eq!(fs::read(&path).unwrap(), written, "{:?}", generator);
+            // The partway distances are already the shortest, so `resume` stops before the next checkpoint.
+            assert_eq!(resume(&path), Ok((n, expected.clone())), "{:?}", generator);
+            assert_eq!(fs::read(&path).unwrap(), written, "{:?}", generator);
+            let options = ApspOptions { stop_at_fixed_point: false, checkpoint: Some(path.clone()), ..Default::default() };
+            assert_eq!(resume_with_options(&path, &options), Ok((n, expected.clone())), "{:?}", generator);
+            assert_eq!(read_checkpoint(&path).unwrap(), (n, 128, expected.clone()), "{:?}", generator);
+            // From paths of 1 edge, with the diagonal already zero.
+            let mut start = d.clone();
+            for i in 0..n {
+                start[n*i + i] = 0.0;
+            }
+            write_checkpoint(&path, &start, n, 1).unwrap();
+            assert_eq!(resume(&path), Ok((n, expected.clone())), "{:?}", generator);
+            assert_ne!(partway, start, "{:?}", generator);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn incomplete_checkpoints_are_invalid_data() {
+        let n = 7;
+        let path = checkpoint_path("invalid");
+        assert_eq!(resume(&path), Err(StepError::Io(io::ErrorKind::NotFound)));
+        write_checkpoint(&path, &graphs(n)[0].1, n, 2).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), CHECKPOINT_HEADER_LEN + 4 * n * n);
+        assert!(resume_with_options(&path, &ApspOptions::default()).is_ok());
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] ^= 1;
+        let mut no_edges = bytes.clone();
+        no_edges[16..24].fill(0);
+        let mut huge = bytes.clone();
+        huge[8..16].fill(0xff);
+        let truncated = [&bytes[..bytes.len() - 1], &bytes[..CHECKPOINT_HEADER_LEN - 1], &[][..]];
+        let invalid = truncated.into_iter().chain([&bad_magic[..], &no_edges, &huge]);
+        for (i, bytes) in invalid.enumerate() {
+            fs::write(&path, bytes).unwrap();
+            assert_eq!(resume(&path), Err(StepError::Io(io::ErrorKind::InvalidData)), "file {}", i);
+        }
+        let mut longer = bytes.clone();
+        longer.extend_from_slice(&[0; 4]);
+        fs::write(&path, longer).unwrap();
+        assert_eq!(resume(&path), Err(StepError::Io(io::ErrorKind::InvalidData)));
+        fs::remove_file(&path).unwrap();
+    }
+}
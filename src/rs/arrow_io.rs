@@ -0,0 +1,102 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, AsArray, FixedSizeListArray, Float32Array};
+use arrow::datatypes::{DataType, Field, Float32Type, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+
+use crate::StepError;
+
+fn invalid(message: String) -> ArrowError {
+    ArrowError::InvalidArgumentError(message)
+}
+
+fn external(e: StepError) -> ArrowError {
+    ArrowError::ExternalError(Box::new(e))
+}
+
+/// The `n * n` matrix in a column of `n` lists of `n` `Float32`s, one row per list, borrowed from
+/// the column unless it has nulls, which are copied as `f32::INFINITY`, a missing edge.
+pub fn matrix_from_arrow(column: &FixedSizeListArray) -> Result<(usize, Cow<'_, [f32]>), ArrowError> {
+    let n = column.len();
+    if column.value_length() as usize != n {
+        return Err(invalid(format!("expected {} lists of {} elements, got lists of {}", n, n, column.value_length())));
+    }
+    let values = column.values().as_primitive_opt::<Float32Type>().ok_or_else(|| {
+        invalid(format!("expected lists of Float32, got lists of {:?}", column.values().data_type()))
+    })?;
+    let elements: &[f32] = values.values();
+    if column.null_count() == 0 && values.null_count() == 0 {
+        return Ok((n, Cow::Borrowed(elements)));
+    }
+    let d = (0..n * n)
+        .map(|index| if column.is_null(index / n) || values.is_null(index) { f32::INFINITY } else { elements[index] })
+        .collect();
+    Ok((n, Cow::Owned(d)))
+}
+
+/// The `n * n` matrix in `batch`, either its only column as in `matrix_from_arrow`, or `n` columns
+/// of `n` `Float32`s each, the columns of the matrix, as pairwise distances often come out of
+/// dataframes. The latter are copied to the row-major order of the kernels.
+pub fn matrix_from_record_batch(batch: &RecordBatch) -> Result<(usize, Cow<'_, [f32]>), ArrowError> {
+    if let [column] = batch.columns() {
+        if let Some(column) = column.as_fixed_size_list_opt() {
+            return matrix_from_arrow(column);
+        }
+    }
+    let n = batch.num_rows();
+    if batch.num_columns() != n {
+        return Err(invalid(format!(
+            "expected a column of lists or {} columns for {} rows, got {} columns",
+            n,
+            n,
+            batch.num_columns()
+        )));
+    }
+    let mut d = vec![0.0; n * n];
+    for (j, column) in batch.columns().iter().enumerate() {
+        let column = column
+            .as_primitive_opt::<Float32Type>()
+            .ok_or_else(|| invalid(format!("expected Float32 columns, got {:?}", column.data_type())))?;
+        for (i, &x) in column.values().iter().enumerate() {
+            d[n*i + j] = if column.is_null(i) { f32::INFINITY } else { x };
+        }
+    }
+    Ok((n, Cow::Owned(d)))
+}
+
+/// `r` as a column of `n` lists of `n` `Float32`s without nulls, one row per list, which takes over
+/// the allocation of `r` instead of copying it.
+pub fn arrow_from_matrix(r: Vec<f32>, n: usize) -> Result<FixedSizeListArray, ArrowError> {
+    if r.len() != n * n {
+        return Err(invalid(format!("expected {} * {} elements, got {}", n, n, r.len())));
+    }
+    let size = i32::try_from(n).map_err(|_| invalid(format!("lists of n = {} elements do not fit in Arrow", n)))?;
+    let values: ArrayRef = Arc::new(Float32Array::new(r.into(), None));
+    FixedSizeListArray::try_new(Arc::new(Field::new("item", DataType::Float32, false)), size, values, None)
+}
+
+/// A batch with the single column `name` of `arrow_from_matrix`.
+pub fn record_batch_from_matrix(name: &str, r: Vec<f32>, n: usize) -> Result<RecordBatch, ArrowError> {
+    let column = arrow_from_matrix(r, n)?;
+    let schema = Schema::new(vec![Field::new(name, column.data_type().clone(), false)]);
+    RecordBatch::try_new(Arc::new(schema), vec![Arc::new(column)])
+}
+
+/// `step` of the matrix of `matrix_from_record_batch`, as a batch with the column `name` of
+/// `record_batch_from_matrix`. A Parquet file read into batches can be passed as it is.
+pub fn step_record_batch(batch: &RecordBatch, name: &str) -> Result<RecordBatch, ArrowError> {
+    let (n, d) = matrix_from_record_batch(batch)?;
+    let mut r = vec![0.0; n * n];
+    crate::step(&mut r, &d, n).map_err(external)?;
+    record_batch_from_matrix(name, r, n)
+}
+
+/// `apsp::apsp` of the matrix of `matrix_from_record_batch`, like `step_record_batch`.
+pub fn apsp_record_batch(batch: &RecordBatch, name: &str) -> Result<RecordBatch, ArrowError> {
+    let (n, d) = matrix_from_record_batch(batch)?;
+    let mut d = d.into_owned();
+    crate::apsp::apsp(&mut d, n).map_err(external)?;
+    record_batch_from_matrix(name, d, n)
+}
@@ -0,0 +1,37 @@
+use crate::{CancelToken, StepError, StepOptions};
+
+/// Cancels the token when the future of `step_async` is dropped before it is done.
+struct CancelOnDrop(Option<CancelToken>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+/// `step_with_options` on the blocking thread pool of the current Tokio runtime, so that it does
+/// not hold up the tasks of the runtime's worker threads, returning `r`.
+/// Dropping the future cancels the step through `options.cancel`, or a new token if it is `None`,
+/// which stops the blocking thread after the block of rows it is working on.
+///
+/// Panics if called outside a Tokio runtime.
+pub async fn step_async<D>(mut r: Vec<f32>, d: D, n: usize, mut options: StepOptions) -> Result<Vec<f32>, StepError>
+where
+    D: AsRef<[f32]> + Send + 'static,
+{
+    let token = options.cancel.get_or_insert_with(CancelToken::new).clone();
+    let mut guard = CancelOnDrop(Some(token));
+    let result = tokio::task::spawn_blocking(move || {
+        crate::step_with_options(&mut r, d.as_ref(), n, &options).map(|()| r)
+    })
+    .await;
+    guard.0 = None;
+    match result {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        // The runtime is shutting down and the step never started.
+        Err(_) => Err(StepError::Cancelled),
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::simd::Packed;
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::{check_lengths, StepError, StepOptions};
+
+/// One of the matrices of `step_batch`, `r` and `d` of length `n * n`.
+#[derive(Debug)]
+pub struct MatrixMut<'a> {
+    pub r: &'a mut [f32],
+    pub d: &'a [f32],
+    pub n: usize,
+}
+
+/// `step` for each of `matrices`, running each one on a single thread and as many of them at a
+/// time as there are cores, which is faster than `step` when the matrices are too small to be
+/// worth splitting over threads.
+/// All lengths are checked before any results are written. `options.progress` is called after
+/// each matrix on the thread that did it, with the fraction of matrices done, and
+/// `options.cancel` is checked before each matrix. The threads are pinned according to
+/// `options.affinity`, and `options.numa_policy` is ignored.
+pub fn step_batch(matrices: &mut [MatrixMut], options: &StepOptions) -> Result<(), StepError> {
+    for m in matrices.iter() {
+        check_lengths(m.r, m.d, m.n)?;
+    }
+    let kernel = options.determinism.kernel();
+    let single = ThreadConfig::with_threads(1);
+    let (done, cancelled) = (AtomicUsize::new(0), AtomicBool::new(false));
+    let total = matrices.len();
+    for_each_chunk(&options.threads(), matrices, 1, |_, m| {
+        let m = &mut m[0];
+        if options.cancel.as_ref().is_some_and(|token| token.is_cancelled()) {
+            cancelled.store(true, Ordering::Relaxed);
+            return;
+        }
+        kernel.run(&single, m.r, m.n, &Packed::square(m.d, m.n, m.n, kernel.lanes()), options.inf_aware);
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(progress) = &options.progress {
+            progress(done as f32 / total as f32);
+        }
+    });
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(StepError::Cancelled);
+    }
+    Ok(())
+}
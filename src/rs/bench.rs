@@ -0,0 +1,883 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::gen::{self, Generator};
+use crate::io::formats::{self, FileFormat};
+use crate::io::Matrix;
+use crate::roofline::{self, Peak};
+use crate::topology::Affinity;
+use crate::tune::{Schedule, Tuning};
+use crate::registry;
+use crate::variants::{self, by_name_with_threads, StepFn, StepWithThreadsFn, VARIANTS_WITH_THREADS};
+use crate::{dispatch, fused, StepOptions, ThreadConfig};
+#[cfg(feature = "energy")]
+use crate::energy::{self, Energy};
+#[cfg(feature = "numa")]
+use crate::numa::{self, NumaPolicy};
+#[cfg(feature = "count-ops")]
+use crate::ops::{count_ops, OpCounts};
+#[cfg(feature = "perf")]
+use crate::perf::{self, Counters};
+
+pub mod baseline;
+
+/// What to benchmark: every variant in `variants` for every `n` in `sizes` and thread count in
+/// `threads`, taking the fastest of `repetitions` runs. `variants` are those of `lookup`, and by
+/// default all of `VARIANTS_WITH_THREADS` and `registry::registered`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct BenchConfig {
+    pub variants: Vec<String>,
+    pub sizes: Vec<usize>,
+    pub threads: Vec<usize>,
+    pub repetitions: usize,
+    /// A matrix file to use as `d` instead of generating inputs of `sizes`, which are then ignored.
+    /// `.mtx` and `.npy` files are read into memory, files of `io::Matrix` are mapped.
+    pub input: Option<PathBuf>,
+    /// Generates the inputs of `sizes` with `gen::generate` from `seed` instead of `random_input`.
+    pub generator: Option<Generator>,
+    pub seed: u64,
+    /// Measures the `Peak` of the machine for each thread count, to report how close to it each
+    /// variant gets.
+    pub roofline: bool,
+    /// Values of `StepOptions::prefetch`, `StepOptions::streaming_stores` and `StepOptions::schedule`
+    /// to run `v7` with, each combination as its own measurement, or only its cached `Tuning` if all
+    /// are empty.
+    pub prefetch: Vec<usize>,
+    pub streaming_stores: Vec<bool>,
+    pub schedules: Vec<Schedule>,
+    /// Values of `ThreadConfig::grain` to run every variant with, each as its own measurement, or
+    /// only the even split of the rows if empty.
+    pub grains: Vec<usize>,
+    /// Threads that keep spinning during the measurements, standing in for other processes on a
+    /// shared machine, under which the even split of the rows waits for the slowest thread.
+    pub background_threads: usize,
+    /// Where the threads of every measurement run, `Affinity::Auto` to make the numbers of `threads`
+    /// below the number of cores comparable between runs and machines.
+    pub affinity: Affinity,
+    /// Runs with every thread count from one to all cores instead of `threads`, and reports the
+    /// `Measurement::speedup` of each over one thread.
+    pub scaling: bool,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            variants: VARIANTS_WITH_THREADS
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .chain(registry::registered().into_iter().map(|(name, _)| name))
+                .collect(),
+            sizes: vec![1000],
+            threads: vec![ThreadConfig::default().effective_threads()],
+            repetitions: 3,
+            input: None,
+            generator: None,
+            seed: 0,
+            roofline: false,
+            prefetch: Vec::new(),
+            streaming_stores: Vec::new(),
+            schedules: Vec::new(),
+            grains: Vec::new(),
+            background_threads: 0,
+            affinity: Affinity::None,
+            scaling: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Measurement {
+    pub variant: String,
+    pub n: usize,
+    pub threads: usize,
+    pub seconds: f64,
+    /// With `BenchConfig::roofline`, for the same number of threads.
+    pub peak: Option<Peak>,
+    /// The parameters `v7` ran with, if `BenchConfig::prefetch`, `BenchConfig::streaming_stores` or
+    /// `BenchConfig::schedules` is set.
+    pub tuning: Option<Tuning>,
+    /// The `ThreadConfig::grain` of the threads, if `BenchConfig::grains` is set.
+    pub grain: Option<usize>,
+    /// How many times faster than the same measurement with one thread, with `BenchConfig::scaling`.
+    pub speedup: Option<f64>,
+    /// The seconds of each of the `BenchConfig::repetitions` runs in order, of which `seconds` is
+    /// the fastest.
+    pub timings: Vec<f64>,
+    /// From one more run after the timed ones.
+    #[cfg(feature = "perf")]
+    pub counters: Option<Counters>,
+    /// From one more run after the timed ones, on all its threads.
+    #[cfg(feature = "count-ops")]
+    pub ops: OpCounts,
+    /// From one more run after the timed ones.
+    #[cfg(feature = "energy")]
+    pub energy: Option<Energy>,
+}
+
+impl Measurement {
+    /// Every `step` does `n * n * n` additions and as many comparisons.
+    pub fn gflops(&self) -> f64 {
+        steps(&self.variant) * 2.0 * (self.n as f64).powi(3) / self.seconds / 1e9
+    }
+
+    /// `gflops` as a fraction of the most that `peak` allows at the `roofline::intensity` of `n`.
+    pub fn roof_fraction(&self) -> Option<f64> {
+        self.peak.map(|peak| self.gflops() / peak.attainable(roofline::intensity(self.n)))
+    }
+
+    /// `speedup` per thread, 1 for perfect scaling.
+    pub fn efficiency(&self) -> Option<f64> {
+        self.speedup.map(|speedup| speedup / self.threads as f64)
+    }
+
+    /// The floating point operations per joule of `energy`, in GFLOP/s per watt.
+    #[cfg(feature = "energy")]
+    pub fn gflops_per_watt(&self) -> Option<f64> {
+        self.energy.map(|energy| steps(&self.variant) * 2.0 * (self.n as f64).powi(3) / energy.joules / 1e9)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format '{}', expected text, csv or json", s)),
+        }
+    }
+}
+
+/// Uniformly distributed in `[0, 1)` like the inputs of the benchmarks in the book,
+/// from a fixed seed so that all variants get the same input.
+pub fn random_input(n: usize) -> Vec<f32> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..n * n)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        })
+        .collect()
+}
+
+/// An input `d` from `random_input` and an output `r` to pass to a variant, for benchmarking it
+/// with other tools, such as Criterion.
+pub fn bench_inputs(n: usize) -> (Vec<f32>, Vec<f32>) {
+    (random_input(n), vec![0.0; n * n])
+}
+
+/// The fastest `dispatch` kernel with `policy`, for `"numa-none"` and `"numa-local"`.
+#[cfg(feature = "numa")]
+fn numa_step(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize, policy: NumaPolicy) {
+    // Without hooks there is nothing to cancel it.
+    let _ = numa::step(threads, r, d, n, false, policy, Default::default());
+}
+
+fn step_twice(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    let mut first = vec![0.0; n * n];
+    dispatch::step(threads, &mut first, d, n);
+    dispatch::step(threads, r, &first, n);
+}
+
+/// How many steps `variant` computes, two for `"step2"` and `"step-twice"`.
+fn steps(variant: &str) -> f64 {
+    if matches!(variant, "step2" | "step-twice") { 2.0 } else { 1.0 }
+}
+
+/// A variant to measure. Those of `registry::registered` ignore the thread count.
+enum BenchStep {
+    WithThreads(StepWithThreadsFn),
+    Registered(StepFn),
+}
+
+impl BenchStep {
+    fn run(&self, threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+        match self {
+            BenchStep::WithThreads(step) => step(threads, r, d, n),
+            BenchStep::Registered(step) => step(r, d, n),
+        }
+    }
+}
+
+/// A variant of `by_name_with_threads`, such as `"portable-simd"` with the feature of the same
+/// name, or `gpu::step` for `"gpu"` and `cuda::step` for `"cuda"` with the features of the same
+/// names, which ignore the thread count.
+/// With the `cpp-compare` feature, `"cpp-v0"` to `"cpp-v7"` run the C++ versions from `cpp`.
+/// With the `numa` feature, `"numa-none"` and `"numa-local"` run the fastest `dispatch` kernel with
+/// each `NumaPolicy`, to compare them on the same kernel. `"step2"` runs the fused `step2`, and
+/// `"step-twice"` the `step` of the `step` with the same kernel to compare it with. Any other
+/// name can be one of `registry::registered`.
+fn lookup(name: &str) -> Result<BenchStep, String> {
+    #[cfg(feature = "gpu")]
+    if name == "gpu" {
+        crate::gpu::device().ok_or_else(|| crate::gpu::GpuError::Unavailable.to_string())?;
+        return Ok(BenchStep::WithThreads(|_, r, d, n| crate::gpu::step(r, d, n).unwrap_or_else(|e| panic!("{}", e))));
+    }
+    #[cfg(feature = "cuda")]
+    if name == "cuda" {
+        crate::cuda::device().ok_or_else(|| crate::cuda::CudaError::Unavailable.to_string())?;
+        return Ok(BenchStep::WithThreads(|_, r, d, n| crate::cuda::step(r, d, n).unwrap_or_else(|e| panic!("{}", e))));
+    }
+    #[cfg(feature = "cpp-compare")]
+    if let Some(variant) = name.strip_prefix("cpp-") {
+        let cpp = crate::cpp::VARIANTS_WITH_THREADS.iter().find(|(name, _)| *name == variant);
+        return cpp.map(|&(_, step)| BenchStep::WithThreads(step)).ok_or_else(|| format!("unknown variant '{}'", name));
+    }
+    #[cfg(feature = "numa")]
+    match name {
+        "numa-none" => return Ok(BenchStep::WithThreads(|threads, r, d, n| numa_step(threads, r, d, n, NumaPolicy::None))),
+        "numa-local" => return Ok(BenchStep::WithThreads(|threads, r, d, n| numa_step(threads, r, d, n, NumaPolicy::Local))),
+        _ => {}
+    }
+    match name {
+        "step2" => return Ok(BenchStep::WithThreads(fused::step2)),
+        "step-twice" => return Ok(BenchStep::WithThreads(step_twice)),
+        _ => {}
+    }
+    by_name_with_threads(name)
+        .map(BenchStep::WithThreads)
+        .or_else(|| registry::by_name(name).map(BenchStep::Registered))
+        .ok_or_else(|| format!("unknown variant '{}'", name))
+}
+
+/// Each of `values`, or only `None` if there are none.
+fn or_none<T: Copy>(values: &[T]) -> Vec<Option<T>> {
+    if values.is_empty() { vec![None] } else { values.iter().copied().map(Some).collect() }
+}
+
+/// The options for each combination of `config.prefetch`, `config.streaming_stores` and
+/// `config.schedules`, or none if all are empty.
+fn tuning_options(config: &BenchConfig) -> Vec<StepOptions> {
+    if config.prefetch.is_empty() && config.streaming_stores.is_empty() && config.schedules.is_empty() {
+        return Vec::new();
+    }
+    let (prefetches, streaming_stores) = (or_none(&config.prefetch), or_none(&config.streaming_stores));
+    let schedules = or_none(&config.schedules);
+    let mut options = Vec::new();
+    for &prefetch in &prefetches {
+        for &streaming_stores in &streaming_stores {
+            for &schedule in &schedules {
+                options.push(StepOptions { prefetch, streaming_stores, schedule, ..Default::default() });
+            }
+        }
+    }
+    options
+}
+
+/// The matrix of `BenchConfig::input`.
+enum Input {
+    Mapped(Matrix),
+    Loaded(usize, Vec<f32>),
+}
+
+impl Input {
+    fn open(path: &Path) -> Result<Input, String> {
+        let input = match FileFormat::from_path(path) {
+            FileFormat::Shortcut => crate::io::read_n(path).and_then(|n| Matrix::open_mmap(path, n)).map(Input::Mapped),
+            _ => formats::read_file(path).map(|(n, d)| Input::Loaded(n, d)),
+        };
+        input.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn n(&self) -> usize {
+        match self {
+            Input::Mapped(d) => d.n(),
+            Input::Loaded(n, _) => *n,
+        }
+    }
+
+    fn as_slice(&self) -> &[f32] {
+        match self {
+            Input::Mapped(d) => d.as_slice(),
+            Input::Loaded(_, d) => d,
+        }
+    }
+}
+
+fn threads(config: &BenchConfig, num_threads: usize, grain: Option<usize>) -> ThreadConfig {
+    config.affinity.apply(&ThreadConfig { grain, ..ThreadConfig::with_threads(num_threads) })
+}
+
+/// Threads that spin until dropped.
+struct BackgroundLoad {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BackgroundLoad {
+    fn start(num_threads: usize) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = (0..num_threads)
+            .map(|_| {
+                let stop = stop.clone();
+                std::thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        std::hint::spin_loop();
+                    }
+                })
+            })
+            .collect();
+        BackgroundLoad { stop, threads }
+    }
+}
+
+impl Drop for BackgroundLoad {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub fn run(config: &BenchConfig) -> Result<Vec<Measurement>, String> {
+    let steps = config
+        .variants
+        .iter()
+        .map(|name| lookup(name).map(|step| (name, step)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let input = config.input.as_deref().map(Input::open).transpose()?;
+    let sizes = input.as_ref().map_or_else(|| config.sizes.clone(), |d| vec![d.n()]);
+    let thread_counts = match config.scaling {
+        true => (1..=ThreadConfig::default().effective_threads()).collect(),
+        false => config.threads.clone(),
+    };
+    let peaks: Vec<_> = thread_counts
+        .iter()
+        .map(|&num_threads| config.roofline.then(|| Peak::measure(&threads(config, num_threads, None))))
+        .collect();
+    let options = tuning_options(config);
+    let grains = or_none(&config.grains);
+    let _load = BackgroundLoad::start(config.background_threads);
+    let mut results = Vec::new();
+    for (name, step) in steps {
+        for &n in &sizes {
+            let (n, generated) = match (&input, config.generator) {
+                (Some(_), _) => (n, Vec::new()),
+                (None, Some(generator)) => gen::generate(generator, n, config.seed),
+                (None, None) => (n, random_input(n)),
+            };
+            let mut r = vec![0.0; n * n];
+            let d = input.as_ref().map_or(&generated[..], |d| d.as_slice());
+            let tunings = match name.as_str() {
+                "v7" if !options.is_empty() => options.iter().map(|options| Some(options.tuning(n))).collect(),
+                _ => vec![None],
+            };
+            for tuning in tunings {
+                let run = |threads: &ThreadConfig, r: &mut [f32]| match &tuning {
+                    Some(tuning) => variants::v7_with_tuning(threads, r, d, n, tuning),
+                    None => step.run(threads, r, d, n),
+                };
+                for (&num_threads, &peak) in thread_counts.iter().zip(&peaks) {
+                    for &grain in &grains {
+                        let threads = threads(config, num_threads, grain);
+                        let timings: Vec<f64> = (0..config.repetitions.max(1))
+                            .map(|_| {
+                                let start = Instant::now();
+                                run(&threads, &mut r);
+                                start.elapsed().as_secs_f64()
+                            })
+                            .collect();
+                        let seconds = timings.iter().copied().fold(f64::INFINITY, f64::min);
+                        results.push(Measurement {
+                            variant: name.clone(),
+                            n,
+                            threads: num_threads,
+                            seconds,
+                            peak,
+                            tuning,
+                            grain,
+                            speedup: None,
+                            timings,
+                            #[cfg(feature = "perf")]
+                            counters: perf::measure(|| run(&threads, &mut r)),
+                            #[cfg(feature = "count-ops")]
+                            ops: count_ops(|| run(&threads, &mut r)),
+                            #[cfg(feature = "energy")]
+                            energy: energy::measure(|| run(&threads, &mut r)),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    if config.scaling {
+        add_speedups(&mut results);
+    }
+    Ok(results)
+}
+
+/// Sets the `speedup` of every measurement for which there is one of the same variant, size,
+/// tuning and grain with one thread.
+fn add_speedups(results: &mut [Measurement]) {
+    let same_run = |a: &Measurement, b: &Measurement| {
+        a.variant == b.variant && a.n == b.n && a.tuning == b.tuning && a.grain == b.grain
+    };
+    let speedups: Vec<_> = results
+        .iter()
+        .map(|m| {
+            let single = results.iter().find(|single| single.threads == 1 && same_run(single, m))?;
+            Some(single.seconds / m.seconds)
+        })
+        .collect();
+    for (m, speedup) in results.iter_mut().zip(speedups) {
+        m.speedup = speedup;
+    }
+}
+
+enum Value {
+    Str(String),
+    Int(u64),
+    Float(f64),
+    Missing,
+}
+
+/// The columns of the report for one measurement, with the roofline if `roofline`, the `Tuning` of
+/// `v7` if `tuned`, the grain if `grained` and the speedup and efficiency if `scaled`.
+fn columns(m: &Measurement, roofline: bool, tuned: bool, grained: bool, scaled: bool) -> Vec<(&'static str, Value)> {
+    let mut columns = vec![
+        ("variant", Value::Str(m.variant.clone())),
+        ("n", Value::Int(m.n as u64)),
+        ("threads", Value::Int(m.threads as u64)),
+        ("seconds", Value::Float(m.seconds)),
+        ("gflops", Value::Float(m.gflops())),
+    ];
+    if roofline {
+        let peak = |f: fn(&Peak) -> f64| m.peak.as_ref().map_or(Value::Missing, |peak| Value::Float(f(peak)));
+        columns.extend([
+            ("intensity", Value::Float(roofline::intensity(m.n))),
+            ("peak_gflops", peak(|peak| peak.gflops)),
+            ("bandwidth_gbs", peak(|peak| peak.bandwidth)),
+            ("roof_gflops", m.peak.map_or(Value::Missing, |peak| Value::Float(peak.attainable(roofline::intensity(m.n))))),
+            ("roof_fraction", m.roof_fraction().map_or(Value::Missing, Value::Float)),
+        ]);
+    }
+    if tuned {
+        columns.extend([
+            ("prefetch", m.tuning.map_or(Value::Missing, |t| Value::Int(t.prefetch as u64))),
+            ("streaming", m.tuning.map_or(Value::Missing, |t| Value::Str(t.streaming_stores.to_string()))),
+            ("schedule", m.tuning.map_or(Value::Missing, |t| Value::Str(t.schedule.name().to_string()))),
+        ]);
+    }
+    if grained {
+        columns.push(("grain", m.grain.map_or(Value::Missing, |grain| Value::Int(grain as u64))));
+    }
+    if scaled {
+        columns.extend([
+            ("speedup", m.speedup.map_or(Value::Missing, Value::Float)),
+            ("efficiency", m.efficiency().map_or(Value::Missing, Value::Float)),
+        ]);
+    }
+    #[cfg(feature = "perf")]
+    {
+        let counter = |f: fn(&Counters) -> u64| m.counters.as_ref().map_or(Value::Missing, |c| Value::Int(f(c)));
+        columns.extend([
+            ("ipc", m.counters.as_ref().map_or(Value::Missing, |c| Value::Float(c.ipc()))),
+            ("instructions", counter(|c| c.instructions)),
+            ("cache_references", counter(|c| c.cache_references)),
+            ("cache_misses", counter(|c| c.cache_misses)),
+            ("branches", counter(|c| c.branches)),
+            ("branch_misses", counter(|c| c.branch_misses)),
+        ]);
+    }
+    #[cfg(feature = "count-ops")]
+    columns.extend([
+        ("loads", Value::Int(m.ops.loads)),
+        ("stores", Value::Int(m.ops.stores)),
+        ("adds", Value::Int(m.ops.adds)),
+        ("mins", Value::Int(m.ops.mins)),
+        ("ops_per_load", Value::Float(m.ops.ops_per_load())),
+    ]);
+    #[cfg(feature = "energy")]
+    columns.extend([
+        ("joules", m.energy.map_or(Value::Missing, |e| Value::Float(e.joules))),
+        ("watts", m.energy.map_or(Value::Missing, |e| Value::Float(e.watts()))),
+        ("gflops_per_watt", m.gflops_per_watt().map_or(Value::Missing, Value::Float)),
+    ]);
+    columns
+}
+
+pub fn write_report<W: Write>(out: &mut W, results: &[Measurement], format: Format) -> io::Result<()> {
+    let roofline = results.iter().any(|m| m.peak.is_some());
+    let tuned = results.iter().any(|m| m.tuning.is_some());
+    let grained = results.iter().any(|m| m.grain.is_some());
+    let scaled = results.iter().any(|m| m.speedup.is_some());
+    let rows: Vec<_> = results.iter().map(|m| columns(m, roofline, tuned, grained, scaled)).collect();
+    let names: Vec<_> = match rows.first() {
+        Some(row) => row.iter().map(|&(name, _)| name).collect(),
+        None => return if format == Format::Json { writeln!(out, "[]") } else { Ok(()) },
+    };
+    match format {
+        Format::Text => {
+            let header: Vec<_> = names.iter().map(|name| format!("{:>16}", name)).collect();
+            writeln!(out, "{}", header.concat())?;
+            for row in &rows {
+                let line: Vec<_> = row
+                    .iter()
+                    .map(|(_, value)| match value {
+                        Value::Str(s) => format!("{:>16}", s),
+                        Value::Int(x) => format!("{:>16}", x),
+                        Value::Float(x) => format!("{:>16.6}", x),
+                        Value::Missing => format!("{:>16}", "-"),
+                    })
+                    .collect();
+                writeln!(out, "{}", line.concat())?;
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "{}", names.join(","))?;
+            for row in &rows {
+                let line: Vec<_> = row
+                    .iter()
+                    .map(|(_, value)| match value {
+                        Value::Str(s) => s.clone(),
+                        Value::Int(x) => x.to_string(),
+                        Value::Float(x) => x.to_string(),
+                        Value::Missing => String::new(),
+                    })
+                    .collect();
+                writeln!(out, "{}", line.join(","))?;
+            }
+        }
+        Format::Json => {
+            writeln!(out, "[")?;
+            for (i, row) in rows.iter().enumerate() {
+                let sep = if i + 1 < rows.len() { "," } else { "" };
+                writeln!(out, "  {{{}}}{}", json_fields(row).join(", "), sep)?;
+            }
+            writeln!(out, "]")?;
+        }
+    }
+    Ok(())
+}
+
+/// `value` in JSON, with `null` for `Value::Missing` and for infinite or NaN floats, which JSON
+/// has no numbers for.
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Str(s) => json_string(s),
+        Value::Int(x) => x.to_string(),
+        Value::Float(x) if x.is_finite() => x.to_string(),
+        Value::Float(_) | Value::Missing => "null".to_string(),
+    }
+}
+
+fn json_fields(row: &[(&str, Value)]) -> Vec<String> {
+    row.iter().map(|(name, value)| format!("\"{}\": {}", name, json_value(value))).collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_list<T>(values: &[T], f: impl Fn(&T) -> Value) -> String {
+    let values: Vec<_> = values.iter().map(|value| json_value(&f(value))).collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// The version of the schema of `write_document`. It only changes when a field is removed or
+/// changes meaning, new fields are added without changing it.
+pub const DOCUMENT_SCHEMA: u64 = 1;
+
+/// The machine and the build that the measurements are taken on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Environment {
+    /// The model name of the CPU, or the architecture if the system does not report it.
+    pub cpu: String,
+    pub arch: String,
+    pub os: String,
+    /// The SIMD extensions that the kernels can use, as detected at run time.
+    pub cpu_features: Vec<String>,
+    /// The `dispatch::Kernel` that `dispatch::selected` runs.
+    pub kernel: String,
+    /// Zero if the system does not report its cores, see `topology::cores`.
+    pub physical_cores: usize,
+    pub logical_cpus: usize,
+    /// The output of `rustc --version` for the compiler that built this crate, if built by Cargo.
+    pub rustc: Option<String>,
+    pub version: String,
+}
+
+impl Environment {
+    pub fn detect() -> Self {
+        Environment {
+            cpu: crate::tune::cpu_model(),
+            arch: std::env::consts::ARCH.to_string(),
+            os: std::env::consts::OS.to_string(),
+            cpu_features: cpu_features().into_iter().map(String::from).collect(),
+            kernel: crate::dispatch::selected().name().to_string(),
+            physical_cores: crate::topology::cores().len(),
+            logical_cpus: ThreadConfig::default().effective_threads(),
+            rustc: option_env!("SHORTCUT_RUSTC_VERSION").map(String::from),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+fn cpu_features() -> Vec<&'static str> {
+    #[cfg(target_arch = "x86_64")]
+    let features = [
+        ("sse4.1", is_x86_feature_detected!("sse4.1")),
+        ("avx", is_x86_feature_detected!("avx")),
+        ("avx2", is_x86_feature_detected!("avx2")),
+        ("fma", is_x86_feature_detected!("fma")),
+        ("avx512f", is_x86_feature_detected!("avx512f")),
+    ];
+    #[cfg(target_arch = "aarch64")]
+    let features = [("neon", std::arch::is_aarch64_feature_detected!("neon"))];
+    #[cfg(target_arch = "wasm32")]
+    let features = [("simd128", cfg!(target_feature = "simd128"))];
+    #[cfg(all(feature = "rvv", target_arch = "riscv64"))]
+    let features = [("v", crate::simd::rvv::is_detected())];
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "wasm32",
+        all(feature = "rvv", target_arch = "riscv64")
+    )))]
+    let features: [(&str, bool); 0] = [];
+    features.into_iter().filter(|&(_, detected)| detected).map(|(name, _)| name).collect()
+}
+
+/// The measurements of a run with the machine and configuration they were taken on, the contents
+/// of `write_document` as a value, which with the `serde` feature `config::write` saves and
+/// `config::read` loads back, to compare with `baseline::Baseline::from_measurements`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BenchReport {
+    pub environment: Environment,
+    pub config: BenchConfig,
+    pub measurements: Vec<Measurement>,
+}
+
+/// Writes a JSON object with the version `DOCUMENT_SCHEMA` of its schema, the `environment`, the
+/// `config` and the `measurements` of `results`, for plots and comparisons between machines to be
+/// made from. Each measurement has all the fields of the JSON report, with `null` for those that it
+/// was not measured with, and the `timings` of all its runs.
+pub fn write_document<W: Write>(
+    out: &mut W,
+    environment: &Environment,
+    config: &BenchConfig,
+    results: &[Measurement],
+) -> io::Result<()> {
+    let str = |s: &str| Value::Str(s.to_string());
+    let int = |x: &usize| Value::Int(*x as u64);
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"schema\": {},", DOCUMENT_SCHEMA)?;
+    let e = environment;
+    let fields = [
+        ("cpu", json_string(&e.cpu)),
+        ("arch", json_string(&e.arch)),
+        ("os", json_string(&e.os)),
+        ("cpu_features", json_list(&e.cpu_features, |feature| str(feature))),
+        ("kernel", json_string(&e.kernel)),
+        ("physical_cores", e.physical_cores.to_string()),
+        ("logical_cpus", e.logical_cpus.to_string()),
+        ("rustc", e.rustc.as_deref().map_or("null".to_string(), json_string)),
+        ("version", json_string(&e.version)),
+    ];
+    write_object(out, "environment", &fields, ",")?;
+    let c = config;
+    let fields = [
+        ("variants", json_list(&c.variants, |variant| str(variant))),
+        ("sizes", json_list(&c.sizes, int)),
+        ("threads", json_list(&c.threads, int)),
+        ("repetitions", c.repetitions.to_string()),
+        ("input", c.input.as_ref().map_or("null".to_string(), |path| json_string(&path.display().to_string()))),
+        ("generator", c.generator.map_or("null".to_string(), |generator| json_string(generator.name()))),
+        ("seed", c.seed.to_string()),
+        ("roofline", c.roofline.to_string()),
+        ("prefetch", json_list(&c.prefetch, int)),
+        ("streaming_stores", json_list(&c.streaming_stores, |&streaming| str(&streaming.to_string()))),
+        ("schedules", json_list(&c.schedules, |schedule| str(schedule.name()))),
+        ("grains", json_list(&c.grains, int)),
+        ("background_threads", c.background_threads.to_string()),
+        ("affinity", match &c.affinity {
+            Affinity::None => json_string("none"),
+            Affinity::Auto => json_string("auto"),
+            Affinity::Pin(cpus) => json_list(cpus, int),
+        }),
+        ("scaling", c.scaling.to_string()),
+    ];
+    write_object(out, "config", &fields, ",")?;
+    writeln!(out, "  \"measurements\": [")?;
+    for (i, m) in results.iter().enumerate() {
+        let mut fields = json_fields(&columns(m, true, true, true, true));
+        fields.push(format!("\"timings\": {}", json_list(&m.timings, |&seconds| Value::Float(seconds))));
+        let sep = if i + 1 < results.len() { "," } else { "" };
+        writeln!(out, "    {{{}}}{}", fields.join(", "), sep)?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")
+}
+
+/// One member of the object of `write_document`, with each of `fields` on a line of its own.
+fn write_object<W: Write>(out: &mut W, name: &str, fields: &[(&str, String)], sep: &str) -> io::Result<()> {
+    writeln!(out, "  \"{}\": {{", name)?;
+    for (i, (field, value)) in fields.iter().enumerate() {
+        let sep = if i + 1 < fields.len() { "," } else { "" };
+        writeln!(out, "    \"{}\": {}{}", field, value, sep)?;
+    }
+    writeln!(out, "  }}{}", sep)
+}
+
+/// How `write_chart` draws the speedups of a scaling run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chart {
+    /// Characters for a terminal, one column of three characters per thread count.
+    Ascii,
+    Svg,
+}
+
+/// The measurements with a speedup grouped into one curve per variant, size, tuning and grain, each
+/// with a label and `(threads, speedup)` in the order measured.
+fn curves(results: &[Measurement]) -> Vec<(String, Vec<(usize, f64)>)> {
+    let several_sizes = results.iter().any(|m| m.n != results[0].n);
+    let mut curves: Vec<(String, Vec<(usize, f64)>)> = Vec::new();
+    for m in results {
+        let Some(speedup) = m.speedup else { continue };
+        let mut label = m.variant.clone();
+        if several_sizes {
+            label += &format!(" n={}", m.n);
+        }
+        if let Some(t) = m.tuning {
+            label += &format!(" prefetch={} streaming={} {}", t.prefetch, t.streaming_stores, t.schedule.name());
+        }
+        if let Some(grain) = m.grain {
+            label += &format!(" grain={}", grain);
+        }
+        match curves.iter_mut().find(|(other, _)| *other == label) {
+            Some((_, points)) => points.push((m.threads, speedup)),
+            None => curves.push((label, vec![(m.threads, speedup)])),
+        }
+    }
+    curves
+}
+
+/// Plots the speedups of `results` over the number of threads, with the perfect scaling of one more
+/// for every thread as a reference, like the scaling plots of the book. Measurements without a
+/// speedup are left out.
+pub fn write_chart<W: Write>(out: &mut W, results: &[Measurement], chart: Chart) -> io::Result<()> {
+    let curves = curves(results);
+    let points = || curves.iter().flat_map(|(_, points)| points.iter().copied());
+    let Some(max_threads) = points().map(|(threads, _)| threads).max() else { return Ok(()) };
+    let max_speedup = points().map(|(_, speedup)| speedup).fold(max_threads as f64, f64::max);
+    match chart {
+        Chart::Ascii => write_ascii_chart(out, &curves, max_threads, max_speedup),
+        Chart::Svg => write_svg_chart(out, &curves, max_threads, max_speedup),
+    }
+}
+
+const MARKERS: &[char] = &['*', 'o', '+', 'x', '#', '@', '%', '&'];
+const COLORS: &[&str] = &["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f"];
+
+fn write_ascii_chart<W: Write>(
+    out: &mut W,
+    curves: &[(String, Vec<(usize, f64)>)],
+    max_threads: usize,
+    max_speedup: f64,
+) -> io::Result<()> {
+    const HEIGHT: usize = 20;
+    const COLUMN: usize = 3;
+    let mut grid = vec![vec![' '; max_threads * COLUMN]; HEIGHT + 1];
+    let mut plot = |threads: usize, speedup: f64, marker: char| {
+        let row = HEIGHT - ((speedup / max_speedup * HEIGHT as f64).round() as usize).min(HEIGHT);
+        grid[row][COLUMN * (threads - 1) + COLUMN / 2] = marker;
+    };
+    for threads in 1..=max_threads {
+        plot(threads, threads as f64, '.');
+    }
+    for ((_, points), &marker) in curves.iter().zip(MARKERS.iter().cycle()) {
+        for &(threads, speedup) in points {
+            plot(threads, speedup, marker);
+        }
+    }
+    writeln!(out, "speedup")?;
+    for (row, line) in grid.iter().enumerate() {
+        let label = match row % 5 {
+            0 => format!("{:.1}", max_speedup * (HEIGHT - row) as f64 / HEIGHT as f64),
+            _ => String::new(),
+        };
+        writeln!(out, "{:>6} |{}", label, line.iter().collect::<String>().trim_end())?;
+    }
+    writeln!(out, "{:>6} +{}", "", "-".repeat(max_threads * COLUMN))?;
+    let ticks: String = (1..=max_threads).map(|threads| format!("{:^3}", threads)).collect();
+    writeln!(out, "{:>6}  {} threads", "", ticks.trim_end())?;
+    for ((label, _), marker) in curves.iter().zip(MARKERS.iter().cycle()) {
+        writeln!(out, "  {} {}", marker, label)?;
+    }
+    writeln!(out, "  . perfect scaling")
+}
+
+fn write_svg_chart<W: Write>(
+    out: &mut W,
+    curves: &[(String, Vec<(usize, f64)>)],
+    max_threads: usize,
+    max_speedup: f64,
+) -> io::Result<()> {
+    const WIDTH: f64 = 640.0;
+    const HEIGHT: f64 = 400.0;
+    const MARGIN: f64 = 50.0;
+    let x = |threads: f64| MARGIN + (threads - 1.0) / (max_threads - 1).max(1) as f64 * (WIDTH - 2.0 * MARGIN);
+    let y = |speedup: f64| HEIGHT - MARGIN - speedup / max_speedup * (HEIGHT - 2.0 * MARGIN);
+    let polyline = |points: &mut dyn Iterator<Item = (usize, f64)>| {
+        points.map(|(threads, speedup)| format!("{:.1},{:.1}", x(threads as f64), y(speedup))).collect::<Vec<_>>().join(" ")
+    };
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+        WIDTH, HEIGHT
+    )?;
+    writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+    let (left, right, top, bottom) = (MARGIN, WIDTH - MARGIN, MARGIN, HEIGHT - MARGIN);
+    writeln!(out, r#"<path d="M{left},{top} V{bottom} H{right}" fill="none" stroke="black"/>"#)?;
+    let step = max_threads.div_ceil(16);
+    for threads in (1..=max_threads).filter(|threads| (threads - 1) % step == 0) {
+        let tick = x(threads as f64);
+        writeln!(out, r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#, tick, bottom + 16.0, threads)?;
+    }
+    for i in 0..=5 {
+        let speedup = max_speedup * i as f64 / 5.0;
+        writeln!(out, r#"<text x="{}" y="{:.1}" text-anchor="end">{:.1}</text>"#, left - 6.0, y(speedup) + 4.0, speedup)?;
+    }
+    writeln!(out, r#"<text x="{}" y="{}" text-anchor="middle">threads</text>"#, (left + right) / 2.0, HEIGHT - 12.0)?;
+    let middle = (top + bottom) / 2.0;
+    writeln!(out, r#"<text x="14" y="{middle}" text-anchor="middle" transform="rotate(-90 14 {middle})">speedup</text>"#)?;
+    let perfect = polyline(&mut (1..=max_threads).map(|threads| (threads, threads as f64)));
+    writeln!(out, r#"<polyline points="{}" fill="none" stroke="gray" stroke-dasharray="4 4"/>"#, perfect)?;
+    for (i, ((label, points), color)) in curves.iter().zip(COLORS.iter().cycle()).enumerate() {
+        let points = polyline(&mut points.iter().copied());
+        writeln!(out, r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#, points, color)?;
+        let legend = top + 16.0 * i as f64;
+        writeln!(out, r#"<text x="{}" y="{}" fill="{}">{}</text>"#, left + 10.0, legend, color, label)?;
+    }
+    writeln!(out, "</svg>")
+}
@@ -0,0 +1,307 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::{columns, Measurement, Value};
+
+/// The columns that identify a measurement, those of `write_report` other than the results.
+const KEY: [&str; 7] = ["variant", "n", "threads", "prefetch", "streaming", "schedule", "grain"];
+
+/// The values of the `KEY` columns of one measurement, `None` for those it was not measured with,
+/// and numbers as they are written in the report.
+type Key = Vec<Option<String>>;
+
+fn key(m: &Measurement) -> Key {
+    let columns = columns(m, false, true, true, false);
+    KEY.iter()
+        .map(|name| match columns.iter().find(|(column, _)| column == name).map(|(_, value)| value) {
+            Some(Value::Str(s)) => Some(s.clone()),
+            Some(Value::Int(x)) => Some(x.to_string()),
+            Some(Value::Float(x)) => Some(x.to_string()),
+            Some(Value::Missing) | None => None,
+        })
+        .collect()
+}
+
+/// `key` as in the text report, such as `v7 n=1000 threads=4 prefetch=20`.
+fn label(key: &Key) -> String {
+    let mut label = key[0].clone().unwrap_or_default();
+    for (name, value) in KEY.iter().zip(key).skip(1) {
+        if let Some(value) = value {
+            label.push_str(&format!(" {}={}", name, value));
+        }
+    }
+    label
+}
+
+/// The seconds of earlier measurements to compare new ones against, from the JSON of
+/// `write_report` with `Format::Json` or of `write_document`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Baseline {
+    entries: Vec<(Key, f64)>,
+}
+
+impl Baseline {
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Baseline> {
+        let path = path.as_ref();
+        Baseline::parse(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    pub fn parse(json: &str) -> Result<Baseline, String> {
+        let json = Parser { s: json.as_bytes(), pos: 0 }.document()?;
+        let measurements = match &json {
+            Json::Array(measurements) => measurements,
+            Json::Object(_) => match json.get("measurements") {
+                Some(Json::Array(measurements)) => measurements,
+                _ => return Err("expected an array of measurements".to_string()),
+            },
+            _ => return Err("expected an array of measurements or a document".to_string()),
+        };
+        let mut entries: Vec<(Key, f64)> = Vec::new();
+        for m in measurements {
+            let key = KEY
+                .iter()
+                .map(|name| match m.get(name) {
+                    Some(Json::Str(s) | Json::Number(s)) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect();
+            let seconds = match m.get("seconds") {
+                Some(Json::Number(s)) => s.parse().map_err(|_| format!("invalid seconds {}", s))?,
+                _ => return Err("measurement without seconds".to_string()),
+            };
+            // Of several measurements of the same configuration, the fastest is the baseline, like
+            // the fastest of the repetitions of each.
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, fastest)) => *fastest = f64::min(*fastest, seconds),
+                None => entries.push((key, seconds)),
+            }
+        }
+        Ok(Baseline { entries })
+    }
+
+    /// The fastest of `measurements` of each configuration, such as those of a `BenchReport`.
+    pub fn from_measurements(measurements: &[Measurement]) -> Baseline {
+        let mut entries: Vec<(Key, f64)> = Vec::new();
+        for m in measurements {
+            let key = key(m);
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, fastest)) => *fastest = f64::min(*fastest, m.seconds),
+                None => entries.push((key, m.seconds)),
+            }
+        }
+        Baseline { entries }
+    }
+
+    /// The seconds of the measurement of the same variant, `n`, thread count and `Tuning` and
+    /// grain as `m`, if there is one.
+    pub fn seconds(&self, m: &Measurement) -> Option<f64> {
+        let key = key(m);
+        self.entries.iter().find(|(k, _)| *k == key).map(|&(_, seconds)| seconds)
+    }
+}
+
+/// A measurement next to the one of the same configuration in a `Baseline`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// The configuration, such as `v7 n=1000 threads=4`.
+    pub label: String,
+    pub baseline: f64,
+    pub seconds: f64,
+}
+
+impl Comparison {
+    /// How much slower than the baseline, as a fraction of it, negative if faster.
+    pub fn slowdown(&self) -> f64 {
+        self.seconds / self.baseline - 1.0
+    }
+
+    /// Whether it is slower than the baseline by more than the fraction `max_regression`.
+    pub fn regressed(&self, max_regression: f64) -> bool {
+        self.slowdown() > max_regression
+    }
+}
+
+/// The `results` that have a measurement in `baseline`, in order.
+pub fn compare(baseline: &Baseline, results: &[Measurement]) -> Vec<Comparison> {
+    results
+        .iter()
+        .filter_map(|m| {
+            let baseline = baseline.seconds(m)?;
+            Some(Comparison { label: label(&key(m)), baseline, seconds: m.seconds })
+        })
+        .collect()
+}
+
+/// A table of `comparisons`, marking those that `Comparison::regressed` by more than `max_regression`.
+pub fn write_comparison<W: Write>(out: &mut W, comparisons: &[Comparison], max_regression: f64) -> io::Result<()> {
+    let width = comparisons.iter().map(|c| c.label.len()).max().unwrap_or(0).max("measurement".len());
+    writeln!(out, "{:<width$}  {:>12}  {:>12}  {:>8}", "measurement", "baseline", "seconds", "change")?;
+    for c in comparisons {
+        let mark = if c.regressed(max_regression) { "  regression" } else { "" };
+        let change = format!("{:+.1}%", 100.0 * c.slowdown());
+        writeln!(out, "{:<width$}  {:>12.6}  {:>12.6}  {:>8}{}", c.label, c.baseline, c.seconds, change, mark)?;
+    }
+    Ok(())
+}
+
+/// The values of JSON that `write_report` and `write_document` write, with numbers kept as they
+/// are written so that they compare equal to those of `key`.
+enum Json {
+    Null,
+    Bool,
+    Number(String),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(field, _)| field == name).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> Result<Json, String> {
+        let json = self.value()?;
+        self.skip_whitespace();
+        if self.pos < self.s.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(json)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.s.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips whitespace and then `c` if it is next.
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.s.get(self.pos) == Some(&c);
+        self.pos += found as usize;
+        found
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Result<Json, String> {
+        if !self.s[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("invalid literal"));
+        }
+        self.pos += word.len();
+        Ok(json)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.s.get(self.pos) {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool),
+            Some(b'f') => self.literal("false", Json::Bool),
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                    }
+                }
+                Ok(Json::Array(values))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.string()?;
+                        if !self.eat(b':') {
+                            return Err(self.error("expected ':'"));
+                        }
+                        fields.push((name, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.error("expected ',' or '}'"));
+                        }
+                    }
+                }
+                Ok(Json::Object(fields))
+            }
+            Some(c) if *c == b'-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.s.get(self.pos).is_some_and(|c| matches!(c, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.s[start..self.pos]).unwrap().to_string();
+                Ok(Json::Number(number))
+            }
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.s.get(self.pos) != Some(&b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.s.get(self.pos) {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match self.s.get(self.pos + 1) {
+                        Some(b'u') => {
+                            let hex = self.s.get(self.pos + 2..self.pos + 6).ok_or_else(|| self.error("invalid escape"))?;
+                            let code = std::str::from_utf8(hex).ok().and_then(|hex| u32::from_str_radix(hex, 16).ok());
+                            let c = code.and_then(char::from_u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            self.pos += 4;
+                            c
+                        }
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(&c @ (b'"' | b'\\' | b'/')) => c as char,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                    self.pos += 2;
+                }
+                Some(&c) => {
+                    bytes.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        self.pos += 1;
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+}
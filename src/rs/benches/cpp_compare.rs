@@ -0,0 +1,34 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use shortcut::reference::compare;
+use shortcut::{bench_inputs, cpp, variants};
+
+/// Runs each Rust version and its C++ counterpart on the same input, checking that they agree
+/// before timing both, so that the report shows the difference between the two languages.
+/// Needs the `cpp-compare` feature.
+fn rust_and_cpp(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rust-vs-cpp");
+    group.sample_size(10);
+    for n in [256, 512, 1024] {
+        let (d, mut r) = bench_inputs(n);
+        let mut expected = vec![0.0; n * n];
+        group.throughput(Throughput::Elements((n * n * n) as u64));
+        for ((name, rust), (_, cpp)) in variants::VARIANTS.into_iter().zip(cpp::VARIANTS) {
+            if name == "v0" && n > 512 {
+                continue;
+            }
+            rust(&mut expected, &d, n);
+            cpp(&mut r, &d, n);
+            let (mismatches, first) = compare(&expected, &r, n, 1e-6);
+            assert_eq!(mismatches, 0, "{} in C++ differs from Rust, first at {:?}", name, first);
+            for (language, step) in [("rust", rust), ("cpp", cpp)] {
+                let id = BenchmarkId::new(format!("{}-{}", name, language), n);
+                group.bench_with_input(id, &n, |b, &n| b.iter(|| step(&mut r, &d, n)));
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rust_and_cpp);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use shortcut::{bench_inputs, layout};
+
+/// The copies of `d` that the kernels read, as prepared once for repeated calls.
+type Layout = fn(&[f32], usize) -> Vec<f32>;
+
+const LAYOUTS: [(&str, Layout); 3] = [
+    ("pad_to_multiple", |d, n| layout::pad_to_multiple(d, n, n, 8, f32::INFINITY).0),
+    ("transpose_blocked", |d, n| layout::transpose_blocked(d, n, n)),
+    ("interleave_rows", |d, n| layout::interleave_rows(d, n, n, 8, f32::INFINITY)),
+];
+
+fn layouts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layout");
+    for n in [256, 1000, 1024, 4096] {
+        let (d, _) = bench_inputs(n);
+        group.throughput(Throughput::Bytes((n * n * std::mem::size_of::<f32>()) as u64));
+        for (name, f) in LAYOUTS {
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| b.iter(|| f(&d, n)));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, layouts);
+criterion_main!(benches);
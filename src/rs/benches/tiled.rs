@@ -0,0 +1,34 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use shortcut::io::Matrix;
+use shortcut::{bench_inputs, tiled};
+
+/// The matrix file that both drivers read, and the one they write to, in the temporary directory.
+fn files(n: usize) -> (Matrix, Matrix) {
+    let dir = std::env::temp_dir();
+    let (d, _) = bench_inputs(n);
+    let mut input = Matrix::create_mmap(dir.join(format!("shortcut-bench-tiled-d-{}", n)), n).unwrap();
+    input.as_mut_slice().copy_from_slice(&d);
+    input.flush().unwrap();
+    let output = Matrix::create_mmap(dir.join(format!("shortcut-bench-tiled-r-{}", n)), n).unwrap();
+    (input, output)
+}
+
+fn step_tiled(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step_tiled");
+    group.sample_size(10);
+    for (n, tile) in [(1024, 256), (2048, 512)] {
+        let (mut input, mut output) = files(n);
+        group.throughput(Throughput::Elements((n * n * n) as u64));
+        group.bench_with_input(BenchmarkId::new("sequential", n), &n, |b, &n| {
+            b.iter(|| tiled::step_tiled(&mut input, &mut output, n, tile).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("pipelined", n), &n, |b, &n| {
+            b.iter(|| tiled::step_tiled_pipelined(&mut input, &mut output, n, tile).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step_tiled);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use shortcut::{bench_inputs, variants};
+
+fn step_variants(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.sample_size(10);
+    for n in [256, 512, 1024] {
+        let (d, mut r) = bench_inputs(n);
+        group.throughput(Throughput::Elements((n * n * n) as u64));
+        for (name, step) in variants::VARIANTS {
+            if name == "v0" && n > 512 {
+                continue;
+            }
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| b.iter(|| step(&mut r, &d, n)));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step_variants);
+criterion_main!(benches);
@@ -0,0 +1,258 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use shortcut::bench::baseline::{self, Baseline};
+#[cfg(feature = "serde")]
+use shortcut::bench::BenchReport;
+use shortcut::bench::{self, BenchConfig, Chart, Environment, Format, Measurement};
+
+const USAGE: &str = "\
+usage: shortcut-bench [options]
+  --config run.toml     start from the shortcut::bench::BenchConfig in a TOML file, if its
+                        name ends in .toml, or else JSON, instead of the defaults, with the
+                        options after it changing it, see shortcut::config
+  --variants v0,v1,...  variants to run, all by default, and gpu or cuda with the features
+                        of the same names, numa-none and numa-local with the numa feature,
+                        or the C++ versions cpp-v0 to cpp-v7 with the cpp-compare feature
+  --sizes 1000,2000     values of n
+  --input d.bin         matrix file to use as the input instead of random inputs of --sizes,
+                        a .mtx MatrixMarket file, a .npy array or any other name for a file
+                        of shortcut::io::Matrix
+  --gen uniform         generate the inputs of --sizes with one of uniform, geometric,
+                        power-law, banded or cache-antagonistic, the last rounding n up to a
+                        multiple of 1024, instead of the uniform inputs of the book
+  --seed 0              seed of --gen
+  --threads 1,4         thread counts, $SHORTCUT_NUM_THREADS, $OMP_NUM_THREADS or all cores by default
+  --affinity auto       auto to pin the threads one per physical core, performance cores
+                        first, or a list of CPUs to pin them to such as 0,2,4, none by default
+  --grains 1,16         numbers of chunks of rows the threads take at a time as they finish
+                        the previous ones, instead of splitting the rows evenly up front
+  --background 2        threads that keep spinning during the measurements, as on a machine
+                        shared with other processes
+  --repetitions 3       runs per measurement, the fastest is reported
+  --format text         text, csv or json
+  --prefetch 0,20       prefetch distances in vectors to run v7 with, the tuned one by default
+  --streaming-stores false,true
+                        whether v7 writes the results with non-temporal stores, the tuned
+                        choice by default
+  --schedules z-order,hilbert
+                        orders in which the threads of v7 take the blocks of r, row-major,
+                        z-order or hilbert, z-order by default
+  --roofline            also measure the peak GFLOP/s and memory bandwidth for each thread
+                        count and report how close to the roofline each measurement gets
+  --scaling             run with every thread count from 1 to all cores instead of --threads,
+                        and report the speedup and efficiency over 1 thread
+  --chart ascii         with --scaling, plot the speedups after the report, or write them to
+                        an SVG file if given a name ending in .svg
+  --document run.json   also write the measurements with the times of all runs, the
+                        parameters and the CPU, compiler and crate version to a JSON file
+                        whose schema stays the same between versions
+  --report run.json     also write the measurements, the configuration and the machine as a
+                        shortcut::bench::BenchReport to a JSON or TOML file, see --config
+  --baseline base.json  compare with the measurements of the same variants, sizes and
+                        parameters in the JSON report, --document or --report of an earlier
+                        run, and exit with status 2 if any got slower by more than
+                        --max-regression
+  --max-regression 5%   slowdown over --baseline allowed before it is a regression, 5% by
+                        default";
+
+/// Where `--chart` plots the speedups.
+enum ChartOutput {
+    Stdout,
+    Svg(PathBuf),
+}
+
+fn parse_list<T: std::str::FromStr>(value: &str) -> Result<Vec<T>, String> {
+    value.split(',').map(|x| x.trim().parse().map_err(|_| format!("invalid value '{}'", x))).collect()
+}
+
+/// `--baseline` and `--max-regression` as a fraction.
+struct Regressions {
+    baseline: PathBuf,
+    max_regression: f64,
+}
+
+/// The files of `--document` and `--report`.
+struct Outputs {
+    document: Option<PathBuf>,
+    report: Option<PathBuf>,
+}
+
+/// The command line, with the `Regressions` to check for last.
+type Args = (BenchConfig, Format, Option<ChartOutput>, Outputs, Option<Regressions>);
+
+/// A percentage such as `5%` or `5` as a fraction.
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.strip_suffix('%').unwrap_or(value).trim().parse::<f64>() {
+        Ok(percent) if percent >= 0.0 => Ok(percent / 100.0),
+        _ => Err(format!("invalid value '{}', expected a percentage such as 5%", value)),
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut config = BenchConfig::default();
+    let (mut format, mut chart) = (Format::Text, None);
+    let mut outputs = Outputs { document: None, report: None };
+    let (mut baseline, mut max_regression) = (None, 0.05);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            exit(0);
+        }
+        if arg == "--roofline" {
+            config.roofline = true;
+            continue;
+        }
+        if arg == "--scaling" {
+            config.scaling = true;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--config" => config = read_config(&value)?,
+            "--variants" => config.variants = parse_list(&value)?,
+            "--sizes" => config.sizes = parse_list(&value)?,
+            "--input" => config.input = Some(value.into()),
+            "--gen" => config.generator = Some(value.parse()?),
+            "--seed" => config.seed = value.parse().map_err(|_| format!("invalid value '{}'", value))?,
+            "--threads" => config.threads = parse_list(&value)?,
+            "--affinity" => config.affinity = value.parse()?,
+            "--grains" => config.grains = parse_list(&value)?,
+            "--background" => config.background_threads = value.parse().map_err(|_| format!("invalid value '{}'", value))?,
+            "--prefetch" => config.prefetch = parse_list(&value)?,
+            "--streaming-stores" => config.streaming_stores = parse_list(&value)?,
+            "--schedules" => config.schedules = parse_list(&value)?,
+            "--repetitions" => config.repetitions = value.parse().map_err(|_| format!("invalid value '{}'", value))?,
+            "--format" => format = value.parse()?,
+            "--chart" if value == "ascii" => chart = Some(ChartOutput::Stdout),
+            "--chart" if value.ends_with(".svg") => chart = Some(ChartOutput::Svg(value.into())),
+            "--chart" => return Err(format!("invalid value '{}', expected ascii or a .svg file", value)),
+            "--document" => outputs.document = Some(value.into()),
+            "--report" => outputs.report = Some(value.into()),
+            "--baseline" => baseline = Some(PathBuf::from(value)),
+            "--max-regression" => max_regression = parse_percent(&value)?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if chart.is_some() && !config.scaling {
+        return Err("--chart needs --scaling".to_string());
+    }
+    if outputs.report.is_some() && !cfg!(feature = "serde") {
+        return Err("--report needs the serde feature".to_string());
+    }
+    let regressions = baseline.map(|baseline| Regressions { baseline, max_regression });
+    Ok((config, format, chart, outputs, regressions))
+}
+
+#[cfg(feature = "serde")]
+fn read_config(path: &str) -> Result<BenchConfig, String> {
+    shortcut::config::read(path).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_config(_path: &str) -> Result<BenchConfig, String> {
+    Err("--config needs the serde feature".to_string())
+}
+
+/// The measurements of a `BenchReport` of `--report`, or else of a JSON report or `--document`.
+#[cfg(feature = "serde")]
+fn read_baseline(path: &Path) -> std::io::Result<Baseline> {
+    match shortcut::config::read::<BenchReport>(path) {
+        Ok(report) => Ok(Baseline::from_measurements(&report.measurements)),
+        Err(_) => Baseline::read(path),
+    }
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_baseline(path: &Path) -> std::io::Result<Baseline> {
+    Baseline::read(path)
+}
+
+#[cfg(feature = "serde")]
+fn write_bench_report(path: &Path, environment: Environment, config: BenchConfig, results: Vec<Measurement>) -> std::io::Result<()> {
+    shortcut::config::write(path, &BenchReport { environment, config, measurements: results })
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_bench_report(_path: &Path, _: Environment, _: BenchConfig, _: Vec<Measurement>) -> std::io::Result<()> {
+    unreachable!("parse_args rejects --report without the serde feature")
+}
+
+fn main() {
+    let (config, format, chart, outputs, regressions) = parse_args().unwrap_or_else(|e| {
+        eprintln!("error: {}\n{}", e, USAGE);
+        exit(1);
+    });
+    // Read before the measurements so that a missing or invalid baseline fails right away.
+    let baseline = regressions.as_ref().map(|regressions| {
+        read_baseline(&regressions.baseline).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            exit(1);
+        })
+    });
+    let results = bench::run(&config).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        exit(1);
+    });
+    if let Err(e) = bench::write_report(&mut std::io::stdout().lock(), &results, format) {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+    let written = match chart {
+        Some(ChartOutput::Stdout) => bench::write_chart(&mut std::io::stdout().lock(), &results, Chart::Ascii),
+        Some(ChartOutput::Svg(path)) => File::create(&path)
+            .and_then(|file| {
+                let mut out = BufWriter::new(file);
+                bench::write_chart(&mut out, &results, Chart::Svg)?;
+                out.flush()
+            })
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+        None => Ok(()),
+    };
+    if let Err(e) = written {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+    if let Some(path) = outputs.document {
+        let written = File::create(&path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            bench::write_document(&mut out, &Environment::detect(), &config, &results)?;
+            out.flush()
+        });
+        if let Err(e) = written {
+            eprintln!("error: {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
+    if let Some(path) = outputs.report {
+        if let Err(e) = write_bench_report(&path, Environment::detect(), config.clone(), results.clone()) {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    }
+    if let (Some(regressions), Some(baseline)) = (regressions, baseline) {
+        let comparisons = baseline::compare(&baseline, &results);
+        if comparisons.is_empty() {
+            eprintln!("error: none of the measurements is in {}", regressions.baseline.display());
+            exit(1);
+        }
+        // On stderr, so that the report on stdout stays in --format.
+        if let Err(e) = baseline::write_comparison(&mut std::io::stderr().lock(), &comparisons, regressions.max_regression) {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+        let regressed = comparisons.iter().filter(|c| c.regressed(regressions.max_regression)).count();
+        if regressed > 0 {
+            eprintln!(
+                "{} of {} measurements regressed by more than {}%",
+                regressed,
+                comparisons.len(),
+                100.0 * regressions.max_regression
+            );
+            exit(2);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use std::process::exit;
+use std::time::Instant;
+
+use std::fs::File;
+use std::io::BufReader;
+
+use shortcut::gen::{self, Generator};
+use shortcut::graph::{self, GraphOptions};
+use shortcut::harness::{self, Language};
+use shortcut::io::formats::{self, write_csv};
+#[cfg(unix)]
+use shortcut::daemon::DaemonOptions;
+use shortcut::{reference, registry, variants, verify, ThreadConfig};
+
+const USAGE: &str = "\
+usage: shortcut [options] input [output]
+       shortcut [options] --gen uniform --n 4000 [output]
+       shortcut verify [--tolerance 1e-6] a b
+       shortcut verify --precisions [--steps 10] input
+       shortcut serve --shm NAME [--create 4000]
+       shortcut serve --http 127.0.0.1:8080 [--concurrency 1] [--max-n 16384]
+       shortcut daemon --socket PATH [--variant v7] [--threads 4] [--warm 256,512,1024]
+                       [--max-n 16384] [--max-connections 64]
+       shortcut gen-harness c|cpp|python|julia [output]
+Reads the matrix d from input, computes r with r[i][j] = min(d[i][k] + d[k][j]) over all k and
+writes it to output, or to standard output as CSV. Files ending in .npy are NumPy arrays, .mtx
+MatrixMarket matrices and .csv one row per line, any other file is a shortcut::io::Matrix.
+shortcut verify prints how the matrices in a and b differ, with f64 arrays rounded to f32, and
+fails unless the relative error of all elements is within the tolerance. With --precisions, it
+instead squares the matrix in input --steps times in f32, in f32 with compensated sums and in f64,
+and prints how long each took and how far the first two are from f64.
+shortcut serve maps the POSIX shared memory object NAME, which holds a matrix in the layout of a
+shortcut::io::Matrix file, and replaces the matrix with its step whenever another process asks
+for it by setting the state in its header, see shortcut::io::shm, until one sets it to shut
+down. With --create, it creates the object for a matrix of that size first and removes it at the
+end. With --http, it instead answers POST /step and POST /apsp requests with a .npy array or the
+raw little-endian f32s of a square matrix as their body, see shortcut::service, computing at most
+--concurrency of them at a time, each with its share of the threads. The threads are those of
+$SHORTCUT_NUM_THREADS or $OMP_NUM_THREADS, or all cores.
+shortcut daemon selects the kernel, tunes it for the sizes of --warm and then computes the steps
+that runs of shortcut with --daemon send to the Unix socket PATH, one at a time, keeping copies
+of d allocated between them, see shortcut::daemon. Scripts running many small steps then skip
+the detection and tuning that each new process would do.
+shortcut gen-harness writes a program in that language to output, or to standard output, that
+times the step of the same input as shortcut-bench through the C ABI of this library and checks
+the result, with the commands to build and run it in its first comment, see shortcut::harness.
+  --variant v7    variant to run, v0 to v7, recursive, transpose-free or one added with
+                  shortcut::registry
+  --auto          run the fastest kernel this CPU supports, the default
+  --threads 4     thread count, all cores by default
+  --verify        check the result against the reference implementation
+  --bench         print how long the step took to standard error
+  --daemon PATH   send the step to the shortcut daemon listening on the Unix socket PATH,
+                  which runs it with its own --variant and --threads
+  --options FILE  run shortcut::step_with_options with the StepOptions in the TOML file FILE, if
+                  it ends in .toml, or else JSON, see shortcut::config
+  --edge-list     read input as a list of edges of a graph, one 'from to [weight]' per line,
+                  with vertices numbered from 0
+  --undirected    with --edge-list, add each edge in both directions
+  --zero-diagonal with --edge-list, make the distance of each vertex to itself zero
+  --gen uniform   generate d with one of uniform, geometric, power-law, banded or
+                  cache-antagonistic instead of reading it, see shortcut::gen
+  --n 4000        with --gen, the size of d, rounded up to a multiple of 1024 for
+                  cache-antagonistic
+  --seed 0        with --gen, the seed to generate d from";
+
+struct Args {
+    /// `None` for `--gen`.
+    input: Option<String>,
+    output: Option<String>,
+    variant: Option<String>,
+    threads: ThreadConfig,
+    verify: bool,
+    bench: bool,
+    /// `Some` for `--edge-list`.
+    graph: Option<GraphOptions>,
+    /// `Some` for `--gen`, with the values of `--n` and `--seed`.
+    generated: Option<(Generator, usize, u64)>,
+    /// The socket of `--daemon`.
+    daemon: Option<String>,
+    /// The file of `--options`.
+    options: Option<String>,
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}'", value))
+}
+
+/// A comma-separated list, empty for an empty `value`.
+#[cfg(unix)]
+fn parse_list<T: std::str::FromStr>(value: &str) -> Result<Vec<T>, String> {
+    value.split(',').filter(|x| !x.is_empty()).map(parse_value).collect()
+}
+
+fn parse_args() -> Result<Args, String> {
+    let (mut paths, mut variant, mut threads) = (Vec::new(), None, ThreadConfig::default());
+    let (mut auto, mut verify, mut bench) = (false, false, false);
+    let (mut edge_list, mut graph) = (false, GraphOptions::default());
+    let (mut generator, mut size, mut seed) = (None, None, None);
+    let (mut daemon, mut threads_set, mut options) = (None, false, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--variant" => variant = Some(value()?),
+            "--auto" => auto = true,
+            "--threads" => {
+                threads = ThreadConfig::with_threads(parse_value(&value()?)?);
+                threads_set = true;
+            }
+            "--verify" => verify = true,
+            "--bench" => bench = true,
+            "--edge-list" => edge_list = true,
+            "--undirected" => graph.undirected = true,
+            "--zero-diagonal" => graph.zero_diagonal = true,
+            "--gen" => generator = Some(value()?.parse::<Generator>()?),
+            "--n" => size = Some(parse_value(&value()?)?),
+            "--seed" => seed = Some(parse_value(&value()?)?),
+            "--daemon" => daemon = Some(value()?),
+            "--options" => options = Some(value()?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    if auto && variant.is_some() {
+        return Err("--auto and --variant cannot be used together".to_string());
+    }
+    if daemon.is_some() && (variant.is_some() || threads_set) {
+        return Err("--variant and --threads are those of the daemon with --daemon".to_string());
+    }
+    if options.is_some() && (daemon.is_some() || variant.is_some() || threads_set) {
+        return Err("--options cannot be used with --daemon, --variant or --threads".to_string());
+    }
+    if !edge_list && graph != GraphOptions::default() {
+        return Err("--undirected and --zero-diagonal need --edge-list".to_string());
+    }
+    let generated = match (generator, size) {
+        (Some(_), _) if edge_list => return Err("--gen and --edge-list cannot be used together".to_string()),
+        (Some(generator), Some(n)) => Some((generator, n, seed.unwrap_or(0))),
+        (Some(_), None) => return Err("--gen needs --n".to_string()),
+        (None, _) if size.is_some() || seed.is_some() => return Err("--n and --seed need --gen".to_string()),
+        (None, _) => None,
+    };
+    let mut paths = paths.into_iter();
+    let input = if generated.is_some() { None } else { Some(paths.next().ok_or("missing input")?) };
+    let output = paths.next();
+    if paths.next().is_some() {
+        return Err("too many arguments".to_string());
+    }
+    Ok(Args { input, output, variant, threads, verify, bench, graph: edge_list.then_some(graph), generated, daemon, options })
+}
+
+/// `shortcut verify`, with `args` the arguments after `verify`.
+fn verify(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (mut paths, mut tolerance, mut precisions, mut steps) = (Vec::new(), 1e-6, false, None);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--tolerance" => tolerance = parse_value(&value()?)?,
+            "--precisions" => precisions = true,
+            "--steps" => steps = Some(parse_value(&value()?)?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    let read = |path: &String| formats::read_file(path).map_err(|e| format!("{}: {}", path, e));
+    if precisions {
+        let [input] = &paths[..] else { return Err("expected one matrix to compare precisions on".to_string()) };
+        return compare_precisions(read(input)?, steps.unwrap_or(10));
+    }
+    if steps.is_some() {
+        return Err("--steps needs --precisions".to_string());
+    }
+    let [a, b] = &paths[..] else { return Err("expected two matrices to compare".to_string()) };
+    let ((n, d_a), (n_b, d_b)) = (read(a)?, read(b)?);
+    if n != n_b {
+        return Err(format!("{} is {} * {} but {} is {} * {}", a, n, n, b, n_b, n_b));
+    }
+    let report = verify::diff(&d_a, &d_b, n);
+    println!("n = {}", n);
+    println!("absolute error: max {:e}, mean {:e}", report.max_abs, report.mean_abs);
+    println!("relative error: max {:e}, mean {:e}", report.max_rel, report.mean_rel);
+    println!("infinity mismatches: {}", report.infinity_mismatches);
+    println!("NaN mismatches: {}", report.nan_mismatches);
+    if let Some((i, j)) = report.worst {
+        println!("worst: [{}][{}] is {} in {} and {} in {}", i, j, d_a[n*i + j], a, d_b[n*i + j], b);
+    }
+    if !report.within(tolerance) {
+        return Err(format!("the matrices differ by more than a relative error of {}", tolerance));
+    }
+    Ok(())
+}
+
+/// `shortcut serve`, with `args` the arguments after `serve`.
+fn serve(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (mut shm, mut create, mut http, mut concurrency, mut max_n) = (None, None, None, None, None);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--shm" => shm = Some(value()?),
+            "--create" => create = Some(parse_value(&value()?)?),
+            "--http" => http = Some(value()?),
+            "--concurrency" => concurrency = Some(parse_value(&value()?)?),
+            "--max-n" => max_n = Some(parse_value(&value()?)?),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    match (shm, http) {
+        (Some(_), _) if concurrency.is_some() || max_n.is_some() => {
+            Err("--concurrency and --max-n need --http".to_string())
+        }
+        (Some(name), None) => serve_shm(&name, create),
+        (None, Some(_)) if create.is_some() => Err("--create needs --shm".to_string()),
+        (None, Some(addr)) => serve_http(&addr, concurrency, max_n),
+        _ => Err("serve needs one of --shm and --http".to_string()),
+    }
+}
+
+/// `shortcut daemon`, with `args` the arguments after `daemon`.
+#[cfg(unix)]
+fn daemon(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let (mut socket, mut options) = (None, DaemonOptions::default());
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--socket" => socket = Some(value()?),
+            "--variant" => options.variant = Some(value()?),
+            "--threads" => options.threads = ThreadConfig::with_threads(parse_value(&value()?)?),
+            "--warm" => options.warm = parse_list(&value()?)?,
+            "--max-n" => options.max_n = parse_value(&value()?)?,
+            "--max-connections" => options.max_connections = parse_value(&value()?)?,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    let socket = socket.ok_or("daemon needs --socket")?;
+    let listener = shortcut::daemon::bind(&socket).map_err(|e| format!("{}: {}", socket, e))?;
+    eprintln!("tuning for n = {:?}, then serving on {}", options.warm, socket);
+    let result = shortcut::daemon::serve(&listener, &options).map_err(|e| e.to_string());
+    let _ = std::fs::remove_file(&socket);
+    result
+}
+
+/// `shortcut gen-harness`, with `args` the arguments after `gen-harness`.
+fn gen_harness(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let args: Vec<String> = args.collect();
+    let (language, output) = match &args[..] {
+        [language] => (language, None),
+        [language, output] => (language, Some(output)),
+        _ => return Err("expected a language, c, cpp, python or julia, and an optional output".to_string()),
+    };
+    let source = harness::source(language.parse::<Language>()?);
+    match output {
+        Some(path) => std::fs::write(path, source).map_err(|e| format!("{}: {}", path, e)),
+        None => {
+            print!("{}", source);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn daemon(_args: impl Iterator<Item = String>) -> Result<(), String> {
+    Err("daemon needs Unix sockets, which this platform does not have".to_string())
+}
+
+#[cfg(all(target_endian = "little", any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+fn serve_shm(name: &str, create: Option<usize>) -> Result<(), String> {
+    use shortcut::io::shm::Region;
+
+    let region = match create {
+        Some(n) => Region::create(name, n),
+        None => Region::open(name),
+    };
+    let mut region = region.map_err(|e| format!("{}: {}", name, e))?;
+    eprintln!("serving n = {} on {}", region.n(), name);
+    region.serve(shortcut::step_in_place);
+    if create.is_some() {
+        Region::unlink(name).map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_endian = "little", any(target_os = "linux", target_os = "android", target_vendor = "apple"))))]
+fn serve_shm(_name: &str, _create: Option<usize>) -> Result<(), String> {
+    Err("--shm needs POSIX shared memory, which this platform does not have".to_string())
+}
+
+#[cfg(feature = "service")]
+fn serve_http(addr: &str, concurrency: Option<usize>, max_n: Option<usize>) -> Result<(), String> {
+    use shortcut::service::{self, ServiceOptions};
+
+    let defaults = ServiceOptions::default();
+    let options = ServiceOptions {
+        concurrency: concurrency.unwrap_or(defaults.concurrency),
+        max_n: max_n.unwrap_or(defaults.max_n),
+        ..defaults
+    };
+    let listener = std::net::TcpListener::bind(addr).map_err(|e| format!("{}: {}", addr, e))?;
+    eprintln!("serving POST /step and POST /apsp on http://{}", listener.local_addr().map_err(|e| e.to_string())?);
+    service::serve(&listener, &options).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "service"))]
+fn serve_http(_addr: &str, _concurrency: Option<usize>, _max_n: Option<usize>) -> Result<(), String> {
+    Err("--http needs the service feature".to_string())
+}
+
+/// `shortcut verify --precisions`, printing one line per precision.
+fn compare_precisions((n, d): (usize, Vec<f32>), steps: usize) -> Result<(), String> {
+    let reports = verify::compare_precisions(&d, n, steps).map_err(|e| e.to_string())?;
+    println!("n = {}, {} steps", n, steps);
+    println!("{:<16} {:>10} {:>12} {:>12} {:>10}", "precision", "seconds", "max rel", "mean rel", "mismatches");
+    for report in reports {
+        let diff = report.diff;
+        let mismatches = diff.infinity_mismatches + diff.nan_mismatches;
+        println!(
+            "{:<16} {:>10.6} {:>12.3e} {:>12.3e} {:>10}",
+            report.precision, report.seconds, diff.max_rel, diff.mean_rel, mismatches
+        );
+    }
+    Ok(())
+}
+
+fn read_input(input: &str, args: &Args) -> std::io::Result<(usize, Vec<f32>)> {
+    match &args.graph {
+        Some(options) => {
+            let edges = graph::read_edge_list(BufReader::new(File::open(input)?))?;
+            let n = graph::vertex_count(&edges);
+            Ok((n, graph::from_edge_list_with_options(&edges, n, options)))
+        }
+        None => formats::read_file(input),
+    }
+}
+
+#[cfg(unix)]
+fn send_to_daemon(socket: &str, r: &mut [f32], d: &[f32], n: usize) -> Result<(), String> {
+    shortcut::daemon::step(socket, r, d, n).map_err(|e| format!("{}: {}", socket, e))
+}
+
+#[cfg(not(unix))]
+fn send_to_daemon(_socket: &str, _r: &mut [f32], _d: &[f32], _n: usize) -> Result<(), String> {
+    Err("--daemon needs Unix sockets, which this platform does not have".to_string())
+}
+
+#[cfg(feature = "serde")]
+fn step_with_options_file(path: &str, r: &mut [f32], d: &[f32], n: usize) -> Result<(), String> {
+    let options: shortcut::StepOptions = shortcut::config::read(path).map_err(|e| e.to_string())?;
+    shortcut::step_with_options(r, d, n, &options).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+fn step_with_options_file(_path: &str, _r: &mut [f32], _d: &[f32], _n: usize) -> Result<(), String> {
+    Err("--options needs the serde feature".to_string())
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let (n, d) = match (&args.input, args.generated) {
+        (Some(input), _) => read_input(input, args).map_err(|e| format!("{}: {}", input, e))?,
+        (None, Some((generator, n, seed))) => gen::generate(generator, n, seed),
+        (None, None) => unreachable!("parse_args requires an input without --gen"),
+    };
+    let mut r = vec![0.0; n * n];
+    let start = Instant::now();
+    match (&args.daemon, &args.variant, &args.options) {
+        (Some(socket), _, _) => send_to_daemon(socket, &mut r, &d, n)?,
+        (None, _, Some(path)) => step_with_options_file(path, &mut r, &d, n)?,
+        (None, Some(name), None) => match (variants::by_name_with_threads(name), registry::by_name(name)) {
+            (Some(step), _) => step(&args.threads, &mut r, &d, n),
+            (None, Some(step)) => step(&mut r, &d, n),
+            (None, None) => return Err(format!("unknown variant '{}'", name)),
+        },
+        (None, None, None) => shortcut::step_with_threads(&mut r, &d, n, &args.threads).map_err(|e| e.to_string())?,
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    if args.bench {
+        let gflops = 2.0 * (n as f64).powi(3) / seconds / 1e9;
+        eprintln!("n = {}, {} threads: {:.6} s, {:.2} GFLOPS", n, args.threads.effective_threads(), seconds, gflops);
+    }
+    if args.verify {
+        let mut expected = vec![0.0; n * n];
+        reference::step(&mut expected, &d, n);
+        let (mismatches, first) = reference::compare(&expected, &r, n, 1e-6);
+        if let Some(m) = first {
+            return Err(format!(
+                "{} elements differ from the reference, first r[{}][{}] = {} instead of {}",
+                mismatches, m.i, m.j, m.actual, m.expected
+            ));
+        }
+        eprintln!("verified against the reference");
+    }
+    match &args.output {
+        Some(path) => formats::write_file(path, &r, n).map_err(|e| format!("{}: {}", path, e)),
+        None => write_csv(std::io::stdout().lock(), &r, n).map_err(|e| e.to_string()),
+    }
+}
+
+fn main() {
+    let command = match std::env::args().nth(1).as_deref() {
+        Some("verify") => Some(verify(std::env::args().skip(2))),
+        Some("serve") => Some(serve(std::env::args().skip(2))),
+        Some("daemon") => Some(daemon(std::env::args().skip(2))),
+        Some("gen-harness") => Some(gen_harness(std::env::args().skip(2))),
+        _ => None,
+    };
+    if let Some(result) = command {
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+        return;
+    }
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("error: {}\n{}", e, USAGE);
+        exit(1);
+    });
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
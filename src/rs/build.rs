@@ -0,0 +1,63 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// The directories of the C++ versions of `step` in `src/cpp` of shortcut-comparison, from `v0`
+/// to `v7`, each with a `step.cpp` defining `void step(float* r, const float* d, int n)`.
+const CPP_VARIANTS: [&str; 8] = [
+    "v0_baseline",
+    "v1_linear_reading",
+    "v2_instr_level_parallelism",
+    "v3_simd",
+    "v4_register_reuse",
+    "v5_more_register_reuse",
+    "v6_prefetch",
+    "v7_cache_reuse",
+];
+
+/// With the `cpp-compare` feature, compiles the C++ versions found in `$SHORTCUT_CPP_DIR` with the
+/// flags of the benchmarks in the book, exporting version `i` as `shortcut_cpp_step_v{i}` for `cpp`.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SHORTCUT_CPP_DIR");
+    // For `bench::Environment`, which records the compiler the measurements were built with.
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    if let Ok(output) = std::process::Command::new(rustc).arg("--version").output() {
+        let version = String::from_utf8_lossy(&output.stdout);
+        println!("cargo:rustc-env=SHORTCUT_RUSTC_VERSION={}", version.trim());
+    }
+    if env::var_os("CARGO_FEATURE_CPP_COMPARE").is_none() {
+        return;
+    }
+    let cpp_dir = PathBuf::from(env::var_os("SHORTCUT_CPP_DIR").expect(
+        "the cpp-compare feature needs SHORTCUT_CPP_DIR set to the src/cpp directory of a shortcut-comparison checkout",
+    ));
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR"));
+    for (i, variant) in CPP_VARIANTS.iter().enumerate() {
+        let step = cpp_dir.join(variant).join("step.cpp");
+        println!("cargo:rerun-if-changed={}", step.display());
+        // All versions define the same `step`, so each is renamed and wrapped in a function with
+        // C linkage of its own.
+        let wrapper = out_dir.join(format!("cpp_step_v{}.cpp", i));
+        let source = format!(
+            "#define step shortcut_cpp_step_v{i}_impl\n\
+             #include {step:?}\n\
+             #undef step\n\
+             extern \"C\" void shortcut_cpp_step_v{i}(float* r, const float* d, int n) {{\n    \
+                 shortcut_cpp_step_v{i}_impl(r, d, n);\n\
+             }}\n",
+        );
+        fs::write(&wrapper, source).expect("writing to OUT_DIR");
+        cc::Build::new()
+            .cpp(true)
+            .file(&wrapper)
+            .include(cpp_dir.join(variant))
+            .include(&cpp_dir)
+            .flag("-std=c++17")
+            .flag("-O3")
+            .flag("-march=native")
+            .flag("-fopenmp")
+            .compile(&format!("shortcut_cpp_v{}", i));
+    }
+    println!("cargo:rustc-link-lib=gomp");
+}
@@ -0,0 +1,145 @@
+use crate::layout::{pad_to_multiple, transpose_blocked};
+use crate::threads::{for_each_chunk, ThreadConfig};
+
+/// A distance `hi + lo`, with `lo` the rounding error of the `f32` sums that `hi` is made of, carried
+/// along like in Kahan summation. Together they keep about twice the precision of `f32`, while
+/// both parts are still added with `f32` arithmetic. `hi` is always `hi + lo` rounded to `f32`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[repr(C)]
+pub struct Compensated {
+    pub hi: f32,
+    pub lo: f32,
+}
+
+impl Compensated {
+    pub const INFINITY: Compensated = Compensated { hi: f32::INFINITY, lo: 0.0 };
+
+    /// `hi + lo` rounded to the nearest `f64`.
+    pub fn to_f64(self) -> f64 {
+        self.hi as f64 + self.lo as f64
+    }
+}
+
+impl From<f32> for Compensated {
+    fn from(hi: f32) -> Self {
+        Compensated { hi, lo: 0.0 }
+    }
+}
+
+impl From<Compensated> for f64 {
+    fn from(x: Compensated) -> f64 {
+        x.to_f64()
+    }
+}
+
+/// `x + y`, with the exact rounding error of `x.hi + y.hi` by Knuth's two-sum added to the low
+/// parts. Infinite sums have no error, as it would be NaN.
+#[inline(always)]
+fn add(x: Compensated, y: Compensated) -> Compensated {
+    let s = x.hi + y.hi;
+    if !s.is_finite() {
+        return Compensated { hi: s, lo: 0.0 };
+    }
+    let b = s - x.hi;
+    let e = (x.hi - (s - b)) + (y.hi - b) + (x.lo + y.lo);
+    let hi = s + e;
+    Compensated { hi, lo: e - (hi - s) }
+}
+
+/// Orders by `hi` first, which `add` keeps rounded from the whole value, and then by `lo`.
+#[inline(always)]
+fn less(x: Compensated, y: Compensated) -> bool {
+    x.hi < y.hi || (x.hi == y.hi && x.lo < y.lo)
+}
+
+/// The rows of `d` and of its transpose padded to a multiple of `lanes`, with the high and low parts
+/// in separate matrices so that `lanes` of each can be loaded as one vector. The padding is
+/// `Compensated::INFINITY`.
+struct Packed {
+    rows_hi: Vec<f32>,
+    rows_lo: Vec<f32>,
+    cols_hi: Vec<f32>,
+    cols_lo: Vec<f32>,
+    width: usize,
+}
+
+impl Packed {
+    fn new(d: &[Compensated], n: usize, lanes: usize) -> Self {
+        let hi: Vec<f32> = d.iter().map(|x| x.hi).collect();
+        let lo: Vec<f32> = d.iter().map(|x| x.lo).collect();
+        let (rows_hi, width) = pad_to_multiple(&hi, n, n, lanes, f32::INFINITY);
+        let (rows_lo, _) = pad_to_multiple(&lo, n, n, lanes, 0.0);
+        let (cols_hi, _) = pad_to_multiple(&transpose_blocked(&hi, n, n), n, n, lanes, f32::INFINITY);
+        let (cols_lo, _) = pad_to_multiple(&transpose_blocked(&lo, n, n), n, n, lanes, 0.0);
+        Packed { rows_hi, rows_lo, cols_hi, cols_lo, width }
+    }
+}
+
+pub(crate) fn step(threads: &ThreadConfig, r: &mut [Compensated], d: &[Compensated], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+        return unsafe { x86::step_avx2(threads, r, d, n) };
+    }
+    let p = Packed::new(d, n, 1);
+    let width = p.width;
+    for_each_chunk(threads, r, n, |i, r_row| {
+        let row = |k| Compensated { hi: p.rows_hi[width*i + k], lo: p.rows_lo[width*i + k] };
+        for (j, res) in r_row.iter_mut().enumerate() {
+            let col = |k| Compensated { hi: p.cols_hi[width*j + k], lo: p.cols_lo[width*j + k] };
+            *res = (0..n).map(|k| add(row(k), col(k))).fold(Compensated::INFINITY, |v, z| if less(z, v) { z } else { v });
+        }
+    });
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{less, Compensated, Packed};
+    use crate::threads::{for_each_chunk, ThreadConfig};
+
+    /// `super::add` for 8 pairs of sums at a time.
+    #[inline(always)]
+    unsafe fn add(x_hi: __m256, x_lo: __m256, y_hi: __m256, y_lo: __m256) -> (__m256, __m256) {
+        let s = _mm256_add_ps(x_hi, y_hi);
+        let b = _mm256_sub_ps(s, x_hi);
+        let e = _mm256_add_ps(
+            _mm256_add_ps(_mm256_sub_ps(x_hi, _mm256_sub_ps(s, b)), _mm256_sub_ps(y_hi, b)),
+            _mm256_add_ps(x_lo, y_lo),
+        );
+        // Clearing the bits of all lanes whose sum is infinite leaves them with no error.
+        let abs = _mm256_andnot_ps(_mm256_set1_ps(-0.0), s);
+        let finite = _mm256_cmp_ps::<_CMP_LT_OQ>(abs, _mm256_set1_ps(f32::INFINITY));
+        let e = _mm256_and_ps(e, finite);
+        let hi = _mm256_add_ps(s, e);
+        (hi, _mm256_and_ps(_mm256_sub_ps(e, _mm256_sub_ps(hi, s)), finite))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn step_avx2(threads: &ThreadConfig, r: &mut [Compensated], d: &[Compensated], n: usize) {
+        let p = Packed::new(d, n, 8);
+        let width = p.width;
+        for_each_chunk(threads, r, n, |i, r_row| {
+            let (x_hi, x_lo) = (&p.rows_hi[width*i..width*(i + 1)], &p.rows_lo[width*i..width*(i + 1)]);
+            for (j, res) in r_row.iter_mut().enumerate() {
+                let (y_hi, y_lo) = (&p.cols_hi[width*j..width*(j + 1)], &p.cols_lo[width*j..width*(j + 1)]);
+                let (mut v_hi, mut v_lo) = (_mm256_set1_ps(f32::INFINITY), _mm256_setzero_ps());
+                for k in (0..width).step_by(8) {
+                    let load = |row: &[f32]| _mm256_loadu_ps(row.as_ptr().add(k));
+                    let (z_hi, z_lo) = add(load(x_hi), load(x_lo), load(y_hi), load(y_lo));
+                    let lt = _mm256_cmp_ps::<_CMP_LT_OQ>(z_hi, v_hi);
+                    let tie = _mm256_and_ps(_mm256_cmp_ps::<_CMP_EQ_OQ>(z_hi, v_hi), _mm256_cmp_ps::<_CMP_LT_OQ>(z_lo, v_lo));
+                    let take = _mm256_or_ps(lt, tie);
+                    (v_hi, v_lo) = (_mm256_blendv_ps(v_hi, z_hi, take), _mm256_blendv_ps(v_lo, z_lo, take));
+                }
+                let (mut lanes_hi, mut lanes_lo) = ([0.0; 8], [0.0; 8]);
+                _mm256_storeu_ps(lanes_hi.as_mut_ptr(), v_hi);
+                _mm256_storeu_ps(lanes_lo.as_mut_ptr(), v_lo);
+                *res = lanes_hi.iter().zip(&lanes_lo).map(|(&hi, &lo)| Compensated { hi, lo }).fold(
+                    Compensated::INFINITY,
+                    |v, z| if less(z, v) { z } else { v },
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,40 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "toml")
+}
+
+fn invalid_data(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+/// Reads a value such as `StepOptions` or `bench::BenchConfig` from the TOML file at `path` if
+/// its name ends in `.toml`, or else from JSON. The fields left out keep their defaults in those
+/// two, and the enums are written as in the options of the command-line tools, such as `"z-order"`.
+pub fn read<T: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<T> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)?;
+    if is_toml(path) {
+        toml::from_str(&contents).map_err(|e| invalid_data(path, e))
+    } else {
+        serde_json::from_str(&contents).map_err(|e| invalid_data(path, e))
+    }
+}
+
+/// Writes `value` to `path` in the format that `read` reads it in, such as a `bench::BenchReport`
+/// to compare with on another machine. TOML has no integers above `i64::MAX`, such as the
+/// `row_block` of `tune::Tuning::default()`, which only JSON can hold.
+pub fn write<T: Serialize>(path: impl AsRef<Path>, value: &T) -> io::Result<()> {
+    let path = path.as_ref();
+    let contents = if is_toml(path) {
+        toml::to_string_pretty(value).map_err(|e| invalid_data(path, e))?
+    } else {
+        serde_json::to_string_pretty(value).map_err(|e| invalid_data(path, e))? + "\n"
+    };
+    fs::write(path, contents)
+}
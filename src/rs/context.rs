@@ -0,0 +1,57 @@
+use crate::scratch::Scratch;
+use crate::threads::ThreadConfig;
+use crate::{check_lengths, dispatch, v4_register_reuse, StepError};
+#[cfg(target_arch = "x86_64")]
+use crate::{tune, v7_cache_reuse};
+
+/// Runs the fastest variant for matrices of size `n`, keeping its temporaries allocated between calls.
+pub struct StepContext {
+    n: usize,
+    threads: ThreadConfig,
+    scratch: Scratch,
+}
+
+impl StepContext {
+    pub fn new(n: usize) -> Self {
+        StepContext::with_threads(n, ThreadConfig::default())
+    }
+
+    pub fn with_threads(n: usize, threads: ThreadConfig) -> Self {
+        StepContext { n, threads, scratch: Scratch::default() }
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn step(&mut self, r: &mut [f32], d: &[f32]) -> Result<(), StepError> {
+        let n = self.n;
+        check_lengths(r, d, n)?;
+        #[cfg(target_arch = "x86_64")]
+        if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+            unsafe { v7_cache_reuse::step_avx2(&self.threads, &mut self.scratch, r, d, n, &tune::tuning(n)) };
+            return Ok(());
+        }
+        #[cfg(target_arch = "aarch64")]
+        if !crate::simd::PARANOID && std::arch::is_aarch64_feature_detected!("neon") {
+            unsafe { v4_register_reuse::step_neon(&self.threads, &mut self.scratch, r, d, n) };
+            return Ok(());
+        }
+        v4_register_reuse::step_portable(&self.threads, &mut self.scratch, r, d, n);
+        Ok(())
+    }
+
+    /// Like `step`, also returning whether any distance in `r` is shorter than in `d`, see
+    /// `crate::step_changed`, comparing each block of `r` with `d` as it is written. Without AVX2
+    /// that is with the `dispatch` kernel of `crate::step_changed`.
+    pub fn step_changed(&mut self, r: &mut [f32], d: &[f32]) -> Result<bool, StepError> {
+        let n = self.n;
+        check_lengths(r, d, n)?;
+        #[cfg(target_arch = "x86_64")]
+        if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+            let tuning = tune::tuning(n);
+            return Ok(unsafe { v7_cache_reuse::step_avx2_changed(&self.threads, &mut self.scratch, r, d, n, &tuning) });
+        }
+        Ok(dispatch::step_changed(&self.threads, r, d, n))
+    }
+}
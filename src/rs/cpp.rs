@@ -0,0 +1,97 @@
+use crate::threads::ThreadConfig;
+use crate::variants::{StepFn, StepWithThreadsFn};
+
+extern "C" {
+    fn shortcut_cpp_step_v0(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v1(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v2(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v3(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v4(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v5(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v6(r: *mut f32, d: *const f32, n: i32);
+    fn shortcut_cpp_step_v7(r: *mut f32, d: *const f32, n: i32);
+    fn omp_set_num_threads(num_threads: i32);
+}
+
+macro_rules! cpp_variants {
+    ($($variant:ident, $with_threads:ident => $symbol:ident),*) => {
+        $(
+            #[doc = concat!("The C++ version of `variants::", stringify!($variant), "`, compiled by `build.rs`.")]
+            pub fn $variant(r: &mut [f32], d: &[f32], n: usize) {
+                assert_eq!(r.len(), n * n, "r.len() must be n * n");
+                assert_eq!(d.len(), n * n, "d.len() must be n * n");
+                let n = i32::try_from(n).expect("the C++ versions take n as an int");
+                unsafe { $symbol(r.as_mut_ptr(), d.as_ptr(), n) }
+            }
+
+            #[doc = concat!("`", stringify!($variant), "` on as many OpenMP threads as `threads` has, ignoring its cores.")]
+            pub fn $with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+                set_num_threads(threads.effective_threads());
+                $variant(r, d, n)
+            }
+        )*
+    };
+}
+
+cpp_variants!(
+    v0, v0_with_threads => shortcut_cpp_step_v0,
+    v1, v1_with_threads => shortcut_cpp_step_v1,
+    v2, v2_with_threads => shortcut_cpp_step_v2,
+    v3, v3_with_threads => shortcut_cpp_step_v3,
+    v4, v4_with_threads => shortcut_cpp_step_v4,
+    v5, v5_with_threads => shortcut_cpp_step_v5,
+    v6, v6_with_threads => shortcut_cpp_step_v6,
+    v7, v7_with_threads => shortcut_cpp_step_v7
+);
+
+/// The C++ counterparts of `variants::VARIANTS`, to run on the same inputs in the same process.
+pub const VARIANTS: [(&str, StepFn); 8] = [
+    ("v0", v0),
+    ("v1", v1),
+    ("v2", v2),
+    ("v3", v3),
+    ("v4", v4),
+    ("v5", v5),
+    ("v6", v6),
+    ("v7", v7),
+];
+
+/// `VARIANTS` with the threads to run on as the first parameter.
+pub const VARIANTS_WITH_THREADS: [(&str, StepWithThreadsFn); 8] = [
+    ("v0", v0_with_threads),
+    ("v1", v1_with_threads),
+    ("v2", v2_with_threads),
+    ("v3", v3_with_threads),
+    ("v4", v4_with_threads),
+    ("v5", v5_with_threads),
+    ("v6", v6_with_threads),
+    ("v7", v7_with_threads),
+];
+
+/// The number of OpenMP threads of all later calls, which otherwise use one per core.
+pub fn set_num_threads(num_threads: usize) {
+    unsafe { omp_set_num_threads(i32::try_from(num_threads).unwrap_or(i32::MAX)) }
+}
+
+#[cfg(all(test, feature = "cpp-compare"))]
+mod tests {
+    use super::*;
+    use crate::reference::{compare, inputs};
+
+    #[test]
+    fn cpp_versions_match_the_rust_ones() {
+        for n in [1, 7, 16, 17, 33, 100] {
+            for (input, d) in inputs(n) {
+                for ((name, rust), (cpp_name, cpp)) in crate::variants::VARIANTS.into_iter().zip(VARIANTS) {
+                    assert_eq!(name, cpp_name);
+                    let (mut expected, mut r) = (vec![0.0; n * n], vec![0.0; n * n]);
+                    rust(&mut expected, &d, n);
+                    cpp(&mut r, &d, n);
+                    let (mismatches, first) = compare(&expected, &r, n, 1e-6);
+                    let case = format!("{} on {} for n = {}", name, input, n);
+                    assert_eq!(mismatches, 0, "{} differs between C++ and Rust, first at {:?}", case, first);
+                }
+            }
+        }
+    }
+}
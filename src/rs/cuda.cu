@@ -0,0 +1,28 @@
+// r = d * d in the min-plus semiring, the CUDA version of gpu.wgsl.
+// Each block of TILE * TILE threads computes one block of r, loading one tile of the rows and one
+// tile of the columns it needs at a time into shared memory.
+
+#define TILE 16
+
+extern "C" __global__ void step(float* r, const float* d, unsigned int n) {
+    __shared__ float rows[TILE][TILE];
+    __shared__ float cols[TILE][TILE];
+    unsigned int i = blockIdx.y * TILE + threadIdx.y;
+    unsigned int j = blockIdx.x * TILE + threadIdx.x;
+    float v = __int_as_float(0x7f800000);
+    for (unsigned int t = 0; t < n; t += TILE) {
+        unsigned int k_row = t + threadIdx.y;
+        unsigned int k_col = t + threadIdx.x;
+        // Pads the tiles past the edges of d, like the packed rows of the CPU versions.
+        rows[threadIdx.y][threadIdx.x] = i < n && k_col < n ? d[n * i + k_col] : __int_as_float(0x7f800000);
+        cols[threadIdx.y][threadIdx.x] = k_row < n && j < n ? d[n * k_row + j] : __int_as_float(0x7f800000);
+        __syncthreads();
+        for (int k = 0; k < TILE; ++k) {
+            v = fminf(v, rows[threadIdx.y][k] + cols[k][threadIdx.x]);
+        }
+        __syncthreads();
+    }
+    if (i < n && j < n) {
+        r[n * i + j] = v;
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+use std::panic::catch_unwind;
+use std::sync::{Arc, OnceLock};
+
+use cudarc::driver::{CudaDevice, DriverError, LaunchAsync, LaunchConfig};
+use cudarc::nvrtc::{compile_ptx, CompileError};
+
+use crate::{check_lengths, StepError};
+
+/// Side length of the block of `r` computed by one thread block, `TILE` in `cuda.cu`.
+const TILE: usize = 16;
+
+#[derive(Debug)]
+pub enum CudaError {
+    Step(StepError),
+    /// There is no CUDA device, or no driver to talk to it.
+    Unavailable,
+    /// The indices of a matrix of `n * n` elements do not fit in the 32-bit indices of `cuda.cu`.
+    TooLarge { n: usize },
+    Compile(CompileError),
+    Driver(DriverError),
+}
+
+impl fmt::Display for CudaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CudaError::Step(e) => e.fmt(f),
+            CudaError::Unavailable => write!(f, "no CUDA device available"),
+            CudaError::TooLarge { n } => write!(f, "a matrix of {} * {} elements is too large for the CUDA kernel", n, n),
+            CudaError::Compile(e) => write!(f, "compiling cuda.cu failed: {}", e),
+            CudaError::Driver(e) => write!(f, "CUDA driver error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CudaError {}
+
+impl From<StepError> for CudaError {
+    fn from(e: StepError) -> Self {
+        CudaError::Step(e)
+    }
+}
+
+impl From<DriverError> for CudaError {
+    fn from(e: DriverError) -> Self {
+        CudaError::Driver(e)
+    }
+}
+
+/// The first CUDA device with `cuda.cu` compiled and loaded, created once and reused by every `step`.
+pub struct Cuda {
+    device: Arc<CudaDevice>,
+}
+
+impl Cuda {
+    /// Compiles `cuda.cu` with NVRTC for device 0.
+    pub fn new() -> Result<Self, CudaError> {
+        // cudarc panics instead of returning an error if it cannot load the driver or NVRTC library.
+        let device = catch_unwind(|| CudaDevice::new(0))
+            .map_err(|_| CudaError::Unavailable)?
+            .map_err(|_| CudaError::Unavailable)?;
+        let ptx = catch_unwind(|| compile_ptx(include_str!("cuda.cu")))
+            .map_err(|_| CudaError::Unavailable)?
+            .map_err(CudaError::Compile)?;
+        device.load_ptx(ptx, "shortcut", &["step"])?;
+        Ok(Cuda { device })
+    }
+
+    /// Like `crate::step`, including the time to copy `d` to the device and `r` back.
+    pub fn step(&self, r: &mut [f32], d: &[f32], n: usize) -> Result<(), CudaError> {
+        check_lengths(r, d, n)?;
+        if n == 0 {
+            return Ok(());
+        }
+        if n * n > u32::MAX as usize {
+            return Err(CudaError::TooLarge { n });
+        }
+        let kernel = self.device.get_func("shortcut", "step").expect("cuda.cu was loaded in Cuda::new");
+        let d_device = self.device.htod_sync_copy(d)?;
+        let mut r_device = self.device.alloc_zeros::<f32>(n * n)?;
+        let groups = n.div_ceil(TILE) as u32;
+        let config = LaunchConfig {
+            grid_dim: (groups, groups, 1),
+            block_dim: (TILE as u32, TILE as u32, 1),
+            shared_mem_bytes: 0,
+        };
+        unsafe { kernel.launch(config, (&mut r_device, &d_device, n as u32)) }?;
+        self.device.dtoh_sync_copy_into(&r_device, r)?;
+        Ok(())
+    }
+}
+
+/// The `Cuda` created on the first call, shared by all later calls, or `None` if that failed.
+pub fn device() -> Option<&'static Cuda> {
+    static CUDA: OnceLock<Option<Cuda>> = OnceLock::new();
+    CUDA.get_or_init(|| Cuda::new().ok()).as_ref()
+}
+
+/// Runs `cuda.cu` on the shared device, from copying `d` to the device to copying `r` back.
+pub fn step(r: &mut [f32], d: &[f32], n: usize) -> Result<(), CudaError> {
+    device().ok_or(CudaError::Unavailable)?.step(r, d, n)
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::variants::{self, StepWithThreadsFn};
+use crate::{bench, check_lengths, dispatch, tune, StepContext, ThreadConfig};
+
+/// The longest request line accepted, in bytes.
+const MAX_LINE: u64 = 256;
+/// Closes connections that send nothing for this long.
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// How many sizes keep their `StepContext` between jobs, each with copies of `d` of its size.
+const CONTEXTS: usize = 4;
+
+/// Options for `serve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonOptions {
+    pub threads: ThreadConfig,
+    /// One of `variants::VARIANTS_WITH_THREADS` to run every job with, instead of the
+    /// `StepContext` of its size, which keeps the temporaries of the fastest variant between jobs.
+    pub variant: Option<String>,
+    /// The sizes to tune `v7` for and run one step of before accepting jobs, so that the first
+    /// jobs of sizes in their ranges do not wait for it, see `tune::tuning`.
+    pub warm: Vec<usize>,
+    /// The largest `n` accepted; the matrices of larger jobs are not read.
+    pub max_n: usize,
+    /// How many connections are open at the same time, each with its own thread and the part of
+    /// its matrix read so far. The next is only accepted once one of them closes.
+    pub max_connections: usize,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        DaemonOptions {
+            threads: ThreadConfig::default(),
+            variant: None,
+            warm: vec![256, 512, 1024],
+            max_n: 16384,
+            max_connections: 64,
+        }
+    }
+}
+
+/// What every job runs with, chosen once by `serve`.
+struct Warm {
+    variant: Option<StepWithThreadsFn>,
+    threads: ThreadConfig,
+    /// The most recently used last.
+    contexts: Vec<StepContext>,
+}
+
+impl Warm {
+    fn step(&mut self, r: &mut [f32], d: &[f32], n: usize) -> Result<(), String> {
+        if let Some(step) = self.variant {
+            check_lengths(r, d, n).map_err(|e| e.to_string())?;
+            step(&self.threads, r, d, n);
+            return Ok(());
+        }
+        let mut ctx = match self.contexts.iter().position(|ctx| ctx.n() == n) {
+            Some(i) => self.contexts.remove(i),
+            None => StepContext::with_threads(n, self.threads.clone()),
+        };
+        let result = ctx.step(r, d).map_err(|e| e.to_string());
+        if self.contexts.len() == CONTEXTS {
+            self.contexts.remove(0);
+        }
+        self.contexts.push(ctx);
+        result
+    }
+}
+
+/// The connections open at the same time, at most `max`, like the shares of `service`.
+struct Slots {
+    taken: Mutex<usize>,
+    freed: Condvar,
+    max: usize,
+}
+
+/// One of `Slots`, given back when dropped, also when the thread of its connection panics.
+struct Slot<'a>(&'a Slots);
+
+impl Slots {
+    /// Waits for a free slot.
+    fn take(&self) -> Slot<'_> {
+        let mut taken = self.taken.lock().unwrap();
+        while *taken >= self.max {
+            taken = self.freed.wait(taken).unwrap();
+        }
+        *taken += 1;
+        Slot(self)
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.0.taken.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Listens on the Unix socket at `path`, replacing a socket left there by a daemon that exited,
+/// but failing with `io::ErrorKind::AddrInUse` if one still answers on it, or for any other file.
+pub fn bind(path: impl AsRef<Path>) -> io::Result<UnixListener> {
+    let path = path.as_ref();
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            let is_socket = fs::symlink_metadata(path)?.file_type().is_socket();
+            if !is_socket || UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected a line ending in a newline"));
+    }
+    line.pop();
+    Ok(line)
+}
+
+/// Reads `d` into a `Vec` that grows as its bytes arrive, so that a job that sends only its size
+/// holds no more memory than it sent.
+fn read_matrix(reader: &mut impl Read, n: usize) -> io::Result<Vec<f32>> {
+    let len = n * n * std::mem::size_of::<f32>();
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("expected {} bytes, got {}", len, bytes.len())));
+    }
+    Ok(bytes.chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect())
+}
+
+fn write_matrix(writer: &mut impl Write, r: &[f32]) -> io::Result<()> {
+    r.iter().try_for_each(|x| writer.write_all(&x.to_le_bytes()))
+}
+
+/// The result of the job on `reader`, or why it failed.
+fn respond(reader: &mut impl BufRead, warm: &Mutex<Warm>, max_n: usize) -> io::Result<Result<Vec<f32>, String>> {
+    let line = read_line(reader)?;
+    let n = match line.split_once(' ') {
+        Some(("step", n)) => match n.parse::<usize>() {
+            Ok(n) if n <= max_n => n,
+            Ok(n) => return Ok(Err(format!("n = {} is more than the largest accepted, {}", n, max_n))),
+            Err(_) => return Ok(Err(format!("invalid size '{}'", n))),
+        },
+        _ => return Ok(Err(format!("expected 'step n', got '{}'", line))),
+    };
+    let d = read_matrix(reader, n)?;
+    let mut r = vec![0.0; n * n];
+    let result = warm.lock().unwrap().step(&mut r, &d, n);
+    Ok(result.map(|()| r))
+}
+
+/// Answers one job on `stream` and closes it.
+fn handle(stream: UnixStream, warm: &Mutex<Warm>, max_n: usize) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    match respond(&mut reader, warm, max_n)? {
+        Ok(r) => {
+            writer.write_all(b"ok\n")?;
+            write_matrix(&mut writer, &r)?;
+        }
+        Err(message) => writeln!(writer, "error {}", message)?,
+    }
+    writer.flush()
+}
+
+/// Selects the kernel and tunes for `options.warm`, then answers the jobs of `step` on `listener`
+/// until the process exits, each connection on its own thread, at most `options.max_connections`
+/// of them, and one job at a time with all threads. This leaves only connecting and sending the
+/// matrices to each job, instead of the detection, tuning and first allocations that a process of
+/// its own starts with; only the threads of each step are still started anew, which takes
+/// microseconds.
+/// A job is the line `step n` and the `n * n` elements of `d` as little-endian `f32`s; the answer
+/// is the line `ok` and those of `r`, or `error` and the reason on one line.
+pub fn serve(listener: &UnixListener, options: &DaemonOptions) -> io::Result<()> {
+    let variant = match &options.variant {
+        Some(name) => Some(variants::by_name_with_threads(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("unknown variant '{}'", name))
+        })?),
+        None => None,
+    };
+    dispatch::selected();
+    let mut warm = Warm { variant, threads: options.threads.clone(), contexts: Vec::new() };
+    for &n in &options.warm {
+        tune::tuning(n);
+        let (d, mut r) = bench::bench_inputs(n);
+        warm.step(&mut r, &d, n).map_err(io::Error::other)?;
+    }
+    let warm = Mutex::new(warm);
+    let slots = Slots { taken: Mutex::new(0), freed: Condvar::new(), max: options.max_connections.max(1) };
+    thread::scope(|s| loop {
+        let slot = slots.take();
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            // Such as too many open files, which closing other connections resolves.
+            Err(e) => {
+                eprintln!("error: accepting a connection failed: {}", e);
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        };
+        let warm = &warm;
+        s.spawn(move || {
+            let _slot = slot;
+            if let Err(e) = handle(stream, warm, options.max_n) {
+                eprintln!("error: {}", e);
+            }
+        });
+    })
+}
+
+/// Sends the step of `d` to the daemon at the Unix socket `path` and writes its answer to `r`.
+/// The reasons that the daemon gives for failing are errors of kind `io::ErrorKind::Other`.
+pub fn step(path: impl AsRef<Path>, r: &mut [f32], d: &[f32], n: usize) -> io::Result<()> {
+    check_lengths(r, d, n).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let stream = UnixStream::connect(path)?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    writeln!(writer, "step {}", n)?;
+    write_matrix(&mut writer, d)?;
+    writer.flush()?;
+    let mut reader = BufReader::new(stream);
+    let line = read_line(&mut reader)?;
+    match line.split_once(' ') {
+        _ if line == "ok" => {
+            r.copy_from_slice(&read_matrix(&mut reader, n)?);
+            Ok(())
+        }
+        Some(("error", message)) => Err(io::Error::other(message)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected answer '{}'", line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn reads_matrices_only_as_far_as_they_arrive() {
+        let d = [1.0f32, 2.0, 3.0, 4.0];
+        let bytes: Vec<u8> = d.iter().flat_map(|x| x.to_le_bytes()).collect();
+        assert_eq!(read_matrix(&mut &bytes[..], 2).unwrap(), d);
+        assert_eq!(read_matrix(&mut &bytes[..15], 2).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        // The job of a large size that then stops sending fails without allocating all of it.
+        let warm = Mutex::new(Warm { variant: None, threads: ThreadConfig::default(), contexts: Vec::new() });
+        let result = respond(&mut &b"step 16384\n\0\0\0\0"[..], &warm, 16384);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert!(respond(&mut &b"step 16385\n"[..], &warm, 16384).unwrap().is_err());
+    }
+
+    #[test]
+    fn slots_limit_the_open_connections() {
+        let slots = Slots { taken: Mutex::new(0), freed: Condvar::new(), max: 2 };
+        let (open, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|s| {
+            for _ in 0..8 {
+                let slot = slots.take();
+                s.spawn(|| {
+                    let _slot = slot;
+                    most.fetch_max(open.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(*slots.taken.lock().unwrap(), 0);
+    }
+}
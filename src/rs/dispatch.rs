@@ -0,0 +1,518 @@
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(all(feature = "std", target_arch = "aarch64"))]
+use std::arch::is_aarch64_feature_detected;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+use crate::simd::{self, AlignedVec, Buffer, Packed, Strided, PARANOID};
+use crate::threads::ThreadConfig;
+use crate::StepError;
+
+/// Without `std` there is no runtime detection, so only the features enabled at compile time count.
+#[cfg(all(not(feature = "std"), target_arch = "x86_64"))]
+macro_rules! is_x86_feature_detected {
+    ($feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+#[cfg(all(not(feature = "std"), target_arch = "aarch64"))]
+macro_rules! is_aarch64_feature_detected {
+    ($feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kernel {
+    Scalar,
+    Sse,
+    Avx2,
+    Avx512,
+    Neon,
+    Simd128,
+    Rvv,
+}
+
+impl Kernel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kernel::Scalar => "scalar",
+            Kernel::Sse => "sse",
+            Kernel::Avx2 => "avx2",
+            Kernel::Avx512 => "avx512",
+            Kernel::Neon => "neon",
+            Kernel::Simd128 => "simd128",
+            Kernel::Rvv => "rvv",
+        }
+    }
+
+    /// Only `Scalar` is with `simd::PARANOID`.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Kernel::Scalar => true,
+            _ if PARANOID => false,
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse => is_x86_feature_detected!("sse"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => is_aarch64_feature_detected!("neon"),
+            // WebAssembly has no runtime detection, the module either validates with SIMD or not at all.
+            #[cfg(target_arch = "wasm32")]
+            Kernel::Simd128 => cfg!(target_feature = "simd128"),
+            #[cfg(all(feature = "rvv", target_arch = "riscv64"))]
+            Kernel::Rvv => simd::rvv::is_detected(),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// The `Tail` of the kernels that take no `StepOptions::tail`: `Mask` for `Avx512`, and for
+    /// `Rvv`, which sets the vector length of the last vector to the rest of the row either way.
+    pub fn default_tail(self) -> Tail {
+        match self {
+            Kernel::Avx512 | Kernel::Rvv => Tail::Mask,
+            _ => Tail::Pad,
+        }
+    }
+
+    /// What the rows are padded to a multiple of with `tail`.
+    pub(crate) fn lanes_with(self, tail: Tail) -> usize {
+        match (tail, self) {
+            (Tail::Mask, _) | (_, Kernel::Scalar | Kernel::Rvv) => 1,
+            (Tail::Pad, Kernel::Sse | Kernel::Neon | Kernel::Simd128) => 4,
+            (Tail::Pad, Kernel::Avx2) => 8,
+            (Tail::Pad, Kernel::Avx512) => 16,
+        }
+    }
+
+    /// `lanes_with` the `default_tail`.
+    pub(crate) fn lanes(self) -> usize {
+        self.lanes_with(self.default_tail())
+    }
+
+    /// The `Tail` that `step` runs this kernel with for size `n`, see `tune::tail`.
+    #[cfg(feature = "std")]
+    pub(crate) fn tail(self, n: usize) -> Tail {
+        crate::tune::tail(self, n)
+    }
+
+    /// Without `std` nothing is tuned.
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn tail(self, _n: usize) -> Tail {
+        self.default_tail()
+    }
+
+    /// Runs this kernel on all cores, or panics if the CPU does not support it.
+    pub fn step(self, r: &mut [f32], d: &[f32], n: usize) {
+        self.step_with_threads(&ThreadConfig::default(), r, d, n)
+    }
+
+    pub fn step_with_threads(self, threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+        self.step_strided(threads, r, n, d, n, n)
+    }
+
+    /// Like `step_with_threads`, but row `i` of `r` and `d` starts at `ld_r * i` and `ld_d * i`.
+    pub fn step_strided(self, threads: &ThreadConfig, r: &mut [f32], ld_r: usize, d: &[f32], ld_d: usize, n: usize) {
+        self.step_strided_with_tail(threads, r, ld_r, d, ld_d, n, self.tail(n))
+    }
+
+    /// Like `step_strided`, handling the ends of the rows with `tail` instead of the one tuned
+    /// for `n`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn step_strided_with_tail(
+        self,
+        threads: &ThreadConfig,
+        r: &mut [f32],
+        ld_r: usize,
+        d: &[f32],
+        ld_d: usize,
+        n: usize,
+        tail: Tail,
+    ) {
+        assert!(self.is_supported(), "kernel {} is not supported on this CPU", self.name());
+        self.run(threads, r, ld_r, &Packed::square(d, ld_d, n, self.lanes_with(tail)), false)
+    }
+
+    pub(crate) fn run(self, threads: &ThreadConfig, r: &mut [f32], ld_r: usize, packed: &Packed, inf_aware: bool) {
+        match self {
+            Kernel::Scalar => simd::step_scalar(threads, r, ld_r, packed, inf_aware),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse => unsafe { simd::x86::step_sse(threads, r, ld_r, packed, inf_aware) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { simd::x86::step_avx2(threads, r, ld_r, packed, inf_aware) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => unsafe { simd::x86::step_avx512(threads, r, ld_r, packed, inf_aware) },
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => unsafe { simd::neon::step_neon(threads, r, ld_r, packed, inf_aware) },
+            #[cfg(target_arch = "wasm32")]
+            Kernel::Simd128 => unsafe { simd::wasm::step_simd128(threads, r, ld_r, packed, inf_aware) },
+            #[cfg(all(feature = "rvv", target_arch = "riscv64"))]
+            Kernel::Rvv => unsafe { simd::rvv::step_rvv(threads, r, ld_r, packed, inf_aware) },
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        }
+    }
+
+    /// Like `run`, but one block of rows at a time, with `hooks` in between.
+    /// Each block is at least one row per thread, so that all threads have work.
+    pub(crate) fn run_in_blocks(
+        self,
+        threads: &ThreadConfig,
+        r: &mut [f32],
+        ld_r: usize,
+        packed: &Packed,
+        inf_aware: bool,
+        hooks: Hooks,
+    ) -> Result<(), StepError> {
+        if hooks.is_empty() {
+            self.run(threads, r, ld_r, packed, inf_aware);
+            return Ok(());
+        }
+        let m = packed.m;
+        let block_rows = m.div_ceil(BLOCKS).max(threads.effective_threads());
+        for start in (0..m).step_by(block_rows) {
+            if hooks.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                return Err(StepError::Cancelled);
+            }
+            let end = m.min(start + block_rows);
+            self.run(threads, &mut r[ld_r*start..], ld_r, &packed.block(start..end, 0..packed.n), inf_aware);
+            if let Some(progress) = hooks.progress {
+                progress(end as f32 / m as f32);
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `step_with_threads`, ignoring the NaN sums of `f32::INFINITY` and `-f32::INFINITY` if
+    /// `inf_aware`, running `hooks` between blocks of rows, and handling the ends of the rows with `tail`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn step_with_hooks(
+        self,
+        threads: &ThreadConfig,
+        r: &mut [f32],
+        d: &[f32],
+        n: usize,
+        inf_aware: bool,
+        hooks: Hooks,
+        tail: Tail,
+    ) -> Result<(), StepError> {
+        self.run_in_blocks(threads, r, n, &Packed::square(d, n, n, self.lanes_with(tail)), inf_aware, hooks)
+    }
+}
+
+/// How the kernels handle the ends of rows whose length is not a multiple of their vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Tail {
+    /// Packs the rows padded with `f32::INFINITY` to a whole number of vectors, as in the book.
+    /// Just above a multiple of the vectors that is almost a vector of padding per row, which
+    /// costs both memory and work.
+    Pad,
+    /// Packs the rows as they are and ends each with a partial vector, masked with AVX-512, or the
+    /// last `LANES` elements overlapping the vector before, see `simd::step_row`. That costs an
+    /// unaligned load or a mask per row instead.
+    Mask,
+}
+
+/// How many blocks `run_in_blocks` splits the rows into at most.
+const BLOCKS: usize = 100;
+
+/// What `Kernel::run_in_blocks` does between blocks of rows, on the calling thread.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Hooks<'a> {
+    /// Called after each block with the fraction of rows done.
+    pub(crate) progress: Option<&'a (dyn Fn(f32) + Sync)>,
+    /// Checked before each block, stopping with `StepError::Cancelled` once it is set.
+    pub(crate) cancel: Option<&'a AtomicBool>,
+}
+
+impl Hooks<'_> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.progress.is_none() && self.cancel.is_none()
+    }
+}
+
+/// Fastest kernel supported by the CPU, preferring the widest vectors.
+pub fn detect() -> Kernel {
+    [Kernel::Avx512, Kernel::Avx2, Kernel::Sse, Kernel::Neon, Kernel::Rvv, Kernel::Simd128]
+        .iter()
+        .copied()
+        .find(|kernel| kernel.is_supported())
+        .unwrap_or(Kernel::Scalar)
+}
+
+/// The kernel chosen by `detect` on the first call, cached for all later calls.
+#[cfg(feature = "std")]
+pub fn selected() -> Kernel {
+    static SELECTED: OnceLock<Kernel> = OnceLock::new();
+    *SELECTED.get_or_init(detect)
+}
+
+/// Without `std`, `detect` only checks the features enabled at compile time, which is cheaper
+/// than caching its result.
+#[cfg(not(feature = "std"))]
+pub fn selected() -> Kernel {
+    detect()
+}
+
+pub(crate) fn step(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    selected().step_with_threads(threads, r, d, n)
+}
+
+/// Rows of `r` that `step_changed` computes at a time before comparing them with `d`, few enough
+/// that they are still in the L2 cache.
+const CHANGED_ROWS: usize = 8;
+
+/// `step`, also returning whether any element of `r` is less than the same element of `d`. Each
+/// block of `CHANGED_ROWS` rows is compared right after the kernel computes it, on the thread that
+/// did, with a flag of its own, instead of reading all of `r` and `d` again after the step.
+pub(crate) fn step_changed(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) -> bool {
+    let kernel = selected();
+    let packed = Packed::square(d, n, n, kernel.lanes_with(kernel.tail(n)));
+    let one = ThreadConfig::with_threads(1);
+    let decreased: Vec<AtomicBool> = (0..n.div_ceil(CHANGED_ROWS)).map(|_| AtomicBool::new(false)).collect();
+    crate::threads::for_each_chunk(threads, &mut r[..n * n], (n * CHANGED_ROWS).max(1), |b, block| {
+        let rows = CHANGED_ROWS*b..n.min(CHANGED_ROWS*(b + 1));
+        kernel.run(&one, block, n, &packed.block(rows.clone(), 0..n), false);
+        decreased[b].store(block.iter().zip(&d[n*rows.start..n*rows.end]).any(|(x, y)| x < y), Ordering::Relaxed);
+    });
+    decreased.iter().any(|flag| flag.load(Ordering::Relaxed))
+}
+
+pub(crate) fn step_strided(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, d: &[f32], ld_d: usize, n: usize) {
+    selected().step_strided(threads, r, ld_r, d, ld_d, n)
+}
+
+/// `Kernel::step_with_hooks` with the selected kernel, for the C ABI.
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub(crate) fn step_with_hooks(
+    threads: &ThreadConfig,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    inf_aware: bool,
+    hooks: Hooks,
+) -> Result<(), StepError> {
+    let kernel = selected();
+    kernel.step_with_hooks(threads, r, d, n, inf_aware, hooks, kernel.tail(n))
+}
+
+/// Rows per thread of the blocks of rows that `step_in_place` copies before overwriting them.
+const IN_PLACE_ROWS: usize = 64;
+
+/// Like `step` with `r` and `d` the same matrix, packing the columns of `d` up front and each block
+/// of rows of `d` right before it is overwritten, which is safe since other blocks only read columns.
+/// The packed columns are a temporary of `n * n` elements, padded to whole vectors, and no step in
+/// place can keep less, not even with panels of columns per thread: every block of results reads
+/// all rows of `d` as the `d[k][j]` of its sums, so no element of `d` may be overwritten before
+/// the last block is computed. Only the temporary for the results is saved.
+pub(crate) fn step_in_place(threads: &ThreadConfig, d: &mut [f32], n: usize) {
+    let kernel = selected();
+    let all = Strided { data: d, ld: n };
+    let cols = Packed::new(all, all, 0, n, n, kernel.lanes());
+    let block_rows = IN_PLACE_ROWS * threads.effective_threads();
+    for start in (0..n).step_by(block_rows) {
+        let end = n.min(start + block_rows);
+        let block = Strided { data: &d[n*start..], ld: n };
+        let rows = Packed::new(block, block, end - start, n, 0, kernel.lanes()).rows;
+        let packed = Packed { rows, cols: Buffer::Borrowed(&cols.cols), m: end - start, n, width: cols.width };
+        kernel.run(threads, &mut d[n*start..], n, &packed, false);
+    }
+}
+
+/// Like `Kernel::step_with_hooks`, with copies of at most `budget` bytes: the rows of `d` are packed
+/// in panels of as many rows as fit in half of it, and for each of them the columns in panels of as
+/// many columns, repacked for each panel of rows, which costs a copy of `d` per panel of rows.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn step_in_panels(
+    kernel: Kernel,
+    threads: &ThreadConfig,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    inf_aware: bool,
+    hooks: Hooks,
+    tail: Tail,
+    budget: usize,
+) -> Result<(), StepError> {
+    let lanes = kernel.lanes_with(tail);
+    let needed = simd::packed_bytes(1, n, 1, lanes);
+    if budget < needed {
+        return Err(StepError::MemoryBudget { needed, budget });
+    }
+    let tile = (budget / needed).clamp(1, n.max(1));
+    let all = Strided { data: d, ld: n };
+    for rows in (0..n).step_by(tile).map(|start| start..n.min(start + tile)) {
+        if hooks.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(StepError::Cancelled);
+        }
+        let block = Strided { data: &d[n*rows.start..], ld: n };
+        let packed_rows = Packed::new(block, block, rows.len(), n, 0, lanes);
+        for cols in (0..n).step_by(tile).map(|start| start..n.min(start + tile)) {
+            let panel = Strided { data: &d[cols.start..], ld: n };
+            let packed_cols = Packed::new(all, panel, 0, n, cols.len(), lanes).cols;
+            let (m, width) = (rows.len(), packed_rows.width);
+            let packed = Packed { rows: Buffer::Borrowed(&packed_rows.rows), cols: packed_cols, m, n: cols.len(), width };
+            kernel.run(threads, &mut r[n*rows.start + cols.start..], n, &packed, inf_aware);
+        }
+        if let Some(progress) = hooks.progress {
+            progress(rows.end as f32 / n as f32);
+        }
+    }
+    Ok(())
+}
+
+/// How many blocks of rows `step_symmetric` splits `r` into at most. Each block also computes the
+/// part of its diagonal block below the diagonal, about `1 / (2 * SYMMETRIC_BLOCKS)` of the work.
+const SYMMETRIC_BLOCKS: usize = 32;
+
+/// Like `step` for a symmetric `d`, computing each block of rows of `r` only from its diagonal
+/// block onwards and mirroring the upper triangle into the lower one.
+pub(crate) fn step_symmetric(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    let kernel = selected();
+    // The columns of a symmetric `d` are its rows, so they are packed only once.
+    let d = Strided { data: d, ld: n };
+    let rows = Packed::new(d, d, n, n, 0, kernel.lanes());
+    let packed = Packed { rows: Buffer::Borrowed(&rows.rows), cols: Buffer::Borrowed(&rows.rows), m: n, n, width: rows.width };
+    let block_rows = n.div_ceil(SYMMETRIC_BLOCKS).max(threads.effective_threads());
+    for start in (0..n).step_by(block_rows) {
+        let end = n.min(start + block_rows);
+        kernel.run(threads, &mut r[n*start + start..], n, &packed.block(start..end, start..n), false);
+    }
+    const TILE: usize = 64;
+    for i0 in (0..n).step_by(TILE) {
+        for j0 in (0..=i0).step_by(TILE) {
+            for i in i0..n.min(i0 + TILE) {
+                for j in j0..i.min(j0 + TILE) {
+                    r[n*i + j] = r[n*j + i];
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn minplus_gemm(threads: &ThreadConfig, r: &mut [f32], a: &[f32], b: &[f32], m: usize, k: usize, n: usize) {
+    let kernel = selected();
+    let a = Strided { data: a, ld: k };
+    let b = Strided { data: b, ld: n };
+    kernel.run(threads, r, n, &Packed::new(a, b, m, k, n, kernel.lanes()), false)
+}
+
+/// Like `step` for only the rows `rows` of `r`, written to consecutive rows of `r`. The columns of
+/// `d` are packed as for `step`, and only the selected rows besides them.
+pub(crate) fn step_rows(threads: &ThreadConfig, r: &mut [f32], d: &[f32], rows: &[u32], n: usize) {
+    let kernel = selected();
+    let all = Strided { data: d, ld: n };
+    let cols = Packed::new(all, all, 0, n, n, kernel.lanes());
+    let width = cols.width;
+    let mut packed_rows = AlignedVec::filled(rows.len() * width, f32::INFINITY);
+    for (row, &i) in packed_rows.chunks_mut(width).zip(rows) {
+        row[..n].copy_from_slice(&d[n * i as usize..n * i as usize + n]);
+    }
+    let packed = Packed { rows: packed_rows.into(), cols: cols.cols, m: rows.len(), n, width };
+    kernel.run(threads, r, n, &packed, false)
+}
+
+/// All kernels that the CPU supports, for the tests of each.
+#[cfg(test)]
+pub(crate) fn supported_kernels() -> impl Iterator<Item = Kernel> {
+    [Kernel::Scalar, Kernel::Sse, Kernel::Avx2, Kernel::Avx512, Kernel::Neon, Kernel::Simd128, Kernel::Rvv]
+        .into_iter()
+        .filter(|kernel| kernel.is_supported())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sizes around the 4, 8 and 16 lanes of the kernels, so that every tail is partial.
+    const SIZES: [usize; 9] = [1, 2, 7, 8, 15, 17, 31, 33, 64];
+
+    /// A graph of two components without edges between them besides a vertex `0` that no edge
+    /// reaches or leaves, so that its row and column are all `f32::INFINITY`, and an edge of
+    /// `-f32::INFINITY` from the last vertex to itself for the sums that are NaN.
+    fn disconnected(n: usize) -> Vec<f32> {
+        let half = n / 2;
+        let mut d = vec![f32::INFINITY; n * n];
+        for i in 1..n {
+            for j in 1..n {
+                if (i < half) == (j < half) {
+                    d[n*i + j] = ((7 * i + 13 * j) % 10) as f32;
+                }
+            }
+        }
+        if n > 1 {
+            d[n*n - 1] = -f32::INFINITY;
+        }
+        d
+    }
+
+    /// `step` without the sums that are NaN, as with `inf_aware`.
+    fn step_ignoring_nan(d: &[f32], n: usize) -> Vec<f32> {
+        let mut r = vec![0.0; n * n];
+        for i in 0..n {
+            for j in 0..n {
+                let sums = (0..n).map(|k| d[n*i + k] + d[n*k + j]).filter(|z| !z.is_nan());
+                r[n*i + j] = sums.fold(f32::INFINITY, f32::min);
+            }
+        }
+        r
+    }
+
+    #[test]
+    fn inf_aware_disconnected_graphs_have_no_nan() {
+        for kernel in supported_kernels() {
+            for tail in [Tail::Pad, Tail::Mask] {
+                for n in SIZES {
+                    let d = disconnected(n);
+                    let mut r = vec![0.0; n * n];
+                    kernel.step_with_hooks(&ThreadConfig::default(), &mut r, &d, n, true, Hooks::default(), tail).unwrap();
+                    assert!(r.iter().all(|x| !x.is_nan()), "{} {:?} n = {}", kernel.name(), tail, n);
+                    assert_eq!(r, step_ignoring_nan(&d, n), "{} {:?} n = {}", kernel.name(), tail, n);
+                    assert!(r[..n].iter().all(|&x| x == f32::INFINITY), "{} {:?} n = {}", kernel.name(), tail, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn avx512_matches_scalar_bit_for_bit() {
+        if !Kernel::Avx512.is_supported() {
+            return;
+        }
+        for tail in [Tail::Pad, Tail::Mask] {
+            for n in [1, 15, 16, 17, 31, 32, 33, 100] {
+                for (input, d) in crate::reference::inputs(n) {
+                    let mut expected = vec![0.0; n * n];
+                    let mut r = vec![0.0; n * n];
+                    let threads = ThreadConfig::default();
+                    Kernel::Scalar.step_strided_with_tail(&threads, &mut expected, n, &d, n, n, tail);
+                    Kernel::Avx512.step_strided_with_tail(&threads, &mut r, n, &d, n, n, tail);
+                    let bits = |r: &[f32]| r.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+                    assert_eq!(bits(&r), bits(&expected), "{:?} n = {} {}", tail, n, input);
+                }
+            }
+        }
+    }
+
+    /// With blocks of rows of one and several threads, and sizes of one and several blocks.
+    #[test]
+    fn step_in_place_matches_step() {
+        for threads in [1, 3] {
+            let threads = ThreadConfig::with_threads(threads);
+            for n in [0, 1, 17, IN_PLACE_ROWS, 3 * IN_PLACE_ROWS + 5] {
+                let mut d = crate::bench::random_input(n);
+                let mut expected = vec![0.0; n * n];
+                step(&threads, &mut expected, &d, n);
+                step_in_place(&threads, &mut d, n);
+                assert_eq!(d, expected, "n = {} threads = {:?}", n, threads.num_threads);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+/// The energy used during one run of a variant, from the RAPL counters of Linux.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Energy {
+    /// Of all CPU packages, which includes what their idle cores and other processes use.
+    pub joules: f64,
+    pub seconds: f64,
+}
+
+impl Energy {
+    pub fn watts(&self) -> f64 {
+        self.joules / self.seconds
+    }
+}
+
+/// Reads the energy counters of all CPU packages before and after `f` runs, or returns `None` if
+/// there are none or they cannot be read, as by anyone but root since Linux 5.10. The counters are
+/// updated about every millisecond, so runs should be much longer than that, and `None` is returned
+/// too if they did not change at all.
+pub fn measure<F: FnOnce()>(f: F) -> Option<Energy> {
+    imp::measure(f)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Instant;
+
+    use super::Energy;
+
+    fn read_u64(path: &Path) -> Option<u64> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    /// The powercap zones of the packages, such as `intel-rapl:0`, which AMD CPUs have too. Their
+    /// subzones, such as `intel-rapl:0:0` for the cores, are already part of them.
+    fn packages() -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir("/sys/class/powercap") else { return Vec::new() };
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                name.starts_with("intel-rapl:") && name.matches(':').count() == 1
+            })
+            .collect()
+    }
+
+    fn read_all(packages: &[PathBuf]) -> Option<Vec<u64>> {
+        packages.iter().map(|package| read_u64(&package.join("energy_uj"))).collect()
+    }
+
+    pub(super) fn measure<F: FnOnce()>(f: F) -> Option<Energy> {
+        let packages = packages();
+        if packages.is_empty() {
+            return None;
+        }
+        let ranges = packages
+            .iter()
+            .map(|package| read_u64(&package.join("max_energy_range_uj")))
+            .collect::<Option<Vec<_>>>()?;
+        let before = read_all(&packages)?;
+        let start = Instant::now();
+        f();
+        let seconds = start.elapsed().as_secs_f64();
+        let after = read_all(&packages)?;
+        // A counter wraps around to zero after its range, assumed to happen at most once per run.
+        let microjoules: u64 = (0..packages.len())
+            .map(|p| if after[p] >= before[p] { after[p] - before[p] } else { after[p] + ranges[p] - before[p] })
+            .sum();
+        if microjoules == 0 {
+            return None;
+        }
+        Some(Energy { joules: microjoules as f64 / 1e6, seconds })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::Energy;
+
+    pub(super) fn measure<F: FnOnce()>(f: F) -> Option<Energy> {
+        f();
+        None
+    }
+}
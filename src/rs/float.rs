@@ -0,0 +1,29 @@
+use std::ops::Add;
+
+pub trait Float: Copy + Add<Output = Self> + PartialOrd + Send + Sync {
+    const ZERO: Self;
+    const INFINITY: Self;
+    const NEG_INFINITY: Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+macro_rules! impl_float {
+    ($($t:ty),*) => {
+        $(
+            impl Float for $t {
+                const ZERO: Self = 0.0;
+                const INFINITY: Self = <$t>::INFINITY;
+                const NEG_INFINITY: Self = <$t>::NEG_INFINITY;
+                fn min(self, other: Self) -> Self {
+                    <$t>::min(self, other)
+                }
+                fn max(self, other: Self) -> Self {
+                    <$t>::max(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
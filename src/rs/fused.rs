@@ -0,0 +1,220 @@
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BinaryHeap, vec, vec::Vec};
+use std::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::collections::BinaryHeap;
+use std::ops::Range;
+
+use crate::simd::{AlignedVec, Packed};
+use crate::{dispatch, layout};
+use crate::threads::{for_each_chunk, ThreadConfig};
+
+/// Rows of `r` each task reduces, and columns of them computed at a time, so that the results of
+/// the kernel for a task take 32 KiB however large `n` is. `COLS` is a multiple of 64, so that the
+/// blocks of `step_within` write whole words of the mask.
+pub(crate) const ROWS: usize = 8;
+const COLS: usize = 1024;
+
+/// Rows per thread of the panels of the first step of `step2`, transposed while they are still in
+/// the L2 cache.
+const PANEL_ROWS: usize = 32;
+
+/// `d` packed for the selected kernel, which computes `ROWS * COLS` blocks of its step for the
+/// functions below to reduce while the block is still in the L1 cache.
+pub(crate) struct ResultBlocks {
+    kernel: dispatch::Kernel,
+    packed: Packed<'static>,
+    n: usize,
+}
+
+impl ResultBlocks {
+    pub(crate) fn new(d: &[f32], n: usize) -> Self {
+        let kernel = dispatch::selected();
+        ResultBlocks { kernel, packed: Packed::square(d, n, n, kernel.lanes()), n }
+    }
+
+    /// Calls `f` with each range of columns and the block of the results of `rows` and those
+    /// columns, row by row, on the calling thread. `rows` must be at most `ROWS` rows.
+    pub(crate) fn for_each(&self, rows: Range<usize>, mut f: impl FnMut(Range<usize>, &[f32])) {
+        let single = ThreadConfig::with_threads(1);
+        let mut block = vec![0.0; ROWS * COLS];
+        for start in (0..self.n).step_by(COLS) {
+            let cols = start..self.n.min(start + COLS);
+            let block = &mut block[..rows.len() * cols.len()];
+            self.kernel.run(&single, block, cols.len(), &self.packed.block(rows.clone(), cols.clone()), false);
+            f(cols, block);
+        }
+    }
+}
+
+/// A distance and its column, ordered by distance and then by column, with NaN after all others.
+#[derive(Clone, Copy)]
+struct Candidate(f32, u32);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// Adds `candidate` to the `k` smallest in `heap`, whose top is the largest of them.
+fn push(heap: &mut BinaryHeap<Candidate>, k: usize, candidate: Candidate) {
+    if heap.len() < k {
+        heap.push(candidate);
+    } else if heap.peek().is_some_and(|top| candidate < *top) {
+        *heap.peek_mut().unwrap() = candidate;
+    }
+}
+
+/// The `k` smallest elements of each row of the step of `d`, in increasing order, into the rows of
+/// `k` elements of `indices` and `values`, taken by the heaps of the rows from each block.
+pub(crate) fn step_topk(threads: &ThreadConfig, indices: &mut [u32], values: &mut [f32], k: usize, d: &[f32], n: usize) {
+    if k == 0 {
+        return;
+    }
+    let blocks = ResultBlocks::new(d, n);
+    let mut rows: Vec<(&mut [u32], &mut [f32])> = indices.chunks_mut(k).zip(values.chunks_mut(k)).collect();
+    for_each_chunk(threads, &mut rows, ROWS, |b, rows| {
+        let mut heaps = vec![BinaryHeap::with_capacity(k + 1); rows.len()];
+        blocks.for_each(ROWS * b..ROWS * b + rows.len(), |cols, block| {
+            for (heap, results) in heaps.iter_mut().zip(block.chunks(cols.len())) {
+                for (j, &x) in cols.clone().zip(results) {
+                    push(heap, k, Candidate(x, j as u32));
+                }
+            }
+        });
+        for ((indices, values), heap) in rows.iter_mut().zip(heaps) {
+            for ((index, value), Candidate(x, j)) in indices.iter_mut().zip(values.iter_mut()).zip(heap.into_sorted_vec()) {
+                (*index, *value) = (j, x);
+            }
+        }
+    });
+}
+
+/// Sets bit `j % 64` of word `j / 64` of row `i` of `mask`, of `n.div_ceil(64)` words, to whether
+/// element `(i, j)` of the step of `d` is at most `radius`, one word of each block at a time.
+pub(crate) fn step_within(threads: &ThreadConfig, mask: &mut [u64], d: &[f32], n: usize, radius: f32) {
+    let words = n.div_ceil(64);
+    let blocks = ResultBlocks::new(d, n);
+    for_each_chunk(threads, mask, ROWS * words, |b, mask_rows| {
+        blocks.for_each(ROWS * b..ROWS * b + mask_rows.len() / words, |cols, block| {
+            for (mask_row, results) in mask_rows.chunks_mut(words).zip(block.chunks(cols.len())) {
+                for (word, results) in mask_row[cols.start / 64..].iter_mut().zip(results.chunks(64)) {
+                    *word = results.iter().enumerate().fold(0, |word, (bit, &x)| word | ((x <= radius) as u64) << bit);
+                }
+            }
+        });
+    });
+}
+
+/// The step of the step of `d`, the shortest paths of at most four edges, into `r`. Instead of
+/// writing the first step to a matrix and packing it for the second as `step` twice does, the
+/// kernel writes each panel of its rows straight into the padded rows of the second, which are
+/// transposed into its columns while the panel is still in the cache.
+pub(crate) fn step2(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    let kernel = dispatch::selected();
+    let first = Packed::square(d, n, n, kernel.lanes());
+    let width = first.width;
+    let mut rows = AlignedVec::filled(n * width, f32::INFINITY);
+    let mut cols = AlignedVec::filled(n * width, f32::INFINITY);
+    let panel_rows = PANEL_ROWS * threads.effective_threads();
+    for start in (0..n).step_by(panel_rows) {
+        let end = n.min(start + panel_rows);
+        let panel = &mut rows[width*start..width*end];
+        kernel.run(threads, panel, width, &first.block(start..end, 0..n), false);
+        layout::transpose_into(&mut cols[start..], width, panel, width, end - start, n);
+    }
+    drop(first);
+    let second = Packed { rows: rows.into(), cols: cols.into(), m: n, n, width };
+    kernel.run(threads, r, n, &second, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sizes just below and past multiples of 64, whose blocks of `COLS` columns end in partial words.
+    const SIZES: [usize; 6] = [1, 3, 63, 65, 130, 201];
+
+    /// Random elements, many of them equal so that the rows of the step have ties, and rows of infinity.
+    fn input(n: usize) -> Vec<f32> {
+        let mut d: Vec<f32> = crate::bench::random_input(n).into_iter().map(|x| (x * 4.0).floor()).collect();
+        for i in (0..n).step_by(5) {
+            d[n*i..n*(i + 1)].fill(f32::INFINITY);
+        }
+        d
+    }
+
+    fn full_step(d: &[f32], n: usize) -> Vec<f32> {
+        let mut r = vec![0.0; n * n];
+        crate::step(&mut r, d, n).unwrap();
+        r
+    }
+
+    #[test]
+    fn step_topk_is_the_start_of_the_sorted_rows() {
+        for n in SIZES {
+            let d = input(n);
+            let r = full_step(&d, n);
+            let sorted: Vec<Vec<Candidate>> = r
+                .chunks(n)
+                .map(|row| {
+                    let mut row: Vec<_> = row.iter().enumerate().map(|(j, &x)| Candidate(x, j as u32)).collect();
+                    row.sort();
+                    row
+                })
+                .collect();
+            for k in [0, 1, 2, 70, n].into_iter().filter(|&k| k <= n) {
+                for threads in [ThreadConfig::default(), ThreadConfig::with_threads(3)] {
+                    let (mut indices, mut values) = (vec![u32::MAX; n * k], vec![f32::NAN; n * k]);
+                    step_topk(&threads, &mut indices, &mut values, k, &d, n);
+                    for (i, row) in sorted.iter().enumerate() {
+                        let expected: (Vec<_>, Vec<_>) = row[..k].iter().map(|&Candidate(x, j)| (j, x)).unzip();
+                        let actual = (indices[k*i..k*(i + 1)].to_vec(), values[k*i..k*(i + 1)].to_vec());
+                        assert_eq!(actual, expected, "n = {} k = {} row {}", n, k, i);
+                    }
+                }
+            }
+            let (mut indices, mut values) = (vec![0; n * n], vec![0.0; n * n]);
+            assert!(crate::step_topk(&mut indices, &mut values, n, &d, n).is_ok());
+            assert!(crate::step_topk(&mut indices, &mut values, n + 1, &d, n).is_err());
+        }
+    }
+
+    #[test]
+    fn step_within_is_the_thresholded_rows() {
+        for n in SIZES {
+            let d = input(n);
+            let r = full_step(&d, n);
+            let words = n.div_ceil(64);
+            for radius in [-1.0, 0.0, 1.0, 2.5, f32::INFINITY, f32::NAN] {
+                let mut expected = vec![0u64; n * words];
+                for (i, row) in r.chunks(n).enumerate() {
+                    for (j, &x) in row.iter().enumerate() {
+                        expected[words*i + j / 64] |= ((x <= radius) as u64) << (j % 64);
+                    }
+                }
+                for threads in [ThreadConfig::default(), ThreadConfig::with_threads(3)] {
+                    // The bits past column `n` must be cleared.
+                    let mut mask = vec![u64::MAX; n * words];
+                    step_within(&threads, &mut mask, &d, n, radius);
+                    assert_eq!(mask, expected, "n = {} radius = {}", n, radius);
+                }
+            }
+        }
+    }
+}
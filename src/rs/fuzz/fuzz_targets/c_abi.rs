@@ -0,0 +1,60 @@
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+extern "C" {
+    fn step(r_raw: *mut f32, d_raw: *const f32, n: usize) -> i32;
+    fn step_checked(r_raw: *mut f32, d_raw: *const f32, n: usize, check_negative: bool) -> i32;
+    fn step_with_threads(r_raw: *mut f32, d_raw: *const f32, n: usize, num_threads: usize) -> i32;
+    fn step_i32(r_raw: *mut i32, d_raw: *const i32, n: usize) -> i32;
+    fn step_u16(r_raw: *mut u16, d_raw: *const u16, n: usize) -> i32;
+    fn apsp(d_raw: *mut f32, n: usize) -> i32;
+    fn step_ctx_new(n: usize) -> *mut std::ffi::c_void;
+    fn step_ctx_run(ctx: *mut std::ffi::c_void, r_raw: *mut f32, d_raw: *const f32) -> i32;
+    fn step_ctx_free(ctx: *mut std::ffi::c_void);
+}
+
+const STEP_OK: i32 = 0;
+const STEP_PANICKED: i32 = 2;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    /// Any `n`, including zero and huge values, with buffers of only `len * len` elements.
+    n: usize,
+    len: u8,
+    num_threads: usize,
+    check_negative: bool,
+    data: Vec<f32>,
+}
+
+fuzz_target!(|input: Input| {
+    let len = (input.len % 32) as usize;
+    let mut d: Vec<f32> = input.data.iter().copied().cycle().take(len * len).collect();
+    d.resize(len * len, 0.0);
+    let mut r = vec![0.0f32; len * len];
+    // Only pass an `n` that disagrees with the buffers when the size check alone has to reject it,
+    // the C API can only check that pointers are non-null and aligned, not how much they point to.
+    let fits = input.n.checked_mul(input.n).is_some_and(|count| count <= isize::MAX as usize / 4);
+    let n = if fits { len } else { input.n };
+    let statuses = unsafe {
+        let di: Vec<i32> = d.iter().map(|&x| x.to_bits() as i32).collect();
+        let du: Vec<u16> = d.iter().map(|&x| x.to_bits() as u16).collect();
+        let (mut ri, mut ru) = (vec![0i32; len * len], vec![0u16; len * len]);
+        let mut a = d.clone();
+        let ctx = step_ctx_new(n);
+        let ctx_status = if ctx.is_null() { STEP_OK } else { step_ctx_run(ctx, r.as_mut_ptr(), d.as_ptr()) };
+        step_ctx_free(ctx);
+        [
+            step(r.as_mut_ptr(), d.as_ptr(), n),
+            step_checked(r.as_mut_ptr(), d.as_ptr(), n, input.check_negative),
+            step_with_threads(r.as_mut_ptr(), d.as_ptr(), n, input.num_threads),
+            step_i32(ri.as_mut_ptr(), di.as_ptr(), n),
+            step_u16(ru.as_mut_ptr(), du.as_ptr(), n),
+            apsp(a.as_mut_ptr(), n),
+            ctx_status,
+        ]
+    };
+    // Invalid sizes must be rejected with a status, never by panicking across the FFI boundary.
+    assert!(statuses.iter().all(|&status| status != STEP_PANICKED), "{:?} for n = {}", statuses, n);
+});
@@ -0,0 +1,142 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/// Kinds of inputs for benchmarks, each reproducible from a seed with `generate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Generator {
+    /// Every element uniformly distributed in `[0, 1)`, like `bench::random_input`.
+    Uniform,
+    /// An undirected random geometric graph: vertices at uniformly random points of the unit
+    /// square, with an edge of the length of their distance between any two less than a radius
+    /// apart, chosen such that a vertex has about `2 ln n` neighbours.
+    Geometric,
+    /// An undirected Chung-Lu graph with degrees following a power law of exponent 2.5 and an
+    /// average degree of 8, so that a few vertices are connected to most others. The degrees decrease
+    /// with the vertex number, and the edge weights are uniformly distributed in `[0, 1)`.
+    PowerLaw,
+    /// A directed graph with edges from each vertex to the 16 vertices on either side of it, of
+    /// weights uniformly distributed in `[0, 1)`.
+    Banded,
+    /// `Uniform`, with `n` rounded up to a multiple of 1024, so that consecutive rows are a
+    /// multiple of 4 KiB apart and the elements of a column all map to the same few sets of the
+    /// caches. The padded copies of the kernels are then as wide as `n` too.
+    CacheAntagonistic,
+}
+
+pub const GENERATORS: [(&str, Generator); 5] = [
+    ("uniform", Generator::Uniform),
+    ("geometric", Generator::Geometric),
+    ("power-law", Generator::PowerLaw),
+    ("banded", Generator::Banded),
+    ("cache-antagonistic", Generator::CacheAntagonistic),
+];
+
+impl FromStr for Generator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let names: Vec<_> = GENERATORS.iter().map(|&(name, _)| name).collect();
+        GENERATORS
+            .iter()
+            .find(|&&(name, _)| name == s)
+            .map(|&(_, generator)| generator)
+            .ok_or_else(|| format!("unknown generator '{}', expected one of {}", s, names.join(", ")))
+    }
+}
+
+impl Generator {
+    /// The name of this generator in `GENERATORS`.
+    pub fn name(self) -> &'static str {
+        GENERATORS.iter().find(|&&(_, generator)| generator == self).map_or("", |&(name, _)| name)
+    }
+
+    /// The size of the matrices of this generator for a requested size `n`.
+    pub fn size(self, n: usize) -> usize {
+        match self {
+            Generator::CacheAntagonistic => n.div_ceil(1024) * 1024,
+            _ => n,
+        }
+    }
+}
+
+/// A splitmix64 generator, which unlike xorshift gives well mixed outputs for small seeds too.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// The matrix of `generator` for `seed`, and its size, `generator.size(n)`. Graphs have zeros on
+/// the diagonal and infinities where there is no edge.
+pub fn generate(generator: Generator, n: usize, seed: u64) -> (usize, Vec<f32>) {
+    let mut rng = Rng(seed);
+    let n = generator.size(n);
+    let d = match generator {
+        Generator::Uniform | Generator::CacheAntagonistic => (0..n * n).map(|_| rng.next_f32()).collect(),
+        Generator::Geometric => geometric(&mut rng, n),
+        Generator::PowerLaw => power_law(&mut rng, n),
+        Generator::Banded => banded(&mut rng, n),
+    };
+    (n, d)
+}
+
+/// An `n * n` matrix with zeros on the diagonal and `edge(i, j)` above it, mirrored below it.
+fn symmetric(n: usize, mut edge: impl FnMut(usize, usize) -> f32) -> Vec<f32> {
+    let mut d = vec![0.0; n * n];
+    for i in 0..n {
+        for j in i + 1..n {
+            d[n*i + j] = edge(i, j);
+            d[n*j + i] = d[n*i + j];
+        }
+    }
+    d
+}
+
+fn geometric(rng: &mut Rng, n: usize) -> Vec<f32> {
+    let points: Vec<(f32, f32)> = (0..n).map(|_| (rng.next_f32(), rng.next_f32())).collect();
+    let radius = (2.0 * (n as f32).ln().max(1.0) / (PI * n as f32)).sqrt();
+    symmetric(n, |i, j| {
+        let distance = (points[i].0 - points[j].0).hypot(points[i].1 - points[j].1);
+        if distance < radius { distance } else { f32::INFINITY }
+    })
+}
+
+fn power_law(rng: &mut Rng, n: usize) -> Vec<f32> {
+    const EXPONENT: f32 = 2.5;
+    const AVERAGE_DEGREE: f32 = 8.0;
+    // The expected degree of vertex `i`, whose mean over all `i` is about `AVERAGE_DEGREE`.
+    let degrees: Vec<f32> = (0..n)
+        .map(|i| {
+            let scale = AVERAGE_DEGREE * (EXPONENT - 2.0) / (EXPONENT - 1.0);
+            scale * (n as f32 / (i + 1) as f32).powf(1.0 / (EXPONENT - 1.0))
+        })
+        .collect();
+    let total: f32 = degrees.iter().sum();
+    symmetric(n, |i, j| {
+        let (connected, weight) = (rng.next_f32() < degrees[i] * degrees[j] / total, rng.next_f32());
+        if connected { weight } else { f32::INFINITY }
+    })
+}
+
+fn banded(rng: &mut Rng, n: usize) -> Vec<f32> {
+    const WIDTH: usize = 16;
+    let mut d = vec![f32::INFINITY; n * n];
+    for i in 0..n {
+        for j in i.saturating_sub(WIDTH)..(i + WIDTH + 1).min(n) {
+            d[n*i + j] = if i == j { 0.0 } else { rng.next_f32() };
+        }
+    }
+    d
+}
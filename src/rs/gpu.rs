@@ -0,0 +1,299 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+
+use crate::{check_lengths, StepError};
+
+/// Side length of the block of `r` computed by one workgroup, `TILE` in `gpu.wgsl`.
+const TILE: usize = 16;
+
+/// Each device splits its rows of `r` into at least this many chunks when they fit in fewer, so that
+/// reading back one chunk overlaps uploading and computing the next.
+const CHUNKS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuError {
+    Step(StepError),
+    /// No adapter was found, or it refused to create a device.
+    Unavailable,
+    /// `StepOptions::devices` names an adapter past the `count` of `adapters`.
+    UnknownDevice { index: usize, count: usize },
+    /// Not even one row of `d` fits in one storage buffer of the device.
+    TooLarge { n: usize },
+    Readback(wgpu::BufferAsyncError),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpuError::Step(e) => e.fmt(f),
+            GpuError::Unavailable => write!(f, "no GPU device available"),
+            GpuError::UnknownDevice { index, count } => {
+                write!(f, "there is no GPU device {}, only {}", index, count)
+            }
+            GpuError::TooLarge { n } => write!(f, "a row of {} elements does not fit in a GPU buffer", n),
+            GpuError::Readback(e) => write!(f, "reading back r from the GPU failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+impl From<StepError> for GpuError {
+    fn from(e: StepError) -> Self {
+        GpuError::Step(e)
+    }
+}
+
+/// The devices that `step_with_options` splits `r` across.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepOptions {
+    /// Indices into `adapters`, each computing one panel of consecutive rows of `r`; an index may
+    /// appear more than once, for more panels on that device. Empty for the shared `device` alone.
+    pub devices: Vec<usize>,
+}
+
+/// The buffers of one chunk of `Gpu::step_rows` in flight.
+struct Slot {
+    params: wgpu::Buffer,
+    a: wgpu::Buffer,
+    r: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// A device and the compiled `gpu.wgsl` pipeline, created once and reused by every `step`.
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Gpu {
+    /// Opens the default high performance adapter, or returns `None` if there is none.
+    pub fn new() -> Option<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))?;
+        Gpu::open(&adapter)
+    }
+
+    /// Creates a device on `adapter`, or returns `None` if it refuses.
+    pub fn open(adapter: &wgpu::Adapter) -> Option<Self> {
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("shortcut"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None)).ok()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("step"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("step"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Some(Gpu { device, queue, pipeline })
+    }
+
+    /// Like `crate::step`, including the time to upload `d` and read back `r`.
+    pub fn step(&self, r: &mut [f32], d: &[f32], n: usize) -> Result<(), GpuError> {
+        check_lengths(r, d, n)?;
+        self.step_rows(r, d, n, 0..n)
+    }
+
+    fn buffer(&self, label: &str, floats: usize, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (floats * std::mem::size_of::<f32>()) as u64,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// The rows `rows` of `r` into `r_rows`, in chunks of rows and columns small enough for the
+    /// buffers of the device: with `d` in row panels `a` and column panels `b`, only one row of `d`
+    /// has to fit in a buffer. Two chunks are in flight at a time, so that reading back the first
+    /// overlaps uploading and computing the second.
+    fn step_rows(&self, r_rows: &mut [f32], d: &[f32], n: usize, rows: Range<usize>) -> Result<(), GpuError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let limits = self.device.limits();
+        let max_floats = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size) as usize / 4;
+        let max_groups = limits.max_compute_workgroups_per_dimension as usize * TILE;
+        let max_lines = (max_floats / n).min(max_groups);
+        if n > u32::MAX as usize || max_lines == 0 {
+            return Err(GpuError::TooLarge { n });
+        }
+        // All columns at once if `d` fits, then `b` is `d` and uploaded only once.
+        let chunk_cols = n.min(max_lines);
+        let chunk_rows = rows.len().div_ceil(CHUNKS).next_multiple_of(TILE).min(max_lines);
+
+        let b = self.buffer("b", n * chunk_cols, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let slots: Vec<Slot> = (0..2)
+            .map(|_| {
+                let params = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("params"),
+                    size: 16,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let a = self.buffer("a", chunk_rows * n, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+                let r = self.buffer(
+                    "r",
+                    chunk_rows * chunk_cols,
+                    wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                );
+                let readback = self.buffer(
+                    "readback",
+                    chunk_rows * chunk_cols,
+                    wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                );
+                let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("step"),
+                    layout: &self.pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                        wgpu::BindGroupEntry { binding: 1, resource: a.as_entire_binding() },
+                        wgpu::BindGroupEntry { binding: 2, resource: b.as_entire_binding() },
+                        wgpu::BindGroupEntry { binding: 3, resource: r.as_entire_binding() },
+                    ],
+                });
+                Slot { params, a, r, readback, bind_group }
+            })
+            .collect();
+
+        let mut pending = None;
+        let mut chunk = 0;
+        for j in (0..n).step_by(chunk_cols) {
+            let j = j..n.min(j + chunk_cols);
+            let b_bytes: Vec<u8> = (0..n).flat_map(|k| &d[n * k..][j.clone()]).flat_map(|x| x.to_ne_bytes()).collect();
+            // Queued writes happen after the work already submitted, so this does not race with
+            // the chunk of the previous columns that is still in flight.
+            self.queue.write_buffer(&b, 0, &b_bytes);
+            for i in rows.clone().step_by(chunk_rows) {
+                let i = i..rows.end.min(i + chunk_rows);
+                let slot = &slots[chunk % 2];
+                chunk += 1;
+                let params: Vec<u8> = [n, i.len(), j.len(), 0].iter().flat_map(|&x| (x as u32).to_ne_bytes()).collect();
+                self.queue.write_buffer(&slot.params, 0, &params);
+                let a_bytes: Vec<u8> = d[n * i.start..n * i.end].iter().flat_map(|x| x.to_ne_bytes()).collect();
+                self.queue.write_buffer(&slot.a, 0, &a_bytes);
+
+                let size = (i.len() * j.len() * std::mem::size_of::<f32>()) as u64;
+                let descriptor = wgpu::CommandEncoderDescriptor { label: Some("step") };
+                let mut encoder = self.device.create_command_encoder(&descriptor);
+                {
+                    let descriptor = wgpu::ComputePassDescriptor { label: Some("step"), timestamp_writes: None };
+                    let mut pass = encoder.begin_compute_pass(&descriptor);
+                    pass.set_pipeline(&self.pipeline);
+                    pass.set_bind_group(0, &slot.bind_group, &[]);
+                    pass.dispatch_workgroups(j.len().div_ceil(TILE) as u32, i.len().div_ceil(TILE) as u32, 1);
+                }
+                encoder.copy_buffer_to_buffer(&slot.r, 0, &slot.readback, 0, size);
+                let submission = self.queue.submit(Some(encoder.finish()));
+                if let Some((slot, i, j, submission)) = pending.replace((slot, i, j.clone(), submission)) {
+                    self.read_back(slot, submission, r_rows, n, i.start - rows.start..i.end - rows.start, j)?;
+                }
+            }
+        }
+        if let Some((slot, i, j, submission)) = pending {
+            self.read_back(slot, submission, r_rows, n, i.start - rows.start..i.end - rows.start, j)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the chunk of `slot` and copies it to the rows `i` and columns `j` of `r_rows`.
+    fn read_back(
+        &self,
+        slot: &Slot,
+        submission: wgpu::SubmissionIndex,
+        r_rows: &mut [f32],
+        n: usize,
+        i: Range<usize>,
+        j: Range<usize>,
+    ) -> Result<(), GpuError> {
+        let slice = slot.readback.slice(..(i.len() * j.len() * std::mem::size_of::<f32>()) as u64);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| sender.send(result).unwrap());
+        self.device.poll(wgpu::Maintain::wait_for(submission));
+        receiver.recv().expect("map_async callback was dropped").map_err(GpuError::Readback)?;
+        {
+            let mapped = slice.get_mapped_range();
+            for (row, bytes) in i.zip(mapped.chunks_exact(4 * j.len())) {
+                for (x, bytes) in r_rows[n * row..][j.clone()].iter_mut().zip(bytes.chunks_exact(4)) {
+                    *x = f32::from_ne_bytes(bytes.try_into().unwrap());
+                }
+            }
+        }
+        slot.readback.unmap();
+        Ok(())
+    }
+}
+
+/// The `Gpu` opened on the first call, shared by all later calls.
+pub fn device() -> Option<&'static Gpu> {
+    static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+    GPU.get_or_init(Gpu::new).as_ref()
+}
+
+/// The adapters of all backends, each with the `Gpu` opened on it by the first `step_with_options`
+/// that uses it.
+fn opened() -> &'static [(wgpu::Adapter, OnceLock<Option<Gpu>>)] {
+    static ADAPTERS: OnceLock<Vec<(wgpu::Adapter, OnceLock<Option<Gpu>>)>> = OnceLock::new();
+    ADAPTERS.get_or_init(|| {
+        let instance = wgpu::Instance::default();
+        instance.enumerate_adapters(wgpu::Backends::all()).into_iter().map(|adapter| (adapter, OnceLock::new())).collect()
+    })
+}
+
+/// The adapters that the indices of `StepOptions::devices` refer to, in order. One GPU may appear
+/// once for each backend that drives it, such as Vulkan and OpenGL.
+pub fn adapters() -> Vec<wgpu::AdapterInfo> {
+    opened().iter().map(|(adapter, _)| adapter.get_info()).collect()
+}
+
+/// Runs `gpu.wgsl` on the shared device, from uploading `d` to reading back `r`.
+pub fn step(r: &mut [f32], d: &[f32], n: usize) -> Result<(), GpuError> {
+    device().ok_or(GpuError::Unavailable)?.step(r, d, n)
+}
+
+/// Like `step`, with `r` split into one panel of consecutive rows for each of `options.devices`,
+/// each uploaded, computed and read back by its own thread.
+pub fn step_with_options(r: &mut [f32], d: &[f32], n: usize, options: &StepOptions) -> Result<(), GpuError> {
+    check_lengths(r, d, n)?;
+    if options.devices.is_empty() {
+        return step(r, d, n);
+    }
+    let adapters = opened();
+    let gpus = options
+        .devices
+        .iter()
+        .map(|&index| {
+            let (adapter, gpu) = adapters.get(index).ok_or(GpuError::UnknownDevice { index, count: adapters.len() })?;
+            gpu.get_or_init(|| Gpu::open(adapter)).as_ref().ok_or(GpuError::Unavailable)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if n == 0 {
+        return Ok(());
+    }
+    let band = n.div_ceil(gpus.len());
+    thread::scope(|s| {
+        let panels: Vec<_> = r
+            .chunks_mut(band * n)
+            .zip(gpus)
+            .enumerate()
+            .map(|(p, (r_panel, gpu))| s.spawn(move || gpu.step_rows(r_panel, d, n, band * p..n.min(band * (p + 1)))))
+            .collect();
+        panels.into_iter().try_for_each(|panel| panel.join().unwrap())
+    })
+}
@@ -0,0 +1,51 @@
+// One chunk of r = d * d in the min-plus semiring, one invocation per element of the chunk.
+// The chunk has `rows` rows and `cols` columns of r: a holds the same rows of d, and b the same
+// columns of d, `cols` floats for each of the n rows. They are all of d for the whole of r.
+// Each workgroup computes a TILE * TILE block of the chunk, loading one tile of the rows and one
+// tile of the columns it needs at a time into workgroup memory.
+
+const TILE: u32 = 16u;
+
+struct Params {
+    n: u32,
+    rows: u32,
+    cols: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> a: array<f32>;
+@group(0) @binding(2) var<storage, read> b: array<f32>;
+@group(0) @binding(3) var<storage, read_write> r: array<f32>;
+
+var<workgroup> rows: array<array<f32, TILE>, TILE>;
+var<workgroup> cols: array<array<f32, TILE>, TILE>;
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) global: vec3<u32>, @builtin(local_invocation_id) local: vec3<u32>) {
+    let n = params.n;
+    let i = global.y;
+    let j = global.x;
+    // Pads the tiles past the edges of d, like the packed rows of the CPU versions.
+    let inf = bitcast<f32>(0x7f800000u);
+    var v = inf;
+    for (var t = 0u; t < n; t += TILE) {
+        let k_row = t + local.y;
+        let k_col = t + local.x;
+        rows[local.y][local.x] = inf;
+        cols[local.y][local.x] = inf;
+        if (i < params.rows && k_col < n) {
+            rows[local.y][local.x] = a[n * i + k_col];
+        }
+        if (k_row < n && j < params.cols) {
+            cols[local.y][local.x] = b[params.cols * k_row + j];
+        }
+        workgroupBarrier();
+        for (var k = 0u; k < TILE; k++) {
+            v = min(v, rows[local.y][k] + cols[k][local.x]);
+        }
+        workgroupBarrier();
+    }
+    if (i < params.rows && j < params.cols) {
+        r[params.cols * i + j] = v;
+    }
+}
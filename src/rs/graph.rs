@@ -0,0 +1,68 @@
+use std::io::{self, BufRead};
+
+pub type Edge = (u32, u32, f32);
+
+/// How `from_edge_list_with_options` turns edges into a distance matrix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GraphOptions {
+    /// Adds every edge in both directions.
+    pub undirected: bool,
+    /// Sets the distance of every vertex to itself to zero, so that `step` keeps the paths it
+    /// already found, instead of leaving it infinite unless there is a loop.
+    pub zero_diagonal: bool,
+}
+
+/// The `n * n` distance matrix of a directed graph with vertices `0..n`, `edges[e].2` from
+/// `edges[e].0` to `edges[e].1`, infinite where there is no edge. Of parallel edges the shortest
+/// is kept.
+///
+/// Panics if an edge has a vertex outside `0..n`.
+pub fn from_edge_list(edges: &[Edge], n: usize) -> Vec<f32> {
+    from_edge_list_with_options(edges, n, &GraphOptions::default())
+}
+
+/// `from_edge_list` with the choices in `options`.
+pub fn from_edge_list_with_options(edges: &[Edge], n: usize, options: &GraphOptions) -> Vec<f32> {
+    let mut d = vec![f32::INFINITY; n * n];
+    if options.zero_diagonal {
+        d.iter_mut().step_by(n + 1).for_each(|x| *x = 0.0);
+    }
+    for &(from, to, weight) in edges {
+        let (from, to) = (from as usize, to as usize);
+        assert!(from < n && to < n, "edge ({}, {}) out of bounds for n = {}", from, to, n);
+        d[n*from + to] = d[n*from + to].min(weight);
+        if options.undirected {
+            d[n*to + from] = d[n*to + from].min(weight);
+        }
+    }
+    d
+}
+
+/// The number of vertices of a graph whose vertices are numbered from zero up to the largest
+/// vertex in `edges`.
+pub fn vertex_count(edges: &[Edge]) -> usize {
+    edges.iter().map(|&(from, to, _)| from.max(to) as usize + 1).max().unwrap_or(0)
+}
+
+/// Reads one edge per line, the two vertices and optionally the weight, which is 1 if missing,
+/// separated by whitespace or commas. Empty lines and lines starting with `#` or `%` are skipped.
+pub fn read_edge_list(reader: impl BufRead) -> io::Result<Vec<Edge>> {
+    let mut edges = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('%') {
+            continue;
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid edge '{}'", line));
+        let mut fields = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|field| !field.is_empty());
+        let from = fields.next().and_then(|field| field.parse().ok()).ok_or_else(invalid)?;
+        let to = fields.next().and_then(|field| field.parse().ok()).ok_or_else(invalid)?;
+        let weight = match fields.next() {
+            Some(field) => field.parse().map_err(|_| invalid())?,
+            None => 1.0,
+        };
+        edges.push((from, to, weight));
+    }
+    Ok(edges)
+}
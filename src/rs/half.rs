@@ -0,0 +1,245 @@
+use crate::simd::{load_at, Vector};
+use crate::threads::ThreadConfig;
+
+/// An IEEE 754 half-precision float, stored as its bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct F16(pub u16);
+
+impl F16 {
+    pub const INFINITY: F16 = F16(0x7c00);
+
+    pub fn to_f32(self) -> f32 {
+        let (sign, exp, mant) = (self.0 & 0x8000, (self.0 >> 10) & 0x1f, (self.0 & 0x3ff) as u32);
+        if exp == 0 {
+            // Zero or subnormal, `mant * 2^-24` is exact in an `f32`.
+            let x = mant as f32 / (1 << 24) as f32;
+            return if sign != 0 { -x } else { x };
+        }
+        if exp == 0x1f {
+            // Infinity, or a NaN made quiet like by F16C.
+            let quiet = if mant != 0 { 0x0040_0000 } else { 0 };
+            return f32::from_bits(((sign as u32) << 16) | 0x7f80_0000 | quiet | (mant << 13));
+        }
+        f32::from_bits(((sign as u32) << 16) | ((exp as u32 + 127 - 15) << 23) | (mant << 13))
+    }
+
+    /// Rounds to the nearest `F16`, ties to even, with overflows to infinity. NaNs keep the upper
+    /// bits of their payload and are made quiet, like the conversions of F16C.
+    pub fn from_f32(x: f32) -> F16 {
+        let bits = x.to_bits();
+        let (sign, abs) = ((bits >> 16) as u16 & 0x8000, bits & 0x7fff_ffff);
+        if abs > 0x7f80_0000 {
+            return F16(sign | 0x7e00 | (abs >> 13) as u16 & 0x3ff);
+        }
+        // 65520 is halfway between the largest `F16`, 65504, and the next power of two.
+        if abs >= 0x477f_f000 {
+            return F16(sign | 0x7c00);
+        }
+        // Below the smallest normal `F16`, 2^-14, the result is a multiple of 2^-24.
+        if abs < 0x3880_0000 {
+            return F16(sign | (f32::from_bits(abs) * (1 << 24) as f32).round_ties_even() as u16);
+        }
+        let half = (((abs >> 23) - 127 + 15) << 10) | ((abs >> 13) & 0x3ff);
+        let rest = abs & 0x1fff;
+        // A carry out of the mantissa correctly rounds up to the next exponent.
+        let round_up = rest > 0x1000 || (rest == 0x1000 && half & 1 == 1);
+        F16(sign | (half + round_up as u32) as u16)
+    }
+}
+
+/// A bfloat16, the upper half of the bits of an `f32`, with its range but 8 bits of precision.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Bf16(pub u16);
+
+impl Bf16 {
+    pub const INFINITY: Bf16 = Bf16(0x7f80);
+
+    pub fn to_f32(self) -> f32 {
+        f32::from_bits((self.0 as u32) << 16)
+    }
+
+    /// Rounds to the nearest `Bf16`, ties to even, with overflows to infinity. NaNs keep the upper
+    /// bits of their payload and are made quiet.
+    pub fn from_f32(x: f32) -> Bf16 {
+        let bits = x.to_bits();
+        if x.is_nan() {
+            return Bf16((bits >> 16) as u16 | 0x40);
+        }
+        Bf16(((bits + 0x7fff + ((bits >> 16) & 1)) >> 16) as u16)
+    }
+}
+
+/// 16-bit floats, which are added and compared as `f32`.
+pub(crate) trait Half: Copy + Send + Sync {
+    const INFINITY: Self;
+    fn to_f32(self) -> f32;
+    fn from_f32(x: f32) -> Self;
+}
+
+macro_rules! impl_half {
+    ($($t:ty),*) => {
+        $(
+            impl Half for $t {
+                const INFINITY: Self = <$t>::INFINITY;
+                #[inline(always)]
+                fn to_f32(self) -> f32 {
+                    <$t>::to_f32(self)
+                }
+                #[inline(always)]
+                fn from_f32(x: f32) -> Self {
+                    <$t>::from_f32(x)
+                }
+            }
+        )*
+    };
+}
+
+impl_half!(F16, Bf16);
+
+/// A `simd::Vector` of `f32` that can be loaded from `LANES` consecutive `H`.
+pub(crate) trait LoadHalf<H: Half>: Vector {
+    unsafe fn load_half(p: *const H) -> Self;
+}
+
+impl<H: Half> LoadHalf<H> for [f32; 4] {
+    unsafe fn load_half(p: *const H) -> Self {
+        [(*p).to_f32(), (*p.add(1)).to_f32(), (*p.add(2)).to_f32(), (*p.add(3)).to_f32()]
+    }
+}
+
+/// Like `integer::pad_and_transpose`, padding with `H::INFINITY`. The rows stay 16-bit, so that
+/// reading them takes half the memory traffic of `f32`.
+pub(crate) fn pad_and_transpose<H: Half>(d: &[H], n: usize, lanes: usize) -> (Vec<H>, Vec<H>, usize) {
+    let width = n.div_ceil(lanes) * lanes;
+    let mut vd = vec![H::INFINITY; n * width];
+    let mut vt = vec![H::INFINITY; n * width];
+    for i in 0..n {
+        for k in 0..n {
+            vd[width*i + k] = d[n*i + k];
+            vt[width*i + k] = d[n*k + i];
+        }
+    }
+    (vd, vt, width)
+}
+
+/// Like `simd::step_row`, into a row of `f32` results that is rounded to `H` afterwards.
+#[inline(always)]
+pub(crate) unsafe fn step_row<V: LoadHalf<H>, H: Half>(row: &mut [f32], vd_row: &[H], vt: &[H], width: usize) {
+    for (res, vt_row) in row.iter_mut().zip(vt.chunks(width)) {
+        let mut v = V::splat(f32::INFINITY);
+        for k in (0..width).step_by(V::LANES) {
+            let x = V::load_half(load_at(vd_row, k, V::LANES));
+            let y = V::load_half(load_at(vt_row, k, V::LANES));
+            v = V::min(v, V::add(x, y));
+        }
+        *res = V::horizontal_min(v);
+    }
+}
+
+/// Rounds each of the results in `row` to the nearest `H`.
+#[inline(always)]
+pub(crate) fn store<H: Half>(r_row: &mut [H], row: &[f32]) {
+    for (res, &x) in r_row.iter_mut().zip(row) {
+        *res = H::from_f32(x);
+    }
+}
+
+/// Like `simd::step_lanes`, a macro to keep the `#[target_feature]`s of the caller in the closure,
+/// with `$store` rounding each row of results.
+macro_rules! step_lanes {
+    ($V:ty, $store:path, $threads:expr, $r:expr, $d:expr, $n:expr) => {{
+        let n: usize = $n;
+        let (vd, vt, width) = $crate::half::pad_and_transpose($d, n, <$V as $crate::simd::Vector>::LANES);
+        $crate::threads::for_each_chunk($threads, $r, n, |i, r_row| unsafe {
+            let mut row = vec![0.0; n];
+            $crate::half::step_row::<$V, _>(&mut row, &vd[width*i..width*(i + 1)], &vt, width);
+            $store(r_row, &row)
+        })
+    }};
+}
+
+pub(crate) fn step_f16(threads: &ThreadConfig, r: &mut [F16], d: &[F16], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") && is_x86_feature_detected!("f16c") {
+        return unsafe { x86::step_f16_f16c(threads, r, d, n) };
+    }
+    step_lanes!([f32; 4], store, threads, r, d, n)
+}
+
+pub(crate) fn step_bf16(threads: &ThreadConfig, r: &mut [Bf16], d: &[Bf16], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx512bf16") && is_x86_feature_detected!("avx512vl") {
+        return unsafe { x86::step_bf16_avx512(threads, r, d, n) };
+    }
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+        return unsafe { x86::step_bf16_avx2(threads, r, d, n) };
+    }
+    step_lanes!([f32; 4], store, threads, r, d, n)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{store, Bf16, LoadHalf, F16};
+    use crate::threads::ThreadConfig;
+
+    impl LoadHalf<F16> for __m256 {
+        #[inline(always)]
+        unsafe fn load_half(p: *const F16) -> Self {
+            _mm256_cvtph_ps(_mm_loadu_si128(p as *const __m128i))
+        }
+    }
+
+    /// A `Bf16` is the upper half of an `f32`, so it is converted with a shift.
+    impl LoadHalf<Bf16> for __m256 {
+        #[inline(always)]
+        unsafe fn load_half(p: *const Bf16) -> Self {
+            let x = _mm256_cvtepu16_epi32(_mm_loadu_si128(p as *const __m128i));
+            _mm256_castsi256_ps(_mm256_slli_epi32(x, 16))
+        }
+    }
+
+    /// Like `super::store`, 8 results at a time.
+    #[inline(always)]
+    unsafe fn store_f16c(r_row: &mut [F16], row: &[f32]) {
+        let split = r_row.len() / 8 * 8;
+        for (res, x) in r_row[..split].chunks_exact_mut(8).zip(row.chunks_exact(8)) {
+            let h = _mm256_cvtps_ph::<_MM_FROUND_TO_NEAREST_INT>(_mm256_loadu_ps(x.as_ptr()));
+            _mm_storeu_si128(res.as_mut_ptr() as *mut __m128i, h);
+        }
+        store(&mut r_row[split..], &row[split..]);
+    }
+
+    /// Like `super::store`, 8 results at a time, except that AVX-512 BF16 flushes results below
+    /// `f32::MIN_POSITIVE` to zero.
+    #[inline(always)]
+    unsafe fn store_bf16_avx512(r_row: &mut [Bf16], row: &[f32]) {
+        let split = r_row.len() / 8 * 8;
+        for (res, x) in r_row[..split].chunks_exact_mut(8).zip(row.chunks_exact(8)) {
+            let h = _mm256_cvtneps_pbh(_mm256_loadu_ps(x.as_ptr()));
+            _mm_storeu_si128(res.as_mut_ptr() as *mut __m128i, std::mem::transmute::<__m128bh, __m128i>(h));
+        }
+        for (res, &x) in r_row[split..].iter_mut().zip(&row[split..]) {
+            *res = Bf16::from_f32(if x.abs() < f32::MIN_POSITIVE { 0.0f32.copysign(x) } else { x });
+        }
+    }
+
+    #[target_feature(enable = "avx2,f16c")]
+    pub(crate) unsafe fn step_f16_f16c(threads: &ThreadConfig, r: &mut [F16], d: &[F16], n: usize) {
+        step_lanes!(__m256, store_f16c, threads, r, d, n)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn step_bf16_avx2(threads: &ThreadConfig, r: &mut [Bf16], d: &[Bf16], n: usize) {
+        step_lanes!(__m256, store, threads, r, d, n)
+    }
+
+    #[target_feature(enable = "avx2,avx512bf16,avx512vl")]
+    pub(crate) unsafe fn step_bf16_avx512(threads: &ThreadConfig, r: &mut [Bf16], d: &[Bf16], n: usize) {
+        step_lanes!(__m256, store_bf16_avx512, threads, r, d, n)
+    }
+}
@@ -0,0 +1,98 @@
+/* Generated by shortcut gen-harness c from shortcut @VERSION@. Build and run it with
+ *     cc -O2 -o harness harness.c -L target/release -lshortcut
+ *     LD_LIBRARY_PATH=target/release ./harness [n] [repetitions] [variant]
+ * to time the step of the n * n input of shortcut-bench, 4000 by default, with the variant, by
+ * default auto for the fastest on this CPU, and check elements of the result against a loop.
+ */
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+int32_t step_variant(float* r_raw, const float* d_raw, size_t n, const char* variant);
+const char* step_strerror(int32_t code);
+const char* shortcut_version(void);
+const char* shortcut_best_variant(void);
+
+/* The input of shortcut::bench::random_input, uniformly distributed in [0, 1). */
+static void random_input(float* d, size_t n) {
+    uint64_t state = 0x9e3779b97f4a7c15u;
+    for (size_t i = 0; i < n * n; ++i) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        d[i] = (float)(state >> 40) / (float)(1 << 24);
+    }
+}
+
+static double now(void) {
+    struct timespec t;
+    timespec_get(&t, TIME_UTC);
+    return (double)t.tv_sec + 1e-9 * (double)t.tv_nsec;
+}
+
+/* Every variant rounds the same sums, so the elements must be equal. Checks a 16 * 16 grid of
+ * them, since all would take as long as v0. */
+static int verify(const float* r, const float* d, size_t n) {
+    for (size_t a = 0; a < 16; ++a) {
+        for (size_t b = 0; b < 16; ++b) {
+            size_t i = a * n / 16, j = b * n / 16;
+            float v = INFINITY;
+            for (size_t k = 0; k < n; ++k) {
+                float x = d[n*i + k] + d[n*k + j];
+                v = x < v ? x : v;
+            }
+            if (r[n*i + j] != v) {
+                fprintf(stderr, "error: r[%zu][%zu] is %g instead of %g\n", i, j, r[n*i + j], v);
+                return 0;
+            }
+        }
+    }
+    return 1;
+}
+
+int main(int argc, char** argv) {
+    size_t n = argc > 1 ? strtoull(argv[1], NULL, 10) : 4000;
+    int repetitions = argc > 2 ? atoi(argv[2]) : 5;
+    const char* variant = argc > 3 ? argv[3] : "auto";
+    if (n == 0 || repetitions < 1 || argc > 4) {
+        fprintf(stderr, "usage: %s [n] [repetitions] [variant]\n", argv[0]);
+        return 1;
+    }
+    if (strcmp(shortcut_version(), "@VERSION@") != 0) {
+        fprintf(stderr, "warning: the library is shortcut %s, not @VERSION@\n", shortcut_version());
+    }
+    if (strcmp(variant, "auto") == 0 && shortcut_best_variant() != NULL) {
+        variant = shortcut_best_variant();
+    }
+    float* d = malloc(n * n * sizeof(float));
+    float* r = malloc(n * n * sizeof(float));
+    if (d == NULL || r == NULL) {
+        fprintf(stderr, "error: out of memory\n");
+        return 1;
+    }
+    random_input(d, n);
+    double fastest = INFINITY;
+    for (int i = 0; i < repetitions; ++i) {
+        double start = now();
+        int32_t code = step_variant(r, d, n, variant);
+        if (code != 0) {
+            fprintf(stderr, "error: %s\n", step_strerror(code));
+            return 1;
+        }
+        double seconds = now() - start;
+        fastest = seconds < fastest ? seconds : fastest;
+    }
+    double gflops = 2.0 * (double)n * (double)n * (double)n / fastest / 1e9;
+    printf("shortcut %s, %s, n = %zu: %.6f s, %.2f GFLOPS, the fastest of %d runs\n",
+           shortcut_version(), variant, n, fastest, gflops, repetitions);
+    if (!verify(r, d, n)) {
+        return 1;
+    }
+    printf("verified against the reference\n");
+    free(d);
+    free(r);
+    return 0;
+}
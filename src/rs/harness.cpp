@@ -0,0 +1,88 @@
+// Generated by shortcut gen-harness cpp from shortcut @VERSION@. Build and run it with
+//     c++ -O2 -std=c++17 -o harness harness.cpp -L target/release -lshortcut
+//     LD_LIBRARY_PATH=target/release ./harness [n] [repetitions] [variant]
+// to time the step of the n * n input of shortcut-bench, 4000 by default, with the variant, by
+// default auto for the fastest on this CPU, and check elements of the result against a loop.
+#include <algorithm>
+#include <chrono>
+#include <cstdint>
+#include <cstdio>
+#include <cstring>
+#include <limits>
+#include <string>
+#include <vector>
+
+extern "C" {
+int32_t step_variant(float* r_raw, const float* d_raw, size_t n, const char* variant);
+const char* step_strerror(int32_t code);
+const char* shortcut_version(void);
+const char* shortcut_best_variant(void);
+}
+
+// The input of shortcut::bench::random_input, uniformly distributed in [0, 1).
+static std::vector<float> random_input(size_t n) {
+    std::vector<float> d(n * n);
+    uint64_t state = 0x9e3779b97f4a7c15u;
+    for (float& x : d) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        x = static_cast<float>(state >> 40) / static_cast<float>(1 << 24);
+    }
+    return d;
+}
+
+// Every variant rounds the same sums, so the elements must be equal. Checks a 16 * 16 grid of
+// them, since all would take as long as v0.
+static bool verify(const std::vector<float>& r, const std::vector<float>& d, size_t n) {
+    for (size_t a = 0; a < 16; ++a) {
+        for (size_t b = 0; b < 16; ++b) {
+            size_t i = a * n / 16, j = b * n / 16;
+            float v = std::numeric_limits<float>::infinity();
+            for (size_t k = 0; k < n; ++k) {
+                v = std::min(v, d[n*i + k] + d[n*k + j]);
+            }
+            if (r[n*i + j] != v) {
+                std::fprintf(stderr, "error: r[%zu][%zu] is %g instead of %g\n", i, j, r[n*i + j], v);
+                return false;
+            }
+        }
+    }
+    return true;
+}
+
+int main(int argc, char** argv) {
+    size_t n = argc > 1 ? std::stoull(argv[1]) : 4000;
+    int repetitions = argc > 2 ? std::stoi(argv[2]) : 5;
+    std::string variant = argc > 3 ? argv[3] : "auto";
+    if (n == 0 || repetitions < 1 || argc > 4) {
+        std::fprintf(stderr, "usage: %s [n] [repetitions] [variant]\n", argv[0]);
+        return 1;
+    }
+    if (std::strcmp(shortcut_version(), "@VERSION@") != 0) {
+        std::fprintf(stderr, "warning: the library is shortcut %s, not @VERSION@\n", shortcut_version());
+    }
+    if (variant == "auto" && shortcut_best_variant() != nullptr) {
+        variant = shortcut_best_variant();
+    }
+    std::vector<float> d = random_input(n);
+    std::vector<float> r(n * n);
+    double fastest = std::numeric_limits<double>::infinity();
+    for (int i = 0; i < repetitions; ++i) {
+        auto start = std::chrono::steady_clock::now();
+        int32_t code = step_variant(r.data(), d.data(), n, variant.c_str());
+        if (code != 0) {
+            std::fprintf(stderr, "error: %s\n", step_strerror(code));
+            return 1;
+        }
+        std::chrono::duration<double> seconds = std::chrono::steady_clock::now() - start;
+        fastest = std::min(fastest, seconds.count());
+    }
+    double gflops = 2.0 * static_cast<double>(n) * n * n / fastest / 1e9;
+    std::printf("shortcut %s, %s, n = %zu: %.6f s, %.2f GFLOPS, the fastest of %d runs\n",
+                shortcut_version(), variant.c_str(), n, fastest, gflops, repetitions);
+    if (!verify(r, d, n)) {
+        return 1;
+    }
+    std::printf("verified against the reference\n");
+}
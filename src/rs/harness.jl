@@ -0,0 +1,77 @@
+# Generated by shortcut gen-harness julia from shortcut @VERSION@. Run it with
+#     SHORTCUT_LIBRARY=target/release/@LIBRARY@ julia harness.jl [n] [repetitions] [variant]
+# to time the step of the n * n input of shortcut-bench, 4000 by default, with the variant, by
+# default auto for the fastest on this CPU, and check elements of the result against a loop.
+# The matrices are flat vectors in the row-major order of the library.
+
+const LIBRARY = get(ENV, "SHORTCUT_LIBRARY", "@LIBRARY@")
+
+version() = unsafe_string(ccall((:shortcut_version, LIBRARY), Cstring, ()))
+
+# The input of shortcut::bench::random_input, uniformly distributed in [0, 1).
+function random_input(n)
+    d = Vector{Float32}(undef, n * n)
+    state = 0x9e3779b97f4a7c15
+    for i in eachindex(d)
+        state ⊻= state << 13
+        state ⊻= state >> 7
+        state ⊻= state << 17
+        d[i] = Float32(state >> 40) / Float32(1 << 24)
+    end
+    d
+end
+
+# Every variant rounds the same sums, so the elements must be equal. Checks a 16 * 16 grid of
+# them, since all would take as long as v0.
+function verify(r, d, n)
+    for a in 0:15, b in 0:15
+        i, j = a * n ÷ 16, b * n ÷ 16
+        v = minimum(d[n*i + k + 1] + d[n*k + j + 1] for k in 0:n-1)
+        if r[n*i + j + 1] != v
+            println(stderr, "error: r[$i][$j] is $(r[n*i + j + 1]) instead of $v")
+            return false
+        end
+    end
+    true
+end
+
+function main(args)
+    if length(args) > 3
+        println(stderr, "usage: julia harness.jl [n] [repetitions] [variant]")
+        exit(1)
+    end
+    n = length(args) >= 1 ? parse(Int, args[1]) : 4000
+    repetitions = length(args) >= 2 ? parse(Int, args[2]) : 5
+    variant = length(args) >= 3 ? args[3] : "auto"
+    if n < 1 || repetitions < 1
+        println(stderr, "usage: julia harness.jl [n] [repetitions] [variant]")
+        exit(1)
+    end
+    if version() != "@VERSION@"
+        println(stderr, "warning: the library is shortcut $(version()), not @VERSION@")
+    end
+    best = ccall((:shortcut_best_variant, LIBRARY), Ptr{UInt8}, ())
+    if variant == "auto" && best != C_NULL
+        variant = unsafe_string(best)
+    end
+    d = random_input(n)
+    r = zeros(Float32, n * n)
+    fastest = Inf
+    for _ in 1:repetitions
+        start = time_ns()
+        code = ccall((:step_variant, LIBRARY), Int32, (Ptr{Float32}, Ptr{Float32}, Csize_t, Cstring), r, d, n, variant)
+        if code != 0
+            message = unsafe_string(ccall((:step_strerror, LIBRARY), Cstring, (Int32,), code))
+            println(stderr, "error: $message")
+            exit(1)
+        end
+        fastest = min(fastest, (time_ns() - start) / 1e9)
+    end
+    gflops = 2.0 * Float64(n)^3 / fastest / 1e9
+    seconds = round(fastest, digits=6)
+    println("shortcut $(version()), $variant, n = $n: $seconds s, $(round(gflops, digits=2)) GFLOPS, the fastest of $repetitions runs")
+    verify(r, d, n) || exit(1)
+    println("verified against the reference")
+end
+
+main(ARGS)
@@ -0,0 +1,85 @@
+#!/usr/bin/env python3
+"""Generated by shortcut gen-harness python from shortcut @VERSION@. Run it with
+    SHORTCUT_LIBRARY=target/release/@LIBRARY@ python3 harness.py [n] [repetitions] [variant]
+to time the step of the n * n input of shortcut-bench, 4000 by default, with the variant, by
+default auto for the fastest on this CPU, and check elements of the result against a loop.
+Generating the input takes longer than the steps, since it is done in Python too.
+"""
+import array
+import ctypes
+import math
+import os
+import sys
+import time
+
+library = ctypes.CDLL(os.environ.get("SHORTCUT_LIBRARY", "@LIBRARY@"))
+library.step_variant.argtypes = [ctypes.c_void_p, ctypes.c_void_p, ctypes.c_size_t, ctypes.c_char_p]
+library.step_variant.restype = ctypes.c_int32
+library.step_strerror.argtypes = [ctypes.c_int32]
+library.step_strerror.restype = ctypes.c_char_p
+library.shortcut_version.restype = ctypes.c_char_p
+library.shortcut_best_variant.restype = ctypes.c_char_p
+
+
+def random_input(n):
+    """The input of shortcut::bench::random_input, uniformly distributed in [0, 1)."""
+    mask = (1 << 64) - 1
+    state = 0x9E3779B97F4A7C15
+
+    def elements():
+        nonlocal state
+        for _ in range(n * n):
+            state ^= (state << 13) & mask
+            state ^= state >> 7
+            state ^= (state << 17) & mask
+            yield (state >> 40) / (1 << 24)
+
+    return array.array("f", elements())
+
+
+def verify(r, d, n):
+    """Every variant rounds the same sums, so the elements must be equal. Checks a 16 * 16 grid of
+    them, since all would take as long as v0. The elements are multiples of 2^-24 below 1, whose
+    sums are exact in Python floats, and rounding them to float keeps the smallest the smallest."""
+    for a in range(16):
+        for b in range(16):
+            i, j = a * n // 16, b * n // 16
+            v = min((d[n * i + k] + d[n * k + j] for k in range(n)), default=math.inf)
+            v = ctypes.c_float(v).value
+            if r[n * i + j] != v:
+                print(f"error: r[{i}][{j}] is {r[n * i + j]} instead of {v}", file=sys.stderr)
+                return False
+    return True
+
+
+def main():
+    if len(sys.argv) > 4:
+        sys.exit(f"usage: {sys.argv[0]} [n] [repetitions] [variant]")
+    n = int(sys.argv[1]) if len(sys.argv) > 1 else 4000
+    repetitions = int(sys.argv[2]) if len(sys.argv) > 2 else 5
+    variant = sys.argv[3] if len(sys.argv) > 3 else "auto"
+    if n < 1 or repetitions < 1:
+        sys.exit(f"usage: {sys.argv[0]} [n] [repetitions] [variant]")
+    version = library.shortcut_version().decode()
+    if version != "@VERSION@":
+        print(f"warning: the library is shortcut {version}, not @VERSION@", file=sys.stderr)
+    if variant == "auto" and library.shortcut_best_variant() is not None:
+        variant = library.shortcut_best_variant().decode()
+    d = random_input(n)
+    r = array.array("f", [0.0]) * (n * n)
+    fastest = math.inf
+    for _ in range(repetitions):
+        start = time.perf_counter()
+        code = library.step_variant(r.buffer_info()[0], d.buffer_info()[0], n, variant.encode())
+        if code != 0:
+            sys.exit(f"error: {library.step_strerror(code).decode()}")
+        fastest = min(fastest, time.perf_counter() - start)
+    gflops = 2.0 * n**3 / fastest / 1e9
+    print(f"shortcut {version}, {variant}, n = {n}: {fastest:.6f} s, {gflops:.2f} GFLOPS, the fastest of {repetitions} runs")
+    if not verify(r, d, n):
+        sys.exit(1)
+    print("verified against the reference")
+
+
+if __name__ == "__main__":
+    main()
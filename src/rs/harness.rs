@@ -0,0 +1,51 @@
+use std::str::FromStr;
+
+/// Languages of the programs of `source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Cpp,
+    /// With `ctypes`, and nothing outside the standard library.
+    Python,
+    Julia,
+}
+
+pub const LANGUAGES: [(&str, Language); 4] =
+    [("c", Language::C), ("cpp", Language::Cpp), ("python", Language::Python), ("julia", Language::Julia)];
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let names: Vec<_> = LANGUAGES.iter().map(|&(name, _)| name).collect();
+        LANGUAGES
+            .iter()
+            .find(|&&(name, _)| name == s)
+            .map(|&(_, language)| language)
+            .ok_or_else(|| format!("unknown language '{}', expected one of {}", s, names.join(", ")))
+    }
+}
+
+impl Language {
+    /// The name of this language in `LANGUAGES`.
+    pub fn name(self) -> &'static str {
+        LANGUAGES.iter().find(|&&(_, language)| language == self).map_or("", |&(name, _)| name)
+    }
+}
+
+/// The source of a program in `language` that loads this library through the C ABI of
+/// `shortcut.h`, times `step_variant` on the input of `bench::random_input` and checks elements
+/// of the result against a loop of its own, with the build and run commands in its first comment.
+/// It warns if the library it loads is not of this version, so that the same build can be
+/// compared across languages, and the interpreted ones load `$SHORTCUT_LIBRARY`, or else the
+/// shared library of this platform by name, such as `libshortcut.so`.
+pub fn source(language: Language) -> String {
+    let template = match language {
+        Language::C => include_str!("harness.c"),
+        Language::Cpp => include_str!("harness.cpp"),
+        Language::Python => include_str!("harness.py"),
+        Language::Julia => include_str!("harness.jl"),
+    };
+    let library = format!("{}shortcut{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX);
+    template.replace("@VERSION@", env!("CARGO_PKG_VERSION")).replace("@LIBRARY@", &library)
+}
@@ -0,0 +1,93 @@
+use crate::threads::ThreadConfig;
+use crate::{check_lengths, dispatch, StepError};
+
+/// Updates `prev`, the `step` of an earlier `d`, to the `step` of `d`, which differs from the
+/// earlier one only in the elements `changes`, given as `(i, j)`. Changing `d[i][j]` only changes
+/// the sums through it, which are in row `i` and in column `j` of the result, so only those rows
+/// and columns are recomputed, taking about `(rows + columns) / n` of the time of `step`. Both
+/// shorter and longer distances are allowed, and the result is the same as that of `step`.
+///
+/// If the changes are in `n` or more different rows and columns, this is just `step`.
+pub fn step_incremental(prev: &mut [f32], d: &[f32], changes: &[(u32, u32)], n: usize) -> Result<(), StepError> {
+    check_lengths(prev, d, n)?;
+    if let Some(&index) = changes.iter().flat_map(|(i, j)| [i, j]).find(|&&index| index as usize >= n) {
+        return Err(StepError::IndexOutOfRange { index, n });
+    }
+    let (rows, cols) = affected(changes);
+    let threads = ThreadConfig::default();
+    if rows.len() + cols.len() >= n {
+        dispatch::step(&threads, prev, d, n);
+        return Ok(());
+    }
+    if !rows.is_empty() {
+        let mut r = vec![0.0; rows.len() * n];
+        dispatch::step_rows(&threads, &mut r, d, &rows, n);
+        for (r_row, &i) in r.chunks(n).zip(&rows) {
+            prev[n * i as usize..n * i as usize + n].copy_from_slice(r_row);
+        }
+    }
+    if !cols.is_empty() {
+        // Column `j` of the result is the product of `d` and column `j` of `d`.
+        let m = cols.len();
+        let d_cols: Vec<f32> = (0..n).flat_map(|k| cols.iter().map(move |&j| d[n*k + j as usize])).collect();
+        let mut r = vec![0.0; n * m];
+        dispatch::minplus_gemm(&threads, &mut r, d, &d_cols, n, n, m);
+        for (i, r_row) in r.chunks(m).enumerate() {
+            for (&x, &j) in r_row.iter().zip(&cols) {
+                prev[n*i + j as usize] = x;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The distinct rows and columns of `changes`, in increasing order.
+fn affected(changes: &[(u32, u32)]) -> (Vec<u32>, Vec<u32>) {
+    let (mut rows, mut cols): (Vec<u32>, Vec<u32>) = changes.iter().copied().unzip();
+    for indices in [&mut rows, &mut cols] {
+        indices.sort_unstable();
+        indices.dedup();
+    }
+    (rows, cols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+    use crate::step;
+
+    /// Changes in no, one, a few and `n` or more rows and columns, to shorter, longer and missing
+    /// edges, compared against `step` of the changed matrix.
+    #[test]
+    fn matches_full_recomputation() {
+        for n in [1, 2, 7, 17, 33, 64] {
+            let d = random_input(n);
+            let last = n as u32 - 1;
+            let changes: [Vec<(u32, u32)>; 5] = [
+                vec![],
+                vec![(0, 0)],
+                vec![(last, 0), (last / 2, last), (last / 2, 0)],
+                (0..3).map(|k| (k % n as u32, last)).collect(),
+                (0..n as u32).map(|i| (i, 3 * i % n as u32)).collect(),
+            ];
+            for changes in changes {
+                let mut changed = d.clone();
+                for (c, &(i, j)) in changes.iter().enumerate() {
+                    changed[n * i as usize + j as usize] = [0.0, 2.0, f32::INFINITY][c % 3];
+                }
+                let (mut incremental, mut expected) = (vec![0.0; n * n], vec![0.0; n * n]);
+                step(&mut incremental, &d, n).unwrap();
+                step_incremental(&mut incremental, &changed, &changes, n).unwrap();
+                step(&mut expected, &changed, n).unwrap();
+                assert_eq!(incremental, expected, "n = {} changes = {:?}", n, changes);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_changes_outside_the_matrix() {
+        let (mut prev, d) = (vec![0.0; 4], vec![0.0; 4]);
+        assert_eq!(step_incremental(&mut prev, &d, &[(0, 2)], 2), Err(StepError::IndexOutOfRange { index: 2, n: 2 }));
+    }
+}
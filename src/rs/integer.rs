@@ -0,0 +1,348 @@
+use crate::simd::load_at;
+use crate::threads::ThreadConfig;
+
+/// Integer distances, with `MAX` standing for infinity.
+pub(crate) trait Int: Copy + Ord + Send + Sync {
+    const MAX: Self;
+    fn saturating_add(self, other: Self) -> Self;
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            impl Int for $t {
+                const MAX: Self = <$t>::MAX;
+                #[inline(always)]
+                fn saturating_add(self, other: Self) -> Self {
+                    <$t>::saturating_add(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(i32, u16);
+
+/// Like `simd::Vector`, for lanes of integers that add with saturation.
+pub(crate) trait IntVector: Copy {
+    type Elem: Int;
+    const LANES: usize;
+    unsafe fn splat(x: Self::Elem) -> Self;
+    unsafe fn load(p: *const Self::Elem) -> Self;
+    unsafe fn adds(a: Self, b: Self) -> Self;
+    unsafe fn min(a: Self, b: Self) -> Self;
+    unsafe fn horizontal_min(a: Self) -> Self::Elem;
+}
+
+impl<T: Int> IntVector for T {
+    type Elem = T;
+    const LANES: usize = 1;
+    unsafe fn splat(x: T) -> Self {
+        x
+    }
+    unsafe fn load(p: *const T) -> Self {
+        *p
+    }
+    unsafe fn adds(a: Self, b: Self) -> Self {
+        a.saturating_add(b)
+    }
+    unsafe fn min(a: Self, b: Self) -> Self {
+        Ord::min(a, b)
+    }
+    unsafe fn horizontal_min(a: Self) -> T {
+        a
+    }
+}
+
+/// Copies `d` and its transpose into rows padded with `T::MAX` to a multiple of `lanes`.
+pub(crate) fn pad_and_transpose<T: Int>(d: &[T], n: usize, lanes: usize) -> (Vec<T>, Vec<T>, usize) {
+    let width = n.div_ceil(lanes) * lanes;
+    let mut vd = vec![T::MAX; n * width];
+    let mut vt = vec![T::MAX; n * width];
+    for i in 0..n {
+        for k in 0..n {
+            vd[width*i + k] = d[n*i + k];
+            vt[width*i + k] = d[n*k + i];
+        }
+    }
+    (vd, vt, width)
+}
+
+#[inline(always)]
+pub(crate) unsafe fn step_row<V: IntVector>(r_row: &mut [V::Elem], vd_row: &[V::Elem], vt: &[V::Elem], width: usize) {
+    for (res, vt_row) in r_row.iter_mut().zip(vt.chunks(width)) {
+        let mut v = V::splat(V::Elem::MAX);
+        for k in (0..width).step_by(V::LANES) {
+            let x = V::load(load_at(vd_row, k, V::LANES));
+            let y = V::load(load_at(vt_row, k, V::LANES));
+            v = V::min(v, V::adds(x, y));
+        }
+        *res = V::horizontal_min(v);
+    }
+}
+
+/// Like `simd::step_lanes`, a macro to keep the `#[target_feature]`s of the caller in the closure.
+macro_rules! step_lanes {
+    ($V:ty, $threads:expr, $r:expr, $d:expr, $n:expr) => {{
+        let n: usize = $n;
+        let (vd, vt, width) = $crate::integer::pad_and_transpose($d, n, <$V as $crate::integer::IntVector>::LANES);
+        $crate::threads::for_each_chunk($threads, $r, n, |i, r_row| unsafe {
+            $crate::integer::step_row::<$V>(r_row, &vd[width*i..width*(i + 1)], &vt, width)
+        })
+    }};
+}
+
+pub(crate) fn step_i32(threads: &ThreadConfig, r: &mut [i32], d: &[i32], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+        return unsafe { x86::step_i32_avx2(threads, r, d, n) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if !crate::simd::PARANOID && std::arch::is_aarch64_feature_detected!("neon") {
+        return unsafe { neon::step_i32_neon(threads, r, d, n) };
+    }
+    step_lanes!(i32, threads, r, d, n)
+}
+
+pub(crate) fn step_u16(threads: &ThreadConfig, r: &mut [u16], d: &[u16], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+        return unsafe { x86::step_u16_avx2(threads, r, d, n) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if !crate::simd::PARANOID && std::arch::is_aarch64_feature_detected!("neon") {
+        return unsafe { neon::step_u16_neon(threads, r, d, n) };
+    }
+    step_lanes!(u16, threads, r, d, n)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::IntVector;
+    use crate::threads::ThreadConfig;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct I32x8(__m256i);
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct U16x16(__m256i);
+
+    impl IntVector for I32x8 {
+        type Elem = i32;
+        const LANES: usize = 8;
+        #[inline(always)]
+        unsafe fn splat(x: i32) -> Self {
+            I32x8(_mm256_set1_epi32(x))
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const i32) -> Self {
+            I32x8(_mm256_loadu_si256(p as *const __m256i))
+        }
+        /// AVX2 has no saturating 32-bit add, so the lanes where `a` and `b` have the same sign
+        /// but the sum does not are replaced with `i32::MIN` or `i32::MAX`, by the sign of `a`.
+        #[inline(always)]
+        unsafe fn adds(a: Self, b: Self) -> Self {
+            let (a, b) = (a.0, b.0);
+            let sum = _mm256_add_epi32(a, b);
+            let overflow = _mm256_andnot_si256(_mm256_xor_si256(a, b), _mm256_xor_si256(a, sum));
+            let saturated = _mm256_xor_si256(_mm256_srai_epi32(a, 31), _mm256_set1_epi32(i32::MAX));
+            let blended = _mm256_blendv_ps(
+                _mm256_castsi256_ps(sum),
+                _mm256_castsi256_ps(saturated),
+                _mm256_castsi256_ps(overflow),
+            );
+            I32x8(_mm256_castps_si256(blended))
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            I32x8(_mm256_min_epi32(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> i32 {
+            let mut lanes = [0; 8];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, a.0);
+            lanes.into_iter().fold(i32::MAX, Ord::min)
+        }
+    }
+
+    impl IntVector for U16x16 {
+        type Elem = u16;
+        const LANES: usize = 16;
+        #[inline(always)]
+        unsafe fn splat(x: u16) -> Self {
+            U16x16(_mm256_set1_epi16(x as i16))
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const u16) -> Self {
+            U16x16(_mm256_loadu_si256(p as *const __m256i))
+        }
+        #[inline(always)]
+        unsafe fn adds(a: Self, b: Self) -> Self {
+            U16x16(_mm256_adds_epu16(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            U16x16(_mm256_min_epu16(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> u16 {
+            let mut lanes = [0; 16];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, a.0);
+            lanes.into_iter().fold(u16::MAX, Ord::min)
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn step_i32_avx2(threads: &ThreadConfig, r: &mut [i32], d: &[i32], n: usize) {
+        step_lanes!(I32x8, threads, r, d, n)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn step_u16_avx2(threads: &ThreadConfig, r: &mut [u16], d: &[u16], n: usize) {
+        step_lanes!(U16x16, threads, r, d, n)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::IntVector;
+    use crate::threads::ThreadConfig;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct I32x4(int32x4_t);
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct U16x8(uint16x8_t);
+
+    impl IntVector for I32x4 {
+        type Elem = i32;
+        const LANES: usize = 4;
+        #[inline(always)]
+        unsafe fn splat(x: i32) -> Self {
+            I32x4(vdupq_n_s32(x))
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const i32) -> Self {
+            I32x4(vld1q_s32(p))
+        }
+        #[inline(always)]
+        unsafe fn adds(a: Self, b: Self) -> Self {
+            I32x4(vqaddq_s32(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            I32x4(vminq_s32(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> i32 {
+            vminvq_s32(a.0)
+        }
+    }
+
+    impl IntVector for U16x8 {
+        type Elem = u16;
+        const LANES: usize = 8;
+        #[inline(always)]
+        unsafe fn splat(x: u16) -> Self {
+            U16x8(vdupq_n_u16(x))
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const u16) -> Self {
+            U16x8(vld1q_u16(p))
+        }
+        #[inline(always)]
+        unsafe fn adds(a: Self, b: Self) -> Self {
+            U16x8(vqaddq_u16(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            U16x8(vminq_u16(a.0, b.0))
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> u16 {
+            vminvq_u16(a.0)
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn step_i32_neon(threads: &ThreadConfig, r: &mut [i32], d: &[i32], n: usize) {
+        step_lanes!(I32x4, threads, r, d, n)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn step_u16_neon(threads: &ThreadConfig, r: &mut [u16], d: &[u16], n: usize) {
+        step_lanes!(U16x8, threads, r, d, n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+
+    /// `step` with the sums widened to `i64` and clamped back to `T`, to check the saturation against.
+    fn reference<T: Int + Into<i64> + TryFrom<i64>>(d: &[T], n: usize, min: T) -> Vec<T> {
+        let clamp = |x: i64| T::try_from(x.clamp(min.into(), T::MAX.into())).ok().unwrap();
+        let mut r = vec![T::MAX; n * n];
+        for i in 0..n {
+            for j in 0..n {
+                r[n*i + j] = (0..n).map(|k| clamp(d[n*i + k].into() + d[n*k + j].into())).fold(T::MAX, Ord::min);
+            }
+        }
+        r
+    }
+
+    /// Mostly elements of more than half of `max`, whose sums overflow, and some infinities and
+    /// elements near `min`.
+    fn input(n: usize, min: i64, max: i64) -> Vec<i64> {
+        random_input(n)
+            .into_iter()
+            .map(|x| match x {
+                x if x < 0.05 => min + (x * 1000.0) as i64,
+                x if x < 0.1 => max,
+                x => max - ((1.0 - x) * max as f32 * 0.6) as i64,
+            })
+            .collect()
+    }
+
+    type Kernel<T> = (&'static str, fn(&ThreadConfig, &mut [T], &[T], usize));
+
+    fn check<T: Int + Into<i64> + TryFrom<i64> + std::fmt::Debug>(min: T, kernels: &[Kernel<T>]) {
+        for n in [1, 5, 9, 17, 33, 70] {
+            let d: Vec<T> = input(n, min.into(), T::MAX.into()).into_iter().map(|x| T::try_from(x).ok().unwrap()).collect();
+            let expected = reference(&d, n, min);
+            assert!(expected.contains(&T::MAX) || n == 1, "n = {}", n);
+            for &(name, step) in kernels {
+                for threads in [ThreadConfig::default(), ThreadConfig::with_threads(3)] {
+                    let mut r = vec![min; n * n];
+                    step(&threads, &mut r, &d, n);
+                    assert_eq!(r, expected, "{} n = {}", name, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sums_saturate_to_infinity() {
+        let mut i32_kernels: Vec<Kernel<i32>> =
+            vec![("dispatched", step_i32), ("portable", |threads, r, d, n| step_lanes!(i32, threads, r, d, n))];
+        let mut u16_kernels: Vec<Kernel<u16>> =
+            vec![("dispatched", step_u16), ("portable", |threads, r, d, n| step_lanes!(u16, threads, r, d, n))];
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            i32_kernels.push(("avx2", |threads, r, d, n| unsafe { x86::step_i32_avx2(threads, r, d, n) }));
+            u16_kernels.push(("avx2", |threads, r, d, n| unsafe { x86::step_u16_avx2(threads, r, d, n) }));
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            i32_kernels.push(("neon", |threads, r, d, n| unsafe { neon::step_i32_neon(threads, r, d, n) }));
+            u16_kernels.push(("neon", |threads, r, d, n| unsafe { neon::step_u16_neon(threads, r, d, n) }));
+        }
+        check(i32::MIN, &i32_kernels);
+        check(0, &u16_kernels);
+    }
+}
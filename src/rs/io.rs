@@ -0,0 +1,281 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::tiled::{TileSink, TileSource};
+use crate::{MatrixView, MatrixViewMut};
+
+pub mod formats;
+// Needs `imp` to map the memory rather than read a copy of it.
+#[cfg(all(target_endian = "little", any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+pub mod shm;
+
+/// Every matrix file starts with `MAGIC`, the format version as a little-endian `u32`, four zero
+/// bytes and `n` as a little-endian `u64`, zero padded to `HEADER_LEN` bytes.
+/// The `n * n` elements follow as little-endian `f32`s, row by row.
+const MAGIC: &[u8; 8] = b"SHORTCUT";
+const VERSION: u32 = 1;
+/// Keeps the elements aligned to 64 bytes in the mapping, which starts at a page boundary.
+pub const HEADER_LEN: usize = 64;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn header(n: usize) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    header[16..24].copy_from_slice(&(n as u64).to_le_bytes());
+    header
+}
+
+fn parse_header(header: &[u8; HEADER_LEN]) -> io::Result<usize> {
+    if &header[..8] != MAGIC {
+        return Err(invalid_data("not a shortcut matrix file".to_string()));
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(invalid_data(format!("unsupported matrix file version {}", version)));
+    }
+    let n = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    usize::try_from(n).map_err(|_| invalid_data(format!("n = {} does not fit in usize", n)))
+}
+
+/// The `n` in the header of the matrix file at `path`.
+pub fn read_n(path: impl AsRef<Path>) -> io::Result<usize> {
+    let mut header = [0; HEADER_LEN];
+    File::open(path)?.read_exact(&mut header)?;
+    parse_header(&header)
+}
+
+fn file_len(n: usize) -> io::Result<usize> {
+    n.checked_mul(n)
+        .and_then(|len| len.checked_mul(std::mem::size_of::<f32>()))
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .ok_or_else(|| invalid_data(format!("a matrix of {} * {} elements does not fit in memory", n, n)))
+}
+
+/// An `n * n` matrix in a file, mapped into memory so that only the parts in use are read.
+pub struct Matrix {
+    n: usize,
+    map: imp::Map,
+    writable: bool,
+}
+
+impl Matrix {
+    /// Maps the matrix file at `path` read-only, checking that it holds an `n * n` matrix.
+    pub fn open_mmap(path: impl AsRef<Path>, n: usize) -> io::Result<Matrix> {
+        let mut file = File::open(path)?;
+        let mut header = [0; HEADER_LEN];
+        file.read_exact(&mut header)?;
+        let file_n = parse_header(&header)?;
+        if file_n != n {
+            return Err(invalid_data(format!("expected a matrix of size n = {}, the file has n = {}", n, file_n)));
+        }
+        let len = file_len(n)?;
+        if file.metadata()?.len() != len as u64 {
+            return Err(invalid_data(format!("expected a file of {} bytes for n = {}", len, n)));
+        }
+        Ok(Matrix { n, map: imp::Map::new(file, len, false)?, writable: false })
+    }
+
+    /// Creates or truncates the file at `path` for an `n * n` matrix of zeros and maps it
+    /// read-write.
+    pub fn create_mmap(path: impl AsRef<Path>, n: usize) -> io::Result<Matrix> {
+        let len = file_len(n)?;
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.write_all(&header(n))?;
+        file.set_len(len as u64)?;
+        Ok(Matrix { n, map: imp::Map::new(file, len, true)?, writable: true })
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        let data = &self.map.bytes()[HEADER_LEN..];
+        unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), self.n * self.n) }
+    }
+
+    /// Panics if the matrix was opened with `open_mmap`.
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        assert!(self.writable, "the matrix was opened read-only");
+        let data = &mut self.map.bytes_mut()[HEADER_LEN..];
+        unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr().cast(), self.n * self.n) }
+    }
+
+    /// The mapped elements, without reading them.
+    pub fn view(&self) -> MatrixView<'_, f32> {
+        MatrixView::new(self.as_slice(), self.n).unwrap()
+    }
+
+    /// Panics if the matrix was opened with `open_mmap`.
+    pub fn view_mut(&mut self) -> MatrixViewMut<'_, f32> {
+        let n = self.n;
+        MatrixViewMut::new(self.as_mut_slice(), n).unwrap()
+    }
+
+    /// Writes all changes to the file, which otherwise happens at some point after they are made.
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+impl TileSource for Matrix {
+    fn read_tile(&mut self, rows: Range<usize>, cols: Range<usize>, tile: &mut [f32]) -> io::Result<()> {
+        let (n, d) = (self.n, self.as_slice());
+        for (tile_row, i) in tile.chunks_mut(cols.len()).zip(rows) {
+            tile_row.copy_from_slice(&d[n*i + cols.start..n*i + cols.end]);
+        }
+        Ok(())
+    }
+}
+
+impl TileSink for Matrix {
+    fn write_tile(&mut self, rows: Range<usize>, cols: Range<usize>, tile: &[f32]) -> io::Result<()> {
+        if !self.writable {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the matrix was opened read-only"));
+        }
+        let n = self.n;
+        let r = self.as_mut_slice();
+        for (tile_row, i) in tile.chunks(cols.len()).zip(rows) {
+            r[n*i + cols.start..n*i + cols.end].copy_from_slice(tile_row);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(target_endian = "little", any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+mod imp {
+    use std::ffi::c_long;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    extern "C" {
+        /// `offset` is an `off_t`, a `long` on the targets here, of 32 bits on 32-bit Linux.
+        fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: c_long) -> *mut u8;
+        fn munmap(addr: *mut u8, len: usize) -> i32;
+        fn msync(addr: *mut u8, len: usize, flags: i32) -> i32;
+    }
+
+    const PROT_READ: i32 = 1;
+    const PROT_WRITE: i32 = 2;
+    const MAP_SHARED: i32 = 1;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const MS_SYNC: i32 = 4;
+    #[cfg(target_vendor = "apple")]
+    const MS_SYNC: i32 = 0x10;
+
+    pub(super) struct Map {
+        ptr: *mut u8,
+        len: usize,
+        // Keeps the file open for as long as it is mapped.
+        _file: File,
+    }
+
+    unsafe impl Send for Map {}
+    unsafe impl Sync for Map {}
+
+    impl Map {
+        pub(super) fn new(file: File, len: usize, writable: bool) -> io::Result<Map> {
+            let prot = if writable { PROT_READ | PROT_WRITE } else { PROT_READ };
+            let ptr = unsafe { mmap(std::ptr::null_mut(), len, prot, MAP_SHARED, file.as_raw_fd(), 0) };
+            if ptr as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Map { ptr, len, _file: file })
+        }
+
+        pub(super) fn as_ptr(&self) -> *mut u8 {
+            self.ptr
+        }
+
+        pub(super) fn bytes(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+
+        pub(super) fn bytes_mut(&mut self) -> &mut [u8] {
+            unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+        }
+
+        pub(super) fn flush(&self) -> io::Result<()> {
+            if unsafe { msync(self.ptr, self.len, MS_SYNC) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Map {
+        fn drop(&mut self) {
+            unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// Reads the whole file into memory where it cannot be mapped, and writes it back on `flush` and
+/// drop if it is writable. On big-endian targets the elements are also byte swapped.
+#[cfg(not(all(target_endian = "little", any(target_os = "linux", target_os = "android", target_vendor = "apple"))))]
+mod imp {
+    use std::fs::File;
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::sync::Mutex;
+
+    use super::HEADER_LEN;
+
+    pub(super) struct Map {
+        /// `f32`s so that the elements are aligned, the header takes up the first 16.
+        data: Vec<f32>,
+        file: Option<Mutex<File>>,
+    }
+
+    impl Map {
+        pub(super) fn new(mut file: File, len: usize, writable: bool) -> io::Result<Map> {
+            let mut bytes = Vec::with_capacity(len);
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_end(&mut bytes)?;
+            let data = bytes.chunks_exact(4).map(|x| f32::from_ne_bytes(x.try_into().unwrap())).collect::<Vec<_>>();
+            let mut map = Map { data, file: None };
+            for x in map.elements_mut() {
+                *x = f32::from_bits(u32::from_le(x.to_bits()));
+            }
+            map.file = writable.then(|| Mutex::new(file));
+            Ok(map)
+        }
+
+        fn elements_mut(&mut self) -> &mut [f32] {
+            &mut self.data[HEADER_LEN / 4..]
+        }
+
+        pub(super) fn bytes(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.data.as_ptr().cast(), 4 * self.data.len()) }
+        }
+
+        pub(super) fn bytes_mut(&mut self) -> &mut [u8] {
+            unsafe { std::slice::from_raw_parts_mut(self.data.as_mut_ptr().cast(), 4 * self.data.len()) }
+        }
+
+        pub(super) fn flush(&self) -> io::Result<()> {
+            let Some(file) = &self.file else { return Ok(()) };
+            let (header, elements) = self.bytes().split_at(HEADER_LEN);
+            let mut file = file.lock().unwrap();
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(header)?;
+            for x in elements.chunks_exact(4) {
+                let x = f32::from_ne_bytes(x.try_into().unwrap());
+                file.write_all(&x.to_le_bytes())?;
+            }
+            file.sync_data()
+        }
+    }
+
+    impl Drop for Map {
+        fn drop(&mut self) {
+            let _ = self.flush();
+        }
+    }
+}
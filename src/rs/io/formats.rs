@@ -0,0 +1,477 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::{invalid_data, Matrix};
+
+/// The format of a matrix file, by the extension of its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// The format of `io::Matrix`, for any extension other than the ones below.
+    Shortcut,
+    /// `.mtx`
+    MatrixMarket,
+    /// `.npy`
+    Npy,
+    /// `.csv`
+    Csv,
+}
+
+impl FileFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> FileFormat {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("mtx") => FileFormat::MatrixMarket,
+            Some(ext) if ext.eq_ignore_ascii_case("npy") => FileFormat::Npy,
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => FileFormat::Csv,
+            _ => FileFormat::Shortcut,
+        }
+    }
+}
+
+/// Reads the matrix at `path` in the format of its extension, returning `n` and the matrix.
+pub fn read_file(path: impl AsRef<Path>) -> io::Result<(usize, Vec<f32>)> {
+    let path = path.as_ref();
+    match FileFormat::from_path(path) {
+        FileFormat::Shortcut => {
+            let matrix = Matrix::open_mmap(path, super::read_n(path)?)?;
+            Ok((matrix.n(), matrix.as_slice().to_vec()))
+        }
+        FileFormat::MatrixMarket => read_matrix_market(BufReader::new(File::open(path)?)),
+        FileFormat::Npy => read_npy(BufReader::new(File::open(path)?)),
+        FileFormat::Csv => read_csv(BufReader::new(File::open(path)?)),
+    }
+}
+
+/// Writes the `n * n` matrix `d` to `path` in the format of its extension, `f32` for `.npy`.
+pub fn write_file(path: impl AsRef<Path>, d: &[f32], n: usize) -> io::Result<()> {
+    let path = path.as_ref();
+    check_len(d, n)?;
+    match FileFormat::from_path(path) {
+        FileFormat::Shortcut => {
+            let mut matrix = Matrix::create_mmap(path, n)?;
+            matrix.as_mut_slice().copy_from_slice(d);
+            matrix.flush()
+        }
+        FileFormat::MatrixMarket => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_matrix_market(&mut writer, d, n)?;
+            writer.flush()
+        }
+        FileFormat::Npy => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_npy(&mut writer, d, n, NpyType::F32)?;
+            writer.flush()
+        }
+        FileFormat::Csv => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_csv(&mut writer, d, n)?;
+            writer.flush()
+        }
+    }
+}
+
+fn check_len(d: &[f32], n: usize) -> io::Result<()> {
+    if n.checked_mul(n) != Some(d.len()) {
+        let message = format!("expected {} * {} elements, got {}", n, n, d.len());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    Ok(())
+}
+
+fn check_square(rows: usize, cols: usize) -> io::Result<usize> {
+    if rows != cols {
+        return Err(invalid_data(format!("expected a square matrix, got {} * {}", rows, cols)));
+    }
+    if rows.checked_mul(cols).is_none() {
+        return Err(invalid_data(format!("a matrix of {} * {} elements is too large", rows, cols)));
+    }
+    Ok(rows)
+}
+
+/// `len` copies of `x`, or an error instead of aborting if they do not fit in memory, for a size
+/// read from a file that has fewer elements than that, such as a sparse MatrixMarket matrix.
+fn filled(len: usize, x: f32) -> io::Result<Vec<f32>> {
+    let mut d = Vec::new();
+    d.try_reserve_exact(len).map_err(|_| invalid_data(format!("a matrix of {} elements does not fit in memory", len)))?;
+    d.resize(len, x);
+    Ok(d)
+}
+
+/// Reads the `len` bytes of `what` into a `Vec` that grows as they arrive, so that a length from
+/// a header is never allocated before the bytes are there.
+fn read_len(reader: impl Read, len: usize, what: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(invalid_data(format!("the {} ends after {} of its {} bytes", what, bytes.len(), len)));
+    }
+    Ok(bytes)
+}
+
+fn parse<T: std::str::FromStr>(field: Option<&str>, line: &str) -> io::Result<T> {
+    field.and_then(|field| field.parse().ok()).ok_or_else(|| invalid_data(format!("invalid line '{}'", line)))
+}
+
+/// Reads a real, integer or pattern MatrixMarket matrix, general or symmetric, in the array or
+/// the coordinate format. Entries missing from a coordinate matrix are infinite, so that a sparse
+/// graph becomes a dense matrix of distances; a pattern entry is an edge of length 1 and of
+/// duplicate entries the shortest is kept.
+pub fn read_matrix_market(reader: impl BufRead) -> io::Result<(usize, Vec<f32>)> {
+    let mut lines = reader.lines();
+    let banner = lines.next().transpose()?.unwrap_or_default().to_ascii_lowercase();
+    let (coordinate, pattern, symmetric) = match banner.split_whitespace().collect::<Vec<_>>()[..] {
+        ["%%matrixmarket", "matrix", format, field, symmetry] => {
+            let coordinate = match format {
+                "array" => false,
+                "coordinate" => true,
+                _ => return Err(invalid_data(format!("unknown MatrixMarket format '{}'", format))),
+            };
+            let pattern = match field {
+                "real" | "double" | "integer" => false,
+                "pattern" if coordinate => true,
+                _ => return Err(invalid_data(format!("unsupported MatrixMarket field '{}'", field))),
+            };
+            let symmetric = match symmetry {
+                "general" => false,
+                "symmetric" => true,
+                _ => return Err(invalid_data(format!("unsupported MatrixMarket symmetry '{}'", symmetry))),
+            };
+            (coordinate, pattern, symmetric)
+        }
+        _ => return Err(invalid_data("not a MatrixMarket matrix".to_string())),
+    };
+    let mut lines = lines.filter(|line| {
+        line.as_ref().map_or(true, |line| !line.starts_with('%') && !line.trim().is_empty())
+    });
+    let size = lines.next().transpose()?.ok_or_else(|| invalid_data("missing MatrixMarket size line".to_string()))?;
+    let mut fields = size.split_whitespace();
+    let n = check_square(parse(fields.next(), &size)?, parse(fields.next(), &size)?)?;
+    let too_few = || invalid_data("too few MatrixMarket entries".to_string());
+    // The entries are all read before the matrix is allocated, so that a size line larger than
+    // the file fails with too few entries.
+    if !coordinate {
+        // Column by column, only the lower triangle if symmetric.
+        let mut entries = Vec::new();
+        for j in 0..n {
+            for i in if symmetric { j..n } else { 0..n } {
+                let line = lines.next().transpose()?.ok_or_else(too_few)?;
+                entries.push((i, j, parse(line.split_whitespace().next(), &line)?));
+            }
+        }
+        let mut d = filled(n * n, 0.0)?;
+        for (i, j, x) in entries {
+            d[n*i + j] = x;
+            if symmetric {
+                d[n*j + i] = x;
+            }
+        }
+        return Ok((n, d));
+    }
+    let count: usize = parse(fields.next(), &size)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let line = lines.next().transpose()?.ok_or_else(too_few)?;
+        let mut fields = line.split_whitespace();
+        let (i, j): (usize, usize) = (parse(fields.next(), &line)?, parse(fields.next(), &line)?);
+        if !(1..=n).contains(&i) || !(1..=n).contains(&j) {
+            return Err(invalid_data(format!("entry ({}, {}) out of bounds for n = {}", i, j, n)));
+        }
+        let x: f32 = if pattern { 1.0 } else { parse(fields.next(), &line)? };
+        entries.push((i - 1, j - 1, x));
+    }
+    // Missing entries are infinite, so a short file may stand for a large matrix.
+    let mut d = filled(n * n, f32::INFINITY)?;
+    for (i, j, x) in entries {
+        d[n*i + j] = d[n*i + j].min(x);
+        if symmetric {
+            d[n*j + i] = d[n*j + i].min(x);
+        }
+    }
+    Ok((n, d))
+}
+
+/// Writes the `n * n` matrix `d` as a general real MatrixMarket matrix, in the array format if
+/// no element is infinite and otherwise in the coordinate format without the infinite ones, so
+/// that `read_matrix_market` returns the same matrix.
+pub fn write_matrix_market(mut writer: impl Write, d: &[f32], n: usize) -> io::Result<()> {
+    check_len(d, n)?;
+    let present = |x: &&f32| **x != f32::INFINITY;
+    if d.iter().all(|x| present(&x)) {
+        writeln!(writer, "%%MatrixMarket matrix array real general")?;
+        writeln!(writer, "{} {}", n, n)?;
+        for j in 0..n {
+            for i in 0..n {
+                writeln!(writer, "{}", d[n*i + j])?;
+            }
+        }
+    } else {
+        writeln!(writer, "%%MatrixMarket matrix coordinate real general")?;
+        writeln!(writer, "{} {} {}", n, n, d.iter().filter(present).count())?;
+        for (index, x) in d.iter().enumerate().filter(|(_, x)| present(x)) {
+            writeln!(writer, "{} {} {}", index / n + 1, index % n + 1, x)?;
+        }
+    }
+    Ok(())
+}
+
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// The element types of `.npy` arrays that can be read and written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NpyType {
+    F32,
+    F64,
+}
+
+/// The value of `key` in the Python dictionary literal of a `.npy` header, up to the next key.
+fn npy_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let start = header
+        .find(&format!("'{}':", key))
+        .ok_or_else(|| invalid_data(format!("missing '{}' in the .npy header", key)))?;
+    let value = header[start + key.len() + 3..].trim_start();
+    let end = match value.starts_with('(') {
+        true => value.find(')').map_or(value.len(), |end| end + 1),
+        false => value.find(',').unwrap_or(value.len()),
+    };
+    Ok(value[..end].trim())
+}
+
+/// The header of a `.npy` array read by `read_npy_header`, before its elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct NpyHeader {
+    pub(crate) n: usize,
+    pub(crate) dtype: NpyType,
+    little_endian: bool,
+    fortran_order: bool,
+}
+
+impl NpyHeader {
+    /// The bytes of the elements, `None` if they would overflow `usize`.
+    pub(crate) fn data_len(&self) -> Option<usize> {
+        let size = match self.dtype {
+            NpyType::F32 => 4,
+            NpyType::F64 => 8,
+        };
+        self.n.checked_mul(self.n)?.checked_mul(size)
+    }
+}
+
+/// Reads a two-dimensional `.npy` array of `f32`s or `f64`s of either byte order, in C or Fortran
+/// order. `f64`s are rounded to `f32`.
+pub fn read_npy(mut reader: impl Read) -> io::Result<(usize, Vec<f32>)> {
+    let header = read_npy_header(&mut reader)?;
+    Ok((header.n, read_npy_data(reader, &header)?))
+}
+
+/// Reads the header of a `.npy` array, so that its size can be checked before `read_npy_data`
+/// reads the elements.
+pub(crate) fn read_npy_header(mut reader: impl Read) -> io::Result<NpyHeader> {
+    let mut preamble = [0; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != NPY_MAGIC {
+        return Err(invalid_data("not a .npy file".to_string()));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return Err(invalid_data(format!("unsupported .npy version {}", version))),
+    };
+    let header = read_len(&mut reader, header_len, ".npy header")?;
+    let header = String::from_utf8(header).map_err(|_| invalid_data("invalid .npy header".to_string()))?;
+
+    let descr = npy_value(&header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+    let (little_endian, dtype) = match descr.as_bytes() {
+        [b'<', rest @ ..] => (true, rest),
+        [b'>', rest @ ..] => (false, rest),
+        [b'=', rest @ ..] => (cfg!(target_endian = "little"), rest),
+        _ => (true, descr.as_bytes()),
+    };
+    let dtype = match dtype {
+        b"f4" => NpyType::F32,
+        b"f8" => NpyType::F64,
+        _ => return Err(invalid_data(format!("unsupported .npy dtype '{}', expected f4 or f8", descr))),
+    };
+    let fortran_order = match npy_value(&header, "fortran_order")? {
+        "True" => true,
+        "False" => false,
+        value => return Err(invalid_data(format!("invalid fortran_order '{}' in the .npy header", value))),
+    };
+    let shape = npy_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|dim| dim.trim())
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().map_err(|_| invalid_data(format!("invalid .npy shape dimension '{}'", dim))))
+        .collect::<io::Result<Vec<usize>>>()?;
+    let n = match shape[..] {
+        [rows, cols] => check_square(rows, cols)?,
+        _ => return Err(invalid_data(format!("expected a two-dimensional array, got shape {:?}", shape))),
+    };
+    let header = NpyHeader { n, dtype, little_endian, fortran_order };
+    if header.data_len().is_none() {
+        return Err(invalid_data(format!("a .npy array of n = {} is too large", n)));
+    }
+    Ok(header)
+}
+
+/// Reads the elements of the `.npy` array of `header` as a row-major matrix.
+pub(crate) fn read_npy_data(reader: impl Read, header: &NpyHeader) -> io::Result<Vec<f32>> {
+    let &NpyHeader { n, dtype, little_endian, fortran_order } = header;
+    let len = header.data_len().ok_or_else(|| invalid_data(format!("a .npy array of n = {} is too large", n)))?;
+    let bytes = read_len(reader, len, ".npy array")?;
+    let mut d: Vec<f32> = match dtype {
+        NpyType::F32 => bytes
+            .chunks_exact(4)
+            .map(|x| {
+                let x = x.try_into().unwrap();
+                if little_endian { f32::from_le_bytes(x) } else { f32::from_be_bytes(x) }
+            })
+            .collect(),
+        NpyType::F64 => bytes
+            .chunks_exact(8)
+            .map(|x| {
+                let x = x.try_into().unwrap();
+                (if little_endian { f64::from_le_bytes(x) } else { f64::from_be_bytes(x) }) as f32
+            })
+            .collect(),
+    };
+    if fortran_order {
+        d = (0..n * n).map(|index| d[n * (index % n) + index / n]).collect();
+    }
+    Ok(d)
+}
+
+/// Writes the `n * n` matrix `d` as a little-endian `.npy` array of `dtype` in C order.
+pub fn write_npy(mut writer: impl Write, d: &[f32], n: usize, dtype: NpyType) -> io::Result<()> {
+    check_len(d, n)?;
+    let descr = match dtype {
+        NpyType::F32 => "<f4",
+        NpyType::F64 => "<f8",
+    };
+    let header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}", descr, n, n);
+    // Padded with spaces so that the data starts at a multiple of 64 bytes, ending with a newline.
+    let padded = (NPY_MAGIC.len() + 4 + header.len() + 1).next_multiple_of(64) - NPY_MAGIC.len() - 4;
+    let header = format!("{:<width$}\n", header, width = padded - 1);
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    match dtype {
+        NpyType::F32 => d.iter().try_for_each(|x| writer.write_all(&x.to_le_bytes())),
+        NpyType::F64 => d.iter().try_for_each(|&x| writer.write_all(&(x as f64).to_le_bytes())),
+    }
+}
+
+/// Reads one row of comma separated numbers per line, skipping empty lines. Infinities are `inf`.
+pub fn read_csv(reader: impl BufRead) -> io::Result<(usize, Vec<f32>)> {
+    let mut d = Vec::new();
+    let mut rows = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let len = d.len();
+        for field in line.split(',') {
+            d.push(parse(Some(field.trim()), &line)?);
+        }
+        if rows > 0 && d.len() - len != len / rows {
+            let message = format!("row {} has {} columns, expected {}", rows + 1, d.len() - len, len / rows);
+            return Err(invalid_data(message));
+        }
+        rows += 1;
+    }
+    let n = check_square(rows, d.len().checked_div(rows).unwrap_or(0))?;
+    Ok((n, d))
+}
+
+/// Writes the `n * n` matrix `d` as one row of comma separated numbers per line.
+pub fn write_csv(mut writer: impl Write, d: &[f32], n: usize) -> io::Result<()> {
+    check_len(d, n)?;
+    for row in d.chunks(n.max(1)) {
+        let row: Vec<_> = row.iter().map(|x| x.to_string()).collect();
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+
+    /// Matrices with and without infinite elements, for both formats of MatrixMarket.
+    fn matrices() -> Vec<(usize, Vec<f32>)> {
+        let mut sparse = random_input(5);
+        sparse[3] = f32::INFINITY;
+        sparse[17] = f32::INFINITY;
+        vec![(0, vec![]), (1, vec![0.5]), (7, random_input(7)), (5, sparse)]
+    }
+
+    #[test]
+    fn npy_round_trips() {
+        for (n, d) in matrices() {
+            for dtype in [NpyType::F32, NpyType::F64] {
+                let mut bytes = Vec::new();
+                write_npy(&mut bytes, &d, n, dtype).unwrap();
+                assert_eq!(read_npy(&bytes[..]).unwrap(), (n, d.clone()), "n = {} {:?}", n, dtype);
+            }
+        }
+    }
+
+    #[test]
+    fn matrix_market_round_trips() {
+        for (n, d) in matrices() {
+            let mut bytes = Vec::new();
+            write_matrix_market(&mut bytes, &d, n).unwrap();
+            assert_eq!(read_matrix_market(&bytes[..]).unwrap(), (n, d), "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn npy_in_fortran_order_and_big_endian() {
+        let header = "{'descr': '>f4', 'fortran_order': True, 'shape': (2, 2), }\n";
+        let mut bytes = [&NPY_MAGIC[..], &[1, 0], &(header.len() as u16).to_le_bytes(), header.as_bytes()].concat();
+        for x in [1.0f32, 2.0, 3.0, 4.0] {
+            bytes.extend(x.to_be_bytes());
+        }
+        assert_eq!(read_npy(&bytes[..]).unwrap(), (2, vec![1.0, 3.0, 2.0, 4.0]));
+    }
+
+    fn assert_invalid<T: std::fmt::Debug>(result: io::Result<T>) {
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Sizes in headers that the rest of the file does not have are errors, not allocations of them.
+    #[test]
+    fn truncated_and_oversized_headers_are_invalid() {
+        let npy = |header: &str| [&NPY_MAGIC[..], &[1, 0], &(header.len() as u16).to_le_bytes(), header.as_bytes()].concat();
+        assert_invalid(read_npy(&npy("{'descr': '<f4', 'fortran_order': False, 'shape': (300000, 300000), }")[..]));
+        let mut short = Vec::new();
+        write_npy(&mut short, &random_input(4), 4, NpyType::F64).unwrap();
+        short.truncate(short.len() - 1);
+        assert_invalid(read_npy(&short[..]));
+        let overflowing = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({0}, {0}), }}", usize::MAX);
+        assert_invalid(read_npy(&npy(&overflowing)[..]));
+        assert_invalid(read_npy(&npy("{'descr': '<i4', 'fortran_order': False, 'shape': (1, 1), }")[..]));
+        assert_invalid(read_npy(&npy("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }")[..]));
+        // A header longer than the file.
+        assert_invalid(read_npy(&[&NPY_MAGIC[..], &[2, 0], &u32::MAX.to_le_bytes()].concat()[..]));
+        assert_invalid(read_npy(&b"\x93NUMPX\x01\x00\x00\x00"[..]));
+
+        assert_invalid(read_matrix_market(&b"%%MatrixMarket matrix array real general\n300000 300000\n1\n"[..]));
+        assert_invalid(read_matrix_market(&b"%%MatrixMarket matrix coordinate real general\n3 3 2\n1 1 1\n"[..]));
+        assert_invalid(read_matrix_market(&b"%%MatrixMarket matrix coordinate real general\n3 3 1\n4 1 1\n"[..]));
+        assert_invalid(read_matrix_market(&b"%%MatrixMarket matrix array real general\n2 3\n"[..]));
+        let huge = format!("%%MatrixMarket matrix coordinate real general\n{0} {0} 0\n", 1usize << 31);
+        assert_invalid(read_matrix_market(huge.as_bytes()));
+    }
+}
@@ -0,0 +1,156 @@
+use std::ffi::{c_char, CString};
+use std::fs::File;
+use std::io;
+use std::os::fd::FromRawFd;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use super::{file_len, header, imp, invalid_data, parse_header, HEADER_LEN};
+use crate::StepError;
+
+extern "C" {
+    fn shm_open(name: *const c_char, oflag: i32, ...) -> i32;
+    fn shm_unlink(name: *const c_char) -> i32;
+}
+
+const O_RDWR: i32 = 2;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const O_CREAT: i32 = 0o100;
+#[cfg(target_vendor = "apple")]
+const O_CREAT: i32 = 0x200;
+
+/// The byte of the header of a `Region` with its `u32` state, and the one with the `i32` status
+/// of the last request.
+const STATE: usize = 24;
+const STATUS: usize = 28;
+
+/// The states of a `Region`: the client writes `d` and then sets `REQUEST`, the server replaces
+/// `d` with its step and the status and then sets `DONE`, and stops once the client sets `SHUTDOWN`.
+/// A new region is `IDLE`.
+pub const IDLE: u32 = 0;
+pub const REQUEST: u32 = 1;
+pub const DONE: u32 = 2;
+pub const SHUTDOWN: u32 = 3;
+
+/// How many times `serve` checks the state in a loop after a request before it sleeps, which
+/// answers requests that come in quick succession without a delay.
+const SPINS: u32 = 1 << 16;
+const SLEEP: Duration = Duration::from_micros(100);
+
+/// `name` with the leading slash of portable POSIX shared memory object names.
+fn object_name(name: &str) -> io::Result<CString> {
+    let name = if name.starts_with('/') { name.to_string() } else { format!("/{}", name) };
+    CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "a NUL in the shared memory name"))
+}
+
+/// A POSIX shared memory object holding a matrix in the layout of a matrix file of `Matrix`, so
+/// that other processes can have the kernels replace it with its step, with two more words in its
+/// header: the state at byte 24, one of `IDLE`, `REQUEST`, `DONE` and `SHUTDOWN`, and at byte 28
+/// the status code of the last request, `STEP_OK` or another of `shortcut.h`. Clients that cannot
+/// use atomics should write the state last and read it first with volatile accesses.
+pub struct Region {
+    n: usize,
+    map: imp::Map,
+}
+
+impl Region {
+    /// Maps the existing shared memory object `name`, such as `/shortcut` or `shortcut`.
+    pub fn open(name: &str) -> io::Result<Region> {
+        let name = object_name(name)?;
+        let fd = unsafe { shm_open(name.as_ptr(), O_RDWR, 0u32) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let len = file.metadata()?.len();
+        if len < HEADER_LEN as u64 {
+            return Err(invalid_data(format!("a shared memory object of {} bytes has no header", len)));
+        }
+        let mut map = imp::Map::new(file, len as usize, true)?;
+        let n = parse_header(map.bytes_mut()[..HEADER_LEN].try_into().unwrap())?;
+        let expected = file_len(n)?;
+        if len != expected as u64 {
+            return Err(invalid_data(format!("expected a shared memory object of {} bytes for n = {}", expected, n)));
+        }
+        Ok(Region { n, map })
+    }
+
+    /// Creates or replaces the shared memory object `name` for an `n * n` matrix of zeros, `IDLE`.
+    pub fn create(name: &str, n: usize) -> io::Result<Region> {
+        let len = file_len(n)?;
+        let object = object_name(name)?;
+        // Made anew rather than truncated, which macOS allows only once for each object.
+        unsafe { shm_unlink(object.as_ptr()) };
+        let fd = unsafe { shm_open(object.as_ptr(), O_RDWR | O_CREAT, 0o600u32) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(len as u64)?;
+        let mut map = imp::Map::new(file, len, true)?;
+        map.bytes_mut()[..HEADER_LEN].copy_from_slice(&header(n));
+        Ok(Region { n, map })
+    }
+
+    /// Removes the shared memory object `name`, which stays mapped in the processes that have it.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        let name = object_name(name)?;
+        if unsafe { shm_unlink(name.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        let data = &self.map.bytes()[HEADER_LEN..];
+        unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), self.n * self.n) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        let data = &mut self.map.bytes_mut()[HEADER_LEN..];
+        unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr().cast(), self.n * self.n) }
+    }
+
+    /// The state, which other processes change at any time.
+    pub fn state(&self) -> &AtomicU32 {
+        unsafe { &*self.map.as_ptr().add(STATE).cast() }
+    }
+
+    /// The status code of the last request, valid once the state is `DONE`.
+    pub fn status(&self) -> &AtomicI32 {
+        unsafe { &*self.map.as_ptr().add(STATUS).cast() }
+    }
+
+    /// Answers requests with `step` until the state is `SHUTDOWN`, on the calling thread. Waits
+    /// for them by checking the state, first in a loop and then every 100 µs.
+    pub fn serve<F>(&mut self, mut step: F)
+    where
+        F: FnMut(&mut [f32], usize) -> Result<(), StepError>,
+    {
+        let mut idle = 0;
+        loop {
+            match self.state().load(Ordering::Acquire) {
+                REQUEST => {
+                    let n = self.n;
+                    let d = self.as_mut_slice();
+                    let status = crate::step_c_abi::catch_status(AssertUnwindSafe(|| step(d, n)));
+                    self.status().store(status, Ordering::Relaxed);
+                    self.state().store(DONE, Ordering::Release);
+                    idle = 0;
+                }
+                SHUTDOWN => return,
+                _ if idle < SPINS => {
+                    idle += 1;
+                    std::hint::spin_loop();
+                }
+                _ => thread::sleep(SLEEP),
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+#[cfg(feature = "std")]
+use crate::scratch::Scratch;
+use crate::strided_len;
+#[cfg(feature = "std")]
+use crate::v5_more_register_reuse::pack_simd;
+
+/// `d`, a row-major `rows * cols` matrix, with each row padded with `value` to `width` elements,
+/// the smallest multiple of `multiple` that is at least `cols`, as the kernels read them `multiple`
+/// lanes at a time. Returns the padded matrix and `width`.
+///
+/// Panics if `d` does not have `rows * cols` elements or `multiple` is zero.
+pub fn pad_to_multiple(d: &[f32], rows: usize, cols: usize, multiple: usize, value: f32) -> (Vec<f32>, usize) {
+    assert_eq!(Some(d.len()), rows.checked_mul(cols), "d.len() must be rows * cols");
+    assert!(multiple > 0, "multiple must be positive");
+    let width = cols.div_ceil(multiple) * multiple;
+    let mut padded = vec![value; rows * width];
+    if cols > 0 {
+        for (row, d_row) in padded.chunks_mut(width).zip(d.chunks(cols)) {
+            row[..cols].copy_from_slice(d_row);
+        }
+    }
+    (padded, width)
+}
+
+/// The `cols * rows` transpose of the row-major `rows * cols` matrix `d`, such as the copy of `d`
+/// whose rows are the columns that `step` reads. It is transposed in tiles of 8 * 8, with AVX if
+/// the CPU has it, so that both reading and writing stay within the L1 cache. The tiles are read
+/// with aligned loads if `d` is aligned to 32 bytes and `cols` is a multiple of 8.
+///
+/// Panics if `d` does not have `rows * cols` elements.
+pub fn transpose_blocked(d: &[f32], rows: usize, cols: usize) -> Vec<f32> {
+    assert_eq!(Some(d.len()), rows.checked_mul(cols), "d.len() must be rows * cols");
+    let mut t = vec![0.0; rows * cols];
+    transpose_into(&mut t, rows, d, cols, rows, cols);
+    t
+}
+
+/// Every `lanes` consecutive rows of the row-major `rows * cols` matrix `d` interleaved element by
+/// element, so that the `lanes` elements of a column of such a block of rows are consecutive and
+/// can be loaded as one vector, like the packed copies of `v5` and `v7` with `lanes = 8`. The rows
+/// are padded with rows of `value` to a multiple of `lanes`, so that block `b` starts at element
+/// `b * cols * lanes`.
+///
+/// Panics if `d` does not have `rows * cols` elements or `lanes` is zero.
+pub fn interleave_rows(d: &[f32], rows: usize, cols: usize, lanes: usize, value: f32) -> Vec<f32> {
+    assert_eq!(Some(d.len()), rows.checked_mul(cols), "d.len() must be rows * cols");
+    assert!(lanes > 0, "lanes must be positive");
+    let mut out = vec![0.0; rows.div_ceil(lanes) * lanes * cols];
+    interleave_into(&mut out, d, cols, rows, cols, lanes, value);
+    out
+}
+
+/// An `n * n` matrix in the order that the kernels of `v5` and `v6` compute from, which they
+/// otherwise build from `d` on every call: each block of 8 rows interleaved as by `interleave_rows`,
+/// and each block of 8 columns likewise, so that `step_preinterleaved` can start computing right
+/// away. Holds both copies, each of `n.div_ceil(8) * 8 * n` elements.
+#[cfg(feature = "std")]
+pub struct InterleavedMatrix {
+    n: usize,
+    pub(crate) scratch: Scratch,
+}
+
+#[cfg(feature = "std")]
+impl InterleavedMatrix {
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Replaces the matrix with `d`, reusing the copies.
+    ///
+    /// Panics if `d` does not have `n * n` elements.
+    pub fn update(&mut self, d: &[f32]) {
+        assert_eq!(d.len(), self.n * self.n, "d.len() must be n * n");
+        pack_simd(&mut self.scratch, d, self.n);
+    }
+
+    /// Sets element `(i, j)` of the matrix to `x`, in both copies, for data that changes a few
+    /// elements at a time.
+    ///
+    /// Panics if `i` or `j` is not less than `n`.
+    pub fn set(&mut self, i: usize, j: usize, x: f32) {
+        let n = self.n;
+        assert!(i < n && j < n, "({}, {}) is out of range for n = {}", i, j, n);
+        self.scratch.vd.as_mut_slice()[8*n*(i / 8) + 8*j + i % 8] = x;
+        self.scratch.vt.as_mut_slice()[8*n*(j / 8) + 8*i + j % 8] = x;
+    }
+}
+
+/// The `InterleavedMatrix` of the `n * n` matrix `d`.
+///
+/// Panics if `d` does not have `n * n` elements.
+#[cfg(feature = "std")]
+pub fn to_interleaved(d: &[f32], n: usize) -> InterleavedMatrix {
+    assert_eq!(Some(d.len()), n.checked_mul(n), "d.len() must be n * n");
+    let mut scratch = Scratch::default();
+    pack_simd(&mut scratch, d, n);
+    InterleavedMatrix { n, scratch }
+}
+
+/// Like `interleave_rows`, for `d` with rows `ld_d` elements apart, into the first
+/// `rows.div_ceil(lanes) * cols * lanes` elements of `out`.
+pub(crate) fn interleave_into(out: &mut [f32], d: &[f32], ld_d: usize, rows: usize, cols: usize, lanes: usize, value: f32) {
+    if rows == 0 || cols == 0 {
+        return;
+    }
+    for (block, out_block) in out.chunks_mut(cols * lanes).take(rows.div_ceil(lanes)).enumerate() {
+        let block_rows = lanes.min(rows - lanes * block);
+        transpose_into(out_block, lanes, &d[ld_d * lanes * block..], ld_d, block_rows, cols);
+        if block_rows < lanes {
+            out_block.chunks_mut(lanes).for_each(|column| column[block_rows..].fill(value));
+        }
+    }
+}
+
+/// Writes the transpose of the `rows * cols` matrix `d`, with rows `ld_d` elements apart, into
+/// the `cols * rows` matrix `out`, with rows `ld_out` elements apart, leaving the elements between
+/// the rows of `out` as they were.
+pub(crate) fn transpose_into(out: &mut [f32], ld_out: usize, d: &[f32], ld_d: usize, rows: usize, cols: usize) {
+    if rows == 0 || cols == 0 {
+        return;
+    }
+    assert!(ld_out >= rows && out.len() >= strided_len(ld_out, cols, rows), "out is too short");
+    assert!(ld_d >= cols && d.len() >= strided_len(ld_d, rows, cols), "d is too short");
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && has_avx() {
+        return unsafe { x86::transpose_avx(out, ld_out, d, ld_d, rows, cols) };
+    }
+    unsafe { transpose_tiles::<Scalar>(out, ld_out, d, ld_d, rows, cols) }
+}
+
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+fn has_avx() -> bool {
+    is_x86_feature_detected!("avx")
+}
+
+#[cfg(all(not(feature = "std"), target_arch = "x86_64"))]
+fn has_avx() -> bool {
+    cfg!(target_feature = "avx")
+}
+
+const TILE: usize = 8;
+/// The tiles are transposed in blocks of `BLOCK * BLOCK` elements, whose rows in `d` and in `out`
+/// fit in the L1 cache together, so that each cache line of `out` is completed before it is evicted.
+const BLOCK: usize = 64;
+/// The columns are transposed in panels of `PANEL`, so that the pages of the rows of `out` they
+/// are written to stay in the TLB.
+const PANEL: usize = 512;
+
+/// Transposes the `TILE * TILE` tile at element `src_at` of `src`, with rows `ld_src` elements
+/// apart, to element `dst_at` of `dst`, with rows `ld_dst` apart.
+trait Tile {
+    unsafe fn transpose(dst: &mut [f32], dst_at: usize, ld_dst: usize, src: &[f32], src_at: usize, ld_src: usize);
+}
+
+/// With bounds checks on every element, which is what `simd::PARANOID` uses.
+struct Scalar;
+
+impl Tile for Scalar {
+    #[inline(always)]
+    unsafe fn transpose(dst: &mut [f32], dst_at: usize, ld_dst: usize, src: &[f32], src_at: usize, ld_src: usize) {
+        for i in 0..TILE {
+            for j in 0..TILE {
+                dst[dst_at + ld_dst*j + i] = src[src_at + ld_src*i + j];
+            }
+        }
+    }
+}
+
+/// `transpose_into` with `T` for the whole tiles, and element by element for the partial tiles
+/// at the right and bottom edges. The lengths must have been checked by `transpose_into`.
+#[inline(always)]
+unsafe fn transpose_tiles<T: Tile>(out: &mut [f32], ld_out: usize, d: &[f32], ld_d: usize, rows: usize, cols: usize) {
+    let (full_rows, full_cols) = (rows / TILE * TILE, cols / TILE * TILE);
+    for panel in (0..full_cols).step_by(PANEL) {
+        for i0 in (0..full_rows).step_by(BLOCK) {
+            for j0 in (panel..full_cols.min(panel + PANEL)).step_by(BLOCK) {
+                for i in (i0..full_rows.min(i0 + BLOCK)).step_by(TILE) {
+                    for j in (j0..full_cols.min(j0 + BLOCK)).step_by(TILE) {
+                        T::transpose(out, ld_out*j + i, ld_out, d, ld_d*i + j, ld_d);
+                    }
+                }
+            }
+        }
+    }
+    for i in 0..rows {
+        for j in if i < full_rows { full_cols..cols } else { 0..cols } {
+            out[ld_out*j + i] = d[ld_d*i + j];
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{transpose_tiles, Tile};
+
+    /// Reads the tiles with aligned loads if `ALIGNED`.
+    struct Avx<const ALIGNED: bool>;
+
+    impl<const ALIGNED: bool> Tile for Avx<ALIGNED> {
+        /// Interleaves pairs of rows, then pairs of pairs, and then swaps the 128-bit halves.
+        #[inline(always)]
+        unsafe fn transpose(dst: &mut [f32], dst_at: usize, ld_dst: usize, src: &[f32], src_at: usize, ld_src: usize) {
+            let (dst, src) = (dst.as_mut_ptr().add(dst_at), src.as_ptr().add(src_at));
+            let mut r = [_mm256_setzero_ps(); 8];
+            for (i, v) in r.iter_mut().enumerate() {
+                let p = src.add(ld_src * i);
+                *v = if ALIGNED { _mm256_load_ps(p) } else { _mm256_loadu_ps(p) };
+            }
+            let mut t = r;
+            for i in (0..8).step_by(2) {
+                t[i] = _mm256_unpacklo_ps(r[i], r[i + 1]);
+                t[i + 1] = _mm256_unpackhi_ps(r[i], r[i + 1]);
+            }
+            let mut s = t;
+            for i in (0..8).step_by(4) {
+                for pair in 0..2 {
+                    s[i + 2*pair] = _mm256_shuffle_ps::<0x44>(t[i + pair], t[i + pair + 2]);
+                    s[i + 2*pair + 1] = _mm256_shuffle_ps::<0xee>(t[i + pair], t[i + pair + 2]);
+                }
+            }
+            for (i, (&low, &high)) in s[..4].iter().zip(&s[4..]).enumerate() {
+                _mm256_storeu_ps(dst.add(ld_dst * i), _mm256_permute2f128_ps::<0x20>(low, high));
+                _mm256_storeu_ps(dst.add(ld_dst * (i + 4)), _mm256_permute2f128_ps::<0x31>(low, high));
+            }
+        }
+    }
+
+    /// Every tile of `d` starts at a multiple of 8 elements into a row, so they are all aligned to
+    /// 32 bytes if `d` is and its rows are a multiple of 8 elements apart.
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn transpose_avx(out: &mut [f32], ld_out: usize, d: &[f32], ld_d: usize, rows: usize, cols: usize) {
+        if (d.as_ptr() as usize).is_multiple_of(32) && ld_d.is_multiple_of(8) {
+            transpose_tiles::<Avx<true>>(out, ld_out, d, ld_d, rows, cols)
+        } else {
+            transpose_tiles::<Avx<false>>(out, ld_out, d, ld_d, rows, cols)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shapes with partial tiles, vectors and blocks of rows at both edges, and more than `BLOCK`
+    /// and `PANEL` columns.
+    const SHAPES: [(usize, usize); 10] =
+        [(0, 3), (3, 0), (1, 1), (3, 5), (8, 8), (9, 17), (17, 9), (65, 70), (7, 600), (600, 7)];
+
+    fn matrix(rows: usize, cols: usize) -> Vec<f32> {
+        (0..rows * cols).map(|x| x as f32).collect()
+    }
+
+    #[test]
+    fn pad_to_multiple_round_trips() {
+        for (rows, cols) in SHAPES {
+            for multiple in [1, 4, 8, 16] {
+                let d = matrix(rows, cols);
+                let (padded, width) = pad_to_multiple(&d, rows, cols, multiple, -1.0);
+                assert!(width >= cols && width < cols + multiple && width % multiple == 0);
+                assert_eq!(padded.len(), rows * width);
+                for (row, d_row) in padded.chunks(width.max(1)).zip(d.chunks(cols.max(1))) {
+                    assert_eq!(&row[..cols], d_row, "{} * {} to {}", rows, cols, multiple);
+                    assert!(row[cols..].iter().all(|&x| x == -1.0), "{} * {} to {}", rows, cols, multiple);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transpose_blocked_round_trips() {
+        for (rows, cols) in SHAPES {
+            let d = matrix(rows, cols);
+            // An aligned copy of `d` too, for the aligned loads of the AVX tiles.
+            let mut buffer = vec![0.0; d.len() + 8];
+            let offset = buffer.as_ptr().align_offset(32).min(8);
+            buffer[offset..offset + d.len()].copy_from_slice(&d);
+            for d in [&d[..], &buffer[offset..offset + d.len()]] {
+                let t = transpose_blocked(d, rows, cols);
+                for i in 0..rows {
+                    for j in 0..cols {
+                        assert_eq!(t[rows*j + i], d[cols*i + j], "{} * {} at ({}, {})", rows, cols, i, j);
+                    }
+                }
+                assert_eq!(transpose_blocked(&t, cols, rows), d, "{} * {}", rows, cols);
+            }
+        }
+    }
+
+    #[test]
+    fn interleave_rows_round_trips() {
+        for (rows, cols) in SHAPES {
+            for lanes in [1, 3, 8, 16] {
+                let d = matrix(rows, cols);
+                let out = interleave_rows(&d, rows, cols, lanes, -1.0);
+                assert_eq!(out.len(), rows.div_ceil(lanes) * lanes * cols);
+                let mut back = vec![0.0; rows * cols];
+                for (x, &y) in out.iter().enumerate() {
+                    let (block, j, lane) = (x / (cols * lanes), x / lanes % cols, x % lanes);
+                    match lanes * block + lane {
+                        i if i < rows => back[cols*i + j] = y,
+                        _ => assert_eq!(y, -1.0, "{} * {} in {} lanes", rows, cols, lanes),
+                    }
+                }
+                assert_eq!(back, d, "{} * {} in {} lanes", rows, cols, lanes);
+            }
+        }
+    }
+}
@@ -0,0 +1,992 @@
+#![cfg_attr(feature = "portable-simd", feature(portable_simd))]
+// Without `std` there are no threads, files or runtime CPU feature detection, which leaves the
+// kernels up to `v2`, `v_portable_simd` and the C ABI for them.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+// The paths into `std` that the kernels use are all in `core` too.
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec, vec::Vec};
+use std::fmt;
+
+#[cfg(feature = "arrow")]
+pub use arrow_io::{
+    apsp_record_batch, arrow_from_matrix, matrix_from_arrow, matrix_from_record_batch, record_batch_from_matrix,
+    step_record_batch,
+};
+#[cfg(feature = "tokio")]
+pub use async_step::step_async;
+pub use batch::{step_batch, MatrixMut};
+#[cfg(feature = "std")]
+pub use bench::bench_inputs;
+pub use cancel::CancelToken;
+#[cfg(feature = "std")]
+pub use compensated::Compensated;
+#[cfg(feature = "std")]
+pub use context::StepContext;
+#[cfg(feature = "std")]
+pub use half::{Bf16, F16};
+#[cfg(feature = "std")]
+pub use matrix::{Element, Matrix, MatrixView, MatrixViewMut};
+#[cfg(all(feature = "nalgebra", feature = "std"))]
+pub use nalgebra_step::apsp_dmatrix;
+#[cfg(feature = "nalgebra")]
+pub use nalgebra_step::step_dmatrix;
+#[cfg(feature = "ndarray")]
+pub use ndarray_step::step_ndarray;
+#[cfg(feature = "count-ops")]
+pub use ops::{count_ops, OpCounts};
+pub use prepared::PreparedMatrix;
+#[cfg(feature = "std")]
+pub use quantized::{dequantize_u8, quantize_u8, U8_INFINITY};
+pub use threads::ThreadConfig;
+#[cfg(feature = "timings")]
+pub use timings::{time_phases, PhaseTimings};
+#[cfg(feature = "std")]
+pub use variants::estimate_memory;
+use semiring::{MinPlus, Semiring};
+
+#[cfg(feature = "std")]
+pub mod alloc;
+#[cfg(feature = "std")]
+pub mod apsp;
+#[cfg(feature = "arrow")]
+mod arrow_io;
+#[cfg(feature = "tokio")]
+mod async_step;
+mod batch;
+#[cfg(feature = "std")]
+pub mod bench;
+mod cancel;
+#[cfg(feature = "std")]
+mod compensated;
+#[cfg(feature = "serde")]
+pub mod config;
+#[cfg(feature = "std")]
+mod context;
+#[cfg(feature = "cpp-compare")]
+pub mod cpp;
+#[cfg(feature = "cuda")]
+pub mod cuda;
+#[cfg(all(feature = "std", unix))]
+pub mod daemon;
+pub mod dispatch;
+#[cfg(feature = "energy")]
+pub mod energy;
+pub mod float;
+mod fused;
+#[cfg(feature = "std")]
+pub mod gen;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+#[cfg(feature = "std")]
+pub mod graph;
+#[cfg(feature = "std")]
+mod half;
+#[cfg(feature = "std")]
+pub mod harness;
+#[cfg(feature = "std")]
+pub mod incremental;
+#[cfg(feature = "std")]
+mod integer;
+#[cfg(feature = "std")]
+pub mod io;
+pub mod layout;
+#[cfg(feature = "std")]
+mod matrix;
+mod matvec;
+pub mod metrics;
+#[cfg(feature = "nalgebra")]
+mod nalgebra_step;
+#[cfg(feature = "ndarray")]
+mod ndarray_step;
+#[cfg(feature = "node")]
+// The `#[napi]` exports are only registered with Node.js outside of tests.
+#[cfg_attr(test, allow(dead_code))]
+mod node;
+#[cfg(feature = "numa")]
+pub mod numa;
+mod ops;
+#[cfg(feature = "perf")]
+pub mod perf;
+mod prepared;
+#[cfg(feature = "std")]
+pub mod properties;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "std")]
+mod quantized;
+#[cfg(feature = "std")]
+pub mod reference;
+#[cfg(feature = "std")]
+pub mod registry;
+#[cfg(feature = "std")]
+pub mod roofline;
+#[cfg(feature = "std")]
+mod scratch;
+pub mod semiring;
+#[cfg(feature = "service")]
+pub mod service;
+mod simd;
+pub mod sparse;
+// Its `step` would clash with the JavaScript `step` of `wasm`.
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+// Nothing returns `STEP_PANICKED` without `std`, but C callers still see it in `shortcut.h`.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+mod step_c_abi;
+mod threads;
+#[cfg(feature = "std")]
+pub mod tiled;
+#[cfg(feature = "timings")]
+mod timings;
+#[cfg(feature = "std")]
+pub mod topology;
+mod trace;
+#[cfg(feature = "std")]
+pub mod tune;
+mod v0_cpp_port;
+#[cfg(feature = "std")]
+mod v4_register_reuse;
+#[cfg(feature = "std")]
+mod v5_more_register_reuse;
+#[cfg(feature = "std")]
+mod v7_cache_reuse;
+#[cfg(feature = "portable-simd")]
+pub mod v_portable_simd;
+#[cfg(feature = "std")]
+mod v_recursive;
+#[cfg(feature = "std")]
+mod v_transpose_free;
+pub mod variants;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+
+macro_rules! variant_modules {
+    ($($variant:ident),*) => {
+        $(
+            #[doc = concat!("`", stringify!($variant), "::step` is `variants::", stringify!($variant), "`.")]
+            pub mod $variant {
+                pub use crate::variants::$variant as step;
+            }
+        )*
+    };
+}
+
+variant_modules!(v0, v1, v2);
+#[cfg(feature = "std")]
+variant_modules!(v3, v4, v5, v6, v7);
+
+/// Whether `step_with_options` may trade reproducibility for speed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Determinism {
+    /// The fastest kernel of the CPU, whose results do not depend on the number of threads, but
+    /// where a minimum of zeros of both signs, or of sums including NaN, may depend on the CPU
+    /// unless `StepOptions::nan_policy` is set.
+    #[default]
+    Fast,
+    /// The scalar kernel, which reduces each element over `k` in increasing order, so that the
+    /// results are bit-identical on every CPU, at the cost of not using SIMD. Ignores `numa_policy`.
+    Strict,
+}
+
+impl Determinism {
+    pub(crate) fn kernel(self) -> dispatch::Kernel {
+        match self {
+            Determinism::Fast => dispatch::selected(),
+            Determinism::Strict => dispatch::Kernel::Scalar,
+        }
+    }
+}
+
+/// What `step_with_options` does with NaN in `d`, the same on every CPU and with every kernel,
+/// unlike the minimum of the vector instructions, which some CPUs take from either operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum NanPolicy {
+    /// An element of `r` is NaN if any of its sums has a NaN of `d`, that is, if row `i` or
+    /// column `j` of `d` has one, and otherwise as with `Ignore`.
+    Propagate,
+    /// NaN is a missing edge: the sums with it are skipped, as are those of `f32::INFINITY` and
+    /// `-f32::INFINITY` like with `inf_aware`, and an element with only such sums is infinite.
+    Ignore,
+    /// Returns `StepError::NaN` for the first NaN of `d`, leaving `r` as it was.
+    Error,
+}
+
+/// How the elements of an `n * n` matrix are ordered in its slice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Layout {
+    /// Row by row, element `(i, j)` at `n*i + j`, as everywhere else in this crate.
+    #[default]
+    RowMajor,
+    /// Column by column, element `(i, j)` at `i + n*j`, as in Fortran, BLAS and Eigen.
+    ColMajor,
+}
+
+/// Options for `step_with_options`.
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct StepOptions {
+    /// Treats `f32::INFINITY` as a missing edge, so that a sum with it is infinite even if the other
+    /// term is `-f32::INFINITY`, instead of NaN.
+    pub inf_aware: bool,
+    /// How NaN in `d` is handled, or however the kernel happens to if `None`, see `Determinism::Fast`.
+    pub nan_policy: Option<NanPolicy>,
+    pub determinism: Determinism,
+    #[cfg(feature = "numa")]
+    pub numa_policy: numa::NumaPolicy,
+    /// Which CPUs the threads run on. Anything but `Affinity::None` replaces the placement of
+    /// `NumaPolicy::Local`.
+    #[cfg(feature = "std")]
+    pub affinity: topology::Affinity,
+    /// How many vectors ahead the blocked kernel of `v7` prefetches, instead of `tune::Tuning::prefetch`.
+    /// If this, `streaming_stores` or `schedule` is set, that kernel replaces the `dispatch` one on
+    /// CPUs with AVX2, unless `inf_aware`, `progress`, `cancel`, `numa_policy` or
+    /// `Determinism::Strict` is set.
+    pub prefetch: Option<usize>,
+    /// Whether the blocked kernel of `v7` writes `r` with non-temporal stores, instead of
+    /// `tune::Tuning::streaming_stores`, see `prefetch`.
+    pub streaming_stores: Option<bool>,
+    /// The order of the blocks of the blocked kernel of `v7`, instead of `tune::Tuning::schedule`,
+    /// see `prefetch`.
+    #[cfg(feature = "std")]
+    pub schedule: Option<tune::Schedule>,
+    /// How the kernels of `dispatch` handle the ends of rows that are not a whole number of vectors,
+    /// instead of `tune::tail`. Ignored by the kernel of `v7` that `prefetch` selects.
+    pub tail: Option<dispatch::Tail>,
+    /// Lets the threads take this many chunks of rows of `r` at a time as they go, see
+    /// `ThreadConfig::grain`, to balance the work when other processes share the cores.
+    pub grain: Option<usize>,
+    /// Runs `sparse::step` instead of the dense kernels if `sparse::density(d)` is below this, and
+    /// none of `progress`, `cancel` and `nan_policy` is set. It skips the sums with `f32::INFINITY` like `inf_aware`.
+    pub sparse_threshold: Option<f32>,
+    /// Called on the calling thread after each block of rows of `r` with the fraction of rows done,
+    /// ending with `1.0`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<Box<dyn Fn(f32) + Send + Sync>>,
+    /// Stops before the next block of rows once cancelled, returning `StepError::Cancelled` and
+    /// leaving the remaining rows of `r` as they were.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: Option<CancelToken>,
+    /// The most bytes of copies of `d` the kernel may allocate, besides `r` and `d`. If its
+    /// `estimate_memory` is more, `d` is packed in panels that fit instead, at the cost of a copy
+    /// of `d` per panel of rows, or `StepError::MemoryBudget` returned if not even one row fits.
+    /// Ignores `sparse_threshold`, `numa_policy`, `prefetch`, `streaming_stores` and `schedule` then.
+    pub memory_budget: Option<usize>,
+}
+
+impl fmt::Debug for StepOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut options = f.debug_struct("StepOptions");
+        options.field("inf_aware", &self.inf_aware);
+        options.field("nan_policy", &self.nan_policy);
+        options.field("determinism", &self.determinism);
+        #[cfg(feature = "numa")]
+        options.field("numa_policy", &self.numa_policy);
+        #[cfg(feature = "std")]
+        options.field("affinity", &self.affinity);
+        options.field("prefetch", &self.prefetch);
+        options.field("streaming_stores", &self.streaming_stores);
+        #[cfg(feature = "std")]
+        options.field("schedule", &self.schedule);
+        options.field("tail", &self.tail);
+        options.field("grain", &self.grain);
+        options.field("sparse_threshold", &self.sparse_threshold);
+        options.field("progress", &self.progress.as_ref().map(|_| "Fn(f32)"));
+        options.field("cancel", &self.cancel);
+        options.field("memory_budget", &self.memory_budget).finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl StepOptions {
+    /// The cached `tune::Tuning` for size `n` with `prefetch`, `streaming_stores` and `schedule`
+    /// replaced by the ones that are set.
+    pub fn tuning(&self, n: usize) -> tune::Tuning {
+        let tuning = tune::tuning(n);
+        tune::Tuning {
+            prefetch: self.prefetch.unwrap_or(tuning.prefetch),
+            streaming_stores: self.streaming_stores.unwrap_or(tuning.streaming_stores),
+            schedule: self.schedule.unwrap_or(tuning.schedule),
+            ..tuning
+        }
+    }
+
+    /// All cores, pinned according to `affinity`, with `grain`.
+    pub(crate) fn threads(&self) -> ThreadConfig {
+        self.affinity.apply(&ThreadConfig { grain: self.grain, ..Default::default() })
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl StepOptions {
+    pub(crate) fn threads(&self) -> ThreadConfig {
+        ThreadConfig::default()
+    }
+}
+
+/// What `step_checked` rejects in `d` before running `step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checks {
+    pub nan: bool,
+    pub negative: bool,
+}
+
+impl Default for Checks {
+    fn default() -> Self {
+        Checks { nan: true, negative: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepError {
+    LengthMismatch { n: usize, r_len: usize, d_len: usize },
+    InvalidStride { n: usize, ld: usize, len: usize },
+    DimensionMismatch { m: usize, k: usize, n: usize, r_len: usize, a_len: usize, b_len: usize },
+    SizeOverflow { rows: usize, cols: usize },
+    /// Even the smallest panels of `StepOptions::memory_budget` take `needed` bytes.
+    MemoryBudget { needed: usize, budget: usize },
+    NaN { i: usize, j: usize },
+    Negative { i: usize, j: usize },
+    NotSymmetric { i: usize, j: usize },
+    /// From `apsp::bellman_ford`, for a vertex whose distance still shortened after `n` rounds.
+    NegativeCycle { vertex: usize },
+    /// From the `Matrix` functions, for a result in another layout than `d`.
+    LayoutMismatch { r: Layout, d: Layout },
+    /// From `step_u8`, for a scale that is not positive and finite, or an offset that is not finite.
+    InvalidScale,
+    IndexOutOfRange { index: u32, n: usize },
+    TopKMismatch { n: usize, k: usize, indices_len: usize, values_len: usize },
+    MaskMismatch { n: usize, mask_len: usize },
+    /// Only from the C ABI, for a null pointer to a matrix or handle.
+    NullPointer,
+    /// Only from the C ABI, for a pointer not aligned to its element type.
+    Misaligned,
+    Overlap,
+    UnknownVariant,
+    Cancelled,
+    /// Only from the C ABI, for the first element where `shortcut_selftest` found the kernel of the
+    /// CPU and `reference::step` to differ.
+    SelftestFailed { i: usize, j: usize },
+    /// Reading or writing a file failed, such as an `apsp` checkpoint.
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind),
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepError::LengthMismatch { n, r_len, d_len } => write!(
+                f,
+                "expected slices of length n * n = {} * {}, got r.len() = {} and d.len() = {}",
+                n, n, r_len, d_len
+            ),
+            StepError::InvalidStride { n, ld, len } => write!(
+                f,
+                "expected a leading dimension of at least n = {} and a slice of length at least ld * (n - 1) + n, \
+                 got ld = {} and a slice of length {}",
+                n, ld, len
+            ),
+            StepError::DimensionMismatch { m, k, n, r_len, a_len, b_len } => write!(
+                f,
+                "expected r, a and b of lengths m * n, m * k and k * n for m = {}, k = {}, n = {}, \
+                 got r.len() = {}, a.len() = {} and b.len() = {}",
+                m, k, n, r_len, a_len, b_len
+            ),
+            StepError::SizeOverflow { rows, cols } => {
+                write!(f, "a matrix of {} * {} elements does not fit in memory", rows, cols)
+            }
+            StepError::MemoryBudget { needed, budget } => {
+                write!(f, "the step needs at least {} bytes of copies, more than the budget of {}", needed, budget)
+            }
+            StepError::NaN { i, j } => write!(f, "d[{}][{}] is NaN", i, j),
+            StepError::Negative { i, j } => write!(f, "d[{}][{}] is a negative distance", i, j),
+            StepError::NotSymmetric { i, j } => write!(f, "d[{}][{}] differs from d[{}][{}]", i, j, j, i),
+            StepError::NegativeCycle { vertex } => {
+                write!(f, "vertex {} is reachable from a cycle of negative length", vertex)
+            }
+            StepError::InvalidScale => write!(f, "expected a positive finite scale and a finite offset"),
+            StepError::LayoutMismatch { r, d } => write!(f, "r is {:?} but d is {:?}", r, d),
+            StepError::IndexOutOfRange { index, n } => write!(f, "index {} is out of range for n = {}", index, n),
+            StepError::TopKMismatch { n, k, indices_len, values_len } => write!(
+                f,
+                "expected k <= n and indices and values of length n * k for n = {}, k = {}, \
+                 got indices.len() = {} and values.len() = {}",
+                n, k, indices_len, values_len
+            ),
+            StepError::MaskMismatch { n, mask_len } => write!(
+                f,
+                "expected a mask of n * n.div_ceil(64) words for n = {}, got mask.len() = {}",
+                n, mask_len
+            ),
+            StepError::NullPointer => write!(f, "a pointer is null"),
+            StepError::Misaligned => write!(f, "a pointer is not aligned to its element type"),
+            StepError::Overlap => write!(f, "the output matrix overlaps an input matrix, see step_in_place"),
+            StepError::UnknownVariant => {
+                write!(f, "unknown variant, expected one of")?;
+                #[cfg(feature = "std")]
+                for (name, _) in variants::VARIANTS {
+                    write!(f, " {},", name)?;
+                }
+                write!(f, " or auto, see list_variants")
+            }
+            StepError::Cancelled => write!(f, "the step was cancelled"),
+            StepError::SelftestFailed { i, j } => {
+                write!(f, "the kernel {} differs from the reference at r[{}][{}]", dispatch::selected().name(), i, j)
+            }
+            #[cfg(feature = "std")]
+            StepError::Io(kind) => write!(f, "i/o error: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for StepError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for StepError {
+    fn from(e: std::io::Error) -> Self {
+        StepError::Io(e.kind())
+    }
+}
+
+fn check_lengths<T>(r: &[T], d: &[T], n: usize) -> Result<(), StepError> {
+    let len = n.checked_mul(n);
+    if len != Some(r.len()) || len != Some(d.len()) {
+        return Err(StepError::LengthMismatch { n, r_len: r.len(), d_len: d.len() });
+    }
+    Ok(())
+}
+
+/// Length of the shortest slice holding `rows` rows of length `cols` that start `ld` elements apart.
+fn strided_len(ld: usize, rows: usize, cols: usize) -> usize {
+    if rows == 0 { 0 } else { ld * (rows - 1) + cols }
+}
+
+fn check_stride<T>(s: &[T], ld: usize, n: usize) -> Result<(), StepError> {
+    let needed = if n == 0 { Some(0) } else { ld.checked_mul(n - 1).and_then(|len| len.checked_add(n)) };
+    if ld < n || needed.is_none_or(|needed| s.len() < needed) {
+        return Err(StepError::InvalidStride { n, ld, len: s.len() });
+    }
+    Ok(())
+}
+
+pub fn step(r: &mut [f32], d: &[f32], n: usize) -> Result<(), StepError> {
+    step_with_threads(r, d, n, &ThreadConfig::default())
+}
+
+pub fn step_with_threads(r: &mut [f32], d: &[f32], n: usize, threads: &ThreadConfig) -> Result<(), StepError> {
+    metrics::record(n, || {
+        check_lengths(r, d, n)?;
+        metrics::chose(dispatch::selected().name());
+        dispatch::step(threads, r, d, n);
+        Ok(())
+    })
+}
+
+/// Like `step`, also returning whether any distance in `r` is shorter than in `d`. Once it is not,
+/// `r` equals `d` if the diagonal of `d` is zero, and `d` is a fixed point of `step`.
+pub fn step_changed(r: &mut [f32], d: &[f32], n: usize) -> Result<bool, StepError> {
+    let mut changed = false;
+    metrics::record(n, || {
+        check_lengths(r, d, n)?;
+        metrics::chose(dispatch::selected().name());
+        changed = dispatch::step_changed(&ThreadConfig::default(), r, d, n);
+        Ok(())
+    })?;
+    Ok(changed)
+}
+
+/// Like `step`, but first scans `d` for the values rejected by `checks` and returns an error
+/// for the first one found.
+pub fn step_checked(r: &mut [f32], d: &[f32], n: usize, checks: &Checks) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    for (index, &x) in d.iter().enumerate() {
+        let (i, j) = (index / n, index % n);
+        if checks.nan && x.is_nan() {
+            return Err(StepError::NaN { i, j });
+        }
+        if checks.negative && x < 0.0 {
+            return Err(StepError::Negative { i, j });
+        }
+    }
+    step(r, d, n)
+}
+
+pub fn step_with_options(r: &mut [f32], d: &[f32], n: usize, options: &StepOptions) -> Result<(), StepError> {
+    metrics::record(n, || step_with_nan_policy(r, d, n, options, options.determinism.kernel()))
+}
+
+/// `step_with_options` with `kernel` instead of that of `options.determinism`, where it runs one.
+fn step_with_nan_policy(
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    options: &StepOptions,
+    kernel: dispatch::Kernel,
+) -> Result<(), StepError> {
+    match options.nan_policy {
+        None => step_with_kernel_options(r, d, n, options, kernel, options.inf_aware),
+        Some(NanPolicy::Ignore) => step_with_kernel_options(r, d, n, options, kernel, true),
+        Some(NanPolicy::Propagate) => {
+            step_with_kernel_options(r, d, n, options, kernel, true)?;
+            propagate_nan(&options.threads(), r, d, n);
+            Ok(())
+        }
+        Some(NanPolicy::Error) => {
+            check_lengths(r, d, n)?;
+            if let Some(index) = d.iter().position(|x| x.is_nan()) {
+                return Err(StepError::NaN { i: index / n, j: index % n });
+            }
+            step_with_kernel_options(r, d, n, options, kernel, options.inf_aware)
+        }
+    }
+}
+
+/// Sets the elements of `r` whose row or column of `d` has a NaN to NaN, for `NanPolicy::Propagate`.
+fn propagate_nan(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    let rows: Vec<bool> = d.chunks(n.max(1)).map(|row| row.iter().any(|x| x.is_nan())).collect();
+    if !rows.contains(&true) {
+        return;
+    }
+    let mut cols = vec![false; n];
+    for row in d.chunks(n) {
+        for (col, x) in cols.iter_mut().zip(row) {
+            *col |= x.is_nan();
+        }
+    }
+    threads::for_each_chunk(threads, r, n, |i, r_row| {
+        for (x, &col) in r_row.iter_mut().zip(&cols) {
+            if rows[i] || col {
+                *x = f32::NAN;
+            }
+        }
+    });
+}
+
+/// `step_with_nan_policy` without `nan_policy`, with `inf_aware` instead of that of `options`.
+fn step_with_kernel_options(
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    options: &StepOptions,
+    kernel: dispatch::Kernel,
+    inf_aware: bool,
+) -> Result<(), StepError> {
+    let hooks = dispatch::Hooks {
+        progress: options.progress.as_deref().map(|progress| progress as _),
+        cancel: options.cancel.as_ref().map(CancelToken::flag),
+    };
+    let threads = options.threads();
+    let tail = options.tail.unwrap_or_else(|| kernel.tail(n));
+    let packed_bytes = simd::packed_bytes(n, n, n, kernel.lanes_with(tail));
+    if let Some(budget) = options.memory_budget.filter(|&budget| packed_bytes > budget) {
+        check_lengths(r, d, n)?;
+        metrics::chose(kernel.name());
+        return dispatch::step_in_panels(kernel, &threads, r, d, n, inf_aware, hooks, tail, budget);
+    }
+    if options.sparse_threshold.is_some_and(|threshold| sparse::density(d) < threshold)
+        && hooks.is_empty()
+        && options.nan_policy.is_none()
+    {
+        check_lengths(r, d, n)?;
+        metrics::chose("sparse");
+        sparse::step_with_threads(&threads, r, d, n);
+        return Ok(());
+    }
+    #[cfg(feature = "numa")]
+    if options.numa_policy != numa::NumaPolicy::None && options.determinism == Determinism::Fast {
+        check_lengths(r, d, n)?;
+        metrics::chose("numa");
+        return numa::step(&threads, r, d, n, inf_aware, options.numa_policy, hooks);
+    }
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if (options.prefetch.is_some() || options.streaming_stores.is_some() || options.schedule.is_some())
+        && !inf_aware
+        && hooks.is_empty()
+        && options.determinism == Determinism::Fast
+        && (simd::PARANOID || is_x86_feature_detected!("avx2"))
+    {
+        check_lengths(r, d, n)?;
+        metrics::chose("v7");
+        variants::v7_with_tuning(&threads, r, d, n, &options.tuning(n));
+        return Ok(());
+    }
+    if !inf_aware && hooks.is_empty() && options.determinism == Determinism::Fast && options.tail.is_none() {
+        return step_with_threads(r, d, n, &threads);
+    }
+    check_lengths(r, d, n)?;
+    metrics::chose(kernel.name());
+    kernel.step_with_hooks(&threads, r, d, n, inf_aware, hooks, tail)
+}
+
+/// Like `step`, for `r` and `d` both in `layout`. The step of the transpose of `d` is the transpose
+/// of the step of `d`, with the same sums in the same order, so a column-major `d` is simply read
+/// as the row-major transpose it is, by the same kernels and without copying it.
+pub fn step_with_layout(r: &mut [f32], d: &[f32], n: usize, layout: Layout) -> Result<(), StepError> {
+    match layout {
+        Layout::RowMajor | Layout::ColMajor => step(r, d, n),
+    }
+}
+
+/// `step` with `sparse::step` for `d` with fewer than `sparse::DENSITY_THRESHOLD` of its edges,
+/// see `StepOptions::sparse_threshold`.
+pub fn step_hybrid(r: &mut [f32], d: &[f32], n: usize) -> Result<(), StepError> {
+    step_with_options(r, d, n, &StepOptions { sparse_threshold: Some(sparse::DENSITY_THRESHOLD), ..Default::default() })
+}
+
+/// `step` that stops with `StepError::Cancelled` once `token` is cancelled, see `StepOptions::cancel`.
+pub fn step_cancellable(r: &mut [f32], d: &[f32], n: usize, token: &CancelToken) -> Result<(), StepError> {
+    step_with_options(r, d, n, &StepOptions { cancel: Some(token.clone()), ..Default::default() })
+}
+
+/// The first `(i, j)` with `i < j` for which `d[n*i + j]` differs from `d[n*j + i]`, if any.
+fn find_asymmetry(d: &[f32], n: usize) -> Option<(usize, usize)> {
+    (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).find(|&(i, j)| d[n*i + j] != d[n*j + i])
+}
+
+/// Whether the `n * n` matrix `d` equals its transpose, as for the distances of an undirected graph.
+/// A NaN is never equal to its mirror.
+pub fn is_symmetric(d: &[f32], n: usize) -> bool {
+    n.checked_mul(n) == Some(d.len()) && find_asymmetry(d, n).is_none()
+}
+
+/// Like `step` with `r` and `d` the same matrix, replacing `d` with its step without a second
+/// `n * n` matrix for the results. The transposed copy of `d` that the kernels read the columns
+/// from is still made up front, a temporary of `n * n` elements like the one `step` makes. Any step
+/// in place needs as much, since every row of the results reads all of the original `d`, so none
+/// of it can be overwritten until all rows are computed. Each block of rows is copied to a
+/// temporary of a few rows per thread just before it is overwritten.
+pub fn step_in_place(d: &mut [f32], n: usize) -> Result<(), StepError> {
+    check_lengths(d, d, n)?;
+    dispatch::step_in_place(&ThreadConfig::default(), d, n);
+    Ok(())
+}
+
+/// Like `step` for a symmetric `d`, whose result is symmetric too, doing about half the work by
+/// computing only the upper triangle of `r` and mirroring it.
+/// Returns `StepError::NotSymmetric` for the first pair found of `d` that differs from its mirror.
+pub fn step_symmetric(r: &mut [f32], d: &[f32], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    if let Some((i, j)) = find_asymmetry(d, n) {
+        return Err(StepError::NotSymmetric { i, j });
+    }
+    dispatch::step_symmetric(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// Like `step`, for `r` and `d` embedded in larger row-major buffers with rows `ld_r` and `ld_d` apart.
+pub fn step_strided(r: &mut [f32], ld_r: usize, d: &[f32], ld_d: usize, n: usize) -> Result<(), StepError> {
+    check_stride(r, ld_r, n)?;
+    check_stride(d, ld_d, n)?;
+    dispatch::step_strided(&ThreadConfig::default(), r, ld_r, d, ld_d, n);
+    Ok(())
+}
+
+/// The min-plus product of the `m * k` matrix `a` and the `k * n` matrix `b`, of which `step` is
+/// the special case `a = b = d`.
+pub fn minplus_gemm(r: &mut [f32], a: &[f32], b: &[f32], m: usize, k: usize, n: usize) -> Result<(), StepError> {
+    if m.checked_mul(n) != Some(r.len()) || m.checked_mul(k) != Some(a.len()) || k.checked_mul(n) != Some(b.len()) {
+        return Err(StepError::DimensionMismatch { m, k, n, r_len: r.len(), a_len: a.len(), b_len: b.len() });
+    }
+    dispatch::minplus_gemm(&ThreadConfig::default(), r, a, b, m, k, n);
+    Ok(())
+}
+
+/// Like `step` for only the rows `rows` of `d`, such as the sources of a few queries, writing the
+/// step of row `rows[s]` to row `s` of the `rows.len() * n` matrix `r`. This is `minplus_gemm` of
+/// those rows and `d`, which takes `rows.len() / n` of the time of `step` besides packing `d`.
+pub fn step_rows(r: &mut [f32], d: &[f32], rows: &[u32], n: usize) -> Result<(), StepError> {
+    check_lengths(d, d, n)?;
+    let m = rows.len();
+    if m.checked_mul(n) != Some(r.len()) {
+        return Err(StepError::DimensionMismatch { m, k: n, n, r_len: r.len(), a_len: m.saturating_mul(n), b_len: d.len() });
+    }
+    if let Some(&row) = rows.iter().find(|&&row| row as usize >= n) {
+        return Err(StepError::IndexOutOfRange { index: row, n });
+    }
+    dispatch::step_rows(&ThreadConfig::default(), r, d, rows, n);
+    Ok(())
+}
+
+/// The min-plus product of the row vector `x` and `d`, `r[j] = min_k(x[k] + d[n*k + j])`: for the
+/// distances `x` from a source, the shortest distances with one more edge of `d`, in `O(n^2)`
+/// rather than the `O(n^3)` of `step`. `apsp::bellman_ford` repeats it until nothing changes.
+pub fn step_vec(r: &mut [f32], d: &[f32], x: &[f32], n: usize) -> Result<(), StepError> {
+    if n.checked_mul(n) != Some(d.len()) || r.len() != n || x.len() != n {
+        return Err(StepError::DimensionMismatch { m: 1, k: n, n, r_len: r.len(), a_len: x.len(), b_len: d.len() });
+    }
+    matvec::step_vec(&ThreadConfig::default(), r, d, x, n);
+    Ok(())
+}
+
+/// `step` of `step` of `d` into `r`, the shortest paths of at most four edges, without writing
+/// the intermediate step to memory and reading it back: its rows go from the kernel straight into
+/// the packed copy of it that the second step reads.
+pub fn step2(r: &mut [f32], d: &[f32], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    fused::step2(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// `step` of `d` kept in the order of `layout::to_interleaved`, which `v6` builds on every call,
+/// for `d` that is stepped many times and changes little in between, see `InterleavedMatrix::set`.
+#[cfg(feature = "std")]
+pub fn step_preinterleaved(r: &mut [f32], d: &layout::InterleavedMatrix) -> Result<(), StepError> {
+    let n = d.n();
+    if n.checked_mul(n) != Some(r.len()) {
+        return Err(StepError::LengthMismatch { n, r_len: r.len(), d_len: n * n });
+    }
+    variants::v6_preinterleaved(&ThreadConfig::default(), r, d);
+    Ok(())
+}
+
+/// The `k` shortest distances from each vertex of the step of `d`, without its `n * n` results: row
+/// `i` of the `n * k` matrices `values` and `indices` is the `k` smallest elements of row `i` of
+/// `r` in increasing order and their columns, the lower column first for equal distances.
+/// Each element of `r` is only computed a few at a time, and kept by a heap of the `k` smallest of
+/// its row so far if it is one of them.
+pub fn step_topk(indices: &mut [u32], values: &mut [f32], k: usize, d: &[f32], n: usize) -> Result<(), StepError> {
+    check_lengths(d, d, n)?;
+    if k > n || n.checked_mul(k).is_none_or(|len| indices.len() != len || values.len() != len) {
+        return Err(StepError::TopKMismatch { n, k, indices_len: indices.len(), values_len: values.len() });
+    }
+    fused::step_topk(&ThreadConfig::default(), indices, values, k, d, n);
+    Ok(())
+}
+
+/// Whether each element of the step of `d` is at most `radius`, such as which vertices can reach
+/// which others within a budget in two hops, without its `n * n` results. Row `i` of `r_mask` is
+/// `n.div_ceil(64)` words, with bit `j % 64` of word `j / 64` set for element `(i, j)`; the bits
+/// past column `n` are zero. NaN is never within `radius`.
+pub fn step_within(r_mask: &mut [u64], d: &[f32], n: usize, radius: f32) -> Result<(), StepError> {
+    check_lengths(d, d, n)?;
+    if n.checked_mul(n.div_ceil(64)) != Some(r_mask.len()) {
+        return Err(StepError::MaskMismatch { n, mask_len: r_mask.len() });
+    }
+    fused::step_within(&ThreadConfig::default(), r_mask, d, n, radius);
+    Ok(())
+}
+
+/// Like `step` for integer weights, where `i32::MAX` is infinity and additions saturate instead of wrapping.
+#[cfg(feature = "std")]
+pub fn step_i32(r: &mut [i32], d: &[i32], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    integer::step_i32(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// Like `step_i32`, with `u16::MAX` as infinity.
+#[cfg(feature = "std")]
+pub fn step_u16(r: &mut [u16], d: &[u16], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    integer::step_u16(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// Like `step` for distances quantized to `u8`, where `q` stands for `offset + scale * q` and
+/// `U8_INFINITY` for infinity, as by `quantize_u8`. The sums are added and compared as integers,
+/// widened to `u16`, and each result is rounded to the nearest quantized value, saturating at
+/// `U8_INFINITY`. The matrices take a quarter of the memory, and of the memory traffic, of `f32`;
+/// `verify::quantization_error` measures how far the results are from those of `step`.
+#[cfg(feature = "std")]
+pub fn step_u8(r: &mut [u8], d: &[u8], n: usize, scale: f32, offset: f32) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    if !(scale.is_finite() && scale > 0.0 && offset.is_finite()) {
+        return Err(StepError::InvalidScale);
+    }
+    quantized::step_u8(&ThreadConfig::default(), r, d, n, scale, offset);
+    Ok(())
+}
+
+/// Like `step` for half-precision floats, which are added and compared as `f32`, rounding only the
+/// results to the nearest `F16`. The matrices take half the memory, and half the memory traffic, of `f32`.
+#[cfg(feature = "std")]
+pub fn step_f16(r: &mut [F16], d: &[F16], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    half::step_f16(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// Like `step_f16`, for bfloat16. With AVX-512 BF16 the results below `f32::MIN_POSITIVE` are
+/// flushed to zero.
+#[cfg(feature = "std")]
+pub fn step_bf16(r: &mut [Bf16], d: &[Bf16], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    half::step_bf16(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// Like `step`, carrying the rounding error of each sum along with it, so that the error of long
+/// paths, such as those of `apsp`, stays close to that of `step_f64`, at a fraction of its cost.
+#[cfg(feature = "std")]
+pub fn step_f32_compensated(r: &mut [Compensated], d: &[Compensated], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    compensated::step(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// `step_semiring` in `MinPlus<f64>`, with half as many elements in each vector as `step`.
+pub fn step_f64(r: &mut [f64], d: &[f64], n: usize) -> Result<(), StepError> {
+    step_semiring::<MinPlus<f64>>(r, d, n)
+}
+
+/// Like `step` in the semiring `S`, with the packed copies, threads and vectors of the kernels of
+/// `dispatch` and blocks of rows like `v5`.
+pub fn step_semiring<S: Semiring>(r: &mut [S::Elem], d: &[S::Elem], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    semiring::step::<S>(&ThreadConfig::default(), r, n, d, n, n);
+    Ok(())
+}
+
+/// Like `step_strided`, in the semiring `S`; only the elements of the rows of `r` are written.
+pub fn step_semiring_strided<S: Semiring>(
+    r: &mut [S::Elem],
+    ld_r: usize,
+    d: &[S::Elem],
+    ld_d: usize,
+    n: usize,
+) -> Result<(), StepError> {
+    check_stride(r, ld_r, n)?;
+    check_stride(d, ld_d, n)?;
+    semiring::step::<S>(&ThreadConfig::default(), r, ld_r, d, ld_d, n);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `bench::random_input` with NaN at the end of row 0, in the partial vector or next to the
+    /// padding for any `n` that is not a multiple of the lanes, and in rows 1 and `n - 1`.
+    fn with_nan(n: usize) -> Vec<f32> {
+        let mut d = bench::random_input(n);
+        d[n - 1] = f32::NAN;
+        d[n*(n - 1) + n / 2] = f32::NAN;
+        if n > 2 {
+            d[n + 1] = f32::NAN;
+        }
+        d
+    }
+
+    /// `step` without the sums that are NaN, as with `NanPolicy::Ignore`.
+    fn step_ignoring_nan(d: &[f32], n: usize) -> Vec<f32> {
+        let mut r = vec![0.0; n * n];
+        for i in 0..n {
+            for j in 0..n {
+                let sums = (0..n).map(|k| d[n*i + k] + d[n*k + j]).filter(|z| !z.is_nan());
+                r[n*i + j] = sums.fold(f32::INFINITY, f32::min);
+            }
+        }
+        r
+    }
+
+    fn step_with_policy(
+        kernel: dispatch::Kernel,
+        tail: dispatch::Tail,
+        policy: NanPolicy,
+        d: &[f32],
+        n: usize,
+    ) -> (Vec<f32>, Result<(), StepError>) {
+        let options = StepOptions { nan_policy: Some(policy), tail: Some(tail), ..Default::default() };
+        let mut r = vec![0.0; n * n];
+        let result = step_with_nan_policy(&mut r, d, n, &options, kernel);
+        (r, result)
+    }
+
+    #[test]
+    fn nan_policies_are_the_same_with_every_kernel() {
+        for kernel in dispatch::supported_kernels() {
+            for tail in [dispatch::Tail::Pad, dispatch::Tail::Mask] {
+                for n in [2, 7, 16, 17, 31, 33] {
+                    let d = with_nan(n);
+                    let expected = step_ignoring_nan(&d, n);
+                    let case = format!("{} {:?} n = {}", kernel.name(), tail, n);
+
+                    let (r, result) = step_with_policy(kernel, tail, NanPolicy::Ignore, &d, n);
+                    assert_eq!(result, Ok(()), "{}", case);
+                    assert_eq!(r, expected, "{}", case);
+
+                    let (r, result) = step_with_policy(kernel, tail, NanPolicy::Propagate, &d, n);
+                    assert_eq!(result, Ok(()), "{}", case);
+                    let has_nan = |row: &[f32]| row.iter().any(|x| x.is_nan());
+                    for i in 0..n {
+                        for j in 0..n {
+                            let nan = has_nan(&d[n*i..n*(i + 1)]) || (0..n).any(|k| d[n*k + j].is_nan());
+                            let (x, y) = (r[n*i + j], expected[n*i + j]);
+                            assert!(if nan { x.is_nan() } else { x == y }, "{} r[{}][{}] = {}", case, i, j, x);
+                        }
+                    }
+
+                    let (r, result) = step_with_policy(kernel, tail, NanPolicy::Error, &d, n);
+                    assert_eq!(result, Err(StepError::NaN { i: 0, j: n - 1 }), "{}", case);
+                    assert!(r.iter().all(|&x| x == 0.0), "{}", case);
+                    let (r, result) = step_with_policy(kernel, tail, NanPolicy::Error, &bench::random_input(n), n);
+                    assert_eq!(result, Ok(()), "{}", case);
+                    assert_eq!(r, step_ignoring_nan(&bench::random_input(n), n), "{}", case);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_determinism_is_bit_identical_for_any_threads_and_grain() {
+        let kernel = Determinism::Strict.kernel();
+        for n in [2, 17, 64, 100] {
+            let mut inputs = crate::reference::inputs(n);
+            // Zeros of both signs and NaN, whose minimum a different order could change.
+            let signed_zeros = (0..n * n).map(|x| if x % 3 == 0 { -0.0 } else { 0.0 }).collect();
+            inputs.push(("signed zeros", signed_zeros));
+            inputs.push(("nan", with_nan(n)));
+            for (input, d) in inputs {
+                let mut expected = vec![0.0; n * n];
+                let one = ThreadConfig { num_threads: Some(1), ..Default::default() };
+                kernel.step_with_hooks(&one, &mut expected, &d, n, false, Default::default(), kernel.tail(n)).unwrap();
+                for num_threads in [1, 2, 3, 8] {
+                    for grain in [None, Some(1), Some(5)] {
+                        let threads = ThreadConfig { num_threads: Some(num_threads), grain, ..Default::default() };
+                        let mut r = vec![0.0; n * n];
+                        kernel.step_with_hooks(&threads, &mut r, &d, n, false, Default::default(), kernel.tail(n)).unwrap();
+                        let bits = |r: &[f32]| r.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+                        assert_eq!(bits(&r), bits(&expected), "{} n = {} {} threads {:?}", input, n, num_threads, grain);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn step_changed_is_whether_any_distance_decreased() {
+        for n in [1, 7, 9, 17, 64] {
+            let d = bench::random_input(n);
+            let (mut r, mut expected) = (vec![0.0; n * n], vec![0.0; n * n]);
+            step(&mut expected, &d, n).unwrap();
+            assert_eq!(step_changed(&mut r, &d, n), Ok(expected.iter().zip(&d).any(|(x, y)| x < y)), "n = {}", n);
+            assert_eq!(r, expected, "n = {}", n);
+            let mut shortest = d.clone();
+            apsp::apsp(&mut shortest, n).unwrap();
+            assert_eq!(step_changed(&mut r, &shortest, n), Ok(false), "n = {}", n);
+            let mut context = StepContext::new(n);
+            assert_eq!(context.step_changed(&mut r, &shortest), Ok(false), "n = {}", n);
+            assert_eq!(context.step_changed(&mut r, &d), step_changed(&mut expected, &d, n), "n = {}", n);
+            assert_eq!(r, expected, "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn unknown_variant_lists_every_variant() {
+        let message = StepError::UnknownVariant.to_string();
+        for (name, _) in variants::VARIANTS {
+            assert!(message.contains(&format!(" {},", name)), "{} in {:?}", name, message);
+        }
+        assert!(message.ends_with(" or auto, see list_variants"), "{:?}", message);
+    }
+}
@@ -0,0 +1,512 @@
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout as AllocLayout};
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::ptr::NonNull;
+
+use crate::alloc::ALIGN;
+use crate::{apsp, check_lengths, Bf16, CancelToken, Compensated, Layout, StepError, StepOptions, F16};
+
+/// The elements of a `Matrix`, aligned to `ALIGN` bytes. Those of a `Vec` that already is are kept
+/// as they are, with the layout it allocated them with.
+struct Elements<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    /// `None` if nothing was allocated, for no elements or elements of no size.
+    layout: Option<AllocLayout>,
+}
+
+unsafe impl<T: Send> Send for Elements<T> {}
+unsafe impl<T: Sync> Sync for Elements<T> {}
+
+impl<T: Copy> Elements<T> {
+    /// Room for `len` elements, none of them written yet.
+    fn allocate(len: usize) -> Self {
+        let layout = AllocLayout::array::<T>(len)
+            .and_then(|layout| layout.align_to(ALIGN))
+            .expect("matrix size overflows isize");
+        if layout.size() == 0 {
+            // Dangling, but aligned like the allocations.
+            let ptr = NonNull::new(layout.align() as *mut T).unwrap();
+            return Elements { ptr, len, layout: None };
+        }
+        let ptr = NonNull::new(unsafe { alloc(layout) }.cast()).unwrap_or_else(|| handle_alloc_error(layout));
+        Elements { ptr, len, layout: Some(layout) }
+    }
+
+    fn filled(len: usize, value: T) -> Self {
+        let elements = Self::allocate(len);
+        for i in 0..len {
+            unsafe { elements.ptr.as_ptr().add(i).write(value) };
+        }
+        elements
+    }
+
+    fn from_slice(data: &[T]) -> Self {
+        let elements = Self::allocate(data.len());
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), elements.ptr.as_ptr(), data.len()) };
+        elements
+    }
+
+    /// Takes over the allocation of `data` if it is aligned, or else copies it.
+    fn from_vec(data: Vec<T>) -> Self {
+        let mut data = ManuallyDrop::new(data);
+        let layout = AllocLayout::array::<T>(data.capacity()).unwrap();
+        if layout.size() == 0 || !(data.as_ptr() as usize).is_multiple_of(ALIGN) {
+            let elements = Self::from_slice(&data);
+            drop(ManuallyDrop::into_inner(data));
+            return elements;
+        }
+        Elements { ptr: NonNull::new(data.as_mut_ptr()).unwrap(), len: data.len(), layout: Some(layout) }
+    }
+}
+
+impl<T> Drop for Elements<T> {
+    fn drop(&mut self) {
+        if let Some(layout) = self.layout {
+            unsafe { dealloc(self.ptr.as_ptr().cast(), layout) };
+        }
+    }
+}
+
+impl<T> Deref for Elements<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for Elements<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+/// The index of element `(i, j)` of an `n * n` matrix in `layout`.
+#[inline]
+fn index(n: usize, layout: Layout, (i, j): (usize, usize)) -> usize {
+    assert!(i < n && j < n, "index ({}, {}) is out of range for n = {}", i, j, n);
+    match layout {
+        Layout::RowMajor => n*i + j,
+        Layout::ColMajor => i + n*j,
+    }
+}
+
+/// The element types with a `step`, the one of `step_f64`, `step_i32` and so on for each.
+pub trait Element: Copy + Send + Sync {
+    fn step(r: &mut [Self], d: &[Self], n: usize) -> Result<(), StepError>;
+}
+
+macro_rules! impl_element {
+    ($($t:ty => $step:path),* $(,)?) => {
+        $(
+            impl Element for $t {
+                fn step(r: &mut [Self], d: &[Self], n: usize) -> Result<(), StepError> {
+                    $step(r, d, n)
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(
+    f32 => crate::step,
+    f64 => crate::step_f64,
+    i32 => crate::step_i32,
+    u16 => crate::step_u16,
+    F16 => crate::step_f16,
+    Bf16 => crate::step_bf16,
+    Compensated => crate::step_f32_compensated,
+);
+
+/// An `n * n` matrix that owns its elements, which start at a multiple of `alloc::ALIGN` bytes
+/// and are ordered by `layout`. Unlike a slice and a separate `n`, the size cannot disagree with
+/// the number of elements, and the functions that take two matrices check that they agree.
+pub struct Matrix<T> {
+    elements: Elements<T>,
+    n: usize,
+    layout: Layout,
+}
+
+impl<T: Copy> Matrix<T> {
+    /// Panics if `n * n` elements do not fit in memory.
+    pub fn filled(n: usize, value: T) -> Self {
+        let len = n.checked_mul(n).expect("matrix size overflows usize");
+        Matrix { elements: Elements::filled(len, value), n, layout: Layout::RowMajor }
+    }
+
+    /// The row-major matrix of `f(i, j)`, called row by row. Panics like `filled`.
+    pub fn from_fn(n: usize, mut f: impl FnMut(usize, usize) -> T) -> Self {
+        let elements = Elements::<T>::allocate(n.checked_mul(n).expect("matrix size overflows usize"));
+        for i in 0..n {
+            for j in 0..n {
+                unsafe { elements.ptr.as_ptr().add(n*i + j).write(f(i, j)) };
+            }
+        }
+        Matrix { elements, n, layout: Layout::RowMajor }
+    }
+
+    /// The row-major matrix of `data`, copied unless it happens to be aligned already.
+    pub fn from_vec(data: Vec<T>, n: usize) -> Result<Self, StepError> {
+        check_lengths(&data, &data, n)?;
+        Ok(Matrix { elements: Elements::from_vec(data), n, layout: Layout::RowMajor })
+    }
+
+    /// A copy of the row-major matrix of `data`.
+    pub fn from_slice(data: &[T], n: usize) -> Result<Self, StepError> {
+        check_lengths(data, data, n)?;
+        Ok(Matrix { elements: Elements::from_slice(data), n, layout: Layout::RowMajor })
+    }
+
+    /// The same elements read in `layout`: the transpose of this matrix if that differs from its own.
+    pub fn with_layout(self, layout: Layout) -> Self {
+        Matrix { layout, ..self }
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.elements
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.elements
+    }
+
+    pub fn view(&self) -> MatrixView<'_, T> {
+        MatrixView { data: &self.elements, n: self.n, layout: self.layout }
+    }
+
+    pub fn view_mut(&mut self) -> MatrixViewMut<'_, T> {
+        MatrixViewMut { data: &mut self.elements, n: self.n, layout: self.layout }
+    }
+}
+
+impl<T: Element> Matrix<T> {
+    /// See `MatrixView::step`.
+    pub fn step(&self) -> Result<Self, StepError> {
+        self.view().step()
+    }
+}
+
+impl Matrix<f32> {
+    /// Like `step_in_place`.
+    pub fn step_in_place(&mut self) -> Result<(), StepError> {
+        crate::step_in_place(&mut self.elements, self.n)
+    }
+
+    /// Like `apsp::apsp`, replacing the matrix with its shortest distances.
+    pub fn apsp(&mut self) -> Result<(), StepError> {
+        apsp::apsp(&mut self.elements, self.n)
+    }
+}
+
+impl<T: Copy> Clone for Matrix<T> {
+    fn clone(&self) -> Self {
+        Matrix { elements: Elements::from_slice(&self.elements), n: self.n, layout: self.layout }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Matrix<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let elements: &[T] = &self.elements;
+        f.debug_struct("Matrix").field("n", &self.n).field("layout", &self.layout).field("elements", &elements).finish()
+    }
+}
+
+/// Matrices in different layouts are different even if one is the transpose of the other.
+impl<T: PartialEq> PartialEq for Matrix<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.n == other.n && self.layout == other.layout && *self.elements == *other.elements
+    }
+}
+
+impl<T> Index<(usize, usize)> for Matrix<T> {
+    type Output = T;
+    fn index(&self, ij: (usize, usize)) -> &T {
+        &self.elements[index(self.n, self.layout, ij)]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Matrix<T> {
+    fn index_mut(&mut self, ij: (usize, usize)) -> &mut T {
+        &mut self.elements[index(self.n, self.layout, ij)]
+    }
+}
+
+/// An `n * n` matrix borrowed from a slice, a `Matrix` or a mapped `io::Matrix`. Its functions are
+/// those of the crate that take a slice and `n`, such as `step_into` for `step`, which stay for
+/// callers with slices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatrixView<'a, T> {
+    data: &'a [T],
+    n: usize,
+    layout: Layout,
+}
+
+impl<'a, T> MatrixView<'a, T> {
+    /// The row-major matrix of `data`, which must have `n * n` elements.
+    pub fn new(data: &'a [T], n: usize) -> Result<Self, StepError> {
+        check_lengths(data, data, n)?;
+        Ok(MatrixView { data, n, layout: Layout::RowMajor })
+    }
+
+    /// See `Matrix::with_layout`.
+    pub fn with_layout(self, layout: Layout) -> Self {
+        MatrixView { layout, ..self }
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn as_slice(&self) -> &'a [T] {
+        self.data
+    }
+
+    /// Whether the elements start at a multiple of `alloc::ALIGN` bytes, which those of a `Matrix`
+    /// and of a mapped `io::Matrix` always do, but those of a slice need not.
+    pub fn is_aligned(&self) -> bool {
+        (self.data.as_ptr() as usize).is_multiple_of(ALIGN)
+    }
+
+    /// Checks that `r` can hold the step of this matrix.
+    fn check(&self, r: &MatrixViewMut<T>) -> Result<(), StepError> {
+        if r.n != self.n {
+            return Err(StepError::LengthMismatch { n: self.n, r_len: r.data.len(), d_len: self.data.len() });
+        }
+        if r.layout != self.layout {
+            return Err(StepError::LayoutMismatch { r: r.layout, d: self.layout });
+        }
+        Ok(())
+    }
+
+    /// Checks that this matrix is row-major, for the functions whose results are rows of its step
+    /// rather than a matrix in its layout.
+    fn check_row_major(&self) -> Result<(), StepError> {
+        match self.layout {
+            Layout::RowMajor => Ok(()),
+            Layout::ColMajor => Err(StepError::LayoutMismatch { r: Layout::RowMajor, d: self.layout }),
+        }
+    }
+}
+
+impl<T: Element> MatrixView<'_, T> {
+    /// Writes the step of this matrix to `r`, which must be of the same size and in the same
+    /// layout. That of a column-major matrix is the column-major step, see `step_with_layout`.
+    pub fn step_into(&self, r: MatrixViewMut<T>) -> Result<(), StepError> {
+        self.check(&r)?;
+        T::step(r.data, self.data, self.n)
+    }
+
+    /// The step of this matrix, in its layout.
+    pub fn step(&self) -> Result<Matrix<T>, StepError> {
+        let mut r = Matrix { elements: Elements::from_slice(self.data), n: self.n, layout: self.layout };
+        self.step_into(r.view_mut())?;
+        Ok(r)
+    }
+}
+
+impl MatrixView<'_, f32> {
+    /// Like `step_into`, with `crate::step_with_options`.
+    pub fn step_with_options(&self, r: MatrixViewMut<f32>, options: &StepOptions) -> Result<(), StepError> {
+        self.check(&r)?;
+        crate::step_with_options(r.data, self.data, self.n, options)
+    }
+
+    /// Like `step_into`, with `crate::step_changed`.
+    pub fn step_changed(&self, r: MatrixViewMut<f32>) -> Result<bool, StepError> {
+        self.check(&r)?;
+        crate::step_changed(r.data, self.data, self.n)
+    }
+
+    /// Like `step_into`, with `crate::step_cancellable`.
+    pub fn step_cancellable(&self, r: MatrixViewMut<f32>, token: &CancelToken) -> Result<(), StepError> {
+        self.check(&r)?;
+        crate::step_cancellable(r.data, self.data, self.n, token)
+    }
+
+    /// Like `step_into`, with `crate::step_symmetric`, for which the layout makes no difference.
+    pub fn step_symmetric(&self, r: MatrixViewMut<f32>) -> Result<(), StepError> {
+        self.check(&r)?;
+        crate::step_symmetric(r.data, self.data, self.n)
+    }
+
+    /// `crate::step_rows` of this matrix, which must be row-major, as are the rows of `r`.
+    pub fn step_rows(&self, r: &mut [f32], rows: &[u32]) -> Result<(), StepError> {
+        self.check_row_major()?;
+        crate::step_rows(r, self.data, rows, self.n)
+    }
+
+    /// `crate::step_vec` of `x` and this matrix, which must be row-major.
+    pub fn step_vec(&self, r: &mut [f32], x: &[f32]) -> Result<(), StepError> {
+        self.check_row_major()?;
+        crate::step_vec(r, self.data, x, self.n)
+    }
+
+    /// `crate::step_topk` of this matrix, which must be row-major, as are `indices` and `values`.
+    pub fn step_topk(&self, indices: &mut [u32], values: &mut [f32], k: usize) -> Result<(), StepError> {
+        self.check_row_major()?;
+        crate::step_topk(indices, values, k, self.data, self.n)
+    }
+
+    /// `crate::step_within` of this matrix, which must be row-major, as are the rows of `r_mask`.
+    pub fn step_within(&self, r_mask: &mut [u64], radius: f32) -> Result<(), StepError> {
+        self.check_row_major()?;
+        crate::step_within(r_mask, self.data, self.n, radius)
+    }
+}
+
+impl MatrixView<'_, u8> {
+    /// Like `step_into`, with `crate::step_u8`.
+    pub fn step_u8(&self, r: MatrixViewMut<u8>, scale: f32, offset: f32) -> Result<(), StepError> {
+        self.check(&r)?;
+        crate::step_u8(r.data, self.data, self.n, scale, offset)
+    }
+}
+
+impl<T> Index<(usize, usize)> for MatrixView<'_, T> {
+    type Output = T;
+    fn index(&self, ij: (usize, usize)) -> &T {
+        &self.data[index(self.n, self.layout, ij)]
+    }
+}
+
+impl<'a, T: Copy> From<&'a Matrix<T>> for MatrixView<'a, T> {
+    fn from(matrix: &'a Matrix<T>) -> Self {
+        matrix.view()
+    }
+}
+
+/// Like `MatrixView`, borrowed mutably, such as for the results of `MatrixView::step_into`.
+#[derive(Debug, PartialEq)]
+pub struct MatrixViewMut<'a, T> {
+    data: &'a mut [T],
+    n: usize,
+    layout: Layout,
+}
+
+impl<'a, T> MatrixViewMut<'a, T> {
+    /// The row-major matrix of `data`, which must have `n * n` elements.
+    pub fn new(data: &'a mut [T], n: usize) -> Result<Self, StepError> {
+        check_lengths(data, data, n)?;
+        Ok(MatrixViewMut { data, n, layout: Layout::RowMajor })
+    }
+
+    /// See `Matrix::with_layout`.
+    pub fn with_layout(self, layout: Layout) -> Self {
+        MatrixViewMut { layout, ..self }
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.data
+    }
+
+    pub fn as_view(&self) -> MatrixView<'_, T> {
+        MatrixView { data: self.data, n: self.n, layout: self.layout }
+    }
+}
+
+impl<T> Index<(usize, usize)> for MatrixViewMut<'_, T> {
+    type Output = T;
+    fn index(&self, ij: (usize, usize)) -> &T {
+        &self.data[index(self.n, self.layout, ij)]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for MatrixViewMut<'_, T> {
+    fn index_mut(&mut self, ij: (usize, usize)) -> &mut T {
+        &mut self.data[index(self.n, self.layout, ij)]
+    }
+}
+
+impl<'a, T: Copy> From<&'a mut Matrix<T>> for MatrixViewMut<'a, T> {
+    fn from(matrix: &'a mut Matrix<T>) -> Self {
+        matrix.view_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+
+    /// The functions of views agree with those of slices and `n`, which they check the views for.
+    #[test]
+    fn views_match_the_slice_functions() {
+        let n = 17;
+        let d = Matrix::from_vec(random_input(n), n).unwrap();
+        let symmetric = Matrix::from_fn(n, |i, j| d[(i.min(j), i.max(j))]);
+        let (mut r, mut expected) = (Matrix::filled(n, 0.0), vec![0.0; n * n]);
+        assert_eq!(d.view().step_changed(r.view_mut()), crate::step_changed(&mut expected, d.as_slice(), n));
+        assert_eq!(r.as_slice(), expected);
+        symmetric.view().step_symmetric(r.view_mut()).unwrap();
+        crate::step_symmetric(&mut expected, symmetric.as_slice(), n).unwrap();
+        assert_eq!(r.as_slice(), expected);
+        d.view().step_cancellable(r.view_mut(), &CancelToken::new()).unwrap();
+        crate::step(&mut expected, d.as_slice(), n).unwrap();
+        assert_eq!(r.as_slice(), expected);
+
+        let rows = [3, 0, 16];
+        let (mut r_rows, mut expected_rows) = (vec![0.0; rows.len() * n], vec![0.0; rows.len() * n]);
+        d.view().step_rows(&mut r_rows, &rows).unwrap();
+        crate::step_rows(&mut expected_rows, d.as_slice(), &rows, n).unwrap();
+        assert_eq!(r_rows, expected_rows);
+        let x = random_input(n * n)[..n].to_vec();
+        let (mut r_vec, mut expected_vec) = (vec![0.0; n], vec![0.0; n]);
+        d.view().step_vec(&mut r_vec, &x).unwrap();
+        crate::step_vec(&mut expected_vec, d.as_slice(), &x, n).unwrap();
+        assert_eq!(r_vec, expected_vec);
+        let k = 4;
+        let (mut indices, mut values) = (vec![0; n * k], vec![0.0; n * k]);
+        let (mut expected_indices, mut expected_values) = (vec![0; n * k], vec![0.0; n * k]);
+        d.view().step_topk(&mut indices, &mut values, k).unwrap();
+        crate::step_topk(&mut expected_indices, &mut expected_values, k, d.as_slice(), n).unwrap();
+        assert_eq!((indices, values), (expected_indices, expected_values));
+        let (mut mask, mut expected_mask) = (vec![0; n], vec![0; n]);
+        d.view().step_within(&mut mask, 0.5).unwrap();
+        crate::step_within(&mut expected_mask, d.as_slice(), n, 0.5).unwrap();
+        assert_eq!(mask, expected_mask);
+
+        let q = Matrix::from_fn(n, |i, j| (i * 7 + j * 3) as u8);
+        let (mut r_q, mut expected_q) = (Matrix::filled(n, 0), vec![0; n * n]);
+        q.view().step_u8(r_q.view_mut(), 0.5, 1.0).unwrap();
+        crate::step_u8(&mut expected_q, q.as_slice(), n, 0.5, 1.0).unwrap();
+        assert_eq!(r_q.as_slice(), expected_q);
+    }
+
+    #[test]
+    fn views_reject_other_sizes_and_layouts() {
+        let d = Matrix::filled(4, 1.0f32);
+        let mut r = Matrix::filled(3, 0.0);
+        assert!(matches!(d.view().step_changed(r.view_mut()), Err(StepError::LengthMismatch { n: 4, .. })));
+        let column_major = d.clone().with_layout(Layout::ColMajor);
+        let mut r_rows = vec![0.0; 4];
+        assert_eq!(
+            column_major.view().step_rows(&mut r_rows, &[0]),
+            Err(StepError::LayoutMismatch { r: Layout::RowMajor, d: Layout::ColMajor })
+        );
+    }
+}
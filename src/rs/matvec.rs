@@ -0,0 +1,29 @@
+use crate::ops::count;
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::trace::span;
+
+/// The most elements of `r` each thread takes at a time, 4 KiB, which stay in the L1 cache while
+/// the rows of `d` stream past them. Fewer for small `n`, so that all threads get some.
+const COLS: usize = 1024;
+/// The fewest, a few vectors of every kernel, so that each thread reads whole cache lines of `d`.
+const MIN_COLS: usize = 64;
+
+/// `r[j] = min_k(x[k] + d[n*k + j])`, the min-plus product of the row vector `x` and `d`. Unlike
+/// `step` this reads each element of `d` once, so it is bound by memory rather than by the adds.
+pub(crate) fn step_vec(threads: &ThreadConfig, r: &mut [f32], d: &[f32], x: &[f32], n: usize) {
+    span!("compute", m = 1, n);
+    let cols = n.div_ceil(threads.effective_threads()).clamp(MIN_COLS, COLS);
+    for_each_chunk(threads, r, cols, |c, r_cols| {
+        let start = cols * c;
+        r_cols.fill(f32::INFINITY);
+        for (&x_k, d_row) in x.iter().zip(d.chunks_exact(n)) {
+            for (res, &y) in r_cols.iter_mut().zip(&d_row[start..]) {
+                let sum = x_k + y;
+                // The operands in the order of `minps`, so that the loop compiles to it, keeping
+                // `*res` if the sum is NaN.
+                *res = if sum < *res { sum } else { *res };
+            }
+        }
+        count!(loads: n * r_cols.len(), stores: r_cols.len(), adds: n * r_cols.len(), mins: n * r_cols.len());
+    });
+}
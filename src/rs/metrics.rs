@@ -0,0 +1,183 @@
+#[cfg(feature = "std")]
+use std::cell::Cell;
+#[cfg(feature = "prometheus")]
+use std::fmt::Write;
+#[cfg(feature = "prometheus")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "prometheus")]
+use std::sync::Mutex;
+#[cfg(feature = "std")]
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::StepError;
+
+/// What `step_with_threads` and `step_with_options` report about each call to the `Metrics` of
+/// `set_metrics`, including the calls through them such as `step`, `step_checked` and those of
+/// `service`. The methods are called on the calling thread, so they should only update counters.
+#[cfg(feature = "std")]
+pub trait Metrics: Send + Sync {
+    /// A call on an `n * n` matrix starts, before its inputs are checked.
+    fn call_started(&self, _n: usize) {}
+
+    fn call_finished(&self, call: &Call);
+}
+
+/// A finished call, for `Metrics::call_finished`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Call {
+    pub n: usize,
+    /// What ran: the name of the `dispatch::Kernel`, or `"sparse"`, `"numa"` or `"v7"` for those
+    /// of `StepOptions`, or `"none"` if the call failed before one was chosen.
+    pub variant: &'static str,
+    pub duration: Duration,
+    /// The bytes of `d` read and of `r` written, or 0 if the call failed.
+    pub bytes: u64,
+    pub result: Result<(), StepError>,
+}
+
+#[cfg(feature = "std")]
+static METRICS: RwLock<Option<Arc<dyn Metrics>>> = RwLock::new(None);
+
+/// Reports the calls on all threads to `metrics` from now on, or to nothing if `None`.
+#[cfg(feature = "std")]
+pub fn set_metrics(metrics: Option<Arc<dyn Metrics>>) {
+    *METRICS.write().unwrap() = metrics;
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+    /// The variant of the call being reported on this thread, `Some` only while one is.
+    static VARIANT: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Runs `f`, a call on an `n * n` matrix, and reports it to the `Metrics` of `set_metrics`, unless
+/// it is part of a call that already is.
+#[cfg(feature = "std")]
+pub(crate) fn record(n: usize, f: impl FnOnce() -> Result<(), StepError>) -> Result<(), StepError> {
+    if VARIANT.get().is_some() {
+        return f();
+    }
+    let Some(metrics) = METRICS.read().unwrap().clone() else { return f() };
+    struct Reporting;
+    impl Drop for Reporting {
+        // Also when `f` panics, so that the later calls on this thread are reported.
+        fn drop(&mut self) {
+            VARIANT.set(None);
+        }
+    }
+    metrics.call_started(n);
+    let reporting = Reporting;
+    VARIANT.set(Some("none"));
+    let start = Instant::now();
+    let result = f();
+    let duration = start.elapsed();
+    let variant = VARIANT.get().unwrap_or("none");
+    drop(reporting);
+    let bytes = if result.is_ok() { 2 * (n as u64).pow(2) * std::mem::size_of::<f32>() as u64 } else { 0 };
+    metrics.call_finished(&Call { n, variant, duration, bytes, result });
+    result
+}
+
+/// Names the variant that the call being reported on this thread runs.
+#[cfg(feature = "std")]
+pub(crate) fn chose(variant: &'static str) {
+    if VARIANT.get().is_some() {
+        VARIANT.set(Some(variant));
+    }
+}
+
+/// Without `std` there are no clocks to time calls with, nor `set_metrics`.
+#[cfg(not(feature = "std"))]
+pub(crate) fn record(_n: usize, f: impl FnOnce() -> Result<(), StepError>) -> Result<(), StepError> {
+    f()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn chose(_variant: &'static str) {}
+
+/// The totals of the calls of one variant in `PrometheusMetrics`.
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Clone, Copy, Default)]
+struct Totals {
+    calls: u64,
+    errors: u64,
+    seconds: f64,
+    bytes: u64,
+}
+
+#[cfg(feature = "prometheus")]
+impl Totals {
+    /// In the order of the series of `PrometheusMetrics::render`.
+    fn values(&self) -> [String; 4] {
+        [self.calls.to_string(), self.errors.to_string(), self.seconds.to_string(), self.bytes.to_string()]
+    }
+}
+
+/// `Metrics` that add up the calls of each variant, for a service to answer the scrapes of
+/// Prometheus with `render`, along with the kernel that `dispatch::selected` chose.
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    started: AtomicU64,
+    variants: Mutex<Vec<(&'static str, Totals)>>,
+}
+
+#[cfg(feature = "prometheus")]
+impl Metrics for PrometheusMetrics {
+    fn call_started(&self, _n: usize) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn call_finished(&self, call: &Call) {
+        let mut variants = self.variants.lock().unwrap();
+        let index = match variants.iter().position(|&(variant, _)| variant == call.variant) {
+            Some(index) => index,
+            None => {
+                variants.push((call.variant, Totals::default()));
+                variants.len() - 1
+            }
+        };
+        let totals = &mut variants[index].1;
+        totals.calls += 1;
+        totals.errors += call.result.is_err() as u64;
+        totals.seconds += call.duration.as_secs_f64();
+        totals.bytes += call.bytes;
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counters in the text exposition format of Prometheus, each finished call counted under
+    /// the label `variant` of what ran.
+    pub fn render(&self) -> String {
+        fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+            writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind).unwrap();
+        }
+        let mut out = String::new();
+        header(&mut out, "shortcut_dispatch_kernel", "gauge", "The kernel that the dispatcher chose for this CPU.");
+        writeln!(out, "shortcut_dispatch_kernel{{kernel=\"{}\"}} 1", crate::dispatch::selected().name()).unwrap();
+        header(&mut out, "shortcut_calls_started_total", "counter", "Calls started.");
+        writeln!(out, "shortcut_calls_started_total {}", self.started.load(Ordering::Relaxed)).unwrap();
+        let variants = self.variants.lock().unwrap().clone();
+        let series = [
+            ("shortcut_calls_total", "Calls finished."),
+            ("shortcut_call_errors_total", "Calls that returned an error."),
+            ("shortcut_call_seconds_total", "Time spent in calls."),
+            ("shortcut_bytes_total", "Bytes of matrices read and written."),
+        ];
+        for (s, (name, help)) in series.into_iter().enumerate() {
+            header(&mut out, name, "counter", help);
+            for (variant, totals) in &variants {
+                writeln!(out, "{}{{variant=\"{}\"}} {}", name, variant, totals.values()[s]).unwrap();
+            }
+        }
+        out
+    }
+}
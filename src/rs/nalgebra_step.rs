@@ -0,0 +1,22 @@
+use nalgebra::DMatrix;
+
+use crate::{Layout, StepError};
+
+/// `step` for matrices of `nalgebra`, whose column-major storage is passed to the kernels as it is,
+/// see `Layout::ColMajor`. `r` must have the same shape as `d`.
+pub fn step_dmatrix(r: &mut DMatrix<f32>, d: &DMatrix<f32>) -> Result<(), StepError> {
+    let n = d.nrows();
+    if r.shape() != (n, n) || d.ncols() != n {
+        return Err(StepError::LengthMismatch { n, r_len: r.len(), d_len: d.len() });
+    }
+    crate::step_with_layout(r.as_mut_slice(), d.as_slice(), n, Layout::ColMajor)
+}
+
+/// `apsp::apsp` for a matrix of `nalgebra`. Each squaring of the transpose is the transpose of the
+/// squaring, so the column-major storage is replaced with the column-major shortest paths. A
+/// matrix that is not square is rejected like a slice of the wrong length.
+#[cfg(feature = "std")]
+pub fn apsp_dmatrix(d: &mut DMatrix<f32>) -> Result<(), StepError> {
+    let n = d.nrows();
+    crate::apsp::apsp(d.as_mut_slice(), n)
+}
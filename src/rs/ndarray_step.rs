@@ -0,0 +1,37 @@
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use ndarray::{s, ArrayView2, ArrayViewMut2, Axis};
+
+use crate::fused::{ResultBlocks, ROWS};
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::{Layout, StepError};
+
+/// `step` for square arrays in any layout, such as columns or every other row of larger arrays.
+/// Arrays in standard or column-major order are passed to the kernels as they are, see
+/// `Layout::ColMajor`. Otherwise `d` is first copied to standard order, which packing it would
+/// do anyway, and the results are written to `r` through the view, one block at a time, so that
+/// the elements between its rows and columns are never touched.
+pub fn step_ndarray(mut r: ArrayViewMut2<f32>, d: ArrayView2<f32>) -> Result<(), StepError> {
+    let n = d.nrows();
+    if d.ncols() != n || r.dim() != (n, n) {
+        return Err(StepError::LengthMismatch { n, r_len: r.len(), d_len: d.len() });
+    }
+    if let (Some(r), Some(d)) = (r.as_slice_mut(), d.as_slice()) {
+        return crate::step(r, d, n);
+    }
+    if let (Some(r), Some(d)) = (r.view_mut().reversed_axes().into_slice(), d.t().to_slice()) {
+        return crate::step_with_layout(r, d, n, Layout::ColMajor);
+    }
+    let d = d.as_standard_layout();
+    let blocks = ResultBlocks::new(d.as_slice().expect("standard layout is contiguous"), n);
+    let mut bands: Vec<ArrayViewMut2<f32>> = r.axis_chunks_iter_mut(Axis(0), ROWS).collect();
+    for_each_chunk(&ThreadConfig::default(), &mut bands, 1, |b, band| {
+        let band = &mut band[0];
+        blocks.for_each(ROWS * b..ROWS * b + band.nrows(), |cols, block| {
+            let block = ArrayView2::from_shape((band.nrows(), cols.len()), block).expect("blocks are rows * cols");
+            band.slice_mut(s![.., cols]).assign(&block);
+        });
+    });
+    Ok(())
+}
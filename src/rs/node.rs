@@ -0,0 +1,41 @@
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+fn run(d: &[f32], n: u32) -> Result<Vec<f32>> {
+    let n = n as usize;
+    let mut r = vec![0.0; d.len()];
+    crate::step(&mut r, d, n).map_err(|e| Error::new(Status::InvalidArg, e.to_string()))?;
+    Ok(r)
+}
+
+/// `step(d, n)` returns `r` for a `Float32Array` `d` of `n * n` elements, blocking the event loop
+/// while it runs.
+#[napi]
+pub fn step(d: Float32Array, n: u32) -> Result<Float32Array> {
+    Ok(Float32Array::new(run(&d, n)?))
+}
+
+pub struct StepTask {
+    d: Float32Array,
+    n: u32,
+}
+
+impl Task for StepTask {
+    type Output = Vec<f32>;
+    type JsValue = Float32Array;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        run(&self.d, self.n)
+    }
+
+    fn resolve(&mut self, _env: Env, r: Self::Output) -> Result<Self::JsValue> {
+        Ok(Float32Array::new(r))
+    }
+}
+
+/// `stepAsync(d, n)` is `step(d, n)` on the libuv thread pool, returning a `Promise` of `r`,
+/// so that large matrices do not block the event loop.
+#[napi]
+pub fn step_async(d: Float32Array, n: u32) -> AsyncTask<StepTask> {
+    AsyncTask::new(StepTask { d, n })
+}
@@ -0,0 +1,79 @@
+use std::fs;
+
+use crate::dispatch::{self, Hooks};
+use crate::simd::{Packed, Strided};
+use crate::threads::ThreadConfig;
+use crate::topology::parse_cpu_list;
+use crate::StepError;
+
+/// Where `step_with_options` places its packed copies of `d` and its threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum NumaPolicy {
+    /// Packs `d` on the calling thread and lets the threads run on any core.
+    #[default]
+    None,
+    /// Packs the rows each thread reads on that thread, and pins the threads to the cores of one
+    /// node after another, so that every thread reads its rows from the memory of its own node.
+    Local,
+}
+
+/// The CPUs of each NUMA node, empty if the system does not report any nodes.
+pub fn nodes() -> Vec<Vec<usize>> {
+    let Ok(online) = fs::read_to_string("/sys/devices/system/node/online") else { return Vec::new() };
+    parse_cpu_list(&online)
+        .into_iter()
+        .filter_map(|node| fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", node)).ok())
+        .map(|cpus| parse_cpu_list(&cpus))
+        .filter(|cpus| !cpus.is_empty())
+        .collect()
+}
+
+/// `threads`, unless it already pins its threads, with its threads spread evenly over the nodes
+/// and pinned to the cores of their node. `for_each_chunk` gives consecutive rows to consecutive
+/// threads, so each node gets a contiguous band of rows. `grain` is cleared, since threads taking
+/// rows as they go would compute rows placed on other nodes.
+pub fn local_threads(threads: &ThreadConfig) -> ThreadConfig {
+    let mut local = ThreadConfig { grain: None, ..threads.clone() };
+    let nodes = nodes();
+    if local.pin_cores.is_none() && !nodes.is_empty() {
+        let num_threads = threads.effective_threads();
+        let mut next = vec![0; nodes.len()];
+        let cores = (0..num_threads)
+            .map(|t| {
+                let node = t * nodes.len() / num_threads;
+                next[node] += 1;
+                nodes[node][(next[node] - 1) % nodes[node].len()]
+            })
+            .collect();
+        local.pin_cores = Some(cores);
+    }
+    local
+}
+
+/// `step` with the fastest kernel, placing its data and threads according to `policy`.
+/// With `hooks`, each block of rows is split over all threads, so that with `Local` only some
+/// of the rows are read from the node of the thread that reads them.
+pub(crate) fn step(
+    threads: &ThreadConfig,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    inf_aware: bool,
+    policy: NumaPolicy,
+    hooks: Hooks,
+) -> Result<(), StepError> {
+    let kernel = dispatch::selected();
+    let d = Strided { data: d, ld: n };
+    match policy {
+        NumaPolicy::None => {
+            let packed = Packed::new(d, d, n, n, n, kernel.lanes());
+            kernel.run_in_blocks(threads, r, n, &packed, inf_aware, hooks)
+        }
+        NumaPolicy::Local => {
+            let threads = local_threads(threads);
+            let packed = Packed::first_touch(&threads, d, d, n, n, n, kernel.lanes());
+            kernel.run_in_blocks(&threads, r, n, &packed, inf_aware, hooks)
+        }
+    }
+}
@@ -0,0 +1,94 @@
+#[cfg(feature = "count-ops")]
+use std::cell::Cell;
+#[cfg(feature = "count-ops")]
+use std::ops::{Add, AddAssign};
+#[cfg(feature = "count-ops")]
+use std::sync::Mutex;
+
+/// Adds to the `OpCounts` of the calling thread, for example `count!(loads: 2 * n, adds: n)`.
+/// Expands to nothing without the `count-ops` feature, like `trace::span`.
+macro_rules! count {
+    ($($field:ident: $value:expr),* $(,)?) => {
+        #[cfg(feature = "count-ops")]
+        #[allow(clippy::needless_update)]
+        $crate::ops::add($crate::ops::OpCounts { $($field: ($value) as u64,)* ..Default::default() });
+    };
+}
+
+pub(crate) use count;
+
+/// The operations on elements of the matrices that the kernels did, counting each lane of a
+/// vector instruction as one, including the lanes of padding. `loads` and `stores` are those of
+/// the inner loops, from the packed copies and the results, not those of packing them; `adds` and
+/// `mins` are the `Semiring::combine` and `Semiring::reduce` of `step_semiring` for other semirings.
+#[cfg(feature = "count-ops")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpCounts {
+    pub loads: u64,
+    pub stores: u64,
+    pub adds: u64,
+    pub mins: u64,
+}
+
+#[cfg(feature = "count-ops")]
+impl OpCounts {
+    /// The arithmetic intensity the tutorial is about: `adds` and `mins` per element loaded.
+    pub fn ops_per_load(&self) -> f64 {
+        (self.adds + self.mins) as f64 / self.loads as f64
+    }
+}
+
+#[cfg(feature = "count-ops")]
+impl Add for OpCounts {
+    type Output = OpCounts;
+    fn add(self, other: OpCounts) -> OpCounts {
+        OpCounts {
+            loads: self.loads + other.loads,
+            stores: self.stores + other.stores,
+            adds: self.adds + other.adds,
+            mins: self.mins + other.mins,
+        }
+    }
+}
+
+#[cfg(feature = "count-ops")]
+impl AddAssign for OpCounts {
+    fn add_assign(&mut self, other: OpCounts) {
+        *self = *self + other;
+    }
+}
+
+#[cfg(feature = "count-ops")]
+thread_local! {
+    static THREAD: Cell<OpCounts> = const { Cell::new(OpCounts { loads: 0, stores: 0, adds: 0, mins: 0 }) };
+}
+
+/// The counts of the threads that have finished since `count_ops` started.
+#[cfg(feature = "count-ops")]
+static MERGED: Mutex<OpCounts> = Mutex::new(OpCounts { loads: 0, stores: 0, adds: 0, mins: 0 });
+
+#[cfg(feature = "count-ops")]
+pub(crate) fn add(counts: OpCounts) {
+    THREAD.with(|thread| thread.set(thread.get() + counts));
+}
+
+/// Moves the counts of the calling thread into the merged counts, which each thread of
+/// `threads::for_each_chunk` does before it exits.
+#[cfg(feature = "count-ops")]
+pub(crate) fn merge() {
+    let counts = THREAD.with(|thread| thread.take());
+    *MERGED.lock().unwrap() += counts;
+}
+
+/// The operations of all kernels that ran on the calling thread and the threads it started while
+/// `f` ran. Kernels running on other threads at the same time, such as under another `count_ops`,
+/// are counted too.
+#[cfg(feature = "count-ops")]
+pub fn count_ops<F: FnOnce()>(f: F) -> OpCounts {
+    THREAD.with(|thread| thread.take());
+    *MERGED.lock().unwrap() = OpCounts::default();
+    f();
+    merge();
+    std::mem::take(&mut *MERGED.lock().unwrap())
+}
@@ -0,0 +1,160 @@
+/// Hardware event counts from one run of a variant, only available on Linux on x86, ARM, RISC-V,
+/// s390x and LoongArch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Counters {
+    pub cycles: u64,
+    pub instructions: u64,
+    pub cache_references: u64,
+    pub cache_misses: u64,
+    pub branches: u64,
+    pub branch_misses: u64,
+}
+
+impl Counters {
+    pub fn ipc(&self) -> f64 {
+        self.instructions as f64 / self.cycles as f64
+    }
+}
+
+/// Counts the events of the calling thread and all threads it spawns while `f` runs,
+/// or returns `None` if the counters could not be opened, e.g. due to `perf_event_paranoid`.
+pub fn measure<F: FnOnce()>(f: F) -> Option<Counters> {
+    imp::measure(f)
+}
+
+/// The targets whose number of `perf_event_open` is known, where the ioctls are encoded like on x86.
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+        target_arch = "loongarch64"
+    )
+))]
+mod imp {
+    use std::ffi::{c_long, c_ulong};
+
+    use super::Counters;
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        fn ioctl(fd: i32, request: c_ulong, ...) -> i32;
+        fn read(fd: i32, buf: *mut u64, count: usize) -> isize;
+        fn close(fd: i32) -> i32;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_PERF_EVENT_OPEN: c_long = 298;
+    #[cfg(target_arch = "x86")]
+    const SYS_PERF_EVENT_OPEN: c_long = 336;
+    #[cfg(target_arch = "arm")]
+    const SYS_PERF_EVENT_OPEN: c_long = 364;
+    #[cfg(target_arch = "s390x")]
+    const SYS_PERF_EVENT_OPEN: c_long = 331;
+    // The generic numbers of the newer architectures.
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64", target_arch = "loongarch64"))]
+    const SYS_PERF_EVENT_OPEN: c_long = 241;
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_EVENT_IOC_ENABLE: c_ulong = 0x2400;
+    const PERF_EVENT_IOC_DISABLE: c_ulong = 0x2401;
+
+    // Bits of the flags field: disabled, inherit, exclude_kernel and exclude_hv. Big-endian ABIs
+    // allocate bit fields from the most significant bit.
+    const FLAGS: u64 = {
+        let flags: u64 = 1 | 1 << 1 | 1 << 5 | 1 << 6;
+        if cfg!(target_endian = "big") { flags.reverse_bits() } else { flags }
+    };
+
+    /// The first fields of `struct perf_event_attr`, zero padded to the size of version 7.
+    #[repr(C)]
+    struct PerfEventAttr {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        rest: [u64; 10],
+    }
+
+    struct Counter(i32);
+
+    impl Counter {
+        fn open(config: u64) -> Option<Counter> {
+            let attr = PerfEventAttr {
+                kind: PERF_TYPE_HARDWARE,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                sample_period: 0,
+                sample_type: 0,
+                read_format: 0,
+                flags: FLAGS,
+                rest: [0; 10],
+            };
+            let attr = &attr as *const PerfEventAttr;
+            let fd = unsafe { syscall(SYS_PERF_EVENT_OPEN, attr, 0i32, -1i32, -1i32, 0 as c_ulong) };
+            if fd < 0 { None } else { Some(Counter(fd as i32)) }
+        }
+
+        fn value(&self) -> Option<u64> {
+            let mut value = 0;
+            let size = std::mem::size_of::<u64>();
+            if unsafe { read(self.0, &mut value, size) } == size as isize { Some(value) } else { None }
+        }
+    }
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            unsafe { close(self.0) };
+        }
+    }
+
+    pub(super) fn measure<F: FnOnce()>(f: F) -> Option<Counters> {
+        // Cycles, instructions, cache references, cache misses, branches and branch misses.
+        let counters = (0..6).map(Counter::open).collect::<Option<Vec<_>>>()?;
+        for counter in &counters {
+            unsafe { ioctl(counter.0, PERF_EVENT_IOC_ENABLE) };
+        }
+        f();
+        for counter in &counters {
+            unsafe { ioctl(counter.0, PERF_EVENT_IOC_DISABLE) };
+        }
+        let values = counters.iter().map(Counter::value).collect::<Option<Vec<_>>>()?;
+        Some(Counters {
+            cycles: values[0],
+            instructions: values[1],
+            cache_references: values[2],
+            cache_misses: values[3],
+            branches: values[4],
+            branch_misses: values[5],
+        })
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+        target_arch = "loongarch64"
+    )
+)))]
+mod imp {
+    use super::Counters;
+
+    pub(super) fn measure<F: FnOnce()>(f: F) -> Option<Counters> {
+        f();
+        None
+    }
+}
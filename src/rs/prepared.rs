@@ -0,0 +1,45 @@
+use crate::cancel::CancelToken;
+use crate::dispatch::{Hooks, Kernel};
+use crate::simd::Packed;
+use crate::threads::ThreadConfig;
+use crate::{check_lengths, StepError, StepOptions};
+
+/// `d` already padded and transposed for one kernel, for workloads that run `step` with the same
+/// `d` many times, such as repeated queries against the same graph. Each `step_into` then only runs
+/// the kernel, without copying `d`.
+pub struct PreparedMatrix {
+    n: usize,
+    kernel: Kernel,
+    threads: ThreadConfig,
+    packed: Packed<'static>,
+    options: StepOptions,
+}
+
+impl PreparedMatrix {
+    /// Packs `d` for the kernel of `options.determinism`, keeping `options` for every `step_into`.
+    /// `prefetch`, `streaming_stores`, `schedule` and `numa_policy` are ignored, as they only apply
+    /// to the kernels that pack `d` themselves.
+    pub fn new(d: &[f32], n: usize, options: StepOptions) -> Result<Self, StepError> {
+        check_lengths(d, d, n)?;
+        let kernel = options.determinism.kernel();
+        let packed = Packed::square(d, n, n, kernel.lanes());
+        Ok(PreparedMatrix { n, kernel, threads: options.threads(), packed, options })
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Writes the step of the prepared `d` to `r`, like `step_with_options` with its options.
+    pub fn step_into(&self, r: &mut [f32]) -> Result<(), StepError> {
+        let n = self.n;
+        if r.len() != n * n {
+            return Err(StepError::LengthMismatch { n, r_len: r.len(), d_len: n * n });
+        }
+        let hooks = Hooks {
+            progress: self.options.progress.as_deref().map(|progress| progress as _),
+            cancel: self.options.cancel.as_ref().map(CancelToken::flag),
+        };
+        self.kernel.run_in_blocks(&self.threads, r, n, &self.packed, self.options.inf_aware, hooks)
+    }
+}
@@ -0,0 +1,189 @@
+use crate::apsp::apsp;
+use crate::{is_symmetric, sparse, step};
+use crate::variants::{StepFn, VARIANTS};
+
+/// Kinds of distance matrices to check the algebraic properties of `step` with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Random,
+    /// Only edges between vertices at most two apart.
+    Banded,
+    Symmetric,
+    /// Random, with about a quarter of the edges missing.
+    WithInfinities,
+}
+
+pub const SHAPES: [Shape; 4] = [Shape::Random, Shape::Banded, Shape::Symmetric, Shape::WithInfinities];
+
+/// A xorshift generator, so that failures can be reproduced from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// An `n * n` matrix of the given shape with zeros on the diagonal and integer weights in `[0, 100)`,
+/// so that all sums are exact and the properties hold without any tolerance.
+pub fn generate(shape: Shape, n: usize, seed: u64) -> Vec<f32> {
+    let mut rng = Rng(seed.max(1));
+    shaped(shape, n, || rng.next())
+}
+
+/// `generate` with the numbers in `[0, 1)` of `next`, at most `2 * n * n` of them.
+fn shaped(shape: Shape, n: usize, mut next: impl FnMut() -> f32) -> Vec<f32> {
+    let mut d = vec![0.0; n * n];
+    for i in 0..n {
+        for j in 0..n {
+            let x = (next() * 100.0).floor();
+            d[n*i + j] = match shape {
+                _ if i == j => 0.0,
+                Shape::Banded if i.abs_diff(j) > 2 => f32::INFINITY,
+                Shape::Symmetric if j < i => d[n*j + i],
+                Shape::WithInfinities if next() < 0.25 => f32::INFINITY,
+                _ => x,
+            };
+        }
+    }
+    d
+}
+
+/// A property that did not hold for some variant on some input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub property: &'static str,
+    pub variant: &'static str,
+    pub shape: Shape,
+    pub n: usize,
+    pub seed: u64,
+}
+
+fn satisfies_triangle_inequality(d: &[f32], n: usize) -> bool {
+    (0..n).all(|i| (0..n).all(|j| (0..n).all(|k| d[n*i + j] <= d[n*i + k] + d[n*k + j])))
+}
+
+/// Whether the step of the shortest path lengths `shortest` is `shortest` again.
+fn is_idempotent(step: StepFn, shortest: &[f32], n: usize) -> bool {
+    let mut r = vec![0.0; n * n];
+    step(&mut r, shortest, n);
+    r == shortest
+}
+
+/// Whether the step of `d` is symmetric if `d` is.
+fn preserves_symmetry(step: StepFn, d: &[f32], n: usize) -> bool {
+    let mut r = vec![0.0; n * n];
+    step(&mut r, d, n);
+    !is_symmetric(d, n) || is_symmetric(&r, n)
+}
+
+/// Whether stepping `d` until it converges, which takes at most `log2(n) + 1` steps, yields a
+/// matrix that satisfies the triangle inequality.
+fn converges_to_triangle_inequality(step: StepFn, d: &[f32], n: usize) -> bool {
+    let mut r = vec![0.0; n * n];
+    let mut converged = d.to_vec();
+    for _ in 0..=n.max(2).ilog2() + 1 {
+        step(&mut r, &converged, n);
+        converged.copy_from_slice(&r);
+    }
+    satisfies_triangle_inequality(&converged, n)
+}
+
+/// Checks on all variants that
+/// * stepping a matrix of shortest path lengths does not change it,
+/// * stepping a symmetric matrix yields a symmetric matrix,
+/// * stepping until convergence yields a matrix that satisfies the triangle inequality,
+///
+/// and that `sparse::step` agrees with `step`.
+pub fn check(shape: Shape, n: usize, seed: u64) -> Vec<Failure> {
+    let d = generate(shape, n, seed);
+    let mut shortest = d.clone();
+    apsp(&mut shortest, n).expect("generated matrices have n * n elements");
+    let mut failures = Vec::new();
+    for (variant, step) in VARIANTS {
+        let mut fail = |property| failures.push(Failure { property, variant, shape, n, seed });
+        if !is_idempotent(step, &shortest, n) {
+            fail("idempotence");
+        }
+        if !preserves_symmetry(step, &d, n) {
+            fail("symmetry");
+        }
+        if !converges_to_triangle_inequality(step, &d, n) {
+            fail("triangle inequality");
+        }
+    }
+    let (mut dense, mut sparse) = (vec![0.0; n * n], vec![0.0; n * n]);
+    step(&mut dense, &d, n).expect("generated matrices have n * n elements");
+    sparse::step(&mut sparse, &d, n).expect("generated matrices have n * n elements");
+    if sparse != dense {
+        failures.push(Failure { property: "sparse kernel", variant: "sparse", shape, n, seed });
+    }
+    failures
+}
+
+/// `check` for all shapes and the sizes in `sizes`, each with `cases` different seeds.
+pub fn check_all(sizes: &[usize], cases: u64) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for &shape in &SHAPES {
+        for &n in sizes {
+            for seed in 1..=cases {
+                failures.extend(check(shape, n, seed));
+            }
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Matrices of `shape` like those of `generate`, from numbers that proptest draws and shrinks
+    /// instead of a seed, so that a failure shrinks to a small matrix with small weights.
+    fn matrices(shape: Shape) -> impl Strategy<Value = (Vec<f32>, usize)> {
+        (1..=24usize).prop_flat_map(move |n| {
+            vec(0.0f32..1.0, 2 * n * n).prop_map(move |numbers| {
+                let mut numbers = numbers.into_iter();
+                (shaped(shape, n, || numbers.next().unwrap_or(0.0)), n)
+            })
+        })
+    }
+
+    /// `matrices` of all of `SHAPES`.
+    fn any_matrices() -> impl Strategy<Value = (Vec<f32>, usize)> {
+        prop::sample::select(SHAPES.to_vec()).prop_flat_map(matrices)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn stepping_shortest_paths_changes_nothing((d, n) in any_matrices()) {
+            let mut shortest = d;
+            apsp(&mut shortest, n).unwrap();
+            for (variant, step) in VARIANTS {
+                prop_assert!(is_idempotent(step, &shortest, n), "{}", variant);
+            }
+        }
+
+        #[test]
+        fn stepping_keeps_symmetry((d, n) in matrices(Shape::Symmetric)) {
+            for (variant, step) in VARIANTS {
+                prop_assert!(preserves_symmetry(step, &d, n), "{}", variant);
+            }
+        }
+
+        #[test]
+        fn stepping_converges_to_the_triangle_inequality((d, n) in any_matrices()) {
+            for (variant, step) in VARIANTS {
+                prop_assert!(converges_to_triangle_inequality(step, &d, n), "{}", variant);
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2, PyUntypedArrayMethods};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::StepError;
+
+fn value_error(e: StepError) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// Borrows the elements of a square, C-contiguous `d` without copying them.
+fn square<'a>(d: &'a PyReadonlyArray2<f32>) -> PyResult<(&'a [f32], usize)> {
+    let (rows, cols) = (d.shape()[0], d.shape()[1]);
+    if rows != cols {
+        return Err(PyValueError::new_err(format!("expected a square matrix, got shape ({}, {})", rows, cols)));
+    }
+    let data = d.as_slice().map_err(|_| PyValueError::new_err("expected a C-contiguous array"))?;
+    Ok((data, rows))
+}
+
+/// Moves `r` into a NumPy array of shape `(n, n)` without copying it.
+fn to_array(py: Python, r: Vec<f32>, n: usize) -> Bound<PyArray2<f32>> {
+    Array2::from_shape_vec((n, n), r).expect("r has n * n elements").into_pyarray(py)
+}
+
+/// `step(d)` returns `r` for a square `numpy.float32` matrix `d`, releasing the GIL while it runs.
+#[pyfunction]
+fn step<'py>(py: Python<'py>, d: PyReadonlyArray2<'py, f32>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+    let (d, n) = square(&d)?;
+    let mut r = vec![0.0; n * n];
+    py.allow_threads(|| crate::step(&mut r, d, n)).map_err(value_error)?;
+    Ok(to_array(py, r, n))
+}
+
+/// `apsp(d)` returns the lengths of the shortest paths in the graph `d`, leaving `d` unchanged.
+#[pyfunction]
+fn apsp<'py>(py: Python<'py>, d: PyReadonlyArray2<'py, f32>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+    let (d, n) = square(&d)?;
+    let mut r = d.to_vec();
+    py.allow_threads(|| crate::apsp::apsp(&mut r, n)).map_err(value_error)?;
+    Ok(to_array(py, r, n))
+}
+
+#[pymodule]
+fn shortcut(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(step, m)?)?;
+    m.add_function(wrap_pyfunction!(apsp, m)?)?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use crate::threads::{for_each_chunk, ThreadConfig};
+
+/// The quantized value standing for infinity, and for NaN.
+pub const U8_INFINITY: u8 = u8::MAX;
+
+/// The nearest of the values `offset + scale * q` that a `u8` `q` below `U8_INFINITY` stands for,
+/// clamped to the smallest, or `U8_INFINITY` for values above the largest, infinity and NaN.
+pub fn quantize_u8(x: f32, scale: f32, offset: f32) -> u8 {
+    match ((x - offset) / scale).round() {
+        q if (0.0..U8_INFINITY as f32).contains(&q) => q as u8,
+        q if q < 0.0 => 0,
+        _ => U8_INFINITY,
+    }
+}
+
+/// The value `offset + scale * q` that `q` stands for, or infinity for `U8_INFINITY`.
+pub fn dequantize_u8(q: u8, scale: f32, offset: f32) -> f32 {
+    if q == U8_INFINITY { f32::INFINITY } else { offset + scale * q as f32 }
+}
+
+/// `q` widened to `u16`, with `U8_INFINITY` as `u16::MAX`, so that sums with it saturate at
+/// infinity, while those of two finite values, at most 508, stay below it.
+#[inline(always)]
+fn widen(q: u8) -> u16 {
+    if q == U8_INFINITY { u16::MAX } else { q as u16 }
+}
+
+/// Copies `d` widened to `u16`, and its transpose as it is, into rows padded with infinity to a
+/// multiple of `lanes`. The transpose is read once for each row of `r`, so it stays in `u8`.
+fn pad_and_transpose(d: &[u8], n: usize, lanes: usize) -> (Vec<u16>, Vec<u8>, usize) {
+    let width = n.div_ceil(lanes) * lanes;
+    let mut vd = vec![u16::MAX; n * width];
+    let mut vt = vec![U8_INFINITY; n * width];
+    for i in 0..n {
+        for k in 0..n {
+            vd[width*i + k] = widen(d[n*i + k]);
+            vt[width*i + k] = d[n*k + i];
+        }
+    }
+    (vd, vt, width)
+}
+
+/// The quantized `dequantize_u8(d[i][k]) + dequantize_u8(d[k][j])` of the least quantized sum
+/// `sum`, `u16::MAX` if it is infinite. The dequantized sums grow with the quantized ones, so the
+/// least of those is the least of these.
+#[inline(always)]
+fn requantize(sum: u16, scale: f32, offset: f32) -> u8 {
+    if sum == u16::MAX { U8_INFINITY } else { quantize_u8(2.0 * offset + scale * sum as f32, scale, offset) }
+}
+
+pub(crate) fn step_u8(threads: &ThreadConfig, r: &mut [u8], d: &[u8], n: usize, scale: f32, offset: f32) {
+    #[cfg(target_arch = "x86_64")]
+    if !crate::simd::PARANOID && is_x86_feature_detected!("avx2") {
+        return unsafe { x86::step_u8_avx2(threads, r, d, n, scale, offset) };
+    }
+    step_u8_portable(threads, r, d, n, scale, offset)
+}
+
+fn step_u8_portable(threads: &ThreadConfig, r: &mut [u8], d: &[u8], n: usize, scale: f32, offset: f32) {
+    let (vd, vt, width) = pad_and_transpose(d, n, 1);
+    for_each_chunk(threads, r, n, |i, r_row| {
+        let vd_row = &vd[width*i..width*(i + 1)];
+        for (res, vt_row) in r_row.iter_mut().zip(vt.chunks(width)) {
+            let sum = vd_row.iter().zip(vt_row).map(|(&x, &y)| x.saturating_add(widen(y))).min();
+            *res = requantize(sum.unwrap_or(u16::MAX), scale, offset);
+        }
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{pad_and_transpose, requantize, U8_INFINITY};
+    use crate::simd::load_at;
+    use crate::threads::{for_each_chunk, ThreadConfig};
+
+    /// Each add widens 16 elements of a row of the transpose to the `u16` lanes of a vector.
+    const LANES: usize = 16;
+
+    #[inline(always)]
+    unsafe fn step_row(r_row: &mut [u8], vd_row: &[u16], vt: &[u8], width: usize, scale: f32, offset: f32) {
+        let infinity = _mm256_set1_epi16(U8_INFINITY as i16);
+        for (res, vt_row) in r_row.iter_mut().zip(vt.chunks(width)) {
+            let mut v = _mm256_set1_epi16(-1);
+            for k in (0..width).step_by(LANES) {
+                let x = _mm256_loadu_si256(load_at(vd_row, k, LANES) as *const __m256i);
+                let y = _mm256_cvtepu8_epi16(_mm_loadu_si128(load_at(vt_row, k, LANES) as *const __m128i));
+                // `widen`: the lanes of infinity become all ones.
+                let y = _mm256_or_si256(y, _mm256_cmpeq_epi16(y, infinity));
+                v = _mm256_min_epu16(v, _mm256_adds_epu16(x, y));
+            }
+            let mut lanes = [0u16; LANES];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, v);
+            *res = requantize(lanes.into_iter().fold(u16::MAX, Ord::min), scale, offset);
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn step_u8_avx2(threads: &ThreadConfig, r: &mut [u8], d: &[u8], n: usize, scale: f32, offset: f32) {
+        let (vd, vt, width) = pad_and_transpose(d, n, LANES);
+        for_each_chunk(threads, r, n, |i, r_row| unsafe {
+            step_row(r_row, &vd[width*i..width*(i + 1)], &vt, width, scale, offset)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+
+    type Kernel = (&'static str, fn(&ThreadConfig, &mut [u8], &[u8], usize, f32, f32));
+
+    /// Quantized elements of every value, a tenth of them `U8_INFINITY`.
+    fn input(n: usize) -> Vec<u8> {
+        random_input(n).into_iter().map(|x| if x < 0.1 { U8_INFINITY } else { (x * 255.0) as u8 }).collect()
+    }
+
+    #[test]
+    fn step_u8_matches_the_quantized_step_of_the_dequantized_matrix() {
+        let mut kernels: Vec<Kernel> = vec![("dispatched", step_u8), ("portable", step_u8_portable)];
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            kernels.push(("avx2", |threads, r, d, n, scale, offset| unsafe {
+                x86::step_u8_avx2(threads, r, d, n, scale, offset)
+            }));
+        }
+        // Powers of two, so that `step` adds the dequantized values exactly, with offsets of either sign.
+        for (scale, offset) in [(0.5, -3.0), (0.25, 1.0), (1.0, 0.0)] {
+            for n in [1, 5, 17, 33, 70] {
+                let d = input(n);
+                let dequantized: Vec<f32> = d.iter().map(|&q| dequantize_u8(q, scale, offset)).collect();
+                let mut stepped = vec![0.0; n * n];
+                crate::step(&mut stepped, &dequantized, n).unwrap();
+                let expected: Vec<u8> = stepped.iter().map(|&x| quantize_u8(x, scale, offset)).collect();
+                for &(name, step) in &kernels {
+                    for threads in [ThreadConfig::default(), ThreadConfig::with_threads(3)] {
+                        let mut r = vec![0; n * n];
+                        step(&threads, &mut r, &d, n, scale, offset);
+                        assert_eq!(r, expected, "{} n = {} scale = {} offset = {}", name, n, scale, offset);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sums_out_of_range_are_clamped_or_infinite() {
+        let (scale, offset) = (0.5, -3.0);
+        // Sums that stand for less than the smallest value are clamped to it, and for more than the
+        // largest become infinite.
+        assert_eq!(requantize(0, scale, offset), 0);
+        assert_eq!(requantize(6, scale, offset), 0);
+        assert_eq!(requantize(7, scale, offset), 1);
+        assert_eq!(requantize(260, scale, offset), 254);
+        assert_eq!(requantize(261, scale, offset), U8_INFINITY);
+        assert_eq!(requantize(u16::MAX, scale, offset), U8_INFINITY);
+        let n = 3;
+        let d = [U8_INFINITY; 9];
+        for step in [step_u8, step_u8_portable] {
+            let mut r = [0; 9];
+            step(&ThreadConfig::default(), &mut r, &d, n, scale, offset);
+            assert_eq!(r, d);
+        }
+    }
+}
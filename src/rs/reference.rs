@@ -0,0 +1,136 @@
+use crate::bench::random_input;
+use crate::dispatch::Kernel;
+use crate::registry;
+use crate::variants::VARIANTS;
+
+/// The definition of `step`, with no attempt at being fast, to check all other versions against.
+pub fn step(r: &mut [f32], d: &[f32], n: usize) {
+    assert_eq!(r.len(), n * n, "r.len() must be n * n");
+    assert_eq!(d.len(), n * n, "d.len() must be n * n");
+    for i in 0..n {
+        for j in 0..n {
+            r[n*i + j] = (0..n).map(|k| d[n*i + k] + d[n*k + j]).fold(f32::INFINITY, f32::min);
+        }
+    }
+}
+
+/// The inputs `verify_all_variants` runs every variant on.
+pub fn inputs(n: usize) -> Vec<(&'static str, Vec<f32>)> {
+    let random = random_input(n);
+    let mut infinite_rows = random.clone();
+    for i in (0..n).step_by(3) {
+        infinite_rows[n*i..n*(i + 1)].fill(f32::INFINITY);
+    }
+    let duplicate_minima = random.iter().map(|&x| (x * 4.0).floor()).collect();
+    let denormals = random.iter().map(|&x| x * f32::MIN_POSITIVE).collect();
+    vec![
+        ("random", random),
+        ("infinite rows", infinite_rows),
+        ("duplicate minima", duplicate_minima),
+        ("denormals", denormals),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub i: usize,
+    pub j: usize,
+    pub expected: f32,
+    pub actual: f32,
+}
+
+/// How one variant did on one input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantResult {
+    pub variant: String,
+    pub input: &'static str,
+    pub mismatches: usize,
+    pub first_mismatch: Option<Mismatch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    pub n: usize,
+    pub tolerance: f32,
+    pub results: Vec<VariantResult>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.mismatches == 0)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &VariantResult> {
+        self.results.iter().filter(|result| result.mismatches > 0)
+    }
+}
+
+/// Infinite results must match exactly, finite ones within `tolerance` relative to the larger of
+/// `1.0` and the expected value.
+fn close(expected: f32, actual: f32, tolerance: f32) -> bool {
+    if expected.is_infinite() || actual.is_infinite() {
+        return expected == actual;
+    }
+    (expected - actual).abs() <= tolerance * expected.abs().max(1.0)
+}
+
+/// How many elements of `actual` are not close to `expected`, and the first of them, for results
+/// of `step` on an input of size `n`.
+pub fn compare(expected: &[f32], actual: &[f32], n: usize, tolerance: f32) -> (usize, Option<Mismatch>) {
+    assert_eq!(actual.len(), expected.len(), "actual.len() must be expected.len()");
+    let mut mismatches = (0..expected.len()).filter(|&x| !close(expected[x], actual[x], tolerance));
+    let first_mismatch = mismatches.next().map(|x| Mismatch {
+        i: x / n,
+        j: x % n,
+        expected: expected[x],
+        actual: actual[x],
+    });
+    (first_mismatch.iter().count() + mismatches.count(), first_mismatch)
+}
+
+type Candidate = Box<dyn Fn(&mut [f32], &[f32])>;
+
+/// Runs all of `variants::VARIANTS`, `variants::portable_simd` with the feature of the same name,
+/// the variants of `registry::registered` and all `dispatch` kernels that the CPU supports on the
+/// `inputs` of size `n`, comparing the results against `step`.
+pub fn verify_all_variants(n: usize, tolerance: f32) -> VerifyReport {
+    let mut candidates: Vec<(String, Candidate)> = Vec::new();
+    for (name, f) in VARIANTS {
+        candidates.push((name.to_string(), Box::new(move |r, d| f(r, d, n))));
+    }
+    #[cfg(feature = "portable-simd")]
+    candidates.push(("portable-simd".to_string(), Box::new(move |r, d| crate::variants::portable_simd(r, d, n))));
+    for (name, f) in registry::registered() {
+        candidates.push((name, Box::new(move |r, d| f(r, d, n))));
+    }
+    for kernel in [Kernel::Scalar, Kernel::Sse, Kernel::Avx2, Kernel::Avx512, Kernel::Neon, Kernel::Simd128, Kernel::Rvv] {
+        if kernel.is_supported() {
+            candidates.push((format!("dispatch::{}", kernel.name()), Box::new(move |r, d| kernel.step(r, d, n))));
+        }
+    }
+    let mut results = Vec::new();
+    for (input, d) in inputs(n) {
+        let mut expected = vec![0.0; n * n];
+        step(&mut expected, &d, n);
+        for (variant, f) in &candidates {
+            let mut r = vec![f32::NAN; n * n];
+            f(&mut r, &d);
+            let (mismatches, first_mismatch) = compare(&expected, &r, n, tolerance);
+            results.push(VariantResult { variant: variant.clone(), input, mismatches, first_mismatch });
+        }
+    }
+    VerifyReport { n, tolerance, results }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_variants_match_the_reference() {
+        for n in [1, 2, 5, 8, 13, 16, 17, 31, 33, 64, 100] {
+            let report = verify_all_variants(n, 0.0);
+            assert!(report.passed(), "n = {}: {:?}", n, report.failures().collect::<Vec<_>>());
+        }
+    }
+}
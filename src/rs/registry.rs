@@ -0,0 +1,33 @@
+use std::sync::RwLock;
+
+use crate::variants::{self, StepFn};
+
+/// The variants added by `register`, in the order they were added.
+static REGISTERED: RwLock<Vec<(String, StepFn)>> = RwLock::new(Vec::new());
+
+/// Adds `step` as a variant named `name`, for downstream crates to compare their own kernels with
+/// the ones of the tutorial. `reference::verify_all_variants` then checks it, the bench harness runs
+/// it, ignoring the thread count, and `variants::by_name` finds it, as do `--variant` and
+/// `step_variant`. Returns `false` without adding it if `name` is `auto`, a variant of
+/// `variants::by_name_with_threads` or already registered.
+pub fn register(name: impl Into<String>, step: StepFn) -> bool {
+    let name = name.into();
+    let mut registered = REGISTERED.write().unwrap();
+    let taken = name == "auto"
+        || variants::by_name_with_threads(&name).is_some()
+        || registered.iter().any(|(variant, _)| *variant == name);
+    if !taken {
+        registered.push((name, step));
+    }
+    !taken
+}
+
+/// The registered variants, in the order they were registered.
+pub fn registered() -> Vec<(String, StepFn)> {
+    REGISTERED.read().unwrap().clone()
+}
+
+pub fn by_name(name: &str) -> Option<StepFn> {
+    let registered = REGISTERED.read().unwrap();
+    registered.iter().find(|(variant, _)| variant == name).map(|&(_, step)| step)
+}
@@ -0,0 +1,177 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use crate::simd::Vector;
+use crate::threads::{for_each_chunk, ThreadConfig};
+
+/// The ceilings of a roofline plot of `step` on this machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Peak {
+    /// Billions of additions and minimums per second, the two operations of `step`, which has no
+    /// use for fused multiply-adds, counted like `Measurement::gflops`.
+    pub gflops: f64,
+    /// Billions of bytes read from memory per second.
+    pub bandwidth: f64,
+}
+
+impl Peak {
+    /// Runs a microbenchmark of the widest vectors of the CPU and a stream over a buffer much
+    /// larger than the caches on all threads of `threads`, taking well under a second each.
+    pub fn measure(threads: &ThreadConfig) -> Peak {
+        Peak { gflops: compute_peak(threads), bandwidth: bandwidth(threads) }
+    }
+
+    /// The most GFLOP/s possible at `intensity` operations per byte of memory traffic.
+    pub fn attainable(&self, intensity: f64) -> f64 {
+        self.gflops.min(intensity * self.bandwidth)
+    }
+}
+
+/// Operations per byte of `step` for size `n`, counting only the traffic of reading `d` and
+/// writing `r` once, which every variant needs at least.
+pub fn intensity(n: usize) -> f64 {
+    2.0 * (n as f64).powi(3) / (8.0 * (n as f64).powi(2)).max(1.0)
+}
+
+/// Independent dependency chains that `add_min` keeps in registers, enough to hide the latency of
+/// an addition followed by a minimum on the CPUs we have tried.
+const CHAINS: usize = 12;
+
+const COMPUTE_ITERATIONS: usize = 1 << 22;
+
+/// Bytes streamed by `bandwidth`, large enough to not fit in any cache.
+const BANDWIDTH_BYTES: usize = 1 << 27;
+
+const REPETITIONS: usize = 3;
+
+/// Runs `CHAINS` chains of an addition and a minimum `iterations` times, returning the number of
+/// operations and a result that depends on all of them.
+#[inline(always)]
+unsafe fn add_min<V: Vector>(iterations: usize) -> (usize, f32) {
+    // Unknown to the compiler, so that it cannot simplify the loop away.
+    let (y, z) = (V::splat(black_box(1e-9)), V::splat(black_box(f32::INFINITY)));
+    let mut v = [V::splat(0.0); CHAINS];
+    for _ in 0..iterations {
+        for v in &mut v {
+            *v = V::min(V::add(*v, y), z);
+        }
+    }
+    (2 * V::LANES * CHAINS * iterations, v.iter().map(|&v| V::horizontal_min(v)).fold(f32::INFINITY, f32::min))
+}
+
+/// The minimum of `data`, reading it with four independent vectors at a time.
+#[inline(always)]
+unsafe fn read<V: Vector>(data: &[f32]) -> f32 {
+    let mut v = [V::splat(f32::INFINITY); 4];
+    for block in data.chunks_exact(4 * V::LANES) {
+        for (b, v) in v.iter_mut().enumerate() {
+            *v = V::min(*v, V::load(block.as_ptr().add(b * V::LANES)));
+        }
+    }
+    v.iter().map(|&v| V::horizontal_min(v)).fold(f32::INFINITY, f32::min)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx512f")]
+    pub(super) unsafe fn add_min_avx512(iterations: usize) -> (usize, f32) {
+        super::add_min::<__m512>(iterations)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn add_min_avx2(iterations: usize) -> (usize, f32) {
+        super::add_min::<__m256>(iterations)
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub(super) unsafe fn read_avx512(data: &[f32]) -> f32 {
+        super::read::<__m512>(data)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn read_avx2(data: &[f32]) -> f32 {
+        super::read::<__m256>(data)
+    }
+}
+
+/// `add_min` with the widest vectors of the CPU.
+fn widest_add_min(iterations: usize) -> (usize, f32) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        if is_x86_feature_detected!("avx512f") {
+            return x86::add_min_avx512(iterations);
+        }
+        if is_x86_feature_detected!("avx2") {
+            return x86::add_min_avx2(iterations);
+        }
+        add_min::<std::arch::x86_64::__m128>(iterations)
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        add_min::<std::arch::aarch64::float32x4_t>(iterations)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    unsafe {
+        add_min::<[f32; 4]>(iterations)
+    }
+}
+
+/// `read` with the widest vectors of the CPU.
+fn widest_read(data: &[f32]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        if is_x86_feature_detected!("avx512f") {
+            return x86::read_avx512(data);
+        }
+        if is_x86_feature_detected!("avx2") {
+            return x86::read_avx2(data);
+        }
+        read::<std::arch::x86_64::__m128>(data)
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        read::<std::arch::aarch64::float32x4_t>(data)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    unsafe {
+        read::<[f32; 4]>(data)
+    }
+}
+
+/// The fastest of `REPETITIONS` runs of `f`, in seconds.
+fn fastest<F: FnMut()>(mut f: F) -> f64 {
+    (0..REPETITIONS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed().as_secs_f64()
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+fn compute_peak(threads: &ThreadConfig) -> f64 {
+    let mut ops = vec![0; threads.effective_threads()];
+    let seconds = fastest(|| {
+        for_each_chunk(threads, &mut ops, 1, |_, ops| {
+            let (count, result) = widest_add_min(COMPUTE_ITERATIONS);
+            black_box(result);
+            ops[0] = count;
+        })
+    });
+    ops.iter().sum::<usize>() as f64 / seconds / 1e9
+}
+
+fn bandwidth(threads: &ThreadConfig) -> f64 {
+    let num_threads = threads.effective_threads();
+    let mut data = vec![1.0f32; BANDWIDTH_BYTES / std::mem::size_of::<f32>()];
+    let chunk_len = data.len().div_ceil(num_threads);
+    let seconds = fastest(|| {
+        for_each_chunk(threads, &mut data, chunk_len, |_, chunk| {
+            black_box(widest_read(chunk));
+        })
+    });
+    BANDWIDTH_BYTES as f64 / seconds / 1e9
+}
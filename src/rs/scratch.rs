@@ -0,0 +1,21 @@
+use crate::alloc::AlignedBuffer;
+
+/// Temporaries of the blocked variants, kept between calls by `StepContext`.
+/// They are as large as `d`, so they are backed by huge pages if large enough.
+pub(crate) struct Scratch {
+    pub(crate) vd: AlignedBuffer,
+    pub(crate) vt: AlignedBuffer,
+    /// Only used by `v7`, which needs AVX2 or NEON.
+    #[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), allow(dead_code))]
+    pub(crate) partial: AlignedBuffer,
+}
+
+impl Default for Scratch {
+    fn default() -> Self {
+        Scratch {
+            vd: AlignedBuffer::with_huge_pages(),
+            vt: AlignedBuffer::with_huge_pages(),
+            partial: AlignedBuffer::with_huge_pages(),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+use std::marker::PhantomData;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use crate::dispatch::Kernel;
+use crate::float::Float;
+use crate::threads::ThreadConfig;
+
+/// The algebra `step` computes in: `reduce` over all `k` of `combine(d[i][k], d[k][j])`.
+/// The kernels pad the rows to whole vectors with `ZERO`, which `combine` must map to `ZERO`
+/// whatever the other element, as in any semiring.
+pub trait Semiring {
+    type Elem: Copy + Send + Sync;
+    /// Identity of `reduce`, the value of an empty reduction.
+    const ZERO: Self::Elem;
+    /// Identity of `combine`.
+    const ONE: Self::Elem;
+    fn combine(x: Self::Elem, y: Self::Elem) -> Self::Elem;
+    fn reduce(acc: Self::Elem, z: Self::Elem) -> Self::Elem;
+}
+
+/// Shortest paths: `min` over sums.
+pub struct MinPlus<T>(PhantomData<T>);
+
+impl<T: Float> Semiring for MinPlus<T> {
+    type Elem = T;
+    const ZERO: T = T::INFINITY;
+    const ONE: T = T::ZERO;
+    fn combine(x: T, y: T) -> T {
+        x + y
+    }
+    /// `acc.min(z)` for the `acc` that is never NaN, which vectorizes to a single instruction.
+    #[inline(always)]
+    fn reduce(acc: T, z: T) -> T {
+        if z < acc { z } else { acc }
+    }
+}
+
+/// Bottleneck paths: `max` over the smallest capacity along a path.
+pub struct MaxMin<T>(PhantomData<T>);
+
+impl<T: Float> Semiring for MaxMin<T> {
+    type Elem = T;
+    const ZERO: T = T::NEG_INFINITY;
+    const ONE: T = T::INFINITY;
+    fn combine(x: T, y: T) -> T {
+        x.min(y)
+    }
+    /// Like `MinPlus::reduce`, `acc.max(z)` for `acc` never NaN.
+    #[inline(always)]
+    fn reduce(acc: T, z: T) -> T {
+        if z > acc { z } else { acc }
+    }
+}
+
+/// Transitive closure: `or` over `and`.
+pub struct Bool;
+
+impl Semiring for Bool {
+    type Elem = bool;
+    const ZERO: bool = false;
+    const ONE: bool = true;
+    fn combine(x: bool, y: bool) -> bool {
+        x && y
+    }
+    fn reduce(acc: bool, z: bool) -> bool {
+        acc || z
+    }
+}
+
+/// Elements of a row that `step_rows` combines and reduces at a time, in as many independent
+/// accumulators, which the compiler turns into vectors of the CPU features of the caller: four
+/// AVX-512 vectors of `f32`, two of `f64`, or a quarter of one of `bool`.
+const LANES: usize = 16;
+
+/// Copies the `n` rows of `d`, which start `ld_d` elements apart, and its transpose, into rows
+/// padded with `S::ZERO` to a multiple of `LANES`, like `simd::Packed`.
+fn pad_and_transpose<S: Semiring>(d: &[S::Elem], ld_d: usize, n: usize) -> (Vec<S::Elem>, Vec<S::Elem>, usize) {
+    let width = n.div_ceil(LANES).max(1) * LANES;
+    let mut vd = vec![S::ZERO; n * width];
+    let mut vt = vec![S::ZERO; n * width];
+    for i in 0..n {
+        for k in 0..n {
+            vd[width*i + k] = d[ld_d*i + k];
+            vt[width*i + k] = d[ld_d*k + i];
+        }
+    }
+    (vd, vt, width)
+}
+
+/// Rows of `r` that `step_rows` computes together, reading each vector of the transpose once for
+/// all of them, like the blocks of rows of `v5`.
+const ROWS: usize = 4;
+
+/// The elements of `R` rows of `r`, which start `ld_r` elements apart, from the rows `vd` of the
+/// packed `d`, which start `width` elements apart.
+#[inline(always)]
+fn step_rows<S: Semiring, const R: usize>(r: &mut [S::Elem], ld_r: usize, vd: &[S::Elem], vt: &[S::Elem], width: usize) {
+    for (j, vt_row) in vt.chunks_exact(width).enumerate() {
+        let mut v = [[S::ZERO; LANES]; R];
+        for (k, y) in vt_row.chunks_exact(LANES).enumerate() {
+            for (q, v) in v.iter_mut().enumerate() {
+                let x = &vd[width*q + LANES*k..width*q + LANES*(k + 1)];
+                *v = std::array::from_fn(|l| S::reduce(v[l], S::combine(x[l], y[l])));
+            }
+        }
+        for (q, v) in v.into_iter().enumerate() {
+            r[ld_r*q + j] = v.into_iter().fold(S::ZERO, S::reduce);
+        }
+    }
+    crate::ops::count!(loads: (R + 1) * vt.len(), stores: R * vt.len() / width, adds: R * vt.len(), mins: R * vt.len());
+}
+
+/// Applies `step_rows` to the blocks of `ROWS` rows of `r`, which start `ld_r` elements apart, in
+/// parallel, and to the rows left one at a time. Like `simd::step_lanes`, a macro so that the
+/// closure inherits the `#[target_feature]`s of the caller.
+macro_rules! step_lanes {
+    ($S:ty, $threads:expr, $r:expr, $ld_r:expr, $d:expr, $ld_d:expr, $n:expr) => {{
+        let (ld_r, n): (usize, usize) = ($ld_r, $n);
+        let (vd, vt, width) = pad_and_transpose::<$S>($d, $ld_d, n);
+        let r = &mut $r[..crate::strided_len(ld_r, n, n)];
+        crate::threads::for_each_chunk($threads, r, ld_r * ROWS, |c, block| {
+            let rows = ROWS*c..n.min(ROWS*(c + 1));
+            if rows.len() == ROWS {
+                return step_rows::<$S, ROWS>(block, ld_r, &vd[width*rows.start..], &vt, width);
+            }
+            for (q, i) in rows.enumerate() {
+                step_rows::<$S, 1>(&mut block[ld_r*q..], ld_r, &vd[width*i..], &vt, width);
+            }
+        })
+    }};
+}
+
+/// `step` in `S` with the packed, threaded loops of the `dispatch` kernels, compiled for the
+/// widest vectors that the CPU supports. Row `i` of `r` and `d` starts at `ld_r * i` and `ld_d * i`.
+pub(crate) fn step<S: Semiring>(
+    threads: &ThreadConfig,
+    r: &mut [S::Elem],
+    ld_r: usize,
+    d: &[S::Elem],
+    ld_d: usize,
+    n: usize,
+) {
+    #[cfg(target_arch = "x86_64")]
+    if Kernel::Avx512.is_supported() {
+        return unsafe { x86::step_avx512::<S>(threads, r, ld_r, d, ld_d, n) };
+    }
+    #[cfg(target_arch = "x86_64")]
+    if Kernel::Avx2.is_supported() {
+        return unsafe { x86::step_avx2::<S>(threads, r, ld_r, d, ld_d, n) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if Kernel::Neon.is_supported() {
+        return unsafe { neon::step_neon::<S>(threads, r, ld_r, d, ld_d, n) };
+    }
+    step_lanes!(S, threads, r, ld_r, d, ld_d, n)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{pad_and_transpose, step_rows, Semiring, ROWS};
+    use crate::threads::ThreadConfig;
+
+    #[target_feature(enable = "avx512f")]
+    pub(super) unsafe fn step_avx512<S: Semiring>(
+        threads: &ThreadConfig,
+        r: &mut [S::Elem],
+        ld_r: usize,
+        d: &[S::Elem],
+        ld_d: usize,
+        n: usize,
+    ) {
+        step_lanes!(S, threads, r, ld_r, d, ld_d, n)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn step_avx2<S: Semiring>(
+        threads: &ThreadConfig,
+        r: &mut [S::Elem],
+        ld_r: usize,
+        d: &[S::Elem],
+        ld_d: usize,
+        n: usize,
+    ) {
+        step_lanes!(S, threads, r, ld_r, d, ld_d, n)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{pad_and_transpose, step_rows, Semiring, ROWS};
+    use crate::threads::ThreadConfig;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn step_neon<S: Semiring>(
+        threads: &ThreadConfig,
+        r: &mut [S::Elem],
+        ld_r: usize,
+        d: &[S::Elem],
+        ld_d: usize,
+        n: usize,
+    ) {
+        step_lanes!(S, threads, r, ld_r, d, ld_d, n)
+    }
+}
@@ -0,0 +1,430 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::apsp::{apsp_with_options, ApspOptions};
+use crate::io::formats::{read_npy_data, read_npy_header, write_npy, NpyType};
+use crate::{step_with_threads, ThreadConfig};
+
+/// The longest request line or header accepted, in bytes.
+const MAX_LINE: u64 = 8 << 10;
+/// Closes connections that send nothing for this long.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Options for `serve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceOptions {
+    /// How many requests compute at the same time, each with its own share of the threads of
+    /// `threads`. The others wait until one of them is done.
+    pub concurrency: usize,
+    pub threads: ThreadConfig,
+    /// The largest `n` accepted; the bodies of larger matrices are not read.
+    pub max_n: usize,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        ServiceOptions { concurrency: 1, threads: ThreadConfig::default(), max_n: 16384 }
+    }
+}
+
+/// The shares of the threads that `ServiceOptions::concurrency` requests compute with, each
+/// taken by one request at a time.
+struct Shares {
+    free: Mutex<Vec<ThreadConfig>>,
+    returned: Condvar,
+}
+
+impl Shares {
+    /// Splits the threads of `threads` between `concurrency` requests, and their cores too if
+    /// they are pinned.
+    fn new(threads: &ThreadConfig, concurrency: usize) -> Shares {
+        let threads = threads.effective();
+        let concurrency = concurrency.max(1);
+        let share = (threads.effective_threads() / concurrency).max(1);
+        let cores = threads.pin_cores.filter(|cores| !cores.is_empty());
+        let free = (0..concurrency)
+            .map(|s| ThreadConfig {
+                num_threads: Some(share),
+                pin_cores: cores.as_ref().map(|cores| (0..share).map(|t| cores[(share * s + t) % cores.len()]).collect()),
+                grain: threads.grain,
+            })
+            .collect();
+        Shares { free: Mutex::new(free), returned: Condvar::new() }
+    }
+
+    /// Waits for a free share and runs `f` with it.
+    fn run<T>(&self, f: impl FnOnce(&ThreadConfig) -> T) -> T {
+        struct Taken<'a>(&'a Shares, Option<ThreadConfig>);
+        impl Drop for Taken<'_> {
+            // Also when `f` panics, so that the share is not lost.
+            fn drop(&mut self) {
+                self.0.free.lock().unwrap().push(self.1.take().unwrap());
+                self.0.returned.notify_one();
+            }
+        }
+        let mut free = self.free.lock().unwrap();
+        let threads = loop {
+            match free.pop() {
+                Some(threads) => break threads,
+                None => free = self.returned.wait(free).unwrap(),
+            }
+        };
+        drop(free);
+        let taken = Taken(self, Some(threads));
+        f(taken.1.as_ref().unwrap())
+    }
+}
+
+/// How the matrix of a request is encoded, and that of its response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Payload {
+    /// A `.npy` array, of `f32`s in the response.
+    Npy,
+    /// The `n * n` elements as little-endian `f32`s, with `n` the square root of their count.
+    Raw,
+}
+
+impl Payload {
+    fn content_type(self) -> &'static str {
+        match self {
+            Payload::Npy => "application/x-npy",
+            Payload::Raw => "application/octet-stream",
+        }
+    }
+}
+
+/// A response other than the result, with its status code and a message for its body.
+#[derive(Debug)]
+struct Failure(u16, String);
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        _ => "Error",
+    }
+}
+
+/// The request line and the headers that `handle` looks at.
+#[derive(Debug)]
+struct Head {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    content_length: Option<u64>,
+    chunked: bool,
+    expect_continue: bool,
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, Failure> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE).read_line(&mut line).map_err(|e| Failure(400, e.to_string()))?;
+    match line.strip_suffix('\n') {
+        Some(line) => Ok(line.strip_suffix('\r').unwrap_or(line).to_string()),
+        None => Err(Failure(400, "incomplete or too long request line or header".to_string())),
+    }
+}
+
+fn read_head(reader: &mut impl BufRead) -> Result<Head, Failure> {
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(Failure(400, format!("invalid request line '{}'", line)));
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let mut head = Head {
+        method: method.to_string(),
+        path,
+        content_type: None,
+        content_length: None,
+        chunked: false,
+        expect_continue: false,
+    };
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(head);
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Failure(400, format!("invalid header '{}'", line)));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-type" => head.content_type = Some(value.to_ascii_lowercase()),
+            "content-length" => {
+                let len = value.parse().map_err(|_| Failure(400, format!("invalid Content-Length '{}'", value)))?;
+                head.content_length = Some(len);
+            }
+            "transfer-encoding" => head.chunked = value.to_ascii_lowercase().contains("chunked"),
+            "expect" => head.expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+}
+
+/// Reads the matrix of a body of `len` bytes, failing with 413 before any element is read or
+/// allocated if its `.npy` header or its length is that of a matrix larger than `max_n`.
+fn read_body(reader: &mut impl BufRead, payload: Payload, len: u64, max_n: usize) -> Result<(usize, Vec<f32>), Failure> {
+    let bad = |e: io::Error| Failure(400, e.to_string());
+    let too_large = || Failure(413, format!("matrices larger than n = {} are not accepted", max_n));
+    let mut body = reader.by_ref().take(len);
+    let (n, d) = match payload {
+        Payload::Npy => {
+            let header = read_npy_header(&mut body).map_err(bad)?;
+            if header.n > max_n {
+                return Err(too_large());
+            }
+            if header.data_len().is_none_or(|data_len| data_len as u64 > body.limit()) {
+                return Err(Failure(400, format!("{} bytes are too few for the .npy array of n = {}", len, header.n)));
+            }
+            (header.n, read_npy_data(&mut body, &header).map_err(bad)?)
+        }
+        Payload::Raw => {
+            let elements = len / 4;
+            let n = (elements as f64).sqrt().round() as usize;
+            if n > max_n {
+                return Err(too_large());
+            }
+            if !len.is_multiple_of(4) || (n * n) as u64 != elements {
+                return Err(Failure(400, format!("{} bytes are not the f32s of a square matrix", len)));
+            }
+            // Grows as the bytes arrive, rather than allocating `len` for a client that stalls.
+            let mut bytes = Vec::new();
+            body.read_to_end(&mut bytes).map_err(bad)?;
+            if bytes.len() as u64 != len {
+                return Err(Failure(400, format!("the body ends after {} of its {} bytes", bytes.len(), len)));
+            }
+            (n, bytes.chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect())
+        }
+    };
+    if body.limit() != 0 {
+        return Err(Failure(400, format!("{} bytes after the matrix", body.limit())));
+    }
+    Ok((n, d))
+}
+
+/// The response to one request, the result and how to encode it unless it failed.
+fn respond(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    shares: &Shares,
+    options: &ServiceOptions,
+) -> Result<(Payload, usize, Vec<f32>), Failure> {
+    let head = read_head(reader)?;
+    let apsp = match head.path.as_str() {
+        "/step" => false,
+        "/apsp" => true,
+        path => return Err(Failure(404, format!("no endpoint {}, only POST /step and POST /apsp", path))),
+    };
+    if head.method != "POST" {
+        return Err(Failure(405, format!("{} accepts only POST", head.path)));
+    }
+    let Some(len) = head.content_length.filter(|_| !head.chunked) else {
+        return Err(Failure(411, "the request needs a Content-Length".to_string()));
+    };
+    // The most an `f64` `.npy` array of `max_n` takes, with a generous header.
+    let max_len = (options.max_n as u64).pow(2).saturating_mul(8).saturating_add(1 << 16);
+    if len > max_len {
+        return Err(Failure(413, format!("matrices larger than n = {} are not accepted", options.max_n)));
+    }
+    if head.expect_continue {
+        let result = writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").and_then(|_| writer.flush());
+        result.map_err(|e| Failure(400, e.to_string()))?;
+    }
+    // Raw unless it says or looks otherwise, since clients often send any bytes as form data.
+    let npy = head.content_type.as_deref().is_some_and(|t| t.contains("npy"))
+        || reader.fill_buf().is_ok_and(|buf| buf.starts_with(b"\x93NUMPY"));
+    let payload = if npy { Payload::Npy } else { Payload::Raw };
+    let (n, mut d) = read_body(reader, payload, len, options.max_n)?;
+    let result = shares.run(|threads| {
+        if apsp {
+            apsp_with_options(&mut d, n, &ApspOptions { threads: threads.clone(), ..Default::default() }).map(|()| d)
+        } else {
+            let mut r = vec![0.0; n * n];
+            step_with_threads(&mut r, &d, n, threads).map(|()| r)
+        }
+    });
+    let r = result.map_err(|e| Failure(422, e.to_string()))?;
+    Ok((payload, n, r))
+}
+
+/// Writes each write as one chunk of the chunked transfer encoding of HTTP/1.1.
+struct Chunked<W: Write>(W);
+
+impl<W: Write> Write for Chunked<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            write!(self.0, "{:x}\r\n", buf.len())?;
+            self.0.write_all(buf)?;
+            self.0.write_all(b"\r\n")?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Answers one request on `stream` and closes it.
+fn handle(stream: TcpStream, shares: &Shares, options: &ServiceOptions) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    match respond(&mut reader, &mut writer, shares, options) {
+        Ok((payload, n, r)) => {
+            write!(writer, "HTTP/1.1 200 OK\r\nContent-Type: {}\r\n", payload.content_type())?;
+            writer.write_all(b"Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n")?;
+            // Streams the result in chunks of 64 KiB instead of encoding all of it first.
+            let mut body = BufWriter::with_capacity(64 << 10, Chunked(writer));
+            match payload {
+                Payload::Npy => write_npy(&mut body, &r, n, NpyType::F32)?,
+                Payload::Raw => r.iter().try_for_each(|x| body.write_all(&x.to_le_bytes()))?,
+            }
+            let Chunked(mut writer) = body.into_inner().map_err(|e| e.into_error())?;
+            writer.write_all(b"0\r\n\r\n")?;
+            writer.flush()
+        }
+        Err(Failure(status, message)) => {
+            write!(writer, "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\n", status, reason(status))?;
+            write!(writer, "Content-Length: {}\r\nConnection: close\r\n\r\n{}\n", message.len() + 1, message)?;
+            writer.flush()
+        }
+    }
+}
+
+/// Answers `POST /step` and `POST /apsp` requests on `listener` until the process exits, each
+/// connection on its own thread. Their bodies are `.npy` arrays if the `Content-Type` mentions
+/// `npy` or they start like one, else the raw `f32`s of a square matrix in row-major order, and
+/// the result is encoded the same way, streamed as it is written. `/step` returns the step of the
+/// matrix and `/apsp` the shortest paths of `apsp::apsp`. Failed requests get a status of 400 or
+/// more with the reason as plain text.
+pub fn serve(listener: &TcpListener, options: &ServiceOptions) -> io::Result<()> {
+    let shares = Shares::new(&options.threads, options.concurrency);
+    thread::scope(|s| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                // Such as too many open files, which closing other connections resolves.
+                Err(e) => {
+                    eprintln!("error: accepting a connection failed: {}", e);
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            let shares = &shares;
+            s.spawn(move || {
+                if let Err(e) = handle(stream, shares, options) {
+                    eprintln!("error: {}", e);
+                }
+            });
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+
+    fn parse_head(request: &str) -> Result<Head, Failure> {
+        read_head(&mut request.as_bytes())
+    }
+
+    #[test]
+    fn reads_the_request_line_and_headers() {
+        let request = "POST /step?x=1 HTTP/1.1\r\nContent-Type: Application/X-NPY\r\ncontent-length:  12 \r\n\
+                       Expect: 100-continue\r\nHost: a\r\n\r\nbody";
+        let head = parse_head(request).unwrap();
+        assert_eq!((head.method.as_str(), head.path.as_str()), ("POST", "/step"));
+        assert_eq!(head.content_type.as_deref(), Some("application/x-npy"));
+        assert_eq!(head.content_length, Some(12));
+        assert!(head.expect_continue && !head.chunked);
+        assert!(parse_head("POST /apsp HTTP/1.1\nTransfer-Encoding: gzip, chunked\n\n").unwrap().chunked);
+    }
+
+    #[test]
+    fn rejects_invalid_heads() {
+        let long = format!("POST /step HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        let requests = [
+            "POST /step\r\n\r\n",
+            "POST /step HTTP/1.1\r\nno colon\r\n\r\n",
+            "POST /step HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            "POST /step HTTP/1.1\r\n",
+            long.as_str(),
+        ];
+        for request in requests {
+            assert_eq!(parse_head(request).unwrap_err().0, 400, "{:?}", request);
+        }
+    }
+
+    fn npy(d: &[f32], n: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, d, n, NpyType::F32).unwrap();
+        bytes
+    }
+
+    fn body(bytes: &[u8], payload: Payload, max_n: usize) -> Result<(usize, Vec<f32>), Failure> {
+        read_body(&mut &bytes[..], payload, bytes.len() as u64, max_n)
+    }
+
+    #[test]
+    fn reads_bodies_of_either_payload() {
+        let d = random_input(5);
+        assert_eq!(body(&npy(&d, 5), Payload::Npy, 5).unwrap(), (5, d.clone()));
+        let raw: Vec<u8> = d.iter().flat_map(|x| x.to_le_bytes()).collect();
+        assert_eq!(body(&raw, Payload::Raw, 5).unwrap(), (5, d));
+        assert_eq!(body(&raw[..raw.len() - 4], Payload::Raw, 5).unwrap_err().0, 400);
+        assert_eq!(body(&[npy(&[1.0], 1), vec![0]].concat(), Payload::Npy, 5).unwrap_err().0, 400);
+    }
+
+    /// Matrices larger than `max_n` are rejected from their header or length alone, without
+    /// allocating them, as are headers of more elements than the body has.
+    #[test]
+    fn rejects_large_matrices_before_reading_them() {
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (300000, 300000), }\n";
+        let huge = [&b"\x93NUMPY\x01\x00"[..], &(header.len() as u16).to_le_bytes(), header.as_bytes()].concat();
+        assert_eq!(body(&huge, Payload::Npy, 16384).unwrap_err().0, 413);
+        assert_eq!(body(&huge, Payload::Npy, usize::MAX).unwrap_err().0, 400);
+        assert_eq!(body(&npy(&random_input(6), 6), Payload::Npy, 5).unwrap_err().0, 413);
+        // A Content-Length of a matrix of n = 300000 with none of its bytes.
+        let len = 4 * 300000u64.pow(2);
+        assert_eq!(read_body(&mut &[][..], Payload::Raw, len, 16384).unwrap_err().0, 413);
+        assert_eq!(read_body(&mut &[][..], Payload::Raw, len, usize::MAX).unwrap_err().0, 400);
+        assert_eq!(body(&[0; 4 * 6 * 6], Payload::Raw, 5).unwrap_err().0, 413);
+    }
+
+    fn respond_to(request: &[u8], max_n: usize) -> Result<(Payload, usize, Vec<f32>), Failure> {
+        let options = ServiceOptions { max_n, ..Default::default() };
+        let shares = Shares::new(&ThreadConfig::default(), 1);
+        respond(&mut &request[..], &mut Vec::new(), &shares, &options)
+    }
+
+    #[test]
+    fn responds_with_the_step_or_the_status() {
+        let d = random_input(4);
+        let body = npy(&d, 4);
+        let request = |target: &str| {
+            let head = format!("{} HTTP/1.1\r\nContent-Length: {}\r\n\r\n", target, body.len());
+            [head.as_bytes(), &body].concat()
+        };
+        let (payload, n, r) = respond_to(&request("POST /step"), 4).unwrap();
+        let mut expected = vec![0.0; 16];
+        crate::step(&mut expected, &d, 4).unwrap();
+        assert_eq!((payload, n, r), (Payload::Npy, 4, expected));
+        assert_eq!(respond_to(&request("POST /other"), 4).unwrap_err().0, 404);
+        assert_eq!(respond_to(&request("GET /apsp"), 4).unwrap_err().0, 405);
+        assert_eq!(respond_to(&request("POST /step"), 3).unwrap_err().0, 413);
+        assert_eq!(respond_to(b"POST /step HTTP/1.1\r\n\r\n", 4).unwrap_err().0, 411);
+        let huge = format!("POST /step HTTP/1.1\r\nContent-Length: {}\r\n\r\n", u64::MAX);
+        assert_eq!(respond_to(huge.as_bytes(), 4).unwrap_err().0, 413);
+    }
+}
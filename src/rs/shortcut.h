@@ -0,0 +1,79 @@
+/* Generated by gen_header.py, do not edit. */
+#ifndef SHORTCUT_H
+#define SHORTCUT_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define STEP_OK 0
+#define STEP_INVALID_ARGUMENT 1
+#define STEP_PANICKED 2
+#define STEP_INVALID_INPUT 3
+#define STEP_CANCELLED 4
+#define STEP_SIZE_OVERFLOW 5
+#define STEP_NULL_POINTER 6
+#define STEP_MISALIGNED 7
+#define STEP_OVERLAP 8
+#define STEP_UNKNOWN_VARIANT 9
+#define STEP_IO_ERROR 10
+#define STEP_MEMORY_BUDGET 11
+#define STEP_SELFTEST_FAILED 12
+
+typedef struct PreparedMatrix PreparedMatrix;
+typedef struct StepContext StepContext;
+
+int32_t step(float* r_raw, const float* d_raw, size_t n);
+const char* step_strerror(int32_t code);
+size_t shortcut_required_alignment(void);
+int32_t step_f64(double* r_raw, const double* d_raw, size_t n);
+int32_t step_minplus_f32(float* r_raw, const float* d_raw, size_t n);
+int32_t step_maxmin_f32(float* r_raw, const float* d_raw, size_t n);
+int32_t step_bool(bool* r_raw, const bool* d_raw, size_t n);
+const char* shortcut_version(void);
+const char* shortcut_best_variant(void);
+int32_t shortcut_selftest(size_t n, uint64_t seed);
+const char* const* list_variants(void);
+int32_t step_variant(float* r_raw, const float* d_raw, size_t n, const char* variant);
+StepContext* step_ctx_new(size_t n);
+int32_t step_ctx_run(StepContext* ctx, float* r_raw, const float* d_raw);
+void step_ctx_free(StepContext* ctx);
+int32_t prepared_new(const float* d_raw, size_t n, PreparedMatrix** prepared);
+int32_t prepared_step(const PreparedMatrix* prepared, float* r_raw);
+void prepared_free(PreparedMatrix* prepared);
+int32_t step_with_threads(float* r_raw, const float* d_raw, size_t n, size_t num_threads);
+int32_t step_strided(float* r_raw, size_t ld_r, const float* d_raw, size_t ld_d, size_t n);
+int32_t step_colmajor(float* r_raw, const float* d_raw, size_t n);
+int32_t minplus_gemm(float* r_raw, const float* a_raw, const float* b_raw, size_t m, size_t k, size_t n);
+int32_t step_in_place(float* d_raw, size_t n);
+int32_t apsp(float* d_raw, size_t n);
+int32_t step_with_pred(float* r_raw, size_t* pred_raw, const float* d_raw, size_t n);
+int32_t apsp_with_pred(float* d_raw, size_t* pred_raw, size_t n);
+int32_t step_i32(int32_t* r_raw, const int32_t* d_raw, size_t n);
+int32_t step_u16(uint16_t* r_raw, const uint16_t* d_raw, size_t n);
+int32_t step_f16(uint16_t* r_raw, const uint16_t* d_raw, size_t n);
+int32_t step_bf16(uint16_t* r_raw, const uint16_t* d_raw, size_t n);
+int32_t step_checked(float* r_raw, const float* d_raw, size_t n, bool check_negative);
+int32_t step_with_progress(float* r_raw, const float* d_raw, size_t n, void (*progress)(float, void*), void* user_data);
+int32_t step_cancellable(float* r_raw, const float* d_raw, size_t n, const bool* cancel);
+int32_t step_batch(float* const* r_raws, const float* const* d_raws, const size_t* ns, size_t count);
+float* shortcut_alloc(size_t n);
+void shortcut_free(float* ptr);
+int32_t shortcut_step_v0(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v1(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v2(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v3(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v4(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v5(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v6(float* r_raw, const float* d_raw, size_t n);
+int32_t shortcut_step_v7(float* r_raw, const float* d_raw, size_t n);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,763 @@
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+#[cfg(feature = "numa")]
+use std::alloc::{alloc_zeroed, handle_alloc_error, Layout};
+use std::ops::{Deref, DerefMut, Range};
+
+use crate::layout;
+use crate::ops::count;
+use crate::threads::ThreadConfig;
+use crate::trace::span;
+
+/// With the `paranoid` feature and under Miri, no CPU feature is used even where detected, so that
+/// every kernel runs the portable version of its loops, and `load` checks every vector it reads
+/// against the end of its row. Tools that look for undefined behavior, which cannot follow most
+/// intrinsics, then check the same indexing as the fast kernels run, only slower.
+pub(crate) const PARANOID: bool = cfg!(any(miri, feature = "paranoid"));
+
+pub(crate) trait Vector: Copy {
+    const LANES: usize;
+    unsafe fn splat(x: f32) -> Self;
+    unsafe fn load(p: *const f32) -> Self;
+    /// Like `load`, for `p` aligned to the size of `Self`.
+    #[inline(always)]
+    unsafe fn load_aligned(p: *const f32) -> Self {
+        Self::load(p)
+    }
+    unsafe fn add(a: Self, b: Self) -> Self;
+    unsafe fn min(a: Self, b: Self) -> Self;
+    unsafe fn horizontal_min(a: Self) -> f32;
+    /// Like `min(a, b)`, but returns `a` where `b` is NaN, assuming `a` never is.
+    /// Every `min` here returns its second operand if one of them is NaN, or ignores NaN.
+    #[inline(always)]
+    unsafe fn min_number(a: Self, b: Self) -> Self {
+        Self::min(b, a)
+    }
+}
+
+impl Vector for f32 {
+    const LANES: usize = 1;
+    unsafe fn splat(x: f32) -> Self {
+        x
+    }
+    unsafe fn load(p: *const f32) -> Self {
+        *p
+    }
+    unsafe fn add(a: Self, b: Self) -> Self {
+        a + b
+    }
+    unsafe fn min(a: Self, b: Self) -> Self {
+        if a < b { a } else { b }
+    }
+    unsafe fn horizontal_min(a: Self) -> f32 {
+        a
+    }
+}
+
+/// Four independent accumulators, leaving the vectorization to the compiler.
+impl Vector for [f32; 4] {
+    const LANES: usize = 4;
+    unsafe fn splat(x: f32) -> Self {
+        [x; 4]
+    }
+    unsafe fn load(p: *const f32) -> Self {
+        *(p as *const [f32; 4])
+    }
+    unsafe fn add(a: Self, b: Self) -> Self {
+        [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]
+    }
+    unsafe fn min(a: Self, b: Self) -> Self {
+        [f32::min(a[0], b[0]), f32::min(a[1], b[1]), f32::min(a[2], b[2]), f32::min(a[3], b[3])]
+    }
+    unsafe fn horizontal_min(a: Self) -> f32 {
+        f32::min(f32::min(a[0], a[1]), f32::min(a[2], a[3]))
+    }
+}
+
+/// Eight independent accumulators, the lanes of the blocks of `v5` and `v7` without AVX2.
+impl Vector for [f32; 8] {
+    const LANES: usize = 8;
+    unsafe fn splat(x: f32) -> Self {
+        [x; 8]
+    }
+    unsafe fn load(p: *const f32) -> Self {
+        *(p as *const [f32; 8])
+    }
+    unsafe fn add(a: Self, b: Self) -> Self {
+        std::array::from_fn(|i| a[i] + b[i])
+    }
+    unsafe fn min(a: Self, b: Self) -> Self {
+        std::array::from_fn(|i| f32::min(a[i], b[i]))
+    }
+    unsafe fn horizontal_min(a: Self) -> f32 {
+        a.into_iter().fold(f32::INFINITY, f32::min)
+    }
+}
+
+/// A row-major matrix whose rows start `ld` elements apart.
+#[derive(Clone, Copy)]
+pub(crate) struct Strided<'a> {
+    pub(crate) data: &'a [f32],
+    pub(crate) ld: usize,
+}
+
+/// Alignment of the packed copies, in bytes, that of the widest vector of any kernel.
+pub(crate) const ALIGN: usize = 64;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(64))]
+struct Line([f32; ALIGN / 4]);
+
+/// `len` floats aligned to `ALIGN` bytes, in a `Vec` of whole lines.
+pub(crate) struct AlignedVec {
+    lines: Vec<Line>,
+    len: usize,
+}
+
+impl AlignedVec {
+    pub(crate) fn filled(len: usize, value: f32) -> Self {
+        AlignedVec { lines: vec![Line([value; ALIGN / 4]); len.div_ceil(ALIGN / 4)], len }
+    }
+
+    /// `len` zeros, whose pages are not touched until they are written.
+    #[cfg(feature = "numa")]
+    pub(crate) fn zeroed(len: usize) -> Self {
+        let lines = len.div_ceil(ALIGN / 4);
+        if lines == 0 {
+            return AlignedVec { lines: Vec::new(), len };
+        }
+        let layout = Layout::array::<Line>(lines).expect("packed matrix size overflows isize");
+        unsafe {
+            let ptr = alloc_zeroed(layout);
+            if ptr.is_null() {
+                handle_alloc_error(layout);
+            }
+            AlignedVec { lines: Vec::from_raw_parts(ptr.cast(), lines, lines), len }
+        }
+    }
+}
+
+impl Deref for AlignedVec {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        unsafe { std::slice::from_raw_parts(self.lines.as_ptr().cast(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedVec {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        unsafe { std::slice::from_raw_parts_mut(self.lines.as_mut_ptr().cast(), self.len) }
+    }
+}
+
+/// The rows or columns of a `Packed`, like a `Cow` of an `AlignedVec`.
+pub(crate) enum Buffer<'a> {
+    Owned(AlignedVec),
+    Borrowed(&'a [f32]),
+}
+
+impl Deref for Buffer<'_> {
+    type Target = [f32];
+    fn deref(&self) -> &[f32] {
+        match self {
+            Buffer::Owned(data) => data,
+            Buffer::Borrowed(data) => data,
+        }
+    }
+}
+
+impl From<AlignedVec> for Buffer<'_> {
+    fn from(data: AlignedVec) -> Self {
+        Buffer::Owned(data)
+    }
+}
+
+/// The `m` rows of `a` and `n` columns of `b` in the product of an `m * k` and a `k * n` matrix,
+/// copied into rows of `width` elements padded with `f32::INFINITY` to a multiple of `lanes`, or
+/// borrowed from a larger `Packed` by `block`. The copies are aligned to `ALIGN` bytes.
+pub(crate) struct Packed<'a> {
+    pub(crate) rows: Buffer<'a>,
+    pub(crate) cols: Buffer<'a>,
+    pub(crate) m: usize,
+    pub(crate) n: usize,
+    pub(crate) width: usize,
+}
+
+/// The bytes of the copies of `Packed::new` for the product of an `m * k` and a `k * n` matrix.
+pub(crate) fn packed_bytes(m: usize, k: usize, n: usize, lanes: usize) -> usize {
+    let width = k.div_ceil(lanes).max(1) * lanes;
+    m.saturating_add(n).saturating_mul(width).saturating_mul(std::mem::size_of::<f32>())
+}
+
+impl Packed<'static> {
+    pub(crate) fn new(a: Strided, b: Strided, m: usize, k: usize, n: usize, lanes: usize) -> Self {
+        span!("pack", m, k, n);
+        let width = k.div_ceil(lanes).max(1) * lanes;
+        let mut rows = AlignedVec::filled(m * width, f32::INFINITY);
+        let mut cols = AlignedVec::filled(n * width, f32::INFINITY);
+        for i in 0..m {
+            rows[width*i..width*i + k].copy_from_slice(&a.data[a.ld*i..a.ld*i + k]);
+        }
+        layout::transpose_into(&mut cols, width, b.data, b.ld, k, n);
+        Packed { rows: rows.into(), cols: cols.into(), m, n, width }
+    }
+
+    /// Like `new`, but each row of `rows` is first written by the thread of `threads` that
+    /// `for_each_chunk` gives the same row of `r` to, placing it on that thread's NUMA node.
+    #[cfg(feature = "numa")]
+    pub(crate) fn first_touch(threads: &ThreadConfig, a: Strided, b: Strided, m: usize, k: usize, n: usize, lanes: usize) -> Self {
+        use crate::threads::for_each_chunk;
+
+        span!("pack", m, k, n);
+        let width = k.div_ceil(lanes).max(1) * lanes;
+        // Zeroed allocations are not touched until they are written.
+        let mut rows = AlignedVec::zeroed(m * width);
+        let mut cols = AlignedVec::zeroed(n * width);
+        for_each_chunk(threads, &mut rows, width, |i, row| {
+            row[..k].copy_from_slice(&a.data[a.ld*i..a.ld*i + k]);
+            row[k..].fill(f32::INFINITY);
+        });
+        for_each_chunk(threads, &mut cols, width, |j, col| {
+            for (l, x) in col[..k].iter_mut().enumerate() {
+                *x = b.data[b.ld*l + j];
+            }
+            col[k..].fill(f32::INFINITY);
+        });
+        Packed { rows: rows.into(), cols: cols.into(), m, n, width }
+    }
+
+    /// `d` and its transpose, for `step`.
+    pub(crate) fn square(d: &[f32], ld_d: usize, n: usize, lanes: usize) -> Self {
+        let d = Strided { data: d, ld: ld_d };
+        Self::new(d, d, n, n, n, lanes)
+    }
+}
+
+impl Packed<'_> {
+    /// The product of `rows` of `a` and `cols` of `b`, without copying them.
+    pub(crate) fn block(&self, rows: Range<usize>, cols: Range<usize>) -> Packed<'_> {
+        let width = self.width;
+        Packed {
+            rows: Buffer::Borrowed(&self.rows[width*rows.start..width*rows.end]),
+            cols: Buffer::Borrowed(&self.cols[width*cols.start..width*cols.end]),
+            m: rows.len(),
+            n: cols.len(),
+            width,
+        }
+    }
+
+    /// Whether every row of `rows` and `cols` starts at a multiple of `align` bytes, so that the
+    /// kernels can use aligned loads of vectors of `align` bytes. The copies always are for the
+    /// lanes they were padded to, but blocks and rows borrowed from elsewhere need not be.
+    pub(crate) fn is_aligned(&self, align: usize) -> bool {
+        let aligned = |data: &[f32]| (data.as_ptr() as usize).is_multiple_of(align);
+        aligned(&self.rows) && aligned(&self.cols) && (self.width * std::mem::size_of::<f32>()).is_multiple_of(align)
+    }
+}
+
+/// A pointer to the `lanes` elements of `s` from `k`. With `PARANOID` they are sliced out of `s`
+/// first, so that reading past its end panics.
+#[inline(always)]
+pub(crate) unsafe fn load_at<T>(s: &[T], k: usize, lanes: usize) -> *const T {
+    if PARANOID { s[k..k + lanes].as_ptr() } else { s.as_ptr().add(k) }
+}
+
+/// The vector of the `V::LANES` elements of `s` from `k`, with aligned loads if `ALIGNED`.
+#[inline(always)]
+pub(crate) unsafe fn load<V: Vector, const ALIGNED: bool>(s: &[f32], k: usize) -> V {
+    let p = load_at(s, k, V::LANES);
+    if ALIGNED { V::load_aligned(p) } else { V::load(p) }
+}
+
+#[inline(always)]
+unsafe fn min_sum<V: Vector, const INF_AWARE: bool>(v: V, x: V, y: V) -> V {
+    if INF_AWARE { V::min_number(v, V::add(x, y)) } else { V::min(v, V::add(x, y)) }
+}
+
+/// With aligned loads if `ALIGNED`, which the rows of `vd_row` and `vt` must then be for `V`.
+/// Rows packed for `Tail::Mask`, whose `width` need not be a multiple of `V::LANES`, end with the
+/// vector of their last `V::LANES` elements, overlapping the one before it, instead of one with
+/// its lanes past the end masked off like `x86::step_avx512`. The minimum is the same with the
+/// overlapping terms taken twice, so they need no blend either. Rows shorter than a vector are
+/// computed one element at a time.
+#[inline(always)]
+pub(crate) unsafe fn step_row<V: Vector, const INF_AWARE: bool, const ALIGNED: bool>(
+    r_row: &mut [f32],
+    vd_row: &[f32],
+    vt: &[f32],
+    width: usize,
+) {
+    if width < V::LANES {
+        return step_row::<f32, INF_AWARE, false>(r_row, vd_row, vt, width);
+    }
+    let full = width / V::LANES * V::LANES;
+    for (res, vt_row) in r_row.iter_mut().zip(vt.chunks(width)) {
+        let mut v = V::splat(f32::INFINITY);
+        for k in (0..full).step_by(V::LANES) {
+            v = min_sum::<V, INF_AWARE>(v, load::<V, ALIGNED>(vd_row, k), load::<V, ALIGNED>(vt_row, k));
+        }
+        if full < width {
+            let k = width - V::LANES;
+            v = min_sum::<V, INF_AWARE>(v, load::<V, false>(vd_row, k), load::<V, false>(vt_row, k));
+        }
+        *res = V::horizontal_min(v);
+    }
+    // The horizontal minimum of each result takes `V::LANES - 1` more.
+    count!(
+        loads: 2 * width.div_ceil(V::LANES) * V::LANES * r_row.len(),
+        stores: r_row.len(),
+        adds: width.div_ceil(V::LANES) * V::LANES * r_row.len(),
+        mins: (width.div_ceil(V::LANES) * V::LANES + V::LANES - 1) * r_row.len(),
+    );
+}
+
+/// Applies `step_row` to all rows of `r`, which start `ld_r` elements apart, in parallel, with
+/// aligned loads if the packed rows and columns are aligned for them.
+/// This is a macro so that the closure running on each thread is defined inside the caller,
+/// and inherits its `#[target_feature]`s.
+macro_rules! step_lanes {
+    ($V:ty, $threads:expr, $r:expr, $ld_r:expr, $packed:expr, $inf_aware:expr) => {{
+        let (r, ld_r, p, inf_aware): (&mut [f32], usize, &$crate::simd::Packed, bool) = ($r, $ld_r, $packed, $inf_aware);
+        let r = &mut r[..$crate::strided_len(ld_r, p.m, p.n)];
+        let aligned = p.is_aligned(::core::mem::size_of::<$V>());
+        $crate::trace::span!("compute", m = p.m, n = p.n);
+        $crate::threads::for_each_chunk($threads, r, ld_r, |i, r_row| unsafe {
+            let (r_row, vd_row) = (&mut r_row[..p.n], &p.rows[p.width*i..p.width*(i + 1)]);
+            match (inf_aware, aligned) {
+                (true, true) => $crate::simd::step_row::<$V, true, true>(r_row, vd_row, &p.cols, p.width),
+                (true, false) => $crate::simd::step_row::<$V, true, false>(r_row, vd_row, &p.cols, p.width),
+                (false, true) => $crate::simd::step_row::<$V, false, true>(r_row, vd_row, &p.cols, p.width),
+                (false, false) => $crate::simd::step_row::<$V, false, false>(r_row, vd_row, &p.cols, p.width),
+            }
+        })
+    }};
+}
+pub(crate) use step_lanes;
+
+pub(crate) fn step_scalar(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, packed: &Packed, inf_aware: bool) {
+    step_lanes!(f32, threads, r, ld_r, packed, inf_aware)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{Packed, Vector};
+    use crate::threads::{for_each_chunk, ThreadConfig};
+
+    fn horizontal_min(lanes: &[f32]) -> f32 {
+        lanes.iter().fold(f32::INFINITY, |acc, &x| if x < acc { x } else { acc })
+    }
+
+    impl Vector for __m128 {
+        const LANES: usize = 4;
+        #[inline(always)]
+        unsafe fn splat(x: f32) -> Self {
+            _mm_set1_ps(x)
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const f32) -> Self {
+            _mm_loadu_ps(p)
+        }
+        #[inline(always)]
+        unsafe fn load_aligned(p: *const f32) -> Self {
+            _mm_load_ps(p)
+        }
+        #[inline(always)]
+        unsafe fn add(a: Self, b: Self) -> Self {
+            _mm_add_ps(a, b)
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            _mm_min_ps(a, b)
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> f32 {
+            let mut lanes = [0.0; 4];
+            _mm_storeu_ps(lanes.as_mut_ptr(), a);
+            horizontal_min(&lanes)
+        }
+    }
+
+    impl Vector for __m256 {
+        const LANES: usize = 8;
+        #[inline(always)]
+        unsafe fn splat(x: f32) -> Self {
+            _mm256_set1_ps(x)
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const f32) -> Self {
+            _mm256_loadu_ps(p)
+        }
+        #[inline(always)]
+        unsafe fn load_aligned(p: *const f32) -> Self {
+            _mm256_load_ps(p)
+        }
+        #[inline(always)]
+        unsafe fn add(a: Self, b: Self) -> Self {
+            _mm256_add_ps(a, b)
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            _mm256_min_ps(a, b)
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> f32 {
+            let mut lanes = [0.0; 8];
+            _mm256_storeu_ps(lanes.as_mut_ptr(), a);
+            horizontal_min(&lanes)
+        }
+    }
+
+    impl Vector for __m512 {
+        const LANES: usize = 16;
+        #[inline(always)]
+        unsafe fn splat(x: f32) -> Self {
+            _mm512_set1_ps(x)
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const f32) -> Self {
+            _mm512_loadu_ps(p)
+        }
+        #[inline(always)]
+        unsafe fn load_aligned(p: *const f32) -> Self {
+            _mm512_load_ps(p)
+        }
+        #[inline(always)]
+        unsafe fn add(a: Self, b: Self) -> Self {
+            _mm512_add_ps(a, b)
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            _mm512_min_ps(a, b)
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> f32 {
+            _mm512_reduce_min_ps(a)
+        }
+    }
+
+    /// Reads rows packed without padding, masking off the lanes past `width` in the last vector.
+    /// Ignores NaN sums like `Vector::min_number` if `inf_aware`, by passing the sum as the first operand.
+    #[target_feature(enable = "avx512f")]
+    pub(crate) unsafe fn step_avx512(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, p: &Packed, inf_aware: bool) {
+        let r = &mut r[..crate::strided_len(ld_r, p.m, p.n)];
+        crate::trace::span!("compute", m = p.m, n = p.n);
+        // Rows are only aligned if `width` happens to be a multiple of 16.
+        if p.is_aligned(64) {
+            step_avx512_loads::<true>(threads, r, ld_r, p, inf_aware)
+        } else {
+            step_avx512_loads::<false>(threads, r, ld_r, p, inf_aware)
+        }
+    }
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn step_avx512_loads<const ALIGNED: bool>(
+        threads: &ThreadConfig,
+        r: &mut [f32],
+        ld_r: usize,
+        p: &Packed,
+        inf_aware: bool,
+    ) {
+        let width = p.width;
+        let full = width / 16 * 16;
+        let tail: __mmask16 = ((1u32 << (width - full)) - 1) as __mmask16;
+        for_each_chunk(threads, r, ld_r, |i, r_row| {
+            let d_row = &p.rows[width*i..width*(i + 1)];
+            let inf = _mm512_set1_ps(f32::INFINITY);
+            for (res, t_row) in r_row[..p.n].iter_mut().zip(p.cols.chunks(width)) {
+                let mut v = inf;
+                for k in (0..full).step_by(16) {
+                    let x = super::load::<__m512, ALIGNED>(d_row, k);
+                    let y = super::load::<__m512, ALIGNED>(t_row, k);
+                    v = min(v, _mm512_add_ps(x, y), inf_aware);
+                }
+                if tail != 0 {
+                    let x = _mm512_mask_loadu_ps(inf, tail, d_row.as_ptr().add(full));
+                    let y = _mm512_mask_loadu_ps(inf, tail, t_row.as_ptr().add(full));
+                    v = min(v, _mm512_add_ps(x, y), inf_aware);
+                }
+                *res = _mm512_reduce_min_ps(v);
+            }
+            // The masked lanes past `width` are added but not loaded.
+            crate::ops::count!(
+                loads: 2 * width * p.n,
+                stores: p.n,
+                adds: width.div_ceil(16) * 16 * p.n,
+                mins: (width.div_ceil(16) * 16 + 15) * p.n,
+            );
+        });
+    }
+
+    #[inline(always)]
+    unsafe fn min(v: __m512, z: __m512, inf_aware: bool) -> __m512 {
+        if inf_aware { _mm512_min_ps(z, v) } else { _mm512_min_ps(v, z) }
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    pub(crate) unsafe fn swap1(v: __m256) -> __m256 {
+        _mm256_permute_ps(v, 0b10_11_00_01)
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    pub(crate) unsafe fn swap2(v: __m256) -> __m256 {
+        _mm256_permute_ps(v, 0b01_00_11_10)
+    }
+
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    pub(crate) unsafe fn swap4(v: __m256) -> __m256 {
+        _mm256_permute2f128_ps(v, v, 1)
+    }
+
+    #[target_feature(enable = "sse")]
+    pub(crate) unsafe fn step_sse(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, packed: &Packed, inf_aware: bool) {
+        step_lanes!(__m128, threads, r, ld_r, packed, inf_aware)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn step_avx2(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, packed: &Packed, inf_aware: bool) {
+        step_lanes!(__m256, threads, r, ld_r, packed, inf_aware)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) mod neon {
+    use std::arch::aarch64::*;
+
+    use super::{Packed, Vector};
+    use crate::threads::ThreadConfig;
+
+    impl Vector for float32x4_t {
+        const LANES: usize = 4;
+        #[inline(always)]
+        unsafe fn splat(x: f32) -> Self {
+            vdupq_n_f32(x)
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const f32) -> Self {
+            vld1q_f32(p)
+        }
+        #[inline(always)]
+        unsafe fn add(a: Self, b: Self) -> Self {
+            vaddq_f32(a, b)
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            vminq_f32(a, b)
+        }
+        /// `vminq_f32` returns NaN if either operand is NaN, unlike `vminnmq_f32`.
+        #[inline(always)]
+        unsafe fn min_number(a: Self, b: Self) -> Self {
+            vminnmq_f32(a, b)
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> f32 {
+            vminvq_f32(a)
+        }
+    }
+
+    /// Two vectors as the 8 lanes of the blocks of `v5` and `v7`, like `__m256` on x86.
+    impl Vector for float32x4x2_t {
+        const LANES: usize = 8;
+        #[inline(always)]
+        unsafe fn splat(x: f32) -> Self {
+            float32x4x2_t(vdupq_n_f32(x), vdupq_n_f32(x))
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const f32) -> Self {
+            vld1q_f32_x2(p)
+        }
+        #[inline(always)]
+        unsafe fn add(a: Self, b: Self) -> Self {
+            float32x4x2_t(vaddq_f32(a.0, b.0), vaddq_f32(a.1, b.1))
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            float32x4x2_t(vminq_f32(a.0, b.0), vminq_f32(a.1, b.1))
+        }
+        #[inline(always)]
+        unsafe fn min_number(a: Self, b: Self) -> Self {
+            float32x4x2_t(vminnmq_f32(a.0, b.0), vminnmq_f32(a.1, b.1))
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> f32 {
+            vminvq_f32(vminq_f32(a.0, a.1))
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn step_neon(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, packed: &Packed, inf_aware: bool) {
+        step_lanes!(float32x4_t, threads, r, ld_r, packed, inf_aware)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) mod wasm {
+    use std::arch::wasm32::*;
+
+    use super::{Packed, Vector};
+    use crate::threads::ThreadConfig;
+
+    impl Vector for v128 {
+        const LANES: usize = 4;
+        #[inline(always)]
+        unsafe fn splat(x: f32) -> Self {
+            f32x4_splat(x)
+        }
+        #[inline(always)]
+        unsafe fn load(p: *const f32) -> Self {
+            v128_load(p as *const v128)
+        }
+        #[inline(always)]
+        unsafe fn add(a: Self, b: Self) -> Self {
+            f32x4_add(a, b)
+        }
+        #[inline(always)]
+        unsafe fn min(a: Self, b: Self) -> Self {
+            f32x4_min(a, b)
+        }
+        /// `f32x4_min` returns NaN if either operand is NaN, `f32x4_pmin` returns `a` if `b` is NaN.
+        #[inline(always)]
+        unsafe fn min_number(a: Self, b: Self) -> Self {
+            f32x4_pmin(a, b)
+        }
+        #[inline(always)]
+        unsafe fn horizontal_min(a: Self) -> f32 {
+            let a = f32x4_min(a, i32x4_shuffle::<2, 3, 0, 1>(a, a));
+            f32x4_extract_lane::<0>(f32x4_min(a, i32x4_shuffle::<1, 0, 3, 2>(a, a)))
+        }
+    }
+
+    #[target_feature(enable = "simd128")]
+    pub(crate) unsafe fn step_simd128(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, packed: &Packed, inf_aware: bool) {
+        step_lanes!(v128, threads, r, ld_r, packed, inf_aware)
+    }
+}
+
+/// The RISC-V Vector kernel, in inline assembly since Rust has no intrinsics for it, with loops
+/// that work for any vector length: each pass over a row takes as many elements as `vsetvli`
+/// allows, and the last one the remaining, so rows need no padding.
+#[cfg(all(feature = "rvv", target_arch = "riscv64"))]
+pub(crate) mod rvv {
+    use std::arch::asm;
+
+    use super::Packed;
+    use crate::threads::{for_each_chunk, ThreadConfig};
+
+    /// The bit of the `V` extension in the hardware capabilities of Linux.
+    #[cfg(all(feature = "std", target_os = "linux"))]
+    pub(crate) fn is_detected() -> bool {
+        use std::ffi::c_ulong;
+
+        extern "C" {
+            fn getauxval(kind: c_ulong) -> c_ulong;
+        }
+        const AT_HWCAP: c_ulong = 16;
+        unsafe { getauxval(AT_HWCAP) & 1 << (b'V' - b'A') != 0 }
+    }
+
+    #[cfg(all(feature = "std", not(target_os = "linux")))]
+    pub(crate) fn is_detected() -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn is_detected() -> bool {
+        cfg!(target_feature = "v")
+    }
+
+    /// The minimums of the sums of the `len` elements from `x` with those from each of `y`, with
+    /// a group of two registers of minimums for each. The last, shorter pass leaves the lanes
+    /// past its end undisturbed, so they keep the minimums of the passes before.
+    #[inline(always)]
+    unsafe fn min_sums4(x: *const f32, y: [*const f32; 4], len: usize) -> [f32; 4] {
+        let (r0, r1, r2, r3): (f32, f32, f32, f32);
+        asm!(
+            ".option push",
+            ".option arch, +v",
+            "vsetvli {vl}, zero, e32, m2, ta, ma",
+            "vfmv.v.f v8, {inf}",
+            "vfmv.v.f v10, {inf}",
+            "vfmv.v.f v12, {inf}",
+            "vfmv.v.f v14, {inf}",
+            "2:",
+            "vsetvli {vl}, {len}, e32, m2, tu, ma",
+            "vle32.v v16, ({x})",
+            "vle32.v v18, ({y0})",
+            "vfadd.vv v18, v16, v18",
+            "vfmin.vv v8, v8, v18",
+            "vle32.v v20, ({y1})",
+            "vfadd.vv v20, v16, v20",
+            "vfmin.vv v10, v10, v20",
+            "vle32.v v22, ({y2})",
+            "vfadd.vv v22, v16, v22",
+            "vfmin.vv v12, v12, v22",
+            "vle32.v v24, ({y3})",
+            "vfadd.vv v24, v16, v24",
+            "vfmin.vv v14, v14, v24",
+            "sub {len}, {len}, {vl}",
+            "slli {vl}, {vl}, 2",
+            "add {x}, {x}, {vl}",
+            "add {y0}, {y0}, {vl}",
+            "add {y1}, {y1}, {vl}",
+            "add {y2}, {y2}, {vl}",
+            "add {y3}, {y3}, {vl}",
+            "bnez {len}, 2b",
+            "vsetvli {vl}, zero, e32, m2, ta, ma",
+            "vfmv.s.f v26, {inf}",
+            "vfredmin.vs v27, v8, v26",
+            "vfmv.f.s {r0}, v27",
+            "vfredmin.vs v27, v10, v26",
+            "vfmv.f.s {r1}, v27",
+            "vfredmin.vs v27, v12, v26",
+            "vfmv.f.s {r2}, v27",
+            "vfredmin.vs v27, v14, v26",
+            "vfmv.f.s {r3}, v27",
+            ".option pop",
+            inf = in(freg) f32::INFINITY,
+            x = inout(reg) x => _,
+            y0 = inout(reg) y[0] => _,
+            y1 = inout(reg) y[1] => _,
+            y2 = inout(reg) y[2] => _,
+            y3 = inout(reg) y[3] => _,
+            len = inout(reg) len => _,
+            vl = out(reg) _,
+            r0 = out(freg) r0,
+            r1 = out(freg) r1,
+            r2 = out(freg) r2,
+            r3 = out(freg) r3,
+            out("v8") _, out("v9") _, out("v10") _, out("v11") _, out("v12") _, out("v13") _, out("v14") _, out("v15") _,
+            out("v16") _, out("v17") _, out("v18") _, out("v19") _, out("v20") _, out("v21") _, out("v22") _, out("v23") _,
+            out("v24") _, out("v25") _, out("v26") _, out("v27") _,
+            options(pure, readonly, nostack),
+        );
+        [r0, r1, r2, r3]
+    }
+
+    /// Ignores NaN sums whether or not `inf_aware`, since `vfmin` returns the other operand.
+    /// Computes four results at a time, each load of the row shared by four columns, and repeats
+    /// the last column for the results past `n` in the last four.
+    pub(crate) unsafe fn step_rvv(threads: &ThreadConfig, r: &mut [f32], ld_r: usize, p: &Packed, _inf_aware: bool) {
+        let r = &mut r[..crate::strided_len(ld_r, p.m, p.n)];
+        crate::trace::span!("compute", m = p.m, n = p.n);
+        let width = p.width;
+        for_each_chunk(threads, r, ld_r, |i, r_row| {
+            let x = p.rows[width*i..width*(i + 1)].as_ptr();
+            for (j, res) in r_row[..p.n].chunks_mut(4).enumerate() {
+                let col = |c: usize| p.cols[width * (4*j + c.min(res.len() - 1))..].as_ptr();
+                let mins = unsafe { min_sums4(x, [col(0), col(1), col(2), col(3)], width) };
+                res.copy_from_slice(&mins[..res.len()]);
+            }
+            // The reductions take as many more minimums as there are lanes, which is only known at runtime.
+            crate::ops::count!(
+                loads: 5 * width * p.n.div_ceil(4),
+                stores: p.n,
+                adds: 4 * width * p.n.div_ceil(4),
+                mins: 4 * width * p.n.div_ceil(4),
+            );
+        });
+    }
+}
@@ -0,0 +1,80 @@
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::{check_lengths, StepError};
+
+/// The density below which `step_hybrid` uses `step` of this module instead of the dense kernels.
+/// Each row of `r` then costs about `density * n * n` operations instead of `n * n`, but without
+/// the register blocking of the dense kernels, which makes them faster for denser matrices.
+pub const DENSITY_THRESHOLD: f32 = 0.25;
+
+/// Rows of `d` with at most one in `SCATTER_RATIO` of their elements finite are added to a row of
+/// `r` element by element from the index, denser ones as a whole row, which vectorizes.
+const SCATTER_RATIO: usize = 8;
+
+/// The fraction of the elements of `d` that are not `f32::INFINITY`, that is, of the edges of the
+/// graph that are present, or 1 for an empty `d`.
+pub fn density(d: &[f32]) -> f32 {
+    if d.is_empty() {
+        return 1.0;
+    }
+    d.iter().filter(|&&x| x != f32::INFINITY).count() as f32 / d.len() as f32
+}
+
+/// The elements of each row of `d` that are not `f32::INFINITY`, in compressed sparse rows: those
+/// of row `i` have columns `cols[starts[i]..starts[i + 1]]` and values `values[starts[i]..starts[i + 1]]`.
+struct RowIndex {
+    starts: Vec<usize>,
+    cols: Vec<u32>,
+    values: Vec<f32>,
+}
+
+impl RowIndex {
+    fn new(d: &[f32], n: usize) -> Self {
+        let mut index = RowIndex { starts: vec![0], cols: Vec::new(), values: Vec::new() };
+        for row in d.chunks(n.max(1)) {
+            for (j, &x) in row.iter().enumerate().filter(|(_, &x)| x != f32::INFINITY) {
+                index.cols.push(j as u32);
+                index.values.push(x);
+            }
+            index.starts.push(index.cols.len());
+        }
+        index
+    }
+
+    fn row(&self, i: usize) -> (&[u32], &[f32]) {
+        let range = self.starts[i]..self.starts[i + 1];
+        (&self.cols[range.clone()], &self.values[range])
+    }
+}
+
+/// Like `step`, but only summing over the `k` for which `d[i][k]` is not `f32::INFINITY`, and
+/// within those, for sparse rows of `d`, only over the `j` for which `d[k][j]` is not either.
+/// The sums it skips are those `StepOptions::inf_aware` makes infinite.
+pub fn step(r: &mut [f32], d: &[f32], n: usize) -> Result<(), StepError> {
+    check_lengths(r, d, n)?;
+    step_with_threads(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+pub(crate) fn step_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    let index = RowIndex::new(d, n);
+    for_each_chunk(threads, r, n, |i, r_row| {
+        r_row.fill(f32::INFINITY);
+        let (ks, weights) = index.row(i);
+        for (&k, &x) in ks.iter().zip(weights) {
+            let (js, ys) = index.row(k as usize);
+            if js.len() * SCATTER_RATIO <= n {
+                for (&j, &y) in js.iter().zip(ys) {
+                    let res = &mut r_row[j as usize];
+                    *res = if x + y < *res { x + y } else { *res };
+                }
+            } else {
+                for (res, &y) in r_row.iter_mut().zip(&d[n * k as usize..]) {
+                    *res = if x + y < *res { x + y } else { *res };
+                }
+            }
+        }
+    });
+}
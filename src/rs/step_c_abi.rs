@@ -1,6 +1,824 @@
 #[no_mangle]
-pub extern "C" fn step(r_raw: *mut f32, d_raw: *const f32, n: i32) {
-    let d = unsafe { std::slice::from_raw_parts(d_raw, (n * n) as usize) };
-    let mut r = unsafe { std::slice::from_raw_parts_mut(r_raw, (n * n) as usize) };
-    _step(&mut r, d, n as usize);
+pub extern "C" fn step(r_raw: *mut f32, d_raw: *const f32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step(r, d, n)
+    })
+}
+
+/// With the `debug-ffi` feature, the checks of the arguments print what is wrong with them and
+/// abort, instead of returning a status code, so that C and C++ harnesses that ignore the codes
+/// stop at the first call that is wrong, with a backtrace naming it, instead of going on with
+/// garbage in `r`. Such builds also reject sizes of zero and sizes that are negative `int`s
+/// converted to `size_t`, without waiting for their products to overflow.
+#[cfg(feature = "debug-ffi")]
+#[cold]
+fn invalid_argument(message: std::fmt::Arguments) -> ! {
+    #[cfg(feature = "std")]
+    {
+        eprintln!("shortcut: invalid argument: {}\n{}", message, std::backtrace::Backtrace::force_capture());
+        std::process::abort()
+    }
+    // The panic handler of a bare-metal target never returns either.
+    #[cfg(not(feature = "std"))]
+    panic!("shortcut: invalid argument: {}", message)
+}
+
+/// `invalid_argument` with the `format!` arguments if `condition` holds, with the `debug-ffi`
+/// feature, and nothing without it.
+macro_rules! debug_ffi {
+    ($condition:expr, $($arg:tt)*) => {
+        #[cfg(feature = "debug-ffi")]
+        if $condition {
+            invalid_argument(format_args!($($arg)*));
+        }
+    };
+}
+
+// ANCHOR: catch_status
+pub const STEP_OK: i32 = 0;
+pub const STEP_INVALID_ARGUMENT: i32 = 1;
+pub const STEP_PANICKED: i32 = 2;
+pub const STEP_INVALID_INPUT: i32 = 3;
+pub const STEP_CANCELLED: i32 = 4;
+pub const STEP_SIZE_OVERFLOW: i32 = 5;
+pub const STEP_NULL_POINTER: i32 = 6;
+pub const STEP_MISALIGNED: i32 = 7;
+pub const STEP_OVERLAP: i32 = 8;
+pub const STEP_UNKNOWN_VARIANT: i32 = 9;
+pub const STEP_IO_ERROR: i32 = 10;
+pub const STEP_MEMORY_BUDGET: i32 = 11;
+pub const STEP_SELFTEST_FAILED: i32 = 12;
+
+/// The status code of `e`. The codes never change meaning, new errors get new codes.
+fn status(e: crate::StepError) -> i32 {
+    let code = match e {
+        crate::StepError::NaN { .. }
+        | crate::StepError::Negative { .. }
+        | crate::StepError::NotSymmetric { .. }
+        | crate::StepError::NegativeCycle { .. } => STEP_INVALID_INPUT,
+        crate::StepError::Cancelled => STEP_CANCELLED,
+        crate::StepError::SizeOverflow { .. } => STEP_SIZE_OVERFLOW,
+        crate::StepError::NullPointer => STEP_NULL_POINTER,
+        crate::StepError::Misaligned => STEP_MISALIGNED,
+        crate::StepError::Overlap => STEP_OVERLAP,
+        crate::StepError::UnknownVariant => STEP_UNKNOWN_VARIANT,
+        crate::StepError::MemoryBudget { .. } => STEP_MEMORY_BUDGET,
+        crate::StepError::SelftestFailed { .. } => STEP_SELFTEST_FAILED,
+        #[cfg(feature = "std")]
+        crate::StepError::Io(_) => STEP_IO_ERROR,
+        crate::StepError::LengthMismatch { .. }
+        | crate::StepError::InvalidStride { .. }
+        | crate::StepError::DimensionMismatch { .. }
+        | crate::StepError::IndexOutOfRange { .. }
+        | crate::StepError::TopKMismatch { .. }
+        | crate::StepError::MaskMismatch { .. }
+        | crate::StepError::InvalidScale
+        | crate::StepError::LayoutMismatch { .. } => STEP_INVALID_ARGUMENT,
+    };
+    debug_ffi!(
+        matches!(
+            code,
+            STEP_INVALID_ARGUMENT | STEP_SIZE_OVERFLOW | STEP_NULL_POINTER | STEP_MISALIGNED | STEP_OVERLAP | STEP_UNKNOWN_VARIANT
+        ),
+        "{}",
+        e
+    );
+    code
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn catch_status<F>(f: F) -> i32
+where
+    F: FnOnce() -> Result<(), crate::StepError> + std::panic::UnwindSafe,
+{
+    match std::panic::catch_unwind(f) {
+        Ok(Ok(())) => STEP_OK,
+        Ok(Err(e)) => {
+            eprintln!("error: {}", e);
+            status(e)
+        }
+        Err(_) => {
+            eprintln!("error: rust panicked");
+            STEP_PANICKED
+        }
+    }
+}
+// ANCHOR_END: catch_status
+
+/// Without `std` a panic never returns to the caller and there is no standard error stream, so
+/// only the errors are converted.
+#[cfg(not(feature = "std"))]
+fn catch_status<F>(f: F) -> i32
+where
+    F: FnOnce() -> Result<(), crate::StepError>,
+{
+    match f() {
+        Ok(()) => STEP_OK,
+        Err(e) => status(e),
+    }
+}
+
+/// A null-terminated description of the status `code`, returned by all functions that return an
+/// `int32_t` status, which is never null and must not be freed.
+#[no_mangle]
+pub extern "C" fn step_strerror(code: i32) -> *const std::ffi::c_char {
+    let message = match code {
+        STEP_OK => c"success",
+        STEP_INVALID_ARGUMENT => c"the sizes or strides do not match the matrices",
+        STEP_PANICKED => c"rust panicked",
+        STEP_INVALID_INPUT => c"the input matrix has a NaN, a negative distance or cycle, or is not symmetric",
+        STEP_CANCELLED => c"the step was cancelled",
+        STEP_SIZE_OVERFLOW => c"a matrix does not fit in memory",
+        STEP_NULL_POINTER => c"a pointer is null",
+        STEP_MISALIGNED => c"a pointer is not aligned to its element type",
+        STEP_OVERLAP => c"the output matrix overlaps an input matrix",
+        STEP_UNKNOWN_VARIANT => c"unknown variant",
+        STEP_IO_ERROR => c"reading or writing a file failed",
+        STEP_MEMORY_BUDGET => c"the step does not fit in the memory budget",
+        STEP_SELFTEST_FAILED => c"the kernel of this CPU differs from the reference",
+        _ => c"unknown status code",
+    };
+    message.as_ptr()
+}
+
+/// The alignment in bytes of matrices that every kernel reads with aligned loads, as returned by
+/// `shortcut_alloc`. Matrices that are only aligned to `float` work as well, with unaligned loads.
+#[no_mangle]
+pub extern "C" fn shortcut_required_alignment() -> usize {
+    crate::simd::ALIGN
+}
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, vec::Vec};
+
+/// Number of elements in a `rows * cols` matrix of `T`, or an error if it does not fit in memory.
+fn element_count<T>(rows: usize, cols: usize) -> Result<usize, crate::StepError> {
+    debug_ffi!(rows == 0 || cols == 0, "a matrix of {} * {} elements is empty", rows, cols);
+    debug_ffi!(
+        (rows as isize) < 0 || (cols as isize) < 0,
+        "a matrix of {} * {} elements, which are {} * {} as signed integers",
+        rows,
+        cols,
+        rows as isize,
+        cols as isize
+    );
+    rows.checked_mul(cols)
+        .filter(|&len| len <= isize::MAX as usize / std::mem::size_of::<T>().max(1))
+        .ok_or(crate::StepError::SizeOverflow { rows, cols })
+}
+
+/// An error if `raw` is null or not aligned for `T`, which no slice may be.
+fn check_pointer<T>(raw: *const T) -> Result<(), crate::StepError> {
+    debug_ffi!(raw.is_null(), "a pointer to {} is null", std::any::type_name::<T>());
+    debug_ffi!(
+        !raw.is_null() && !raw.is_aligned(),
+        "{:p} is not aligned to the {} bytes of {}",
+        raw,
+        std::mem::align_of::<T>(),
+        std::any::type_name::<T>()
+    );
+    if raw.is_null() {
+        return Err(crate::StepError::NullPointer);
+    }
+    if !raw.is_aligned() {
+        return Err(crate::StepError::Misaligned);
+    }
+    Ok(())
+}
+
+/// An error if `r_raw` or `d_raw` fails `check_pointer`, or if the `r_len` elements at `r_raw`
+/// overlap the `d_len` elements at `d_raw`, since a mutable slice must not alias any other slice.
+/// Overwriting `d` with its step is `step_in_place`.
+fn check_disjoint<T, U>(r_raw: *mut T, r_len: usize, d_raw: *const U, d_len: usize) -> Result<(), crate::StepError> {
+    check_pointer(r_raw)?;
+    check_pointer(d_raw)?;
+    let (r, d) = (r_raw as usize, d_raw as usize);
+    let (r_end, d_end) = (r + r_len * std::mem::size_of::<T>(), d + d_len * std::mem::size_of::<U>());
+    let overlap = r < r_end && d < d_end && r < d_end && d < r_end;
+    debug_ffi!(overlap, "the output at {:#x}..{:#x} overlaps an input at {:#x}..{:#x}", r, r_end, d, d_end);
+    if overlap {
+        return Err(crate::StepError::Overlap);
+    }
+    Ok(())
+}
+
+/// An error if any output of `ranges`, the byte ranges of the matrices of a batch with whether each
+/// is an output, overlaps any other matrix. Inputs may overlap each other.
+fn check_batch_disjoint(mut ranges: Vec<(usize, usize, bool)>) -> Result<(), crate::StepError> {
+    ranges.retain(|&(start, end, _)| start < end);
+    ranges.sort_unstable();
+    // Each range overlaps an earlier one exactly if that ends after it starts.
+    let (mut any_end, mut output_end) = (0, 0);
+    for (start, end, output) in ranges {
+        let overlap = start < output_end || (output && start < any_end);
+        debug_ffi!(overlap, "the matrix at {:#x}..{:#x} overlaps an output of the batch", start, end);
+        if overlap {
+            return Err(crate::StepError::Overlap);
+        }
+        any_end = any_end.max(end);
+        if output {
+            output_end = output_end.max(end);
+        }
+    }
+    Ok(())
+}
+
+/// Like `element_count`, for `n` rows of length `n` that start `ld` elements apart.
+fn strided_element_count<T>(ld: usize, n: usize) -> Result<usize, crate::StepError> {
+    debug_ffi!(n == 0, "a matrix of 0 * 0 elements is empty");
+    if n == 0 {
+        return Ok(0);
+    }
+    let len = element_count::<T>(ld, n - 1)?.checked_add(n).ok_or(crate::StepError::SizeOverflow { rows: n, cols: ld })?;
+    element_count::<T>(len, 1)
+}
+
+#[no_mangle]
+pub extern "C" fn step_f64(r_raw: *mut f64, d_raw: *const f64, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f64>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step_f64(r, d, n)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn step_minplus_f32(r_raw: *mut f32, d_raw: *const f32, n: usize) -> i32 {
+    step(r_raw, d_raw, n)
+}
+
+#[no_mangle]
+pub extern "C" fn step_maxmin_f32(r_raw: *mut f32, d_raw: *const f32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step_semiring::<crate::semiring::MaxMin<f32>>(r, d, n)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn step_bool(r_raw: *mut bool, d_raw: *const bool, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<bool>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step_semiring::<crate::semiring::Bool>(r, d, n)
+    })
+}
+
+// ANCHOR: create_extern_c_wrapper
+macro_rules! create_extern_c_wrapper {
+    ($name:ident, $variant:path) => {
+        #[no_mangle]
+        pub extern "C" fn $name(r_raw: *mut f32, d_raw: *const f32, n: usize) -> i32 {
+            catch_status(|| {
+                let len = element_count::<f32>(n, n)?;
+                check_disjoint(r_raw, len, d_raw, len)?;
+                let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+                let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+                crate::check_lengths(r, d, n)?;
+                $variant(r, d, n);
+                Ok(())
+            })
+        }
+    };
+}
+
+create_extern_c_wrapper!(shortcut_step_v0, crate::variants::v0);
+// ANCHOR_END: create_extern_c_wrapper
+create_extern_c_wrapper!(shortcut_step_v1, crate::variants::v1);
+create_extern_c_wrapper!(shortcut_step_v2, crate::variants::v2);
+#[cfg(feature = "std")]
+create_extern_c_wrapper!(shortcut_step_v3, crate::variants::v3);
+#[cfg(feature = "std")]
+create_extern_c_wrapper!(shortcut_step_v4, crate::variants::v4);
+#[cfg(feature = "std")]
+create_extern_c_wrapper!(shortcut_step_v5, crate::variants::v5);
+#[cfg(feature = "std")]
+create_extern_c_wrapper!(shortcut_step_v6, crate::variants::v6);
+#[cfg(feature = "std")]
+create_extern_c_wrapper!(shortcut_step_v7, crate::variants::v7);
+
+#[cfg(feature = "std")]
+const VARIANT_NAMES: [&std::ffi::CStr; 10] =
+    [c"v0", c"v1", c"v2", c"v3", c"v4", c"v5", c"v6", c"v7", c"recursive", c"transpose-free"];
+
+#[no_mangle]
+pub extern "C" fn shortcut_version() -> *const std::ffi::c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn shortcut_best_variant() -> *const std::ffi::c_char {
+    let best = crate::variants::best();
+    VARIANT_NAMES
+        .iter()
+        .find(|name| name.to_bytes() == best.as_bytes())
+        .map_or(std::ptr::null(), |name| name.as_ptr())
+}
+
+/// Runs `step` on an `n * n` matrix of `gen::Generator::Uniform` for `seed`, with the `dispatch`
+/// kernel it uses on this CPU, and compares the result with that of `reference::step`, for C
+/// callers to check once at startup that the library works on the machine it is deployed to.
+/// The sums of such a matrix are the same with every kernel, so the results must be identical.
+/// Returns `STEP_SELFTEST_FAILED` with the first element that differs on the standard error stream
+/// if they are not. The reference takes about as long as `v0`, so `n` should be small, such as 256.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn shortcut_selftest(n: usize, seed: u64) -> i32 {
+    catch_status(|| {
+        element_count::<f32>(n, n)?;
+        let (n, d) = crate::gen::generate(crate::gen::Generator::Uniform, n, seed);
+        let mut r = vec![0.0; n * n];
+        let mut expected = vec![0.0; n * n];
+        crate::step(&mut r, &d, n)?;
+        crate::reference::step(&mut expected, &d, n);
+        match crate::reference::compare(&expected, &r, n, 0.0) {
+            (_, Some(mismatch)) => Err(crate::StepError::SelftestFailed { i: mismatch.i, j: mismatch.j }),
+            (_, None) => Ok(()),
+        }
+    })
+}
+
+/// The names accepted by `step_variant`, followed by a null pointer.
+#[cfg(feature = "std")]
+struct VariantList([*const std::ffi::c_char; 12]);
+
+// The pointers are to string literals, which are never written.
+#[cfg(feature = "std")]
+unsafe impl Sync for VariantList {}
+
+#[cfg(feature = "std")]
+static VARIANT_LIST: VariantList = VariantList([
+    VARIANT_NAMES[0].as_ptr(),
+    VARIANT_NAMES[1].as_ptr(),
+    VARIANT_NAMES[2].as_ptr(),
+    VARIANT_NAMES[3].as_ptr(),
+    VARIANT_NAMES[4].as_ptr(),
+    VARIANT_NAMES[5].as_ptr(),
+    VARIANT_NAMES[6].as_ptr(),
+    VARIANT_NAMES[7].as_ptr(),
+    VARIANT_NAMES[8].as_ptr(),
+    VARIANT_NAMES[9].as_ptr(),
+    c"auto".as_ptr(),
+    std::ptr::null(),
+]);
+
+/// The null-terminated list of the names of the variants `step_variant` accepts.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn list_variants() -> *const *const std::ffi::c_char {
+    VARIANT_LIST.0.as_ptr()
+}
+
+/// Like `step` with the variant named by the null-terminated string `variant`, one of
+/// `list_variants`, where `"auto"` is `shortcut_best_variant`. Returns `STEP_UNKNOWN_VARIANT` for
+/// any other name, or if `variant` is null.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_variant(r_raw: *mut f32, d_raw: *const f32, n: usize, variant: *const std::ffi::c_char) -> i32 {
+    catch_status(|| {
+        let name = (!variant.is_null()).then(|| unsafe { std::ffi::CStr::from_ptr(variant) }.to_str().ok()).flatten();
+        let name = if name == Some("auto") { Some(crate::variants::best()) } else { name };
+        let step = name.and_then(crate::variants::by_name).ok_or(crate::StepError::UnknownVariant)?;
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::check_lengths(r, d, n)?;
+        step(r, d, n);
+        Ok(())
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_ctx_new(n: usize) -> *mut crate::StepContext {
+    match std::panic::catch_unwind(|| Box::new(crate::StepContext::new(n))) {
+        Ok(ctx) => Box::into_raw(ctx),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_ctx_run(ctx: *mut crate::StepContext, r_raw: *mut f32, d_raw: *const f32) -> i32 {
+    catch_status(|| {
+        check_pointer(ctx)?;
+        let ctx = unsafe { &mut *ctx };
+        let len = element_count::<f32>(ctx.n(), ctx.n())?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        ctx.step(r, d)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_ctx_free(ctx: *mut crate::StepContext) {
+    if !ctx.is_null() {
+        drop(unsafe { Box::from_raw(ctx) });
+    }
+}
+
+/// Packs the `n * n` matrix `d` once for many `prepared_step`, writing the handle to free with
+/// `prepared_free` to `*prepared`, which is left as it was on errors.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn prepared_new(d_raw: *const f32, n: usize, prepared: *mut *mut crate::PreparedMatrix) -> i32 {
+    // Only the progress callback of its options is not `RefUnwindSafe`, and C callers cannot set one.
+    let prepared = std::panic::AssertUnwindSafe(prepared);
+    catch_status(|| {
+        check_pointer(*prepared)?;
+        check_pointer(d_raw)?;
+        let len = element_count::<f32>(n, n)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let handle = Box::new(crate::PreparedMatrix::new(d, n, Default::default())?);
+        unsafe { prepared.write(Box::into_raw(handle)) };
+        Ok(())
+    })
+}
+
+/// Writes the step of the `d` of `prepared` to the `n * n` matrix `r`.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn prepared_step(prepared: *const crate::PreparedMatrix, r_raw: *mut f32) -> i32 {
+    // See `prepared_new`.
+    let prepared = std::panic::AssertUnwindSafe(prepared);
+    catch_status(|| {
+        check_pointer(*prepared)?;
+        check_pointer(r_raw)?;
+        let prepared = unsafe { &**prepared };
+        let len = prepared.n() * prepared.n();
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        prepared.step_into(r)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn prepared_free(prepared: *mut crate::PreparedMatrix) {
+    if !prepared.is_null() {
+        drop(unsafe { Box::from_raw(prepared) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn step_with_threads(r_raw: *mut f32, d_raw: *const f32, n: usize, num_threads: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        let threads = crate::ThreadConfig::with_threads(num_threads.max(1));
+        crate::step_with_threads(r, d, n, &threads)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn step_strided(r_raw: *mut f32, ld_r: usize, d_raw: *const f32, ld_d: usize, n: usize) -> i32 {
+    catch_status(|| {
+        let (d_len, r_len) = (strided_element_count::<f32>(ld_d, n)?, strided_element_count::<f32>(ld_r, n)?);
+        check_disjoint(r_raw, r_len, d_raw, d_len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, d_len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, r_len) };
+        crate::step_strided(r, ld_r, d, ld_d, n)
+    })
+}
+
+/// Like `step` for `n * n` matrices stored column by column, as from Fortran or Eigen.
+#[no_mangle]
+pub extern "C" fn step_colmajor(r_raw: *mut f32, d_raw: *const f32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step_with_layout(r, d, n, crate::Layout::ColMajor)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn minplus_gemm(r_raw: *mut f32, a_raw: *const f32, b_raw: *const f32, m: usize, k: usize, n: usize) -> i32 {
+    catch_status(|| {
+        let (a_len, b_len, r_len) = (element_count::<f32>(m, k)?, element_count::<f32>(k, n)?, element_count::<f32>(m, n)?);
+        check_disjoint(r_raw, r_len, a_raw, a_len)?;
+        check_disjoint(r_raw, r_len, b_raw, b_len)?;
+        let a = unsafe { std::slice::from_raw_parts(a_raw, a_len) };
+        let b = unsafe { std::slice::from_raw_parts(b_raw, b_len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, r_len) };
+        crate::minplus_gemm(r, a, b, m, k, n)
+    })
+}
+
+/// Replaces the `n * n` matrix at `d_raw` with its step, which `step` rejects with `STEP_OVERLAP`
+/// for `r_raw == d_raw`. It still allocates a transposed copy of the `n * n` matrix, like `step`,
+/// which no step in place can do without, see `crate::step_in_place`.
+#[no_mangle]
+pub extern "C" fn step_in_place(d_raw: *mut f32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_pointer(d_raw)?;
+        let d = unsafe { std::slice::from_raw_parts_mut(d_raw, len) };
+        crate::step_in_place(d, n)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn apsp(d_raw: *mut f32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_pointer(d_raw)?;
+        let d = unsafe { std::slice::from_raw_parts_mut(d_raw, len) };
+        crate::apsp::apsp(d, n)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_with_pred(r_raw: *mut f32, pred_raw: *mut usize, d_raw: *const f32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        let len_usize = element_count::<usize>(n, n)?;
+        check_disjoint(pred_raw, len_usize, d_raw, len)?;
+        check_disjoint(pred_raw, len_usize, r_raw, len)?;
+        let pred = unsafe { std::slice::from_raw_parts_mut(pred_raw, len_usize) };
+        crate::apsp::step_with_pred(r, pred, d, n)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn apsp_with_pred(d_raw: *mut f32, pred_raw: *mut usize, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        let len_usize = element_count::<usize>(n, n)?;
+        check_disjoint(pred_raw, len_usize, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts_mut(d_raw, len) };
+        let pred = unsafe { std::slice::from_raw_parts_mut(pred_raw, len_usize) };
+        crate::apsp::apsp_with_pred(d, pred, n)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_i32(r_raw: *mut i32, d_raw: *const i32, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<i32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step_i32(r, d, n)
+    })
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_u16(r_raw: *mut u16, d_raw: *const u16, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<u16>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::step_u16(r, d, n)
+    })
+}
+
+/// `step` for the bits of IEEE 754 half-precision floats, see `crate::step_f16`.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_f16(r_raw: *mut u16, d_raw: *const u16, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<u16>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw as *const crate::F16, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw as *mut crate::F16, len) };
+        crate::step_f16(r, d, n)
+    })
+}
+
+/// `step` for the bits of bfloat16s, see `crate::step_bf16`.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn step_bf16(r_raw: *mut u16, d_raw: *const u16, n: usize) -> i32 {
+    catch_status(|| {
+        let len = element_count::<u16>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw as *const crate::Bf16, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw as *mut crate::Bf16, len) };
+        crate::step_bf16(r, d, n)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn step_checked(r_raw: *mut f32, d_raw: *const f32, n: usize, check_negative: bool) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        let checks = crate::Checks { nan: true, negative: check_negative };
+        crate::step_checked(r, d, n, &checks)
+    })
+}
+
+/// Like `step`, calling `progress(fraction, user_data)` on the calling thread after each block of
+/// rows with the fraction of rows done, unless `progress` is null.
+#[no_mangle]
+pub extern "C" fn step_with_progress(
+    r_raw: *mut f32,
+    d_raw: *const f32,
+    n: usize,
+    progress: Option<extern "C" fn(f32, *mut std::ffi::c_void)>,
+    user_data: *mut std::ffi::c_void,
+) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        // Only passed back to `progress`, which is called on this thread.
+        let user_data = user_data as usize;
+        let progress = progress.map(|progress| {
+            Box::new(move |done| progress(done, user_data as *mut std::ffi::c_void)) as Box<dyn Fn(f32) + Send + Sync>
+        });
+        crate::step_with_options(r, d, n, &crate::StepOptions { progress, ..Default::default() })
+    })
+}
+
+/// Like `step`, but returns `STEP_CANCELLED` after the block of rows it is working on once another
+/// thread sets `*cancel` to true, unless `cancel` is null. In C11 `cancel` can point to an
+/// `atomic_bool` and in C++ to a `std::atomic<bool>`.
+#[no_mangle]
+pub extern "C" fn step_cancellable(r_raw: *mut f32, d_raw: *const f32, n: usize, cancel: *const bool) -> i32 {
+    catch_status(|| {
+        let len = element_count::<f32>(n, n)?;
+        check_disjoint(r_raw, len, d_raw, len)?;
+        let d = unsafe { std::slice::from_raw_parts(d_raw, len) };
+        let r = unsafe { std::slice::from_raw_parts_mut(r_raw, len) };
+        crate::check_lengths(r, d, n)?;
+        // Read atomically, since other threads write it while it is read.
+        let cancel = (!cancel.is_null()).then(|| unsafe { std::sync::atomic::AtomicBool::from_ptr(cancel.cast_mut()) });
+        let hooks = crate::dispatch::Hooks { cancel, ..Default::default() };
+        crate::dispatch::step_with_hooks(&crate::ThreadConfig::default(), r, d, n, false, hooks)
+    })
+}
+
+/// `step` for the `count` matrices `r_raws[i]` and `d_raws[i]` of size `ns[i]`, many at a time and
+/// each on one thread. No `r_raws[i]` may overlap another matrix, which returns `STEP_OVERLAP`.
+#[no_mangle]
+pub extern "C" fn step_batch(r_raws: *const *mut f32, d_raws: *const *const f32, ns: *const usize, count: usize) -> i32 {
+    catch_status(|| {
+        if count == 0 {
+            return Ok(());
+        }
+        let len = element_count::<usize>(count, 1)?;
+        check_pointer(r_raws)?;
+        check_pointer(d_raws)?;
+        check_pointer(ns)?;
+        let r_raws = unsafe { std::slice::from_raw_parts(r_raws, len) };
+        let d_raws = unsafe { std::slice::from_raw_parts(d_raws, len) };
+        let ns = unsafe { std::slice::from_raw_parts(ns, len) };
+        let lens = (0..count)
+            .map(|i| {
+                let len = element_count::<f32>(ns[i], ns[i])?;
+                check_disjoint(r_raws[i], len, d_raws[i], len)?;
+                Ok(len)
+            })
+            .collect::<Result<Vec<_>, crate::StepError>>()?;
+        let bytes = |raw: usize, len: usize| (raw, raw + len * std::mem::size_of::<f32>());
+        let ranges = (0..count).flat_map(|i| {
+            let ((r, r_end), (d, d_end)) = (bytes(r_raws[i] as usize, lens[i]), bytes(d_raws[i] as usize, lens[i]));
+            [(r, r_end, true), (d, d_end, false)]
+        });
+        check_batch_disjoint(ranges.collect())?;
+        let mut matrices: Vec<_> = (0..count)
+            .map(|i| {
+                let r = unsafe { std::slice::from_raw_parts_mut(r_raws[i], lens[i]) };
+                let d = unsafe { std::slice::from_raw_parts(d_raws[i], lens[i]) };
+                crate::MatrixMut { r, d, n: ns[i] }
+            })
+            .collect();
+        crate::step_batch(&mut matrices, &Default::default())
+    })
+}
+
+/// `n` uninitialized floats aligned to 64 bytes, and to 2 MiB huge pages if they span at least one,
+/// or null if `n` floats do not fit in memory. Free them with `shortcut_free`.
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn shortcut_alloc(n: usize) -> *mut f32 {
+    crate::alloc::alloc_raw(n)
+}
+
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn shortcut_free(ptr: *mut f32) {
+    unsafe { crate::alloc::free_raw(ptr) }
+}
+
+// With `debug-ffi`, the calls that the tests expect an error code from abort instead.
+#[cfg(all(test, not(feature = "debug-ffi")))]
+mod tests {
+    use super::*;
+    use crate::bench::random_input;
+
+    /// `step_batch` of the matrices `d` into `r`, picking each pointer by its index in them.
+    fn batch(r: &mut [Vec<f32>], d: &[Vec<f32>], outputs: &[usize], inputs: &[usize], n: usize) -> i32 {
+        let r_raws: Vec<*mut f32> = r.iter_mut().map(|r| r.as_mut_ptr()).collect();
+        let r_raws: Vec<_> = outputs.iter().map(|&i| r_raws[i]).collect();
+        let d_raws: Vec<_> = inputs.iter().map(|&i| d[i].as_ptr()).collect();
+        step_batch(r_raws.as_ptr(), d_raws.as_ptr(), vec![n; outputs.len()].as_ptr(), outputs.len())
+    }
+
+    #[test]
+    fn step_batch_rejects_outputs_that_overlap_any_matrix() {
+        let n = 5;
+        let d: Vec<Vec<f32>> = (0..3).map(|_| random_input(n)).collect();
+        let mut r = vec![vec![0.0; n * n]; 3];
+        // Inputs may be shared.
+        assert_eq!(batch(&mut r, &d, &[0, 1, 2], &[0, 0, 1], n), STEP_OK);
+        let mut expected = vec![0.0; n * n];
+        crate::step(&mut expected, &d[0], n).unwrap();
+        assert_eq!((&r[0], &r[1]), (&expected, &expected));
+        assert_eq!(batch(&mut r, &d, &[0, 1, 0], &[0, 1, 2], n), STEP_OVERLAP);
+        // An output that is the input of another matrix of the batch.
+        let mut both = [random_input(n), vec![0.0; n * n]];
+        let (input, output) = (both[0].as_mut_ptr(), both[1].as_mut_ptr());
+        let ns = [n, n];
+        let inputs = [input.cast_const(), input.cast_const()];
+        assert_eq!(step_batch([output, input].as_ptr(), inputs.as_ptr(), ns.as_ptr(), 2), STEP_OVERLAP);
+        // Partly overlapping outputs.
+        let mut long = vec![0.0; 2 * n * n];
+        let (first, second) = (long.as_mut_ptr(), unsafe { long.as_mut_ptr().add(n * n - 1) });
+        let inputs = [d[0].as_ptr(), d[1].as_ptr()];
+        assert_eq!(step_batch([first, second].as_ptr(), inputs.as_ptr(), ns.as_ptr(), 2), STEP_OVERLAP);
+        let second = unsafe { long.as_mut_ptr().add(n * n) };
+        assert_eq!(step_batch([first, second].as_ptr(), inputs.as_ptr(), ns.as_ptr(), 2), STEP_OK);
+    }
+
+    #[test]
+    fn each_error_path_returns_its_code() {
+        let n = 3;
+        let d = random_input(n);
+        let mut r = vec![0.0; n * n];
+        let (r_raw, d_raw) = (r.as_mut_ptr(), d.as_ptr());
+        assert_eq!(step(r_raw, d_raw, n), STEP_OK);
+        assert_eq!(step_strided(r_raw, n - 1, d_raw, n, n), STEP_INVALID_ARGUMENT);
+        let mut negative = d.clone();
+        negative[1] = -1.0;
+        assert_eq!(step_checked(r_raw, negative.as_ptr(), n, true), STEP_INVALID_INPUT);
+        assert_eq!(step_checked(r_raw, negative.as_ptr(), n, false), STEP_OK);
+        let mut nan = d.clone();
+        nan[1] = f32::NAN;
+        assert_eq!(step_checked(r_raw, nan.as_ptr(), n, false), STEP_INVALID_INPUT);
+        let cancel = true;
+        assert_eq!(step_cancellable(r_raw, d_raw, n, &cancel), STEP_CANCELLED);
+        assert_eq!(step_cancellable(r_raw, d_raw, n, std::ptr::null()), STEP_OK);
+        assert_eq!(step(r_raw, d_raw, usize::MAX), STEP_SIZE_OVERFLOW);
+        assert_eq!(step_strided(r_raw, usize::MAX, d_raw, n, n), STEP_SIZE_OVERFLOW);
+        assert_eq!(step(std::ptr::null_mut(), d_raw, n), STEP_NULL_POINTER);
+        assert_eq!(step(r_raw, std::ptr::null(), n), STEP_NULL_POINTER);
+        assert_eq!(step_in_place(std::ptr::null_mut(), n), STEP_NULL_POINTER);
+        let mut bytes = vec![0.0f32; n * n + 1];
+        let misaligned = unsafe { bytes.as_mut_ptr().cast::<u8>().add(1) }.cast::<f32>();
+        assert_eq!(step(misaligned, d_raw, n), STEP_MISALIGNED);
+        assert_eq!(step(r_raw, misaligned, n), STEP_MISALIGNED);
+        assert_eq!(step(r_raw, r_raw, n), STEP_OVERLAP);
+        assert_eq!(step(unsafe { r_raw.add(1) }, r_raw, n), STEP_OVERLAP);
+        assert_eq!(step_variant(r_raw, d_raw, n, c"nope".as_ptr()), STEP_UNKNOWN_VARIANT);
+        assert_eq!(step_variant(r_raw, d_raw, n, std::ptr::null()), STEP_UNKNOWN_VARIANT);
+        assert_eq!(step_variant(r_raw, d_raw, n, c"auto".as_ptr()), STEP_OK);
+        assert_eq!(catch_status(|| panic!("in a test")), STEP_PANICKED);
+        // No export reaches these with any arguments: they need a file, a budget or a broken kernel.
+        assert_eq!(status(crate::StepError::Io(std::io::ErrorKind::NotFound)), STEP_IO_ERROR);
+        assert_eq!(status(crate::StepError::MemoryBudget { needed: 2, budget: 1 }), STEP_MEMORY_BUDGET);
+        assert_eq!(status(crate::StepError::SelftestFailed { i: 0, j: 1 }), STEP_SELFTEST_FAILED);
+        assert_eq!(shortcut_selftest(16, 1), STEP_OK);
+    }
+
+    #[test]
+    fn each_code_has_its_own_message() {
+        let message = |code| unsafe { std::ffi::CStr::from_ptr(step_strerror(code)) };
+        let mut messages: Vec<_> = (STEP_OK..=STEP_SELFTEST_FAILED).map(message).collect();
+        messages.sort_unstable();
+        messages.dedup();
+        assert_eq!(messages.len(), 13);
+        assert!(!messages.contains(&message(STEP_SELFTEST_FAILED + 1)));
+    }
 }
@@ -0,0 +1,291 @@
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// How many threads the parallel variants use, and optionally which cores they run on.
+///
+/// Fields left `None` come from the environment, read once, as under schedulers that only set the
+/// variables of OpenMP: the thread count from `SHORTCUT_NUM_THREADS`, else the first level of
+/// `OMP_NUM_THREADS`, else one thread per available core; the cores from `OMP_PLACES`, unless
+/// `OMP_PROC_BIND` is `false`, with thread `t` pinned to the first CPU of place `t % places`. Places
+/// are lists such as `{0,1},{2,3}` or `{0:4}:4:4`, or `threads` or `cores` with an optional count,
+/// such as `cores(8)`; `sockets` and other names pin nothing. `effective` shows the result.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadConfig {
+    /// `None` takes it from the environment, or uses one thread per available core. Without `std`
+    /// everything runs on the calling thread.
+    pub num_threads: Option<usize>,
+    /// Pins thread `t` to core `pin_cores[t % pin_cores.len()]`, Linux only. `None` takes them from
+    /// `OMP_PLACES` if it is set.
+    pub pin_cores: Option<Vec<usize>>,
+    /// Lets each thread take this many chunks of rows at a time, the next ones not taken yet,
+    /// whenever it has finished its previous ones. `None` splits the chunks evenly between the
+    /// threads up front, which has no overhead but waits for the slowest thread, such as one that
+    /// shares its core with another process.
+    pub grain: Option<usize>,
+}
+
+impl ThreadConfig {
+    pub fn with_threads(num_threads: usize) -> Self {
+        ThreadConfig { num_threads: Some(num_threads), pin_cores: None, grain: None }
+    }
+
+    pub fn effective_threads(&self) -> usize {
+        match self.num_threads.or_else(env_threads) {
+            Some(num_threads) => num_threads.max(1),
+            None => available_parallelism(),
+        }
+    }
+
+    /// This config with the thread count and cores it runs with, from the environment for those
+    /// left `None`.
+    pub fn effective(&self) -> ThreadConfig {
+        ThreadConfig {
+            num_threads: Some(self.effective_threads()),
+            pin_cores: self.pin_cores.clone().or_else(env_places),
+            grain: self.grain,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn core_for(&self, thread: usize) -> Option<usize> {
+        match self.pin_cores.as_deref().or(environment().places.as_deref()) {
+            Some(cores) if !cores.is_empty() => Some(cores[thread % cores.len()]),
+            _ => None,
+        }
+    }
+}
+
+/// The thread count and cores of the environment, see `ThreadConfig`.
+#[cfg(feature = "std")]
+struct Environment {
+    num_threads: Option<usize>,
+    places: Option<Vec<usize>>,
+}
+
+#[cfg(feature = "std")]
+fn environment() -> &'static Environment {
+    static ENVIRONMENT: std::sync::OnceLock<Environment> = std::sync::OnceLock::new();
+    ENVIRONMENT.get_or_init(|| {
+        let var = |name| std::env::var(name).ok();
+        let count = |value: String| value.split(',').next()?.trim().parse().ok().filter(|&n| n > 0);
+        let num_threads = var("SHORTCUT_NUM_THREADS").and_then(count).or_else(|| var("OMP_NUM_THREADS").and_then(count));
+        let bind = var("OMP_PROC_BIND").is_none_or(|bind| !bind.trim().eq_ignore_ascii_case("false"));
+        let places = var("OMP_PLACES").filter(|_| bind).and_then(|places| parse_places(&places)).filter(|p| !p.is_empty());
+        Environment { num_threads, places }
+    })
+}
+
+#[cfg(feature = "std")]
+fn env_threads() -> Option<usize> {
+    environment().num_threads
+}
+
+#[cfg(feature = "std")]
+fn env_places() -> Option<Vec<usize>> {
+    environment().places.clone()
+}
+
+#[cfg(not(feature = "std"))]
+fn env_threads() -> Option<usize> {
+    None
+}
+
+#[cfg(not(feature = "std"))]
+fn env_places() -> Option<Vec<usize>> {
+    None
+}
+
+/// The first CPU of each place of `OMP_PLACES`, or `None` if it is not valid.
+#[cfg(feature = "std")]
+fn parse_places(places: &str) -> Option<Vec<usize>> {
+    let places = places.trim();
+    let (name, count) = match places.split_once('(') {
+        Some((name, count)) => (name.trim(), Some(count.strip_suffix(')')?.trim().parse::<usize>().ok()?)),
+        None => (places, None),
+    };
+    let cpus: Vec<usize> = match name {
+        "threads" => {
+            let mut cpus: Vec<usize> = crate::topology::cores().into_iter().flat_map(|core| core.cpus).collect();
+            cpus.sort_unstable();
+            cpus
+        }
+        "cores" => crate::topology::cores().iter().map(|core| core.cpus[0]).collect(),
+        _ if name.starts_with(|c: char| c.is_ascii_alphabetic()) => return Some(Vec::new()),
+        _ => return place_list(places),
+    };
+    Some(cpus.into_iter().take(count.unwrap_or(usize::MAX)).collect())
+}
+
+/// The `length` values from `lower`, `stride` apart, of `lower:length:stride` or `lower:length`
+/// with a stride of 1, or `lower` alone.
+#[cfg(feature = "std")]
+fn interval(s: &str) -> Option<impl Iterator<Item = isize>> {
+    let parts = s.split(':').map(|part| part.trim().parse().ok()).collect::<Option<Vec<isize>>>()?;
+    let (lower, length, stride) = match parts[..] {
+        [lower] => (lower, 1, 1),
+        [lower, length] => (lower, length, 1),
+        [lower, length, stride] => (lower, length, stride),
+        _ => return None,
+    };
+    Some((0..length.max(0)).map(move |i| lower + i * stride))
+}
+
+/// The first CPUs of an explicit list of places such as `{0,1},{2:2}`, where `{0:4}:4:4` is a place
+/// repeated 4 times 4 CPUs apart and a number outside braces a place of one CPU.
+#[cfg(feature = "std")]
+fn place_list(list: &str) -> Option<Vec<usize>> {
+    let mut depth = 0;
+    let items = list.split(|c| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        c == ',' && depth == 0
+    });
+    let mut firsts = Vec::new();
+    for item in items {
+        let item = item.trim();
+        let cpus: Vec<isize> = match item.strip_prefix('{') {
+            Some(place) => {
+                let (place, repeat) = place.split_once('}')?;
+                let first = interval(place.split(',').next()?)?.next()?;
+                interval(&format!("{}{}", first, repeat))?.collect()
+            }
+            None => interval(item)?.collect(),
+        };
+        for cpu in cpus {
+            firsts.push(usize::try_from(cpu).ok()?);
+        }
+    }
+    Some(firsts)
+}
+
+#[cfg(feature = "std")]
+fn available_parallelism() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(not(feature = "std"))]
+fn available_parallelism() -> usize {
+    1
+}
+
+/// Applies `f` to all `chunk_len` sized chunks of `data` and their indexes, on scoped threads that
+/// exist only for the duration of the call, which split the chunks as `ThreadConfig::grain` says.
+#[cfg(feature = "std")]
+pub(crate) fn for_each_chunk<T, F>(threads: &ThreadConfig, data: &mut [T], chunk_len: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    if data.is_empty() {
+        return;
+    }
+    let chunks = data.len().div_ceil(chunk_len);
+    let num_threads = threads.effective_threads().min(chunks);
+    #[cfg(feature = "timings")]
+    let timed = crate::timings::is_computing();
+    if num_threads <= 1 {
+        #[cfg(feature = "timings")]
+        let _timer = timed.then(|| crate::timings::ThreadTimer::start(0));
+        data.chunks_mut(chunk_len).enumerate().for_each(|(i, chunk)| f(i, chunk));
+        return;
+    }
+    if let Some(grain) = threads.grain {
+        return for_each_group(threads, num_threads, data, chunk_len, grain.max(1), f);
+    }
+    let chunks_per_thread = chunks.div_ceil(num_threads);
+    std::thread::scope(|s| {
+        for (t, group) in data.chunks_mut(chunks_per_thread * chunk_len).enumerate() {
+            let f = &f;
+            let core = threads.core_for(t);
+            s.spawn(move || {
+                if let Some(core) = core {
+                    pin_to_core(core);
+                }
+                #[cfg(feature = "timings")]
+                let _timer = timed.then(|| crate::timings::ThreadTimer::start(t));
+                for (i, chunk) in group.chunks_mut(chunk_len).enumerate() {
+                    f(t * chunks_per_thread + i, chunk);
+                }
+                #[cfg(feature = "count-ops")]
+                crate::ops::merge();
+            });
+        }
+    });
+}
+
+/// `for_each_chunk` with `num_threads` threads taking groups of `grain` chunks from a shared
+/// iterator until all are taken.
+#[cfg(feature = "std")]
+fn for_each_group<T, F>(
+    threads: &ThreadConfig,
+    num_threads: usize,
+    data: &mut [T],
+    chunk_len: usize,
+    grain: usize,
+    f: F,
+) where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let groups = std::sync::Mutex::new(data.chunks_mut(grain.saturating_mul(chunk_len)).enumerate());
+    #[cfg(feature = "timings")]
+    let timed = crate::timings::is_computing();
+    std::thread::scope(|s| {
+        for t in 0..num_threads {
+            let (f, groups) = (&f, &groups);
+            let core = threads.core_for(t);
+            s.spawn(move || {
+                if let Some(core) = core {
+                    pin_to_core(core);
+                }
+                #[cfg(feature = "timings")]
+                let _timer = timed.then(|| crate::timings::ThreadTimer::start(t));
+                loop {
+                    // The lock is released before running `f`, so that other threads can take groups.
+                    let next = groups.lock().unwrap().next();
+                    let Some((g, group)) = next else { break };
+                    for (i, chunk) in group.chunks_mut(chunk_len).enumerate() {
+                        f(g * grain + i, chunk);
+                    }
+                }
+                #[cfg(feature = "count-ops")]
+                crate::ops::merge();
+            });
+        }
+    });
+}
+
+/// Like the `std` version, doing the chunks in order on the calling thread.
+#[cfg(not(feature = "std"))]
+pub(crate) fn for_each_chunk<T, F>(_threads: &ThreadConfig, data: &mut [T], chunk_len: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    if data.is_empty() {
+        return;
+    }
+    data.chunks_mut(chunk_len).enumerate().for_each(|(i, chunk)| f(i, chunk));
+}
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+fn pin_to_core(core: usize) {
+    use std::ffi::c_ulong;
+
+    extern "C" {
+        fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const c_ulong) -> i32;
+    }
+    // Like the mask of `topology::allowed_cpus`.
+    const BITS: usize = c_ulong::BITS as usize;
+    let mut mask = [0 as c_ulong; 1024 / BITS];
+    if core < BITS * mask.len() {
+        mask[core / BITS] |= 1 << (core % BITS);
+        unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) };
+    }
+}
+
+#[cfg(all(feature = "std", not(target_os = "linux")))]
+fn pin_to_core(_core: usize) {}
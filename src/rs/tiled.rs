@@ -0,0 +1,121 @@
+use std::io;
+use std::iter;
+use std::ops::Range;
+use std::sync::mpsc;
+use std::thread;
+
+/// Where `step_tiled` reads `d` from, one rectangle at a time.
+pub trait TileSource {
+    /// Reads rows `rows` and columns `cols` of `d` into `tile`, row by row.
+    fn read_tile(&mut self, rows: Range<usize>, cols: Range<usize>, tile: &mut [f32]) -> io::Result<()>;
+}
+
+/// Where `step_tiled` writes `r` to, one rectangle at a time.
+pub trait TileSink {
+    /// Writes `tile` into rows `rows` and columns `cols` of `r`, row by row.
+    fn write_tile(&mut self, rows: Range<usize>, cols: Range<usize>, tile: &[f32]) -> io::Result<()>;
+}
+
+impl<T: TileSource + ?Sized> TileSource for &mut T {
+    fn read_tile(&mut self, rows: Range<usize>, cols: Range<usize>, tile: &mut [f32]) -> io::Result<()> {
+        (**self).read_tile(rows, cols, tile)
+    }
+}
+
+impl<T: TileSink + ?Sized> TileSink for &mut T {
+    fn write_tile(&mut self, rows: Range<usize>, cols: Range<usize>, tile: &[f32]) -> io::Result<()> {
+        (**self).write_tile(rows, cols, tile)
+    }
+}
+
+fn panels(n: usize, tile: usize) -> impl Iterator<Item = Range<usize>> {
+    (0..n).step_by(tile).map(move |start| start..n.min(start + tile))
+}
+
+/// `step` for an `n * n` matrix `d` that does not have to fit in memory, holding only one panel
+/// of `tile` rows, one panel of `tile` columns and one `tile * tile` block of `r` at a time.
+/// Each block of `r` is the `minplus_gemm` of a row panel and a column panel of `d`, so every
+/// column panel is read once for each row panel.
+pub fn step_tiled(mut reader: impl TileSource, mut writer: impl TileSink, n: usize, tile: usize) -> io::Result<()> {
+    if tile == 0 && n > 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "tile must be at least 1"));
+    }
+    let tile = tile.clamp(1, n.max(1));
+    let invalid = |e: crate::StepError| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let mut rows = vec![0.0; tile * n];
+    let mut cols = vec![0.0; n * tile];
+    let mut block = vec![0.0; tile * tile];
+    for i in panels(n, tile) {
+        let rows = &mut rows[..i.len() * n];
+        reader.read_tile(i.clone(), 0..n, rows)?;
+        for j in panels(n, tile) {
+            let cols = &mut cols[..n * j.len()];
+            let block = &mut block[..i.len() * j.len()];
+            reader.read_tile(0..n, j.clone(), cols)?;
+            crate::minplus_gemm(block, rows, cols, i.len(), n, j.len()).map_err(invalid)?;
+            writer.write_tile(i.clone(), j, block)?;
+        }
+    }
+    Ok(())
+}
+
+/// How many panels `step_tiled_pipelined` holds: the row panel and column panel in use and the
+/// next of each.
+const BUFFERS: usize = 4;
+
+/// Like `step_tiled`, but reads the next panels on another thread while the current block is
+/// computed and written, so that the cores do not wait for a `reader` that waits for the disk.
+/// Holds `BUFFERS` panels of `tile` rows or columns instead of two.
+pub fn step_tiled_pipelined(
+    mut reader: impl TileSource + Send,
+    mut writer: impl TileSink,
+    n: usize,
+    tile: usize,
+) -> io::Result<()> {
+    if tile == 0 && n > 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "tile must be at least 1"));
+    }
+    let tile = tile.clamp(1, n.max(1));
+    let invalid = |e: crate::StepError| io::Error::new(io::ErrorKind::InvalidInput, e);
+    thread::scope(|s| {
+        // Panels go to the reading thread empty on `free` and come back read on `full`, in the order
+        // they are used in, each row panel followed by all column panels.
+        let (free_sender, free) = mpsc::channel::<Vec<f32>>();
+        let (full_sender, full) = mpsc::channel::<io::Result<Vec<f32>>>();
+        for _ in 0..BUFFERS {
+            free_sender.send(vec![0.0; tile * n]).unwrap();
+        }
+        s.spawn(move || {
+            for i in panels(n, tile) {
+                for (rows, cols) in iter::once((i.clone(), 0..n)).chain(panels(n, tile).map(|j| (0..n, j))) {
+                    // Stops once the other end is gone, after an error on either side.
+                    let Ok(mut panel) = free.recv() else { return };
+                    let len = rows.len() * cols.len();
+                    let panel = reader.read_tile(rows, cols, &mut panel[..len]).map(|()| panel);
+                    let failed = panel.is_err();
+                    if full_sender.send(panel).is_err() || failed {
+                        return;
+                    }
+                }
+            }
+        });
+        // The reading thread only stops early after sending an error, or by panicking, which the
+        // scope then passes on.
+        let next = || full.recv().unwrap_or_else(|_| Err(io::Error::other("the reading thread stopped")));
+        let mut block = vec![0.0; tile * tile];
+        for i in panels(n, tile) {
+            let rows = next()?;
+            for j in panels(n, tile) {
+                let cols = next()?;
+                let block = &mut block[..i.len() * j.len()];
+                crate::minplus_gemm(block, &rows[..i.len() * n], &cols[..n * j.len()], i.len(), n, j.len())
+                    .map_err(invalid)?;
+                writer.write_tile(i.clone(), j, block)?;
+                // Fails once the reading thread has read the last panel and stopped.
+                let _ = free_sender.send(cols);
+            }
+            let _ = free_sender.send(rows);
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,103 @@
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where the time of the kernels that ran under `time_phases` went, by the `trace::span`s of the
+/// calling thread: `preprocess` in the `"pack"` spans, `compute` in the `"compute"` spans and
+/// `postprocess` in the `"copy_out"` spans, and the time outside all of them in none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseTimings {
+    /// Packing and padding the copies of `d` that the kernels read.
+    pub preprocess: Duration,
+    pub compute: Duration,
+    /// Writing the blocks of results of `v7` to `r`.
+    pub postprocess: Duration,
+    /// The least and most time that one of the threads computing spent on its rows, which differ
+    /// by how unevenly the work was split, or other processes took the cores.
+    pub thread_compute_min: Duration,
+    pub thread_compute_max: Duration,
+}
+
+thread_local! {
+    /// The timings of the calling thread of `time_phases`, `None` on all other threads.
+    static THREAD: Cell<Option<PhaseTimings>> = const { Cell::new(None) };
+    /// Whether the calling thread of `time_phases` is in a `"compute"` span.
+    static COMPUTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The compute time of each thread of `threads::for_each_chunk` so far, by the index of the thread.
+static THREADS: Mutex<Vec<Duration>> = Mutex::new(Vec::new());
+
+/// Adds the time until it is dropped to the phase `name` of the calling thread, which `trace::span`
+/// enters for each span.
+pub(crate) struct Phase {
+    name: &'static str,
+    start: Instant,
+    was_computing: bool,
+}
+
+impl Phase {
+    /// `None` unless the calling thread is in `time_phases`.
+    pub(crate) fn enter(name: &'static str) -> Option<Phase> {
+        THREAD.get()?;
+        let was_computing = COMPUTING.replace(COMPUTING.get() || name == "compute");
+        Some(Phase { name, start: Instant::now(), was_computing })
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        COMPUTING.set(self.was_computing);
+        let Some(mut timings) = THREAD.get() else { return };
+        match self.name {
+            "pack" => timings.preprocess += elapsed,
+            "compute" if !self.was_computing => timings.compute += elapsed,
+            "copy_out" => timings.postprocess += elapsed,
+            _ => {}
+        }
+        THREAD.set(Some(timings));
+    }
+}
+
+/// Whether the threads that `threads::for_each_chunk` starts now should time themselves.
+pub(crate) fn is_computing() -> bool {
+    COMPUTING.get()
+}
+
+/// Adds the time until it is dropped to the compute time of thread `t`.
+pub(crate) struct ThreadTimer {
+    t: usize,
+    start: Instant,
+}
+
+impl ThreadTimer {
+    pub(crate) fn start(t: usize) -> ThreadTimer {
+        ThreadTimer { t, start: Instant::now() }
+    }
+}
+
+impl Drop for ThreadTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let mut threads = THREADS.lock().unwrap();
+        if threads.len() <= self.t {
+            threads.resize(self.t + 1, Duration::ZERO);
+        }
+        threads[self.t] += elapsed;
+    }
+}
+
+/// The `PhaseTimings` of the kernels that `f` runs on the calling thread. Kernels running on the
+/// threads of another `time_phases` at the same time add to its thread compute times too.
+pub fn time_phases<F: FnOnce()>(f: F) -> PhaseTimings {
+    THREADS.lock().unwrap().clear();
+    THREAD.set(Some(PhaseTimings::default()));
+    f();
+    let mut timings = THREAD.take().unwrap_or_default();
+    let threads = std::mem::take(&mut *THREADS.lock().unwrap());
+    timings.thread_compute_min = threads.iter().copied().min().unwrap_or_default();
+    timings.thread_compute_max = threads.iter().copied().max().unwrap_or_default();
+    timings
+}
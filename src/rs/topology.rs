@@ -0,0 +1,142 @@
+use std::fs;
+use std::str::FromStr;
+
+use crate::threads::ThreadConfig;
+
+/// How `step_with_options` and `step_batch` pin their threads to CPUs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Affinity {
+    /// Lets the OS move the threads between CPUs.
+    #[default]
+    None,
+    /// Pins the threads to the CPUs of `pin_order`, so that as long as there are at most as many
+    /// threads as physical cores each one gets a core of its own, the performance cores of a hybrid
+    /// CPU first. Pins nothing if the system does not report its cores.
+    Auto,
+    /// Pins thread `t` to CPU `cpus[t % cpus.len()]`, like `ThreadConfig::pin_cores`.
+    Pin(Vec<usize>),
+}
+
+impl Affinity {
+    /// `threads`, unless it already pins its threads, with them pinned according to this affinity.
+    pub fn apply(&self, threads: &ThreadConfig) -> ThreadConfig {
+        let mut pinned = threads.clone();
+        if pinned.pin_cores.is_none() {
+            pinned.pin_cores = match self {
+                Affinity::None => None,
+                Affinity::Auto => Some(pin_order(&cores())).filter(|cpus| !cpus.is_empty()),
+                Affinity::Pin(cpus) => Some(cpus.clone()),
+            };
+        }
+        pinned
+    }
+}
+
+/// `none`, `auto`, or a list of CPUs such as `0,2,4` to `Pin` to.
+impl FromStr for Affinity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Affinity::None),
+            "auto" => Ok(Affinity::Auto),
+            _ => s
+                .split(',')
+                .map(|cpu| cpu.trim().parse().ok())
+                .collect::<Option<_>>()
+                .map(Affinity::Pin)
+                .ok_or_else(|| format!("unknown affinity '{}', expected none, auto or a list of CPUs such as 0,2,4", s)),
+        }
+    }
+}
+
+/// A physical core.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Core {
+    /// The logical CPUs of the core, more than one with SMT, in increasing order.
+    pub cpus: Vec<usize>,
+    /// Whether it is one of the slower cores of a hybrid CPU, an E-core of Intel or, on ARM, a core
+    /// of less than the largest `cpu_capacity`.
+    pub efficiency: bool,
+}
+
+/// Expands a sysfs CPU list such as `0-3,8,10-11`.
+pub(crate) fn parse_cpu_list(list: &str) -> Vec<usize> {
+    list.trim()
+        .split(',')
+        .filter_map(|range| match range.split_once('-') {
+            Some((first, last)) => Some((first.parse().ok()?..=last.parse().ok()?).collect::<Vec<_>>()),
+            None => range.parse().ok().map(|cpu| vec![cpu]),
+        })
+        .flatten()
+        .collect()
+}
+
+fn read_cpu_list(path: &str) -> Vec<usize> {
+    fs::read_to_string(path).map_or_else(|_| Vec::new(), |list| parse_cpu_list(&list))
+}
+
+fn capacity(cpu: usize) -> Option<u32> {
+    let capacity = fs::read_to_string(format!("/sys/devices/system/cpu/cpu{}/cpu_capacity", cpu)).ok()?;
+    capacity.trim().parse().ok()
+}
+
+/// The physical cores with a CPU this process may run on, in order of their first CPU, empty if
+/// the system does not report them.
+pub fn cores() -> Vec<Core> {
+    let allowed = allowed_cpus();
+    let online: Vec<usize> = read_cpu_list("/sys/devices/system/cpu/online")
+        .into_iter()
+        .filter(|cpu| allowed.as_ref().is_none_or(|allowed| allowed.contains(cpu)))
+        .collect();
+    let atoms = read_cpu_list("/sys/devices/cpu_atom/cpus");
+    let max_capacity = online.iter().filter_map(|&cpu| capacity(cpu)).max();
+    let mut cores: Vec<Core> = Vec::new();
+    for &cpu in &online {
+        if cores.iter().any(|core| core.cpus.contains(&cpu)) {
+            continue;
+        }
+        let path = format!("/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list", cpu);
+        let mut cpus = read_cpu_list(&path);
+        cpus.retain(|sibling| online.contains(sibling));
+        if !cpus.contains(&cpu) {
+            cpus = vec![cpu];
+        }
+        let efficiency = atoms.contains(&cpu) || capacity(cpu).is_some_and(|c| Some(c) < max_capacity);
+        cores.push(Core { cpus, efficiency });
+    }
+    cores
+}
+
+/// The CPUs to pin consecutive threads to: the first CPU of each performance core, then of each
+/// efficiency core, and only then their SMT siblings, which share the SIMD units of their core and
+/// add little to kernels that keep them busy.
+pub fn pin_order(cores: &[Core]) -> Vec<usize> {
+    let first = |efficiency: bool| cores.iter().filter(move |core| core.efficiency == efficiency).map(|core| core.cpus[0]);
+    let siblings = cores.iter().flat_map(|core| core.cpus[1..].iter().copied());
+    first(false).chain(first(true)).chain(siblings).collect()
+}
+
+/// The CPUs in the affinity mask of this process, as set by `taskset`, if it can be read.
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Option<Vec<usize>> {
+    use std::ffi::c_ulong;
+
+    extern "C" {
+        fn sched_getaffinity(pid: i32, cpusetsize: usize, mask: *mut c_ulong) -> i32;
+    }
+    // A `cpu_set_t` of 1024 CPUs, in words of `unsigned long`, which are not pairs of them in a `u64`
+    // on 32-bit big-endian targets.
+    const BITS: usize = c_ulong::BITS as usize;
+    let mut mask = [0 as c_ulong; 1024 / BITS];
+    if unsafe { sched_getaffinity(0, std::mem::size_of_val(&mask), mask.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    Some((0..BITS * mask.len()).filter(|cpu| mask[cpu / BITS] & (1 << (cpu % BITS)) != 0).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> Option<Vec<usize>> {
+    None
+}
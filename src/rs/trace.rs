@@ -0,0 +1,14 @@
+/// Enters a `tracing` span at the info level until the end of the enclosing block, with the fields
+/// of `tracing::info_span!`, for example `span!("pack", n)`, and the phase `name` of
+/// `timings::time_phases`. Expands to nothing without the `trace` and `timings` features, so that
+/// the spans cost nothing unless they are wanted.
+macro_rules! span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "trace")]
+        let _span = tracing::info_span!($name $(, $($fields)*)?).entered();
+        #[cfg(feature = "timings")]
+        let _phase = $crate::timings::Phase::enter($name);
+    };
+}
+
+pub(crate) use span;
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::{env, fs};
+
+use crate::dispatch::{Kernel, Tail};
+use crate::simd::PARANOID;
+use crate::v4_register_reuse::{Shape, ShapedStepFn, SHAPES};
+
+/// Blocking parameters of `v7`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tuning {
+    /// Rows of `r` whose results are kept in memory while streaming over all columns of `d`.
+    pub row_block: usize,
+    /// Columns of `d` packed at a time, 500 in the book.
+    pub col_block: usize,
+    /// How many vectors ahead to prefetch, zero for none like in the book.
+    pub prefetch: usize,
+    /// Writes `r` with non-temporal stores that bypass the caches, so that the results do not
+    /// evict the packed stripes of `d`, which only pays off if `r` is much larger than the caches.
+    pub streaming_stores: bool,
+    /// The order in which the threads are given the 8 * 8 blocks of a band of rows. It is not
+    /// tuned, nor saved with the other parameters.
+    pub schedule: Schedule,
+    /// Runs the `"transpose-free"` variant instead, which reads the columns of `d` in place rather
+    /// than from a transposed copy. Whether that is faster depends on the memory system more than
+    /// on the cores, so it is measured on each machine.
+    pub transpose_free: bool,
+}
+
+impl Default for Tuning {
+    /// The parameters of `v7` in the book, all rows at once in stripes of 500 columns.
+    fn default() -> Self {
+        Tuning {
+            row_block: usize::MAX,
+            col_block: 500,
+            prefetch: 0,
+            streaming_stores: false,
+            schedule: Schedule::ZOrder,
+            transpose_free: false,
+        }
+    }
+}
+
+/// Orders of the blocks of `v7`, which threads take in consecutive chunks. Along a space-filling
+/// curve the blocks of each thread, and of threads running at the same time, share more of
+/// their rows and columns, so that more of them are still in the shared caches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Schedule {
+    /// Row by row, all blocks of a row of blocks before the next.
+    RowMajor,
+    /// Along a Z-order curve, by interleaving the bits of the row and the column.
+    #[default]
+    ZOrder,
+    /// Along a Hilbert curve, which unlike the Z-order one never jumps between distant blocks.
+    Hilbert,
+}
+
+impl Schedule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Schedule::RowMajor => "row-major",
+            Schedule::ZOrder => "z-order",
+            Schedule::Hilbert => "hilbert",
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Schedule::RowMajor, Schedule::ZOrder, Schedule::Hilbert]
+            .into_iter()
+            .find(|schedule| schedule.name() == s)
+            .ok_or_else(|| format!("unknown schedule '{}', expected row-major, z-order or hilbert", s))
+    }
+}
+
+/// Sizes below this use `Tuning::default()`, blocking makes little difference when everything
+/// fits in the cache.
+const MIN_TUNED_N: usize = 256;
+
+/// Sizes share their tuning with all sizes between the same two powers of two.
+fn range(n: usize) -> usize {
+    1 << n.ilog2()
+}
+
+/// Identifies the CPU the cached parameters were measured on.
+pub(crate) fn cpu_model() -> String {
+    fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|info| info.lines().find(|line| line.starts_with("model name")).map(|line| line.to_string()))
+        .and_then(|line| line.split_once(':').map(|(_, model)| model.trim().to_string()))
+        .unwrap_or_else(|| env::consts::ARCH.to_string())
+}
+
+/// `$SHORTCUT_TUNE_FILE`, or `shortcut/tune.tsv` in the user's configuration directory.
+fn config_file() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("SHORTCUT_TUNE_FILE") {
+        return Some(path.into());
+    }
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("shortcut").join("tune.tsv"))
+}
+
+/// Each line of the file is the CPU model, `range(n)`, `row_block`, `col_block`, `prefetch`,
+/// `streaming_stores` and `transpose_free`, separated by tabs. Lines without `transpose_free`, saved
+/// before it was tuned, are read as `false`.
+fn load(cpu: &str) -> HashMap<usize, Tuning> {
+    let contents = config_file().and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split('\t').collect();
+            match fields[..] {
+                [model, range, row_block, col_block, prefetch, streaming_stores, ref transpose_free @ ..]
+                    if model == cpu && transpose_free.len() <= 1 =>
+                {
+                    let tuning = Tuning {
+                        row_block: row_block.parse().ok()?,
+                        col_block: col_block.parse().ok()?,
+                        prefetch: prefetch.parse().ok()?,
+                        streaming_stores: streaming_stores.parse().ok()?,
+                        schedule: Schedule::default(),
+                        transpose_free: match transpose_free {
+                            [transpose_free] => transpose_free.parse().ok()?,
+                            _ => false,
+                        },
+                    };
+                    Some((range.parse().ok()?, tuning))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Appends one line for `range` to the file, leaving the entries of other CPUs as they are.
+/// The cache still works in memory if the file cannot be written.
+fn save(cpu: &str, range: usize, tuning: &Tuning) {
+    let Some(path) = config_file() else { return };
+    let mut contents = fs::read_to_string(&path).unwrap_or_default();
+    contents.push_str(&format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        cpu, range, tuning.row_block, tuning.col_block, tuning.prefetch, tuning.streaming_stores, tuning.transpose_free
+    ));
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(&path, contents);
+}
+
+/// The tunings of this CPU by `range`, loaded from the file on first use.
+fn cache() -> &'static Mutex<HashMap<usize, Tuning>> {
+    static CACHE: OnceLock<Mutex<HashMap<usize, Tuning>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(load(&cpu_model())))
+}
+
+/// The fastest parameters on an input of size `n`, running `v7` once for each combination, and
+/// whether the `"transpose-free"` variant is faster still.
+#[cfg(target_arch = "x86_64")]
+fn fastest(n: usize) -> Tuning {
+    use std::time::Instant;
+
+    use crate::{bench, scratch::Scratch, v7_cache_reuse, v_transpose_free, ThreadConfig};
+
+    /// The values of each parameter to try.
+    const ROW_BLOCKS: [usize; 3] = [usize::MAX, 1024, 256];
+    const COL_BLOCKS: [usize; 3] = [250, 500, 1000];
+    const PREFETCHES: [usize; 2] = [0, 20];
+    const STREAMING_STORES: [bool; 2] = [false, true];
+
+    if PARANOID || !is_x86_feature_detected!("avx2") {
+        return Tuning::default();
+    }
+    let (d, mut r) = bench::bench_inputs(n);
+    let (threads, mut scratch) = (ThreadConfig::default(), Scratch::default());
+    let mut time = |tuning: &Tuning| {
+        let start = Instant::now();
+        unsafe { v7_cache_reuse::step_avx2(&threads, &mut scratch, &mut r, &d, n, tuning) };
+        start.elapsed()
+    };
+    time(&Tuning::default());
+    let (tuning, elapsed) = ROW_BLOCKS
+        .iter()
+        .flat_map(|&row_block| COL_BLOCKS.iter().map(move |&col_block| (row_block, col_block)))
+        .flat_map(|(row_block, col_block)| PREFETCHES.iter().map(move |&prefetch| (row_block, col_block, prefetch)))
+        .flat_map(|(row_block, col_block, prefetch)| {
+            STREAMING_STORES.iter().map(move |&streaming_stores| Tuning {
+                row_block,
+                col_block,
+                prefetch,
+                streaming_stores,
+                schedule: Schedule::default(),
+                transpose_free: false,
+            })
+        })
+        .map(|tuning| (tuning, time(&tuning)))
+        .min_by_key(|&(_, elapsed)| elapsed)
+        .unwrap();
+    let start = Instant::now();
+    unsafe { v_transpose_free::step_avx2(&threads, &mut r, &d, n) };
+    Tuning { transpose_free: start.elapsed() < elapsed, ..tuning }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn fastest(_n: usize) -> Tuning {
+    Tuning::default()
+}
+
+/// Benchmarks all combinations of the parameters on an input of size `n` and caches the fastest
+/// for the range of sizes `n` belongs to, replacing any earlier result.
+pub fn tune(n: usize) -> Tuning {
+    if n < MIN_TUNED_N {
+        return Tuning::default();
+    }
+    let tuning = fastest(n);
+    cache().lock().unwrap().insert(range(n), tuning);
+    save(&cpu_model(), range(n), &tuning);
+    tuning
+}
+
+/// The size `shape` times the shapes on, large enough that the sums no longer fit in the L1 cache,
+/// where the tiles with fewer loads per result pay off.
+const SHAPE_N: usize = 512;
+
+/// The shape of the register tile of `v4` with which `step` is fastest on this CPU, timed on an
+/// input of size `SHAPE_N` on the first call with each `cache`, one per instruction set. It only
+/// takes about a tenth of a second, so unlike `Tuning` it is not saved to the file. With
+/// `simd::PARANOID`, where timing the portable loops would take far longer, it is the 3 * 3 tile
+/// of the book.
+pub(crate) fn shape(cache: &OnceLock<Shape>, step: ShapedStepFn) -> Shape {
+    use std::time::{Duration, Instant};
+
+    use crate::{bench, scratch::Scratch, ThreadConfig};
+
+    if PARANOID {
+        return SHAPES[0];
+    }
+
+    *cache.get_or_init(|| {
+        let (d, mut r) = bench::bench_inputs(SHAPE_N);
+        let (threads, mut scratch) = (ThreadConfig::with_threads(1), Scratch::default());
+        let mut time = |shape: Shape| {
+            let start = Instant::now();
+            unsafe { step(shape, &threads, &mut scratch, &mut r, &d, SHAPE_N) };
+            start.elapsed()
+        };
+        // The fastest of a few runs of each, the very first also allocates the scratch buffers.
+        SHAPES
+            .into_iter()
+            .min_by_key(|&shape| (0..3).map(|_| time(shape)).min().unwrap_or(Duration::MAX))
+            .unwrap()
+    })
+}
+
+/// Sizes up to this are timed as they are by `tail`, larger ones at the largest size up to it with
+/// the same remainder modulo the vectors of the kernel.
+const TAIL_N: usize = 256;
+
+/// Whether `kernel` is faster with its rows padded or masked for size `n`, timed with one thread
+/// on the first call for each size that `n` is timed at, and not saved to the file, like `shape`.
+/// Both pack the same rows where `n` is a multiple of the vectors, which `default_tail` then does.
+/// Padding takes at most a vector per row, so beyond `TAIL_N` it is the remainder that decides.
+pub(crate) fn tail(kernel: Kernel, n: usize) -> Tail {
+    use std::time::{Duration, Instant};
+
+    use crate::{bench, ThreadConfig};
+
+    static CACHE: OnceLock<Mutex<HashMap<(Kernel, usize), Tail>>> = OnceLock::new();
+
+    let lanes = kernel.lanes_with(Tail::Pad);
+    if PARANOID || n.is_multiple_of(lanes) || !kernel.is_supported() {
+        return kernel.default_tail();
+    }
+    let timed_n = if n <= TAIL_N { n } else { (TAIL_N - n % lanes) / lanes * lanes + n % lanes };
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+    *cache.entry((kernel, timed_n)).or_insert_with(|| {
+        let (d, mut r) = bench::bench_inputs(timed_n);
+        let threads = ThreadConfig::with_threads(1);
+        let mut time = |tail: Tail| {
+            let start = Instant::now();
+            kernel.step_strided_with_tail(&threads, &mut r, timed_n, &d, timed_n, timed_n, tail);
+            start.elapsed()
+        };
+        // The fastest of a few runs of each, as fast as the other when within a hundredth of it.
+        let mut fastest = |tail| (0..3).map(|_| time(tail)).min().unwrap_or(Duration::MAX);
+        let (pad, mask) = (fastest(Tail::Pad), fastest(Tail::Mask));
+        if mask < pad.mul_f64(0.99) { Tail::Mask } else if pad < mask.mul_f64(0.99) { Tail::Pad } else { kernel.default_tail() }
+    })
+}
+
+/// The cached parameters for size `n`, tuned on the smallest size of its range on first use.
+pub fn tuning(n: usize) -> Tuning {
+    if n < MIN_TUNED_N {
+        return Tuning::default();
+    }
+    let mut cache = cache().lock().unwrap();
+    *cache.entry(range(n)).or_insert_with(|| {
+        let tuning = fastest(range(n));
+        save(&cpu_model(), range(n), &tuning);
+        tuning
+    })
+}
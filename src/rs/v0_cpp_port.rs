@@ -1,12 +1,14 @@
-fn _step(r: &mut [f32], d: &[f32], n: usize) {
+use crate::semiring::Semiring;
+
+pub(crate) fn _step<S: Semiring>(r: &mut [S::Elem], d: &[S::Elem], n: usize) {
     for i in 0..n {
         for j in 0..n {
-            let mut v = std::f32::INFINITY;
+            let mut v = S::ZERO;
             for k in 0..n {
                 let x = d[n*i + k];
                 let y = d[n*k + j];
-                let z = x + y;
-                v = v.min(z);
+                let z = S::combine(x, y);
+                v = S::reduce(v, z);
             }
             r[n*i + j] = v;
         }
@@ -0,0 +1,188 @@
+use std::sync::OnceLock;
+
+use crate::ops::count;
+use crate::scratch::Scratch;
+use crate::{layout, tune};
+use crate::simd::{self, Vector};
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::trace::span;
+
+/// The shape of the register tile of `step_row_block`, in rows of `d` and columns of `d`, whose
+/// `rows * cols` results are kept in vector registers together with `rows + cols` inputs.
+pub(crate) type Shape = (usize, usize);
+
+/// The shapes `step_row_block` is instantiated for. The 3 * 3 tile of the book needs 15 of the
+/// 16 registers of AVX2, the larger tiles only fit in the 32 registers of AVX-512 and NEON.
+pub(crate) const SHAPES: [Shape; 4] = [(3, 3), (2, 4), (4, 3), (4, 4)];
+
+/// Packs `d` and its transpose into rows of `width` floats, padded at the bottom with
+/// `f32::INFINITY` rows to make the row count of `d` divisible by `rows` and that of the
+/// transpose by `cols`.
+fn preprocess(scratch: &mut Scratch, d: &[f32], n: usize, lanes: usize, (rows, cols): Shape) -> usize {
+    span!("pack", n);
+    let width = n.div_ceil(lanes) * lanes;
+    scratch.vd.reset(n.div_ceil(rows) * rows * width, f32::INFINITY);
+    scratch.vt.reset(n.div_ceil(cols) * cols * width, f32::INFINITY);
+    let vd = scratch.vd.as_mut_slice();
+    let vt = scratch.vt.as_mut_slice();
+    if n > 0 {
+        for (vd_row, d_row) in vd.chunks_mut(width).zip(d.chunks(n)) {
+            vd_row[..n].copy_from_slice(d_row);
+        }
+    }
+    layout::transpose_into(vt, width, d, n, n, n);
+    width
+}
+
+/// The results of `ROWS` rows of `r`, of all blocks of `COLS` columns in turn, each with the
+/// `ROWS * COLS` minimums in registers. The arrays are fully unrolled, like the separate variables
+/// of the book.
+#[inline(always)]
+unsafe fn step_row_block<V: Vector, const ROWS: usize, const COLS: usize>(
+    r_row_block: &mut [f32],
+    vd_row_block: &[f32],
+    vt: &[f32],
+    n: usize,
+    width: usize,
+) {
+    for (j, vt_col_block) in vt.chunks(COLS * width).enumerate() {
+        let mut v = [[V::splat(f32::INFINITY); COLS]; ROWS];
+        for k in (0..width).step_by(V::LANES) {
+            let mut x = [V::splat(0.0); ROWS];
+            for (i, x) in x.iter_mut().enumerate() {
+                *x = simd::load::<V, false>(vd_row_block, width*i + k);
+            }
+            let mut y = [V::splat(0.0); COLS];
+            for (jj, y) in y.iter_mut().enumerate() {
+                *y = simd::load::<V, false>(vt_col_block, width*jj + k);
+            }
+            for (v_row, &x) in v.iter_mut().zip(&x) {
+                for (v, &y) in v_row.iter_mut().zip(&y) {
+                    *v = V::min(*v, V::add(x, y));
+                }
+            }
+        }
+        for (results_row, r_row) in v.iter().zip(r_row_block.chunks_mut(n)) {
+            for (jj, &v) in results_row.iter().enumerate() {
+                if let Some(res) = r_row.get_mut(COLS*j + jj) {
+                    *res = V::horizontal_min(v);
+                }
+            }
+        }
+        // All `ROWS * COLS` minimums are reduced, also those of the padding, which are not stored.
+        count!(
+            loads: (ROWS + COLS) * width,
+            stores: r_row_block.len().div_ceil(n.max(1)) * COLS.min(n.saturating_sub(COLS * j)),
+            adds: ROWS * COLS * width,
+            mins: ROWS * COLS * (width + V::LANES - 1),
+        );
+    }
+}
+
+/// Like `simd::step_lanes`, a macro to keep the `#[target_feature]`s of the caller in the closure.
+macro_rules! step_lanes {
+    ($V:ty, $rows:literal, $cols:literal, $threads:expr, $scratch:expr, $r:expr, $d:expr, $n:expr) => {{
+        let (scratch, r, d, n): (&mut Scratch, &mut [f32], &[f32], usize) = ($scratch, $r, $d, $n);
+        let width = preprocess(scratch, d, n, <$V as Vector>::LANES, ($rows, $cols));
+        let (vd, vt) = (scratch.vd.as_slice(), scratch.vt.as_slice());
+        span!("compute", n);
+        for_each_chunk($threads, r, $rows * n, |i, r_row_block| unsafe {
+            let vd_row_block = &vd[$rows*width*i..$rows*width*(i + 1)];
+            step_row_block::<$V, $rows, $cols>(r_row_block, vd_row_block, vt, n, width);
+        })
+    }};
+}
+
+/// `step_lanes!` with the tile of `shape`, one of `SHAPES`, so that all the outer loops are shared
+/// by the instruction sets and the shapes.
+macro_rules! step_shape {
+    ($V:ty, $shape:expr, $threads:expr, $scratch:expr, $r:expr, $d:expr, $n:expr) => {
+        match $shape {
+            (2, 4) => step_lanes!($V, 2, 4, $threads, $scratch, $r, $d, $n),
+            (4, 3) => step_lanes!($V, 4, 3, $threads, $scratch, $r, $d, $n),
+            (4, 4) => step_lanes!($V, 4, 4, $threads, $scratch, $r, $d, $n),
+            _ => step_lanes!($V, 3, 3, $threads, $scratch, $r, $d, $n),
+        }
+    };
+}
+
+/// The entry points of one instruction set with each of `SHAPES`, taking the shape first.
+pub(crate) type ShapedStepFn = unsafe fn(Shape, &ThreadConfig, &mut Scratch, &mut [f32], &[f32], usize);
+
+/// The entry points run the shape `tune::shape` finds fastest for their instruction set, timed
+/// once on first use.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+pub(crate) unsafe fn step_avx512(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    static SHAPE: OnceLock<Shape> = OnceLock::new();
+    step_avx512_shaped(tune::shape(&SHAPE, step_avx512_shaped), threads, scratch, r, d, n)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+unsafe fn step_avx512_shaped(
+    shape: Shape,
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+) {
+    step_shape!(std::arch::x86_64::__m512, shape, threads, scratch, r, d, n)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn step_avx2(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    static SHAPE: OnceLock<Shape> = OnceLock::new();
+    step_avx2_shaped(tune::shape(&SHAPE, step_avx2_shaped), threads, scratch, r, d, n)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn step_avx2_shaped(
+    shape: Shape,
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+) {
+    step_shape!(std::arch::x86_64::__m256, shape, threads, scratch, r, d, n)
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+pub(crate) unsafe fn step_neon(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    static SHAPE: OnceLock<Shape> = OnceLock::new();
+    step_neon_shaped(tune::shape(&SHAPE, step_neon_shaped), threads, scratch, r, d, n)
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn step_neon_shaped(
+    shape: Shape,
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+) {
+    step_shape!(std::arch::aarch64::float32x4_t, shape, threads, scratch, r, d, n)
+}
+
+pub(crate) fn step_portable(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    static SHAPE: OnceLock<Shape> = OnceLock::new();
+    step_portable_shaped(tune::shape(&SHAPE, step_portable_shaped), threads, scratch, r, d, n)
+}
+
+fn step_portable_shaped(
+    shape: Shape,
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+) {
+    step_shape!([f32; 4], shape, threads, scratch, r, d, n)
+}
@@ -0,0 +1,276 @@
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::layout;
+use crate::ops::count;
+use crate::scratch::Scratch;
+use crate::simd::{self, Vector, PARANOID};
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::trace::span;
+
+pub(crate) const PREFETCH_LENGTH: usize = 20;
+
+/// Vectors of 8 lanes that `step_block` permutes: `swap1` swaps neighbouring lanes, `swap2`
+/// neighbouring pairs and `swap4` the two halves, so that lane `l` of the result is lane `l ^ 1`,
+/// `l ^ 2` or `l ^ 4` of `self`.
+pub(crate) trait Permute: Vector {
+    unsafe fn swap1(self) -> Self;
+    unsafe fn swap2(self) -> Self;
+    unsafe fn swap4(self) -> Self;
+    unsafe fn to_array(self) -> [f32; 8];
+}
+
+/// The lanes of `step_portable`, which the compiler may or may not vectorize.
+impl Permute for [f32; 8] {
+    unsafe fn swap1(self) -> Self {
+        std::array::from_fn(|l| self[l ^ 1])
+    }
+    unsafe fn swap2(self) -> Self {
+        std::array::from_fn(|l| self[l ^ 2])
+    }
+    unsafe fn swap4(self) -> Self {
+        std::array::from_fn(|l| self[l ^ 4])
+    }
+    unsafe fn to_array(self) -> [f32; 8] {
+        self
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Permute for __m256 {
+    #[inline(always)]
+    unsafe fn swap1(self) -> Self {
+        simd::x86::swap1(self)
+    }
+    #[inline(always)]
+    unsafe fn swap2(self) -> Self {
+        simd::x86::swap2(self)
+    }
+    #[inline(always)]
+    unsafe fn swap4(self) -> Self {
+        simd::x86::swap4(self)
+    }
+    #[inline(always)]
+    unsafe fn to_array(self) -> [f32; 8] {
+        let mut lanes = [0.0; 8];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), self);
+        lanes
+    }
+}
+
+/// The lane permutations of `float32x4x2_t`, with `swap4` swapping the two vectors.
+#[cfg(target_arch = "aarch64")]
+impl Permute for float32x4x2_t {
+    #[inline(always)]
+    unsafe fn swap1(self) -> Self {
+        float32x4x2_t(vrev64q_f32(self.0), vrev64q_f32(self.1))
+    }
+    #[inline(always)]
+    unsafe fn swap2(self) -> Self {
+        float32x4x2_t(vextq_f32::<2>(self.0, self.0), vextq_f32::<2>(self.1, self.1))
+    }
+    #[inline(always)]
+    unsafe fn swap4(self) -> Self {
+        float32x4x2_t(self.1, self.0)
+    }
+    #[inline(always)]
+    unsafe fn to_array(self) -> [f32; 8] {
+        let mut lanes = [0.0; 8];
+        vst1q_f32_x2(lanes.as_mut_ptr(), self);
+        lanes
+    }
+}
+
+/// Packs 8 rows of `d` into each `f32x8` of `vd` and 8 columns of `d` into each `f32x8` of `vt`,
+/// so that row `i` of `vd` holds all columns of rows `8i..8i+8` as vectors, stored lane by lane.
+pub(crate) fn pack_simd(scratch: &mut Scratch, d: &[f32], n: usize) {
+    span!("pack", n);
+    let blocks = n.div_ceil(8);
+    scratch.vd.reset(blocks * n * 8, f32::INFINITY);
+    scratch.vt.reset(blocks * n * 8, f32::INFINITY);
+    let vd = scratch.vd.as_mut_slice();
+    let vt = scratch.vt.as_mut_slice();
+    if n == 0 {
+        return;
+    }
+    layout::interleave_into(vd, d, n, n, n, 8, f32::INFINITY);
+    // The columns of `d` are the rows of its transpose, so each vector of `vt` is a piece of a row.
+    for (i, vt_row) in vt.chunks_mut(n * 8).enumerate() {
+        let cols = 8*i..(8*i + 8).min(n);
+        for (vy, d_row) in vt_row.chunks_mut(8).zip(d.chunks(n)) {
+            vy[..cols.len()].copy_from_slice(&d_row[cols.clone()]);
+        }
+    }
+}
+
+/// Prefetches element `k` of `s`, which may be past its end, into the L1 cache where SSE can, and
+/// with `prfm` on AArch64, which has no stable intrinsic for it.
+#[inline(always)]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), allow(unused_variables))]
+unsafe fn prefetch(s: &[f32], k: usize) {
+    #[cfg(target_arch = "x86_64")]
+    _mm_prefetch(s.as_ptr().wrapping_add(k) as *const i8, _MM_HINT_T0);
+    #[cfg(target_arch = "aarch64")]
+    std::arch::asm!(
+        "prfm pldl1keep, [{}]",
+        in(reg) s.as_ptr().wrapping_add(k),
+        options(nostack, readonly, preserves_flags),
+    );
+}
+
+/// Accumulates the 8 permuted products of one pair of 8-row blocks over `len` vectors into `tmp`,
+/// prefetching `prefetch` vectors ahead if `PREFETCH`.
+#[inline(always)]
+pub(crate) unsafe fn step_block<V: Permute, const PREFETCH: bool>(
+    tmp: &mut [V; 8],
+    vd_row: &[f32],
+    vt_row: &[f32],
+    len: usize,
+    prefetch: usize,
+) {
+    let mut tmp0 = tmp[0];
+    let mut tmp1 = tmp[1];
+    let mut tmp2 = tmp[2];
+    let mut tmp3 = tmp[3];
+    let mut tmp4 = tmp[4];
+    let mut tmp5 = tmp[5];
+    let mut tmp6 = tmp[6];
+    let mut tmp7 = tmp[7];
+    for k in 0..len {
+        if PREFETCH && !PARANOID {
+            self::prefetch(vd_row, 8 * (k + prefetch));
+            self::prefetch(vt_row, 8 * (k + prefetch));
+        }
+        let a000: V = simd::load::<V, false>(vd_row, 8 * k);
+        let b000: V = simd::load::<V, false>(vt_row, 8 * k);
+        let a100 = a000.swap4();
+        let a010 = a000.swap2();
+        let a110 = a100.swap2();
+        let b001 = b000.swap1();
+        tmp0 = V::min(tmp0, V::add(a000, b000));
+        tmp1 = V::min(tmp1, V::add(a000, b001));
+        tmp2 = V::min(tmp2, V::add(a010, b000));
+        tmp3 = V::min(tmp3, V::add(a010, b001));
+        tmp4 = V::min(tmp4, V::add(a100, b000));
+        tmp5 = V::min(tmp5, V::add(a100, b001));
+        tmp6 = V::min(tmp6, V::add(a110, b000));
+        tmp7 = V::min(tmp7, V::add(a110, b001));
+    }
+    *tmp = [tmp0, tmp1, tmp2, tmp3, tmp4, tmp5, tmp6, tmp7];
+    count!(loads: 16 * len, adds: 64 * len, mins: 64 * len);
+}
+
+/// Undoes the permutations of `step_block`, so that row `i` and column `j` of the 8-by-8 result block
+/// is `lanes[i ^ j][j]`.
+#[inline(always)]
+pub(crate) unsafe fn unpermute<V: Permute>(tmp: &[V; 8]) -> [[f32; 8]; 8] {
+    let mut lanes = [[0.0f32; 8]; 8];
+    for (i, (lane, &v)) in lanes.iter_mut().zip(tmp.iter()).enumerate() {
+        let v = if i % 2 == 1 { v.swap1() } else { v };
+        *lane = v.to_array();
+    }
+    lanes
+}
+
+/// Writes the 8-by-8 result block of `step_block` at column block `j`.
+#[inline(always)]
+pub(crate) unsafe fn write_block<V: Permute>(r_row_block: &mut [f32], tmp: &[V; 8], j: usize, n: usize) {
+    let lanes = unpermute(tmp);
+    for (tmp_i, r_row) in r_row_block.chunks_mut(n).enumerate() {
+        for tmp_j in 0..8 {
+            if let Some(res) = r_row.get_mut(8*j + tmp_j) {
+                *res = lanes[tmp_i ^ tmp_j][tmp_j];
+            }
+        }
+        count!(stores: r_row.len().saturating_sub(8 * j).min(8));
+    }
+}
+
+/// A macro to keep the `#[target_feature]`s of the caller in the closure, see `simd::step_lanes`.
+macro_rules! step_lanes {
+    ($V:ty, $prefetch:expr, $threads:expr, $scratch:expr, $r:expr, $n:expr) => {{
+        let (scratch, r, n): (&Scratch, &mut [f32], usize) = ($scratch, $r, $n);
+        let (vd, vt) = (scratch.vd.as_slice(), scratch.vt.as_slice());
+        span!("compute", n);
+        for_each_chunk($threads, r, 8 * n, |i, r_row_block| unsafe {
+            let vd_row = &vd[8*n*i..8*n*(i + 1)];
+            for (j, vt_row) in vt.chunks(8 * n).enumerate() {
+                let mut tmp = [<$V as Vector>::splat(f32::INFINITY); 8];
+                step_block::<$V, $prefetch>(&mut tmp, vd_row, vt_row, n, PREFETCH_LENGTH);
+                write_block(r_row_block, &tmp, j, n);
+            }
+        })
+    }};
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) unsafe fn step_avx2(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    pack_simd(scratch, d, n);
+    step_packed_avx2(threads, scratch, r, n, false)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) unsafe fn step_prefetch_avx2(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    pack_simd(scratch, d, n);
+    step_packed_avx2(threads, scratch, r, n, true)
+}
+
+/// The loops of `step_avx2`, or of `step_prefetch_avx2` if `prefetch`, on `scratch` already
+/// packed by `pack_simd`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn step_packed_avx2(threads: &ThreadConfig, scratch: &Scratch, r: &mut [f32], n: usize, prefetch: bool) {
+    if prefetch {
+        step_lanes!(__m256, true, threads, scratch, r, n)
+    } else {
+        step_lanes!(__m256, false, threads, scratch, r, n)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) unsafe fn step_neon(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    pack_simd(scratch, d, n);
+    step_packed_neon(threads, scratch, r, n, false)
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) unsafe fn step_prefetch_neon(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize) {
+    pack_simd(scratch, d, n);
+    step_packed_neon(threads, scratch, r, n, true)
+}
+
+/// `step_packed_avx2` with a pair of NEON vectors for each vector of 8 lanes.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+pub(crate) unsafe fn step_packed_neon(threads: &ThreadConfig, scratch: &Scratch, r: &mut [f32], n: usize, prefetch: bool) {
+    if prefetch {
+        step_lanes!(float32x4x2_t, true, threads, scratch, r, n)
+    } else {
+        step_lanes!(float32x4x2_t, false, threads, scratch, r, n)
+    }
+}
+
+/// The loops of `step_avx2`, or of `step_prefetch_avx2` if `prefetch`, with `[f32; 8]` for the
+/// vectors, for `simd::PARANOID`.
+pub(crate) fn step_portable(
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    prefetch: bool,
+) {
+    pack_simd(scratch, d, n);
+    step_packed_portable(threads, scratch, r, n, prefetch)
+}
+
+/// `step_packed_avx2` with `[f32; 8]` for the vectors.
+pub(crate) fn step_packed_portable(threads: &ThreadConfig, scratch: &Scratch, r: &mut [f32], n: usize, prefetch: bool) {
+    if prefetch {
+        step_lanes!([f32; 8], true, threads, scratch, r, n)
+    } else {
+        step_lanes!([f32; 8], false, threads, scratch, r, n)
+    }
+}
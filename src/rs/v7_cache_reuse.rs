@@ -0,0 +1,229 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::ops::Range;
+
+use crate::layout;
+use crate::ops::count;
+use crate::scratch::Scratch;
+use crate::simd;
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::trace::span;
+use crate::tune::{Schedule, Tuning};
+use crate::v5_more_register_reuse::{step_block, write_block, Permute};
+#[cfg(target_arch = "x86_64")]
+use crate::v5_more_register_reuse::unpermute;
+
+/// Interleaves the bits of `i` (odd bits) and `j` (even bits) into a Z-order index.
+pub(crate) fn z_encode(i: u32, j: u32) -> u64 {
+    let spread = |mut x: u64| {
+        x &= 0xffff_ffff;
+        x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+        x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+        x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+        (x | (x << 1)) & 0x5555_5555_5555_5555
+    };
+    (spread(i as u64) << 1) | spread(j as u64)
+}
+
+/// The index of `(i, j)` along a Hilbert curve over a `side * side` grid, `side` a power of two.
+fn hilbert_index(side: u64, mut i: u64, mut j: u64) -> u64 {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let (ri, rj) = (i & s != 0, j & s != 0);
+        index += s * s * ((3 * ri as u64) ^ rj as u64);
+        // Rotates the quadrant so that the curve within it starts where the previous one ended.
+        if !rj {
+            if ri {
+                (i, j) = (side - 1 - i, side - 1 - j);
+            }
+            (i, j) = (j, i);
+        }
+        s /= 2;
+    }
+    index
+}
+
+/// All pairs of 8-row blocks `(i, j)` with `i` in `rows` and `j < blocks`, sorted by `schedule`.
+pub(crate) fn row_pairs(rows: Range<usize>, blocks: usize, schedule: Schedule) -> Vec<(usize, usize)> {
+    let side = rows.end.max(blocks).next_power_of_two() as u64;
+    let key = |i: usize, j: usize| match schedule {
+        Schedule::RowMajor => (i * blocks + j) as u64,
+        Schedule::ZOrder => z_encode(i as u32, j as u32),
+        Schedule::Hilbert => hilbert_index(side, i as u64, j as u64),
+    };
+    let mut pairs: Vec<(u64, usize, usize)> = rows.flat_map(|i| (0..blocks).map(move |j| (key(i, j), i, j))).collect();
+    pairs.sort_unstable();
+    pairs.into_iter().map(|(_, i, j)| (i, j)).collect()
+}
+
+/// Packs columns `k0..k0+len` of `d` like `v5`, 8 rows (or columns) per `f32x8`.
+fn pack_stripe(vd: &mut [f32], vt: &mut [f32], d: &[f32], n: usize, k0: usize, len: usize) {
+    span!("pack", n, k0, len);
+    let blocks = n.div_ceil(8);
+    layout::interleave_into(vd, &d[k0..], n, n, len, 8, f32::INFINITY);
+    for (i, vt_row) in vt.chunks_mut(8 * len).take(blocks).enumerate() {
+        let cols = 8*i..(8*i + 8).min(n);
+        for (vy, d_row) in vt_row.chunks_mut(8).zip(d[n*k0..].chunks(n)) {
+            vy[..cols.len()].copy_from_slice(&d_row[cols.clone()]);
+            vy[cols.len()..].fill(f32::INFINITY);
+        }
+    }
+}
+
+/// The 8 vectors of a block of results in `partial`, which is aligned to 64 bytes.
+#[inline(always)]
+unsafe fn load_block<V: Permute>(partial: &[f32]) -> [V; 8] {
+    let mut tmp = [V::splat(0.0); 8];
+    for (b, v) in tmp.iter_mut().enumerate() {
+        *v = simd::load::<V, true>(partial, 8 * b);
+    }
+    count!(loads: 64);
+    tmp
+}
+
+#[inline(always)]
+unsafe fn store_block<V: Permute>(partial: &mut [f32], tmp: &[V; 8]) {
+    for (b, &v) in tmp.iter().enumerate() {
+        partial[8*b..8*b + 8].copy_from_slice(&v.to_array());
+    }
+    count!(stores: 64);
+}
+
+/// Like `write_block`, with non-temporal stores of each element.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn stream_block(r_row_block: &mut [f32], tmp: &[__m256; 8], j: usize, n: usize) {
+    let lanes = unpermute(tmp);
+    for (tmp_i, r_row) in r_row_block.chunks_mut(n).enumerate() {
+        for tmp_j in 0..8 {
+            if let Some(res) = r_row.get_mut(8*j + tmp_j) {
+                _mm_stream_si32((res as *mut f32).cast(), lanes[tmp_i ^ tmp_j][tmp_j].to_bits() as i32);
+            }
+        }
+        count!(stores: r_row.len().saturating_sub(8 * j).min(8));
+    }
+}
+
+/// Writes a block of results with `stream_block` if `streaming`, otherwise `write_block`.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn copy_out_avx2(r_row_block: &mut [f32], tmp: &[__m256; 8], j: usize, n: usize, streaming: bool) {
+    if streaming {
+        stream_block(r_row_block, tmp, j, n)
+    } else {
+        write_block(r_row_block, tmp, j, n)
+    }
+}
+
+/// Without non-temporal stores, `streaming` has no effect.
+#[inline(always)]
+unsafe fn copy_out_portable<V: Permute>(r_row_block: &mut [f32], tmp: &[V; 8], j: usize, n: usize, _streaming: bool) {
+    write_block(r_row_block, tmp, j, n)
+}
+
+/// Whether any element of block `(i, j)` of `r`, in the rows `r_row_block`, is less than the same
+/// element of `d`.
+fn block_decreased(r_row_block: &[f32], d: &[f32], i: usize, j: usize, n: usize) -> bool {
+    let cols = 8*j..n.min(8*j + 8);
+    let decreased =
+        |(r_row, d_row): (&[f32], &[f32])| r_row[cols.clone()].iter().zip(&d_row[cols.clone()]).any(|(x, y)| x < y);
+    r_row_block.chunks(n).zip(d[8*n*i..].chunks(n)).any(decreased)
+}
+
+/// Computes `r` in bands of `tuning.row_block` rows, streaming over `d` in stripes of
+/// `tuning.col_block` columns for each band, with `$copy_out` writing each block of `r`. If
+/// `$changed`, each block is also compared with `d` right after it is written, and the macro is
+/// whether any element decreased, as for `crate::step_changed`.
+/// Like `simd::step_lanes`, a macro to keep the `#[target_feature]`s of the caller in the closure.
+macro_rules! step_tuned {
+    ($V:ty, $copy_out:ident, $threads:expr, $scratch:expr, $r:expr, $d:expr, $n:expr, $tuning:expr, $changed:expr) => {{
+        let (threads, scratch, r, d, n, tuning): (&ThreadConfig, &mut Scratch, &mut [f32], &[f32], usize, &Tuning) =
+            ($threads, $scratch, $r, $d, $n, $tuning);
+        let blocks = n.div_ceil(8);
+        let band = (tuning.row_block / 8).max(1);
+        let stripe = tuning.col_block.clamp(1, n.max(1));
+        scratch.vd.reset(blocks * stripe * 8, 0.0);
+        scratch.vt.reset(blocks * stripe * 8, 0.0);
+        let mut decreased = false;
+        for i0 in (0..blocks).step_by(band) {
+            let pairs = row_pairs(i0..blocks.min(i0.saturating_add(band)), blocks, tuning.schedule);
+            scratch.partial.reset(64 * pairs.len(), f32::INFINITY);
+            for k0 in (0..n).step_by(stripe) {
+                let len = stripe.min(n - k0);
+                pack_stripe(scratch.vd.as_mut_slice(), scratch.vt.as_mut_slice(), d, n, k0, len);
+                let (vd, vt) = (scratch.vd.as_slice(), scratch.vt.as_slice());
+                span!("compute", n, i0, k0, len);
+                for_each_chunk(threads, scratch.partial.as_mut_slice(), 64, |z, partial| unsafe {
+                    let (i, j) = pairs[z];
+                    let mut tmp = load_block::<$V>(partial);
+                    let (vd_row, vt_row) = (&vd[8*len*i..8*len*(i + 1)], &vt[8*len*j..8*len*(j + 1)]);
+                    if tuning.prefetch > 0 {
+                        step_block::<$V, true>(&mut tmp, vd_row, vt_row, len, tuning.prefetch);
+                    } else {
+                        step_block::<$V, false>(&mut tmp, vd_row, vt_row, len, 0);
+                    }
+                    store_block(partial, &tmp);
+                });
+            }
+            span!("copy_out", n, i0);
+            for (partial, &(i, j)) in scratch.partial.as_slice().chunks(64).zip(pairs.iter()) {
+                let r_row_block_end = (8 * (i + 1)).min(n) * n;
+                let r_row_block = &mut r[8 * n * i..r_row_block_end];
+                unsafe { $copy_out(r_row_block, &load_block::<$V>(partial), j, n, tuning.streaming_stores) };
+                if $changed && !decreased {
+                    decreased = block_decreased(r_row_block, d, i, j, n);
+                }
+            }
+        }
+        decreased
+    }};
+}
+
+/// The results are written with non-temporal stores if `tuning.streaming_stores`, and fenced
+/// before returning, since they are not ordered with other stores.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn step_avx2(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize, tuning: &Tuning) {
+    step_tuned!(__m256, copy_out_avx2, threads, scratch, r, d, n, tuning, false);
+    if tuning.streaming_stores {
+        _mm_sfence();
+    }
+}
+
+/// `step_avx2`, also returning whether any element of `r` is less than the same element of `d`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn step_avx2_changed(
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    tuning: &Tuning,
+) -> bool {
+    // The blocks are read back right after they are written, which non-temporal stores would evict.
+    let tuning = &Tuning { streaming_stores: false, ..*tuning };
+    step_tuned!(__m256, copy_out_avx2, threads, scratch, r, d, n, tuning, true)
+}
+
+/// The loops of `step_avx2` with a pair of NEON vectors for each vector of 8 lanes, without
+/// non-temporal stores.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+pub(crate) unsafe fn step_neon(threads: &ThreadConfig, scratch: &mut Scratch, r: &mut [f32], d: &[f32], n: usize, tuning: &Tuning) {
+    step_tuned!(std::arch::aarch64::float32x4x2_t, copy_out_portable, threads, scratch, r, d, n, tuning, false);
+}
+
+/// The loops of `step_avx2` with `[f32; 8]` for the vectors, for `simd::PARANOID`.
+pub(crate) fn step_portable(
+    threads: &ThreadConfig,
+    scratch: &mut Scratch,
+    r: &mut [f32],
+    d: &[f32],
+    n: usize,
+    tuning: &Tuning,
+) {
+    step_tuned!([f32; 8], copy_out_portable, threads, scratch, r, d, n, tuning, false);
+}
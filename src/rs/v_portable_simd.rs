@@ -0,0 +1,41 @@
+use std::simd::num::SimdFloat;
+use std::simd::f32x8;
+
+use crate::simd::{step_lanes, Packed, Vector};
+use crate::threads::ThreadConfig;
+use crate::StepError;
+
+impl Vector for f32x8 {
+    const LANES: usize = 8;
+    #[inline(always)]
+    unsafe fn splat(x: f32) -> Self {
+        f32x8::splat(x)
+    }
+    #[inline(always)]
+    unsafe fn load(p: *const f32) -> Self {
+        f32x8::from_slice(std::slice::from_raw_parts(p, 8))
+    }
+    #[inline(always)]
+    unsafe fn add(a: Self, b: Self) -> Self {
+        a + b
+    }
+    #[inline(always)]
+    unsafe fn min(a: Self, b: Self) -> Self {
+        a.simd_min(b)
+    }
+    #[inline(always)]
+    unsafe fn horizontal_min(a: Self) -> f32 {
+        a.reduce_min()
+    }
+}
+
+pub fn step(r: &mut [f32], d: &[f32], n: usize) -> Result<(), StepError> {
+    crate::check_lengths(r, d, n)?;
+    step_with_threads(&ThreadConfig::default(), r, d, n);
+    Ok(())
+}
+
+/// `step` on `threads`, for `"portable-simd"` in `variants::by_name_with_threads`.
+pub(crate) fn step_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    step_lanes!(f32x8, threads, r, n, &Packed::square(d, n, n, 8), false)
+}
@@ -0,0 +1,84 @@
+use std::ops::Range;
+
+use crate::ops::count;
+use crate::threads::{for_each_chunk, ThreadConfig};
+use crate::trace::span;
+
+/// Blocks with at most `LEAF` rows, columns and terms are summed directly. The three tiles of
+/// `LEAF * LEAF` floats they read and write together take 48 KiB, about the size of an L1 cache,
+/// but no cache size is assumed above them: each halving keeps a block in whichever cache it fits.
+const LEAF: usize = 64;
+
+/// The block of `r` with rows `i` and columns `j`, reduced over the terms `k`, with `r_band` the
+/// rows of `r` from `band_start`. Halves the longest of the three ranges until all are at most
+/// `LEAF`; the halves of `k` both reduce into the same block of `r`, one after the other.
+fn recurse(
+    r_band: &mut [f32],
+    band_start: usize,
+    d: &[f32],
+    n: usize,
+    i: Range<usize>,
+    j: Range<usize>,
+    k: Range<usize>,
+) {
+    let split = |range: &Range<usize>| {
+        let mid = range.start + range.len() / 2;
+        (range.start..mid, mid..range.end)
+    };
+    if i.len() >= j.len() && i.len() >= k.len() && i.len() > LEAF {
+        let (top, bottom) = split(&i);
+        recurse(r_band, band_start, d, n, top, j.clone(), k.clone());
+        recurse(r_band, band_start, d, n, bottom, j, k);
+    } else if j.len() >= k.len() && j.len() > LEAF {
+        let (left, right) = split(&j);
+        recurse(r_band, band_start, d, n, i.clone(), left, k.clone());
+        recurse(r_band, band_start, d, n, i, right, k);
+    } else if k.len() > LEAF {
+        let (front, back) = split(&k);
+        recurse(r_band, band_start, d, n, i.clone(), j.clone(), front);
+        recurse(r_band, band_start, d, n, i, j, back);
+    } else {
+        leaf(r_band, band_start, d, n, i, j, k);
+    }
+}
+
+/// The sums of one block, with the columns of `r` innermost so that a row of `d` is added to a
+/// row of `r` at a time, which vectorizes without a transposed copy of `d`.
+fn leaf(
+    r_band: &mut [f32],
+    band_start: usize,
+    d: &[f32],
+    n: usize,
+    i: Range<usize>,
+    j: Range<usize>,
+    k: Range<usize>,
+) {
+    // Each sum loads an element of `d` and one of `r` and stores the latter, and each `x` one more.
+    count!(
+        loads: i.len() * k.len() * (2 * j.len() + 1),
+        stores: i.len() * k.len() * j.len(),
+        adds: i.len() * k.len() * j.len(),
+        mins: i.len() * k.len() * j.len(),
+    );
+    for i in i {
+        let r_row = &mut r_band[n * (i - band_start)..][j.clone()];
+        for k in k.clone() {
+            let x = d[n*i + k];
+            for (res, &y) in r_row.iter_mut().zip(&d[n * k..][j.clone()]) {
+                *res = if x + y < *res { x + y } else { *res };
+            }
+        }
+    }
+}
+
+/// `step` by cache-oblivious divide and conquer instead of the explicit blocking of `v7`, with
+/// one band of rows for each thread.
+pub(crate) fn step_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    span!("compute", n);
+    let band = n.div_ceil(threads.effective_threads()).max(1);
+    for_each_chunk(threads, r, band * n, |b, r_band| {
+        let rows = band * b..n.min(band * (b + 1));
+        r_band.fill(f32::INFINITY);
+        recurse(r_band, rows.start, d, n, rows, 0..n, 0..n);
+    });
+}
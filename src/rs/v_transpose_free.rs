@@ -0,0 +1,129 @@
+use std::ops::Range;
+
+use crate::ops::count;
+use crate::simd::{load, Vector};
+use crate::threads::ThreadConfig;
+
+/// Rows of `r` each tile computes, which share the vectors loaded from a row of `d`.
+const ROWS: usize = 4;
+/// Vectors of consecutive columns each tile computes, which share the broadcast elements of the
+/// rows of `d`. With `ROWS` they take 12 of the 16 registers of AVX2, one per accumulator.
+const VECTORS: usize = 3;
+/// Rows of `r` each thread takes at a time. It computes them `TERMS` terms and one panel of
+/// columns after another, so that the `TERMS` rows of a panel of `d`, 24 KiB with AVX2, are read
+/// from memory for the first `ROWS` rows of the band and from the L1 cache for the others.
+const BAND: usize = 128;
+const TERMS: usize = 256;
+
+/// The tile of `R * C * V::LANES` results of the rows `i` to `i + R` and the columns from `j` of
+/// `r_band`, the rows of `r` from `band_start`, reduced over the terms `k`, and over the earlier
+/// terms already in `r_band` unless `k` starts at zero. There is no transposed copy of `d`: for
+/// each `k` the columns come from contiguous vectors of row `k` of `d`, and each row `i` adds its
+/// `d[i][k]` broadcast to all lanes, so that no horizontal minimum is needed either.
+#[inline(always)]
+unsafe fn tile<V: Vector, const R: usize, const C: usize>(
+    r_band: &mut [f32],
+    band_start: usize,
+    d: &[f32],
+    n: usize,
+    i: usize,
+    j: usize,
+    k: Range<usize>,
+) {
+    let d_rows: [&[f32]; R] = std::array::from_fn(|row| &d[n * (band_start + i + row)..][..n]);
+    let mut acc = [[V::splat(f32::INFINITY); C]; R];
+    if k.start > 0 {
+        for (row, acc_row) in acc.iter_mut().enumerate() {
+            for (c, acc) in acc_row.iter_mut().enumerate() {
+                *acc = load::<V, false>(r_band, n * (i + row) + j + c * V::LANES);
+            }
+        }
+    }
+    for k in k.clone() {
+        let y: [V; C] = std::array::from_fn(|c| load::<V, false>(d, n*k + j + c * V::LANES));
+        for (acc_row, d_row) in acc.iter_mut().zip(&d_rows) {
+            let x = V::splat(d_row[k]);
+            for (acc, &y) in acc_row.iter_mut().zip(&y) {
+                *acc = V::min(*acc, V::add(x, y));
+            }
+        }
+    }
+    for (row, acc_row) in acc.iter().enumerate() {
+        let r_row = &mut r_band[n * (i + row)..];
+        for (c, acc) in acc_row.iter().enumerate() {
+            // Every `Vector` is its `LANES` elements in memory.
+            let out = &mut r_row[j + c * V::LANES..j + (c + 1) * V::LANES];
+            std::ptr::copy_nonoverlapping(acc as *const V as *const f32, out.as_mut_ptr(), V::LANES);
+        }
+    }
+    count!(
+        loads: k.len() * (C * V::LANES + R) + if k.start > 0 { R * C * V::LANES } else { 0 },
+        stores: R * C * V::LANES,
+        adds: k.len() * R * C * V::LANES,
+        mins: k.len() * R * C * V::LANES,
+    );
+}
+
+/// The columns from `j` of all rows of `r_band`, `ROWS` at a time and the last few one by one.
+#[inline(always)]
+unsafe fn panel<V: Vector, const C: usize>(
+    r_band: &mut [f32],
+    band_start: usize,
+    d: &[f32],
+    n: usize,
+    j: usize,
+    k: Range<usize>,
+) {
+    let band_rows = r_band.len() / n;
+    let mut i = 0;
+    while i + ROWS <= band_rows {
+        tile::<V, ROWS, C>(r_band, band_start, d, n, i, j, k.clone());
+        i += ROWS;
+    }
+    for i in i..band_rows {
+        tile::<V, 1, C>(r_band, band_start, d, n, i, j, k.clone());
+    }
+}
+
+/// The rows of `r_band`, for each `TERMS` terms in panels of `VECTORS` vectors of columns, then of
+/// single vectors, then of single columns where fewer than `V::LANES` are left.
+#[inline(always)]
+unsafe fn step_band<V: Vector>(r_band: &mut [f32], band_start: usize, d: &[f32], n: usize) {
+    for k in (0..n).step_by(TERMS).map(|k| k..n.min(k + TERMS)) {
+        let mut j = 0;
+        while j + VECTORS * V::LANES <= n {
+            panel::<V, VECTORS>(r_band, band_start, d, n, j, k.clone());
+            j += VECTORS * V::LANES;
+        }
+        while j + V::LANES <= n {
+            panel::<V, 1>(r_band, band_start, d, n, j, k.clone());
+            j += V::LANES;
+        }
+        for j in j..n {
+            panel::<f32, 1>(r_band, band_start, d, n, j, k.clone());
+        }
+    }
+}
+
+/// Applies `step_band` to the bands of `BAND` rows of `r` in parallel.
+/// Like `simd::step_lanes`, a macro to keep the `#[target_feature]`s of the caller in the closure.
+macro_rules! step_bands {
+    ($V:ty, $threads:expr, $r:expr, $d:expr, $n:expr) => {{
+        let (r, d, n): (&mut [f32], &[f32], usize) = ($r, $d, $n);
+        crate::trace::span!("compute", n);
+        crate::threads::for_each_chunk($threads, r, BAND * n, |b, r_band| unsafe {
+            step_band::<$V>(r_band, BAND * b, d, n)
+        })
+    }};
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn step_avx2(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    step_bands!(std::arch::x86_64::__m256, threads, r, d, n)
+}
+
+/// The same tiles with the portable vectors of 8 lanes, which the compiler vectorizes.
+pub(crate) fn step_portable(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    step_bands!([f32; 8], threads, r, d, n)
+}
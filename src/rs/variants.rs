@@ -0,0 +1,357 @@
+#[cfg(feature = "std")]
+use crate::scratch::Scratch;
+use crate::semiring::MinPlus;
+use crate::threads::ThreadConfig;
+use crate::trace::span;
+use crate::{simd, v0_cpp_port};
+#[cfg(feature = "std")]
+use crate::{dispatch, layout, registry, tune, v4_register_reuse, v_recursive};
+#[cfg(feature = "std")]
+use crate::simd::PARANOID;
+#[cfg(feature = "std")]
+use crate::{v5_more_register_reuse, v7_cache_reuse, v_transpose_free};
+
+pub type StepFn = fn(&mut [f32], &[f32], usize);
+
+/// The eight versions of `step` from the tutorial, from slowest to fastest, and `recursive` and
+/// `transpose_free` to compare their blocking and their reading of `d` with. Without `std` only
+/// `v0` to `v2` exist, and are called directly.
+#[cfg(feature = "std")]
+pub const VARIANTS: [(&str, StepFn); 10] = [
+    ("v0", v0),
+    ("v1", v1),
+    ("v2", v2),
+    ("v3", v3),
+    ("v4", v4),
+    ("v5", v5),
+    ("v6", v6),
+    ("v7", v7),
+    ("recursive", recursive),
+    ("transpose-free", transpose_free),
+];
+
+pub type StepWithThreadsFn = fn(&ThreadConfig, &mut [f32], &[f32], usize);
+
+/// `VARIANTS` with the threads to run on as the first parameter.
+#[cfg(feature = "std")]
+pub const VARIANTS_WITH_THREADS: [(&str, StepWithThreadsFn); 10] = [
+    ("v0", v0_with_threads),
+    ("v1", v1_with_threads),
+    ("v2", v2_with_threads),
+    ("v3", v3_with_threads),
+    ("v4", v4_with_threads),
+    ("v5", v5_with_threads),
+    ("v6", v6_with_threads),
+    ("v7", v7_with_threads),
+    ("recursive", recursive_with_threads),
+    ("transpose-free", transpose_free_with_threads),
+];
+
+/// One of `VARIANTS`, `"portable-simd"` for `portable_simd` with the feature of the same name, or
+/// else a variant added with `registry::register`.
+#[cfg(feature = "std")]
+pub fn by_name(name: &str) -> Option<StepFn> {
+    #[cfg(feature = "portable-simd")]
+    if name == "portable-simd" {
+        return Some(portable_simd);
+    }
+    VARIANTS.iter().find(|(variant, _)| *variant == name).map(|&(_, f)| f).or_else(|| registry::by_name(name))
+}
+
+/// One of `VARIANTS_WITH_THREADS`, or `"portable-simd"` like `by_name`.
+#[cfg(feature = "std")]
+pub fn by_name_with_threads(name: &str) -> Option<StepWithThreadsFn> {
+    #[cfg(feature = "portable-simd")]
+    if name == "portable-simd" {
+        return Some(portable_simd_with_threads);
+    }
+    VARIANTS_WITH_THREADS.iter().find(|(variant, _)| *variant == name).map(|&(_, f)| f)
+}
+
+/// The fastest variant that does not fall back to a slower one on this CPU.
+#[cfg(feature = "std")]
+pub fn best() -> &'static str {
+    if has_avx2() || has_neon() { "v7" } else { "v4" }
+}
+
+#[cfg(feature = "std")]
+fn has_avx2() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        !PARANOID && is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+#[cfg(feature = "std")]
+fn has_neon() -> bool {
+    #[cfg(target_arch = "aarch64")]
+    {
+        !PARANOID && std::arch::is_aarch64_feature_detected!("neon")
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        false
+    }
+}
+
+/// The bytes that `variant` allocates for copies of `d` for size `n`, besides `r` and `d`, on this
+/// CPU: the padded and transposed copies, and the results of the band of rows of `v7`. `"auto"`
+/// is `best`, and any other name, such as `"step"`, the `dispatch` kernel of `crate::step`. Those
+/// of `v4` and `v7` are the most for any shape and tuning, to compare with a memory budget without
+/// tuning them first.
+#[cfg(feature = "std")]
+pub fn estimate_memory(n: usize, variant: &str) -> usize {
+    let floats = |len: usize| len.saturating_mul(std::mem::size_of::<f32>());
+    let blocks = n.div_ceil(8);
+    match if variant == "auto" { best() } else { variant } {
+        "v0" | "recursive" | "transpose-free" => 0,
+        "v1" => simd::packed_bytes(n, n, n, 1),
+        "v2" => simd::packed_bytes(n, n, n, 4),
+        "v3" if has_avx2() => simd::packed_bytes(n, n, n, 8),
+        // Rows padded to the 4 of the largest shapes, to the 16 lanes of AVX-512.
+        "v4" => floats(n.div_ceil(4) * 4).saturating_mul(n.div_ceil(16) * 16).saturating_mul(2),
+        "v5" | "v6" if has_avx2() || has_neon() || PARANOID => floats(blocks * 8).saturating_mul(n).saturating_mul(2),
+        "v5" | "v6" => estimate_memory(n, "v4"),
+        "v7" if has_avx2() || has_neon() || PARANOID => {
+            let stripes = floats(blocks * 8).saturating_mul(2 * tune::Tuning::default().col_block.clamp(1, n.max(1)));
+            // Each pair of 8-row blocks of a band has 64 results, and is sorted by `row_pairs` with its key.
+            let pairs = blocks.saturating_mul(blocks);
+            stripes.saturating_add(pairs.saturating_mul(64 * std::mem::size_of::<f32>() + 5 * std::mem::size_of::<usize>()))
+        }
+        "v7" => estimate_memory(n, "v4"),
+        _ => simd::packed_bytes(n, n, n, dispatch::selected().lanes()),
+    }
+}
+
+fn assert_lengths(r: &[f32], d: &[f32], n: usize) {
+    assert_eq!(r.len(), n * n, "r.len() must be n * n");
+    assert_eq!(d.len(), n * n, "d.len() must be n * n");
+}
+
+/// `v3` with the vectors of `std::simd` instead of intrinsics, from `v_portable_simd`.
+#[cfg(all(feature = "std", feature = "portable-simd"))]
+pub fn portable_simd(r: &mut [f32], d: &[f32], n: usize) {
+    portable_simd_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+pub fn v0(r: &mut [f32], d: &[f32], n: usize) {
+    v0_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+pub fn v1(r: &mut [f32], d: &[f32], n: usize) {
+    v1_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+pub fn v2(r: &mut [f32], d: &[f32], n: usize) {
+    v2_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v3(r: &mut [f32], d: &[f32], n: usize) {
+    v3_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v4(r: &mut [f32], d: &[f32], n: usize) {
+    v4_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v5(r: &mut [f32], d: &[f32], n: usize) {
+    v5_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v6(r: &mut [f32], d: &[f32], n: usize) {
+    v6_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v7(r: &mut [f32], d: &[f32], n: usize) {
+    v7_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn recursive(r: &mut [f32], d: &[f32], n: usize) {
+    recursive_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn transpose_free(r: &mut [f32], d: &[f32], n: usize) {
+    transpose_free_with_threads(&ThreadConfig::default(), r, d, n)
+}
+
+/// `v0` is always sequential, like the C++ version it was ported from.
+pub fn v0_with_threads(_threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    span!("compute", n);
+    v0_cpp_port::_step::<MinPlus<f32>>(r, d, n);
+    crate::ops::count!(loads: 2 * n * n * n, stores: n * n, adds: n * n * n, mins: n * n * n);
+}
+
+#[cfg(all(feature = "std", feature = "portable-simd"))]
+pub fn portable_simd_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    span!("compute", n);
+    crate::v_portable_simd::step_with_threads(threads, r, d, n)
+}
+
+pub fn v1_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    simd::step_scalar(threads, r, n, &simd::Packed::square(d, n, n, 1), false)
+}
+
+pub fn v2_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    simd::step_lanes!([f32; 4], threads, r, n, &simd::Packed::square(d, n, n, 4), false)
+}
+
+/// `v3` and later use AVX2 when the CPU has it, otherwise the best kernel from `dispatch`. With
+/// `simd::PARANOID`, `v5` to `v7` run the loops of their AVX2 kernels with portable vectors
+/// instead, and `v3` those of `dispatch` with one lane. On AArch64, `v4` to `v7` run the same loops
+/// with NEON, a pair of vectors standing for each AVX2 vector of `v5` to `v7`.
+#[cfg(feature = "std")]
+pub fn v3_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        return unsafe { simd::x86::step_avx2(threads, r, n, &simd::Packed::square(d, n, n, 8), false) };
+    }
+    dispatch::step(threads, r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v4_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    #[cfg(target_arch = "x86_64")]
+    if !PARANOID && is_x86_feature_detected!("avx512f") {
+        return unsafe { v4_register_reuse::step_avx512(threads, &mut Scratch::default(), r, d, n) };
+    }
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        return unsafe { v4_register_reuse::step_avx2(threads, &mut Scratch::default(), r, d, n) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if has_neon() {
+        return unsafe { v4_register_reuse::step_neon(threads, &mut Scratch::default(), r, d, n) };
+    }
+    v4_register_reuse::step_portable(threads, &mut Scratch::default(), r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v5_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        assert_lengths(r, d, n);
+        return unsafe { v5_more_register_reuse::step_avx2(threads, &mut Scratch::default(), r, d, n) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if has_neon() {
+        assert_lengths(r, d, n);
+        return unsafe { v5_more_register_reuse::step_neon(threads, &mut Scratch::default(), r, d, n) };
+    }
+    if PARANOID {
+        assert_lengths(r, d, n);
+        return v5_more_register_reuse::step_portable(threads, &mut Scratch::default(), r, d, n, false);
+    }
+    v4_with_threads(threads, r, d, n)
+}
+
+#[cfg(feature = "std")]
+pub fn v6_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        assert_lengths(r, d, n);
+        return unsafe { v5_more_register_reuse::step_prefetch_avx2(threads, &mut Scratch::default(), r, d, n) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if has_neon() {
+        assert_lengths(r, d, n);
+        return unsafe { v5_more_register_reuse::step_prefetch_neon(threads, &mut Scratch::default(), r, d, n) };
+    }
+    if PARANOID {
+        assert_lengths(r, d, n);
+        return v5_more_register_reuse::step_portable(threads, &mut Scratch::default(), r, d, n, true);
+    }
+    v4_with_threads(threads, r, d, n)
+}
+
+/// `v6` of a matrix already packed by `layout::to_interleaved`, with the same loops without SIMD
+/// instructions on CPUs without AVX2 or NEON, which `v6` would leave to `v4`.
+#[cfg(feature = "std")]
+pub(crate) fn v6_preinterleaved(threads: &ThreadConfig, r: &mut [f32], d: &layout::InterleavedMatrix) {
+    let n = d.n();
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        return unsafe { v5_more_register_reuse::step_packed_avx2(threads, &d.scratch, r, n, true) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if has_neon() {
+        return unsafe { v5_more_register_reuse::step_packed_neon(threads, &d.scratch, r, n, true) };
+    }
+    v5_more_register_reuse::step_packed_portable(threads, &d.scratch, r, n, true)
+}
+
+#[cfg(feature = "std")]
+pub fn v7_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        return v7_with_tuning(threads, r, d, n, &tune::tuning(n));
+    }
+    #[cfg(target_arch = "aarch64")]
+    if has_neon() {
+        return v7_with_tuning(threads, r, d, n, &tune::tuning(n));
+    }
+    if PARANOID {
+        return v7_with_tuning(threads, r, d, n, &tune::Tuning::default());
+    }
+    v4_with_threads(threads, r, d, n)
+}
+
+/// `v7` with `tuning` instead of the cached parameters for `n`, which are only used with AVX2, NEON
+/// and `simd::PARANOID`, including `Tuning::transpose_free` except with NEON.
+#[cfg(feature = "std")]
+pub fn v7_with_tuning(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize, tuning: &tune::Tuning) {
+    if tuning.transpose_free && (has_avx2() || PARANOID) {
+        return transpose_free_with_threads(threads, r, d, n);
+    }
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        assert_lengths(r, d, n);
+        return unsafe { v7_cache_reuse::step_avx2(threads, &mut Scratch::default(), r, d, n, tuning) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if has_neon() {
+        assert_lengths(r, d, n);
+        return unsafe { v7_cache_reuse::step_neon(threads, &mut Scratch::default(), r, d, n, tuning) };
+    }
+    if PARANOID {
+        assert_lengths(r, d, n);
+        return v7_cache_reuse::step_portable(threads, &mut Scratch::default(), r, d, n, tuning);
+    }
+    v4_with_threads(threads, r, d, n)
+}
+
+/// Splits the matrices in halves until the blocks fit in the L1 cache, without tuning any block
+/// size to the caches like `v7`.
+#[cfg(feature = "std")]
+pub fn recursive_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    v_recursive::step_with_threads(threads, r, d, n)
+}
+
+/// Reads the columns of `d` with vector loads from its rows, for register tiles of four rows and
+/// 24 columns of `r`, instead of from a transposed copy, so that it allocates nothing besides `r`.
+#[cfg(feature = "std")]
+pub fn transpose_free_with_threads(threads: &ThreadConfig, r: &mut [f32], d: &[f32], n: usize) {
+    assert_lengths(r, d, n);
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        return unsafe { v_transpose_free::step_avx2(threads, r, d, n) };
+    }
+    v_transpose_free::step_portable(threads, r, d, n)
+}
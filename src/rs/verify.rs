@@ -0,0 +1,131 @@
+use std::time::Instant;
+
+use crate::{Compensated, StepError};
+
+/// How two `n * n` matrices differ, such as results of `step` from different variants,
+/// precisions or the C++ versions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffReport {
+    pub n: usize,
+    /// Of the elements that are both finite, or equal infinities, which differ by zero.
+    pub max_abs: f64,
+    pub mean_abs: f64,
+    /// The absolute error divided by the larger magnitude of the two elements, zero where both are
+    /// zero, of the same elements as `max_abs`.
+    pub max_rel: f64,
+    pub mean_rel: f64,
+    /// `(i, j)` of the first disagreement about infinities or NaN, or else of the first largest
+    /// absolute error, if any elements differ.
+    pub worst: Option<(usize, usize)>,
+    /// Elements where one matrix has an infinity and the other a finite value or the other infinity.
+    pub infinity_mismatches: usize,
+    /// Elements where only one matrix has NaN.
+    pub nan_mismatches: usize,
+}
+
+impl DiffReport {
+    /// Whether the matrices agree on all infinities and NaNs, and all other elements are within a
+    /// relative error of `tolerance`.
+    pub fn within(&self, tolerance: f64) -> bool {
+        self.infinity_mismatches == 0 && self.nan_mismatches == 0 && self.max_rel <= tolerance
+    }
+}
+
+/// Compares `a` and `b` element by element, in `f64`, so that results in `f32` can be compared
+/// against the ones of `step_f64` without rounding those first.
+///
+/// Panics if `a` or `b` does not have `n * n` elements.
+pub fn diff<A: Copy + Into<f64>, B: Copy + Into<f64>>(a: &[A], b: &[B], n: usize) -> DiffReport {
+    assert_eq!(a.len(), n * n, "a.len() must be n * n");
+    assert_eq!(b.len(), n * n, "b.len() must be n * n");
+    let mut report = DiffReport { n, ..Default::default() };
+    let (mut compared, mut sum_abs, mut sum_rel) = (0, 0.0, 0.0);
+    // Disagreements about infinities and NaNs count as infinite errors, so they are the worst.
+    let mut worst_error = 0.0;
+    for (index, (&x, &y)) in a.iter().zip(b).enumerate() {
+        let (x, y): (f64, f64) = (x.into(), y.into());
+        let error = if x.is_nan() || y.is_nan() {
+            if x.is_nan() != y.is_nan() {
+                report.nan_mismatches += 1;
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        } else if x.is_infinite() || y.is_infinite() {
+            if x != y {
+                report.infinity_mismatches += 1;
+                f64::INFINITY
+            } else {
+                compared += 1;
+                0.0
+            }
+        } else {
+            let abs = (x - y).abs();
+            let rel = if abs == 0.0 { 0.0 } else { abs / x.abs().max(y.abs()) };
+            (report.max_abs, report.max_rel) = (report.max_abs.max(abs), report.max_rel.max(rel));
+            (compared, sum_abs, sum_rel) = (compared + 1, sum_abs + abs, sum_rel + rel);
+            abs
+        };
+        if error > worst_error {
+            (worst_error, report.worst) = (error, Some((index / n, index % n)));
+        }
+    }
+    if compared > 0 {
+        (report.mean_abs, report.mean_rel) = (sum_abs / compared as f64, sum_rel / compared as f64);
+    }
+    report
+}
+
+/// How long `steps` squarings of a matrix took in one precision, and how far the result is from the
+/// one in `f64`, as by `compare_precisions`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrecisionReport {
+    /// `"f32"`, `"f32-compensated"` or `"f64"`.
+    pub precision: &'static str,
+    pub seconds: f64,
+    /// Against the result in `f64`, which `f64` itself matches exactly.
+    pub diff: DiffReport,
+}
+
+/// Squares `d` `steps` times, like `apsp` does, with `step`, `step_f32_compensated` and `step_f64`,
+/// so that the accuracy of the first two can be weighed against how much faster they are.
+pub fn compare_precisions(d: &[f32], n: usize, steps: usize) -> Result<Vec<PrecisionReport>, StepError> {
+    fn repeat<T: Copy>(
+        mut d: Vec<T>,
+        steps: usize,
+        step: impl Fn(&mut [T], &[T]) -> Result<(), StepError>,
+    ) -> Result<(Vec<T>, f64), StepError> {
+        let start = Instant::now();
+        let mut r = d.clone();
+        for _ in 0..steps {
+            step(&mut r, &d)?;
+            std::mem::swap(&mut r, &mut d);
+        }
+        Ok((d, start.elapsed().as_secs_f64()))
+    }
+    let (single, single_seconds) = repeat(d.to_vec(), steps, |r, d| crate::step(r, d, n))?;
+    let compensated_d = d.iter().map(|&x| Compensated::from(x)).collect();
+    let (compensated, compensated_seconds) = repeat(compensated_d, steps, |r, d| crate::step_f32_compensated(r, d, n))?;
+    let (double, double_seconds) = repeat(d.iter().map(|&x| x as f64).collect(), steps, |r, d| crate::step_f64(r, d, n))?;
+    Ok(vec![
+        PrecisionReport { precision: "f32", seconds: single_seconds, diff: diff(&single, &double, n) },
+        PrecisionReport { precision: "f32-compensated", seconds: compensated_seconds, diff: diff(&compensated, &double, n) },
+        PrecisionReport { precision: "f64", seconds: double_seconds, diff: diff(&double, &double, n) },
+    ])
+}
+
+/// How far the results of `step_u8` are from those of `step`: quantizes `d` with `scale` and
+/// `offset`, steps it with `step_u8`, and compares the dequantized result against the step of `d`
+/// itself in `f32`, so that the error of quantizing `d` is included along with that of rounding
+/// the results. Values of `d` beyond the largest quantized one become infinities, and show up as
+/// `infinity_mismatches` where they are on the shortest paths.
+pub fn quantization_error(d: &[f32], n: usize, scale: f32, offset: f32) -> Result<DiffReport, StepError> {
+    let quantized: Vec<u8> = d.iter().map(|&x| crate::quantize_u8(x, scale, offset)).collect();
+    let mut r = vec![0; quantized.len()];
+    crate::step_u8(&mut r, &quantized, n, scale, offset)?;
+    let mut expected = vec![0.0; d.len()];
+    crate::step(&mut expected, d, n)?;
+    let dequantized: Vec<f32> = r.iter().map(|&q| crate::dequantize_u8(q, scale, offset)).collect();
+    Ok(diff(&dequantized, &expected, n))
+}
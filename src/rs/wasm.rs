@@ -0,0 +1,37 @@
+use wasm_bindgen::prelude::*;
+
+use crate::variants::{by_name, VARIANTS};
+
+/// `crate::step` for JavaScript, throwing an `Error` with the `StepError` message on invalid input.
+#[wasm_bindgen]
+pub fn step(r: &mut [f32], d: &[f32], n: usize) -> Result<(), JsError> {
+    Ok(crate::step(r, d, n)?)
+}
+
+/// The names accepted by `step_variant`, from slowest to fastest.
+#[wasm_bindgen]
+pub fn variants() -> Vec<String> {
+    VARIANTS.iter().map(|(name, _)| name.to_string()).collect()
+}
+
+/// Runs one of `variants()`, for timing them against each other with `performance.now()`.
+#[wasm_bindgen]
+pub fn step_variant(name: &str, r: &mut [f32], d: &[f32], n: usize) -> Result<(), JsError> {
+    let step = by_name(name).ok_or_else(|| JsError::new(&format!("unknown variant '{}'", name)))?;
+    crate::check_lengths(r, d, n)?;
+    step(r, d, n);
+    Ok(())
+}
+
+/// `bench::random_input`, so that the browser runs the same inputs as `shortcut-bench`.
+#[wasm_bindgen]
+pub fn random_input(n: usize) -> Vec<f32> {
+    crate::bench::random_input(n)
+}
+
+/// The `dispatch` kernel used by `step`, `"simd128"` if the module was built with
+/// `-C target-feature=+simd128`.
+#[wasm_bindgen]
+pub fn kernel() -> String {
+    crate::dispatch::selected().name().to_string()
+}